@@ -0,0 +1,137 @@
+use crate::ask::{self, Answer, Button};
+use crate::telegram::TelegramClient;
+use crate::{Args, Outcome, config};
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::time::Duration;
+
+/// Approve/deny buttons, also answered by typing yes or no.
+pub const APPROVAL: &[Button] = &[
+    Button {
+        name: "approve",
+        label: "✅ Approve",
+        words: &["yes", "y", "approve", "ok"],
+    },
+    Button {
+        name: "deny",
+        label: "❌ Deny",
+        words: &["no", "n", "deny"],
+    },
+];
+pub const APPROVE: usize = 0;
+
+/// How long `--pam` waits for an answer unless `--timeout` is given; logins shouldn't hang.
+const PAM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Environment variables pam_exec sets, and how the prompt labels them.
+const PAM_FIELDS: &[(&str, &str)] = &[
+    ("PAM_USER", "user"),
+    ("PAM_RHOST", "from"),
+    ("PAM_RUSER", "remote user"),
+    ("PAM_SERVICE", "service"),
+    ("PAM_TTY", "tty"),
+];
+
+/// Ask for approval and run `command` only if it is given, exiting with its exit code. A
+/// denial, cancel or timeout leaves the command unrun and ends like a prompt run would.
+pub async fn run(
+    args: &Args,
+    config_path: &Path,
+    message: &str,
+    to: &[String],
+    command: &[String],
+    timeout: Option<Duration>,
+) -> Result<Outcome> {
+    let (program, program_args) = command.split_first().context("no command to run")?;
+    let mut cfg = config::load(config_path)?;
+    if !to.is_empty() {
+        cfg.user_ids = to
+            .iter()
+            .map(|name| cfg.resolve_recipient(name))
+            .collect::<Result<_>>()?;
+    }
+    let client =
+        TelegramClient::for_config(&cfg)?.with_fix_webhook(args.fix_webhook || cfg.fix_webhook);
+    let timeout =
+        timeout.unwrap_or_else(|| Duration::from_secs(cfg.timeout_minutes.saturating_mul(60)));
+
+    match ask::ask(&client, &cfg, args, message, APPROVAL, timeout).await? {
+        Answer::Chose(APPROVE) => {}
+        Answer::Chose(_) => {
+            tracing::info!("denied; not running {program}");
+            return Ok(Outcome::Rejected);
+        }
+        Answer::Cancelled => return Ok(Outcome::Cancelled),
+        Answer::TimedOut => return Ok(Outcome::TimedOut),
+    }
+
+    tracing::info!("approved; running {program}");
+    let status = tokio::process::Command::new(program)
+        .args(program_args)
+        .status()
+        .await
+        .with_context(|| format!("run {program}"))?;
+    Ok(match status.code() {
+        Some(0) => Outcome::Done,
+        code => Outcome::Exit(code.unwrap_or(1)),
+    })
+}
+
+/// `--pam`: approve a login for pam_exec. Exits `0` only when someone presses Approve (or
+/// types yes); a denial, cancel, timeout or any error fails the login. Writes nothing to
+/// stdout.
+pub async fn pam(args: &Args, config_path: &Path) -> Result<Outcome> {
+    // Closing a session is not a decision to make.
+    if std::env::var("PAM_TYPE").is_ok_and(|t| t == "close_session") {
+        return Ok(Outcome::Done);
+    }
+    let cfg = config::load(config_path)?;
+    let client =
+        TelegramClient::for_config(&cfg)?.with_fix_webhook(args.fix_webhook || cfg.fix_webhook);
+    let host = std::fs::read_to_string("/etc/hostname").ok();
+    let message = pam_message(|name| std::env::var(name).ok(), host.as_deref());
+    let timeout = args.timeout.unwrap_or(PAM_TIMEOUT);
+
+    Ok(
+        match ask::ask(&client, &cfg, args, &message, APPROVAL, timeout).await? {
+            Answer::Chose(APPROVE) => Outcome::Done,
+            Answer::Chose(_) => Outcome::Rejected,
+            Answer::Cancelled => Outcome::Cancelled,
+            Answer::TimedOut => Outcome::TimedOut,
+        },
+    )
+}
+
+/// The login approval prompt, with whatever context pam_exec provided.
+fn pam_message(env: impl Fn(&str) -> Option<String>, host: Option<&str>) -> String {
+    let mut message = "🔐 Login approval".to_string();
+    let host = host.map(str::trim).filter(|h| !h.is_empty());
+    let fields = PAM_FIELDS
+        .iter()
+        .filter_map(|(var, label)| env(var).map(|value| (*label, value)))
+        .chain(host.map(|h| ("host", h.to_string())));
+    for (label, value) in fields {
+        message.push_str(&format!("\n{label}: {value}"));
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pam_message_lists_the_login_context_present() {
+        let env = |name: &str| match name {
+            "PAM_USER" => Some("alice".to_string()),
+            "PAM_RHOST" => Some("203.0.113.7".to_string()),
+            "PAM_SERVICE" => Some("sshd".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            pam_message(env, Some("web1\n")),
+            "🔐 Login approval\nuser: alice\nfrom: 203.0.113.7\nservice: sshd\nhost: web1"
+        );
+        assert_eq!(pam_message(|_| None, None), "🔐 Login approval");
+    }
+}
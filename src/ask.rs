@@ -0,0 +1,245 @@
+use crate::telegram::{self, SendOptions, TelegramClient, Update};
+use crate::{Args, backoff, config, lock, offset, state};
+use anyhow::Result;
+use chrono::Utc;
+use std::time::{Duration, Instant};
+
+/// Callback data prefix of the buttons.
+const BUTTON_PREFIX: &str = "ask:";
+
+/// One answer offered as an inline button.
+pub struct Button {
+    /// Callback data (after the prefix).
+    pub name: &'static str,
+    /// Text on the button.
+    pub label: &'static str,
+    /// Messages that also pick this answer, compared ignoring case and surrounding whitespace.
+    /// Emojis among them work as reactions to the question too.
+    pub words: &'static [&'static str],
+}
+
+/// How an `ask` ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    /// Index of the chosen button.
+    Chose(usize),
+    /// The recipient sent the cancel command.
+    Cancelled,
+    TimedOut,
+}
+
+/// Send `text` to the recipients with one inline button per answer and wait for a press, or
+/// a message naming one, for at most `timeout`. Polls like a prompt run: under the polling
+/// lock, taken once the question is out, from the saved offset. Afterwards the buttons are replaced by a note
+/// of the answer.
+pub async fn ask(
+    client: &TelegramClient,
+    cfg: &config::Config,
+    args: &Args,
+    text: &str,
+    buttons: &[Button],
+    timeout: Duration,
+) -> Result<Answer> {
+    let store = state::StateStore::for_config(cfg).ok();
+    let lock_dir = cfg.state_dir().ok();
+    let mode = args.poll_lock.unwrap_or(cfg.poll_lock);
+    // As in a prompt run: fail before sending, or wait for the lock after it.
+    let mut _poll_lock = match &lock_dir {
+        Some(dir) if mode != lock::LockMode::Wait => {
+            lock::acquire(dir, &cfg.bot_token, mode, timeout).await?
+        }
+        _ => None,
+    };
+    let run_started = Utc::now().timestamp();
+
+    let opts = SendOptions {
+        reply_markup: Some(markup(buttons)),
+        thread_id: cfg.thread_id,
+        ..Default::default()
+    };
+    let mut prompts = Vec::new();
+    for &chat_id in &cfg.user_ids {
+        prompts.push((chat_id, client.send_message(chat_id, text, &opts).await?));
+    }
+
+    let sent_at = Utc::now().timestamp();
+    let start = Instant::now();
+    if let Some(dir) = lock_dir.as_ref().filter(|_| mode == lock::LockMode::Wait) {
+        let patience = timeout.saturating_sub(start.elapsed());
+        match lock::acquire(dir, &cfg.bot_token, mode, patience).await {
+            Ok(lock) => _poll_lock = lock,
+            Err(e) if start.elapsed() >= timeout => tracing::info!("{e:#}"),
+            Err(e) => return Err(e),
+        }
+    }
+    let saved_offset = store
+        .as_ref()
+        .and_then(|store| offset::load(store, &cfg.bot_token));
+    let (mut offset, not_before) = match saved_offset {
+        Some(saved) => (saved, run_started - offset::PENDING_GRACE_S),
+        // Answers typed before the question are no answers.
+        None => (0, sent_at),
+    };
+
+    let mut backoff = backoff::Backoff::default();
+    let answer = loop {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            break Answer::TimedOut;
+        }
+        let long_poll = remaining.min(Duration::from_secs(30));
+        let updates = match client.get_updates(offset, long_poll.as_secs()).await {
+            Ok(updates) => {
+                backoff.reset();
+                updates
+            }
+            Err(e) if backoff::transient(&e) => {
+                let delay = backoff.fail().min(remaining);
+                tracing::warn!(
+                    "polling failed ({e:#}); retrying in {:.1}s",
+                    delay.as_secs_f64()
+                );
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let mut found = None;
+        for update in &updates {
+            offset = offset.max(update.update_id + 1);
+            if found.is_none() {
+                found = answer_in(client, cfg, buttons, &prompts, update, not_before).await;
+            }
+        }
+        if let Some(answer) = found {
+            break answer;
+        }
+    };
+
+    if let Some(store) = &store
+        && let Err(e) = offset::save(store, &cfg.bot_token, offset)
+    {
+        tracing::warn!("could not save the update offset: {e:#}");
+    }
+    // The buttons sit on the last part of a split message.
+    let last_part = telegram::split_message(text, telegram::MAX_MESSAGE_LEN)
+        .pop()
+        .unwrap_or_default();
+    let note = match answer {
+        Answer::Chose(i) => format!("{last_part}\n\n→ {}", buttons[i].label),
+        Answer::Cancelled => format!("{last_part}\n\n→ cancelled"),
+        Answer::TimedOut => format!("{last_part}\n\n→ no answer"),
+    };
+    let plain = SendOptions {
+        thread_id: cfg.thread_id,
+        ..Default::default()
+    };
+    for &(chat_id, message_id) in &prompts {
+        if let Err(e) = client
+            .edit_message_text(chat_id, message_id, &note, &plain)
+            .await
+        {
+            tracing::warn!("could not remove the buttons: {e:#}");
+        }
+    }
+    Ok(answer)
+}
+
+fn markup(buttons: &[Button]) -> serde_json::Value {
+    let row: Vec<_> = buttons
+        .iter()
+        .map(|b| {
+            serde_json::json!({
+                "text": b.label,
+                "callback_data": format!("{BUTTON_PREFIX}{}", b.name),
+            })
+        })
+        .collect();
+    serde_json::json!({ "inline_keyboard": [row] })
+}
+
+/// The button a typed answer names, if any.
+fn parse_words(buttons: &[Button], text: &str) -> Option<usize> {
+    let text = text.trim();
+    buttons
+        .iter()
+        .position(|b| b.words.iter().any(|w| w.eq_ignore_ascii_case(text)))
+}
+
+/// The answer an update gives, if it's a press of our buttons, or an answer typed or reacted
+/// by someone who may answer.
+async fn answer_in(
+    client: &TelegramClient,
+    cfg: &config::Config,
+    buttons: &[Button],
+    prompts: &[(i64, i64)],
+    update: &Update,
+    not_before: i64,
+) -> Option<Answer> {
+    if let Some(query) = &update.callback_query {
+        let on_prompt = query
+            .message
+            .as_ref()
+            .is_some_and(|m| prompts.contains(&(m.chat.id, m.message_id)));
+        let name = query.data.as_deref()?.strip_prefix(BUTTON_PREFIX)?;
+        let i = buttons
+            .iter()
+            .position(|b| b.name == name)
+            .filter(|_| on_prompt && cfg.may_answer(query.chat_id(), query.from.id))?;
+        let _ = client
+            .answer_callback_query(&query.id, &format!("Got it: {}", buttons[i].label))
+            .await;
+        return Some(Answer::Chose(i));
+    }
+    if let Some(reaction) = &update.message_reaction {
+        let user = reaction.user.as_ref()?;
+        if !prompts.contains(&(reaction.chat.id, reaction.message_id))
+            || !cfg.may_answer(reaction.chat.id, user.id)
+        {
+            return None;
+        }
+        // `new_reaction` lists all of the user's current reactions; only added ones count.
+        return reaction
+            .new_reaction
+            .iter()
+            .filter(|r| !reaction.old_reaction.contains(r))
+            .filter_map(|r| r.emoji.as_deref())
+            .find_map(|emoji| parse_words(buttons, emoji))
+            .map(Answer::Chose);
+    }
+    let msg = cfg.reply_message(update).filter(|m| m.date >= not_before)?;
+    let text = msg.text.as_deref()?;
+    if cfg.is_cancel(text) {
+        return Some(Answer::Cancelled);
+    }
+    parse_words(buttons, text).map(Answer::Chose)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUTTONS: &[Button] = &[
+        Button {
+            name: "yes",
+            label: "✅ Yes",
+            words: &["yes", "y"],
+        },
+        Button {
+            name: "no",
+            label: "❌ No",
+            words: &["no", "n"],
+        },
+    ];
+
+    #[test]
+    fn typed_words_pick_buttons_and_markup_carries_names() {
+        assert_eq!(parse_words(BUTTONS, " Y "), Some(0));
+        assert_eq!(parse_words(BUTTONS, "no"), Some(1));
+        assert_eq!(parse_words(BUTTONS, "nope"), None);
+
+        let markup = markup(BUTTONS);
+        assert_eq!(markup["inline_keyboard"][0][1]["callback_data"], "ask:no");
+        assert_eq!(markup["inline_keyboard"][0][1]["text"], "❌ No");
+    }
+}
@@ -0,0 +1,227 @@
+use crate::Outcome;
+use crate::config::{AuditPrompt, Config};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// One line of the audit log. Field names are part of the documented interface; only add
+/// fields.
+#[derive(Debug, Default, Serialize)]
+pub struct Entry {
+    /// RFC 3339 time the run started.
+    pub ts: String,
+    /// Name of the config file without its extension.
+    pub profile: String,
+    pub recipients: Vec<i64>,
+    /// Recipients added by `--escalate-after`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub escalated_to: Vec<i64>,
+    /// Full prompt text, with `audit_prompt = "full"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// Hex SHA-256 of the prompt text, with `audit_prompt = "hash"` (the default).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_sha256: Option<String>,
+    /// Accepted answers, in the `--output json` envelope.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub replies: Vec<serde_json::Value>,
+    /// `--quorum` votes.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub votes: Vec<Vote>,
+    /// `sent`, `replied`, `timed_out`, `cancelled`, `suppressed`, `rejected` or `error`.
+    pub outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Vote {
+    pub user_id: i64,
+    pub approve: bool,
+}
+
+/// Collects what a run did for the `audit_log`, and for the history table of the SQLite store.
+/// Inert unless the config uses one of them.
+pub struct Audit {
+    path: Option<PathBuf>,
+    #[cfg(feature = "sqlite")]
+    history_db: Option<PathBuf>,
+    started: Instant,
+    pub entry: Entry,
+}
+
+impl Audit {
+    pub fn new(config_path: &Path) -> Self {
+        Self {
+            path: None,
+            #[cfg(feature = "sqlite")]
+            history_db: None,
+            started: Instant::now(),
+            entry: Entry {
+                ts: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                profile: config_path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Enable the log if the config asks for it, and record the prompt.
+    pub fn begin(&mut self, cfg: &Config, message: &str) {
+        self.path = cfg.audit_log.clone();
+        #[cfg(feature = "sqlite")]
+        if cfg.store == crate::config::StoreKind::Sqlite {
+            match cfg.state_dir() {
+                Ok(dir) => self.history_db = Some(dir.join(crate::db::FILE_NAME)),
+                Err(e) => tracing::warn!("run history will not be recorded: {e:#}"),
+            }
+        }
+        self.entry.recipients = cfg.user_ids.clone();
+        match cfg.audit_prompt {
+            AuditPrompt::Full => self.entry.prompt = Some(message.to_string()),
+            AuditPrompt::Hash => {
+                let digest = Sha256::digest(message.as_bytes());
+                self.entry.prompt_sha256 =
+                    Some(digest.iter().map(|b| format!("{b:02x}")).collect());
+            }
+        }
+    }
+
+    /// Record an accepted answer. Under `--secret` its text is left out.
+    pub fn answer(&mut self, reply: &impl Serialize, secret: bool) {
+        let mut reply = serde_json::to_value(reply).expect("reply serializes");
+        if secret && let Some(map) = reply.as_object_mut() {
+            map.insert("reply".to_string(), serde_json::Value::Null);
+            map.insert("redacted".to_string(), true.into());
+            map.remove("location");
+            map.remove("contact");
+        }
+        self.entry.replies.push(reply);
+    }
+
+    /// Append the entry for a finished run. Failing to write the log doesn't change how the
+    /// run ends; it is only logged.
+    pub fn finish(mut self, res: &Result<Outcome>) {
+        let (outcome, exit_code) = match res {
+            Ok(outcome) => describe(outcome),
+            Err(_) => ("error", None),
+        };
+        self.entry.outcome = outcome;
+        self.entry.exit_code = exit_code;
+        self.entry.error = res.as_ref().err().map(|e| format!("{e:#}"));
+        self.entry.duration_ms = self.started.elapsed().as_millis() as u64;
+
+        if let Some(path) = &self.path
+            && let Err(e) = append(path, &self.entry)
+        {
+            tracing::warn!("could not write the audit log: {e:#}");
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(path) = &self.history_db {
+            let res = crate::db::Db::open(path)
+                .and_then(|db| db.append_history(&serde_json::to_value(&self.entry)?));
+            if let Err(e) = res {
+                tracing::warn!("could not record the run history: {e:#}");
+            }
+        }
+    }
+}
+
+/// Append `entry` as one line. The log holds answers, so it is created readable by the
+/// current user only.
+/// The `outcome` and `exit_code` fields for how a run ended.
+pub fn describe(outcome: &Outcome) -> (&'static str, Option<i32>) {
+    match outcome {
+        Outcome::Done => ("sent", None),
+        Outcome::Replied => ("replied", None),
+        Outcome::Exit(code) => ("replied", Some(*code)),
+        Outcome::TimedOut => ("timed_out", None),
+        Outcome::Cancelled => ("cancelled", None),
+        Outcome::Suppressed => ("suppressed", None),
+        Outcome::Rejected => ("rejected", None),
+        Outcome::Queued => ("queued", None),
+        Outcome::Mapped(outcome, code) => (describe(outcome).0, Some(*code)),
+    }
+}
+
+fn append(path: &Path, entry: &Entry) -> Result<()> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    let mut opts = std::fs::OpenOptions::new();
+    opts.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+    let mut f = opts
+        .open(path)
+        .with_context(|| format!("open audit log: {}", path.display()))?;
+    // A single write keeps lines from concurrent runs whole.
+    f.write_all(&line)
+        .with_context(|| format!("write audit log: {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finished_run_appends_one_line_with_hashed_prompt_and_redacted_secret() {
+        let dir = std::env::temp_dir().join(format!(
+            "teleprompt_audit_test_{}_{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let log = dir.join("audit.jsonl");
+        let cfg: Config = toml::from_str(&format!(
+            "user_id = [1, 2]\naudit_log = {:?}",
+            log.display().to_string()
+        ))
+        .unwrap();
+
+        for secret in [false, true] {
+            let mut audit = Audit::new(Path::new("/etc/teleprompt/work.toml"));
+            audit.begin(&cfg, "deploy?");
+            audit.answer(
+                &serde_json::json!({ "reply": "yes", "chat_id": 1, "location": {}, "contact": {} }),
+                secret,
+            );
+            audit.finish(&Ok(Outcome::Replied));
+        }
+
+        let raw = std::fs::read_to_string(&log).unwrap();
+        let lines: Vec<serde_json::Value> = raw
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["profile"], "work");
+        assert_eq!(lines[0]["recipients"], serde_json::json!([1, 2]));
+        assert_eq!(lines[0]["outcome"], "replied");
+        assert!(lines[0].get("prompt").is_none());
+        assert_eq!(
+            lines[0]["prompt_sha256"],
+            "dd2d251f9addad6de99e5fa153bbe09a8103e37527eac6971e3adede9b49ac1c"
+        );
+        assert_eq!(lines[0]["replies"][0]["reply"], "yes");
+        assert_eq!(lines[1]["replies"][0]["reply"], serde_json::Value::Null);
+        assert_eq!(lines[1]["replies"][0]["redacted"], true);
+        assert!(lines[1]["replies"][0].get("location").is_none());
+        assert!(lines[1]["replies"][0].get("contact").is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::telegram::TelegramError;
+use rand::Rng;
+use std::time::Duration;
+
+/// Delay before the first retry; it doubles with every further failure.
+const BASE: Duration = Duration::from_secs(1);
+
+/// Longest delay between retries.
+const MAX: Duration = Duration::from_secs(60);
+
+/// Exponential backoff with jitter for retrying requests after transient failures.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    failures: u32,
+}
+
+impl Backoff {
+    /// Failures since the last success.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Record a failure and return how long to wait before retrying: a random delay between
+    /// half and all of the exponential step, so concurrent runs don't retry in lockstep.
+    pub fn fail(&mut self) -> Duration {
+        let step = BASE.saturating_mul(1 << self.failures.min(16)).min(MAX);
+        self.failures += 1;
+        step.mul_f64(rand::rng().random_range(0.5..=1.0))
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+/// Whether a failed request is worth retrying: network trouble and Telegram 5xx, but not API
+/// rejections such as a revoked token.
+pub fn transient(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<TelegramError>()
+        .is_some_and(|t| t.kind.retryable())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telegram::ErrorKind;
+
+    #[test]
+    fn delays_grow_up_to_the_cap_and_reset() {
+        let mut backoff = Backoff::default();
+        for step in [1, 2, 4, 8, 16, 32, 60, 60] {
+            let delay = backoff.fail();
+            let step = Duration::from_secs(step);
+            assert!(delay >= step / 2 && delay <= step, "{delay:?} vs {step:?}");
+        }
+        assert_eq!(backoff.failures(), 8);
+        backoff.reset();
+        assert!(backoff.fail() <= BASE);
+    }
+
+    #[test]
+    fn only_retryable_telegram_errors_are_transient() {
+        let server = TelegramError::new(ErrorKind::Server, "502");
+        let api = TelegramError::new(ErrorKind::Api, "401 Unauthorized");
+        assert!(transient(&anyhow::Error::new(server).context("getUpdates")));
+        assert!(!transient(&api.into()));
+        assert!(!transient(&anyhow::anyhow!("disk full")));
+    }
+}
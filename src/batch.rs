@@ -0,0 +1,785 @@
+use crate::telegram::{self, SendOptions, TelegramClient};
+use crate::{Args, Outcome, backoff, config, correlate, duration, lock, offset, pending, state};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How long to wait before polling again when only updates left for live runs came back.
+const LIVE_IDLE: Duration = Duration::from_secs(1);
+
+/// Sent when a message can't be told apart between several waiting questions in one chat.
+const AMBIGUOUS_TEXT: &str = "Several questions are waiting for you; please reply to the one \
+     you're answering, or include its #tp tag in your message.";
+
+/// One line of the input file.
+#[derive(Debug, Deserialize)]
+struct Item {
+    /// Echoed back in the result. Default: the line number.
+    #[serde(default)]
+    id: serde_json::Value,
+    text: String,
+    /// A name from `[recipients]` or a user id. Default: the first `user_id`.
+    #[serde(default)]
+    to: Option<Recipient>,
+    /// e.g. `"30m"`. Default: `--timeout`, or `timeout_minutes`.
+    #[serde(default)]
+    timeout: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Recipient {
+    Id(i64),
+    Name(String),
+}
+
+/// An item ready to send.
+#[derive(Debug)]
+struct Prompt {
+    id: serde_json::Value,
+    text: String,
+    chat_id: i64,
+    timeout: Duration,
+    /// Correlation tag, for prompts sharing their chat with others.
+    tag: Option<String>,
+}
+
+/// One line of the results.
+#[derive(Debug, Serialize)]
+pub struct ItemResult {
+    pub id: serde_json::Value,
+    /// `replied`, `timed_out`, `cancelled` or `failed` (the prompt couldn't be sent).
+    pub status: &'static str,
+    pub chat_id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<telegram::User>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replied_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ItemResult {
+    fn new(prompt: &Prompt, status: &'static str) -> Self {
+        Self {
+            id: prompt.id.clone(),
+            status,
+            chat_id: prompt.chat_id,
+            message_id: None,
+            tag: prompt.tag.clone(),
+            reply: None,
+            from: None,
+            replied_at: None,
+            error: None,
+        }
+    }
+
+    fn settled(pending: &Pending, status: &'static str) -> Self {
+        Self {
+            id: pending.id.clone(),
+            status,
+            chat_id: pending.chat_id,
+            message_id: Some(pending.message_id),
+            tag: pending.tag.clone(),
+            reply: None,
+            from: None,
+            replied_at: None,
+            error: None,
+        }
+    }
+}
+
+/// A sent prompt waiting for its answer.
+struct Pending {
+    id: serde_json::Value,
+    chat_id: i64,
+    message_id: i64,
+    tag: Option<String>,
+    deadline: Instant,
+    /// Its record in the state dir, for `recover`.
+    record: Option<pending::Prompt>,
+    /// Another live run's prompt, which `recover` leaves alone. It is only listed so messages
+    /// meant for it aren't taken for the others, or confirmed.
+    live: bool,
+}
+
+/// Send every prompt in `input` (NDJSON, `-` for stdin), `interval` apart, then wait for their
+/// answers and write one result line per prompt to `out_file` or stdout, in the order they are
+/// settled. Exits `0` if every prompt was answered, `2` otherwise.
+pub async fn run(
+    args: &Args,
+    config_path: &Path,
+    input: &Path,
+    out_file: Option<&Path>,
+    interval: Duration,
+    timeout: Option<Duration>,
+) -> Result<Outcome> {
+    let mut cfg = config::load(config_path)?;
+    let default_timeout =
+        timeout.unwrap_or_else(|| Duration::from_secs(cfg.timeout_minutes.saturating_mul(60)));
+    let text = if input == Path::new("-") {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .context("read stdin")?;
+        text
+    } else {
+        std::fs::read_to_string(input).with_context(|| format!("read {}", input.display()))?
+    };
+    let prompts = parse(&text, &cfg, default_timeout)?;
+    anyhow::ensure!(!prompts.is_empty(), "{} has no prompts", input.display());
+
+    // Replies are only accepted from the chats prompts were sent to.
+    cfg.user_ids = prompts.iter().map(|p| p.chat_id).collect();
+    cfg.user_ids.sort_unstable();
+    cfg.user_ids.dedup();
+    let client =
+        TelegramClient::for_config(&cfg)?.with_fix_webhook(args.fix_webhook || cfg.fix_webhook);
+    let store = state::StateStore::for_config(&cfg).ok();
+    let longest = prompts.iter().map(|p| p.timeout).max().unwrap_or_default();
+    let _poll_lock = match cfg.state_dir() {
+        Ok(dir) => {
+            let mode = args.poll_lock.unwrap_or(cfg.poll_lock);
+            lock::acquire(&dir, &cfg.bot_token, mode, longest).await?
+        }
+        Err(e) => {
+            tracing::debug!("polling without a lock: {e:#}");
+            None
+        }
+    };
+    let saved_offset = store
+        .as_ref()
+        .and_then(|store| offset::load(store, &cfg.bot_token));
+    let (offset, not_before) = match saved_offset {
+        Some(saved) => (saved, Utc::now().timestamp() - offset::PENDING_GRACE_S),
+        None => (client.drain_updates().await?.0, 0),
+    };
+
+    let mut out = Results::open(out_file)?;
+    let opts = SendOptions {
+        thread_id: cfg.thread_id,
+        ..Default::default()
+    };
+    let mut pending = Vec::new();
+    for (index, prompt) in prompts.iter().enumerate() {
+        if index > 0 {
+            tokio::time::sleep(interval).await;
+        }
+        let text = match &prompt.tag {
+            Some(tag) => format!("{}\n\n{tag}", prompt.text),
+            None => prompt.text.clone(),
+        };
+        match client
+            .send_message_parts(prompt.chat_id, &text, &opts)
+            .await
+        {
+            Ok(ids) => {
+                let message_id = *ids.last().expect("at least one part is sent");
+                let record = store.as_ref().map(|store| {
+                    let deadline = Utc::now().timestamp() + prompt.timeout.as_secs() as i64;
+                    let record = pending::Prompt {
+                        tag: prompt.tag.clone(),
+                        id: prompt.id.clone(),
+                        source: Some(pending::Source::Batch),
+                        ..pending::Prompt::new(&cfg.bot_token, prompt.chat_id, message_id, deadline)
+                    };
+                    pending::save(store, &record);
+                    record
+                });
+                pending.push(Pending {
+                    id: prompt.id.clone(),
+                    chat_id: prompt.chat_id,
+                    message_id,
+                    tag: prompt.tag.clone(),
+                    deadline: Instant::now() + prompt.timeout,
+                    record,
+                    live: false,
+                });
+            }
+            Err(e) => {
+                tracing::warn!("could not send prompt {}: {e:#}", prompt.id);
+                let mut result = ItemResult::new(prompt, "failed");
+                result.error = Some(format!("{e:#}"));
+                out.write(&result)?;
+            }
+        }
+    }
+    tracing::info!("Sent {} prompts; waiting for answers...", pending.len());
+
+    let all_sent = pending.len() == prompts.len();
+    let all_replied = wait(
+        &client,
+        &cfg,
+        store.as_ref(),
+        (offset, not_before),
+        pending,
+        &mut out,
+        None,
+    )
+    .await?;
+    Ok(if all_sent && all_replied {
+        Outcome::Done
+    } else {
+        Outcome::TimedOut
+    })
+}
+
+/// Wait, as `batch` does, for the answers to every prompt the bot's state records as pending,
+/// e.g. after the runs waiting on them crashed or the machine rebooted, and write one result
+/// line per prompt. Answers that arrived while nobody was waiting count, as long as Telegram
+/// still holds them; prompts already past their deadline only get that one chance.
+pub async fn recover(args: &Args, config_path: &Path, out_file: Option<&Path>) -> Result<Outcome> {
+    let cfg = config::load(config_path)?;
+    let store = state::StateStore::for_config(&cfg)?;
+    let now = Utc::now().timestamp();
+    let remaining = |record: &pending::Prompt| {
+        Duration::from_secs(u64::try_from(record.deadline - now).unwrap_or(0))
+    };
+    let records = pending::list(&store, &cfg.bot_token)?;
+    let longest = records
+        .iter()
+        .filter(|r| !r.owner_alive())
+        .map(remaining)
+        .max()
+        .unwrap_or_default();
+    // A live uncorrelated run holds the lock until it is done with its prompts.
+    let mode = args.poll_lock.unwrap_or(cfg.poll_lock);
+    let state_dir = cfg.state_dir()?;
+    let poll_lock = lock::acquire(&state_dir, &cfg.bot_token, mode, longest).await?;
+    // Correlated runs and `serve` don't hold the lock, so the lock alone can't tell a live
+    // run from a crashed one.
+    let (live, records): (Vec<_>, Vec<_>) = pending::list(&store, &cfg.bot_token)?
+        .into_iter()
+        .partition(pending::Prompt::owner_alive);
+    if records.is_empty() {
+        tracing::info!("No pending prompts to recover.");
+        return Ok(Outcome::Done);
+    }
+    let turns = if live.is_empty() {
+        None
+    } else {
+        tracing::info!(
+            "Leaving {} pending prompts to the live runs waiting on them.",
+            live.len()
+        );
+        // Their runs poll in turns, as this one does from here on.
+        drop(poll_lock);
+        Some(state_dir.as_path())
+    };
+
+    tracing::info!("Recovering {} pending prompts...", records.len());
+    let mut out = Results::open(out_file)?;
+    let all_replied = wait_on(
+        &cfg,
+        &store,
+        records,
+        live,
+        args.fix_webhook,
+        turns,
+        &mut out,
+    )
+    .await?;
+    Ok(if all_replied {
+        Outcome::Done
+    } else {
+        Outcome::TimedOut
+    })
+}
+
+/// Wait, as `recover` does, for the answers to `records`, which a `serve` instance that is
+/// gone left pending, and pass each result to `settled`. Prompts other live runs wait on, such
+/// as this process's own questions, are left to them.
+pub async fn take_over(
+    cfg: &config::Config,
+    records: Vec<pending::Prompt>,
+    fix_webhook: bool,
+    settled: impl FnMut(&ItemResult) + 'static,
+) -> Result<()> {
+    let store = state::StateStore::for_config(cfg)?;
+    let live = pending::list(&store, &cfg.bot_token)?
+        .into_iter()
+        .filter(pending::Prompt::owner_alive)
+        .collect();
+    // The live runs poll in turns, as correlated runs do.
+    let turns = match cfg.state_dir() {
+        Ok(dir) if cfg.poll_lock != lock::LockMode::Off => Some(dir),
+        _ => None,
+    };
+    let mut out = Results::Handler(Box::new(settled));
+    wait_on(
+        cfg,
+        &store,
+        records,
+        live,
+        fix_webhook,
+        turns.as_deref(),
+        &mut out,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Wait for the answers to the recorded prompts `records`, with the `live` ones of other runs
+/// in mind, from the saved offset. Returns whether all were answered.
+async fn wait_on(
+    cfg: &config::Config,
+    store: &state::StateStore,
+    records: Vec<pending::Prompt>,
+    live: Vec<pending::Prompt>,
+    fix_webhook: bool,
+    turns: Option<&Path>,
+    out: &mut Results,
+) -> Result<bool> {
+    let mut cfg = cfg.clone();
+    cfg.user_ids = records.iter().map(|r| r.chat_id).collect();
+    cfg.user_ids.sort_unstable();
+    cfg.user_ids.dedup();
+    let client = TelegramClient::for_config(&cfg)?.with_fix_webhook(fix_webhook || cfg.fix_webhook);
+    let now = Utc::now().timestamp();
+    let start = Instant::now();
+    let pending = records
+        .into_iter()
+        .map(|record| (record, false))
+        .chain(live.into_iter().map(|record| (record, true)))
+        .map(|(record, live)| Pending {
+            id: match &record.id {
+                serde_json::Value::Null => record.message_id.into(),
+                id => id.clone(),
+            },
+            chat_id: record.chat_id,
+            message_id: record.message_id,
+            tag: record.tag.clone(),
+            deadline: start
+                + Duration::from_secs(u64::try_from(record.deadline - now).unwrap_or(0)),
+            record: Some(record),
+            live,
+        })
+        .collect();
+
+    // Nothing is skipped by age: the answers may predate this run.
+    let offset = offset::load(store, &cfg.bot_token).unwrap_or(0);
+    wait(&client, &cfg, Some(store), (offset, 0), pending, out, turns).await
+}
+
+/// Poll from `offset` until every prompt is answered or past its deadline, writing each result
+/// as it is settled, and save the offset. Messages sent before `not_before` are ignored. Every
+/// prompt gets at least one poll, even if its deadline has passed. Returns whether all were
+/// answered.
+///
+/// Updates for live prompts are left unconfirmed, for their runs, and with `turns` (the state
+/// dir) each poll takes its turn with theirs, as correlated runs do.
+async fn wait(
+    client: &TelegramClient,
+    cfg: &config::Config,
+    store: Option<&state::StateStore>,
+    (mut offset, not_before): (i64, i64),
+    mut pending: Vec<Pending>,
+    out: &mut Results,
+    turns: Option<&Path>,
+) -> Result<bool> {
+    let mut all_replied = true;
+    let mut backoff = backoff::Backoff::default();
+    // Update ids already looked at; the offset may stay behind them for live prompts.
+    let mut seen = HashSet::new();
+    while let Some(next_deadline) = pending.iter().filter(|p| !p.live).map(|p| p.deadline).min() {
+        let long_poll = next_deadline
+            .saturating_duration_since(Instant::now())
+            .min(Duration::from_secs(30));
+        let turn = match turns {
+            Some(dir) => lock::turn(dir, &cfg.bot_token, long_poll).await?,
+            None => None,
+        };
+        let res = match (turns, &turn) {
+            // Out of time waiting for a turn.
+            (Some(_), None) => Ok(Vec::new()),
+            _ => client.get_updates(offset, long_poll.as_secs()).await,
+        };
+        drop(turn);
+        let updates = match res {
+            Ok(updates) => {
+                backoff.reset();
+                updates
+            }
+            Err(e) if backoff::transient(&e) => {
+                let delay = backoff
+                    .fail()
+                    .min(next_deadline.saturating_duration_since(Instant::now()));
+                tracing::warn!(
+                    "polling failed ({e:#}); retrying in {:.1}s",
+                    delay.as_secs_f64()
+                );
+                tokio::time::sleep(delay).await;
+                Vec::new()
+            }
+            Err(e) => return Err(e),
+        };
+        if !updates.is_empty() && updates.iter().all(|u| seen.contains(&u.update_id)) {
+            // Unconfirmed updates come back immediately; don't spin on them.
+            let idle = LIVE_IDLE.min(next_deadline.saturating_duration_since(Instant::now()));
+            tokio::time::sleep(idle).await;
+        }
+        let mut confirming = true;
+        for update in &updates {
+            confirming &= !for_live(&pending, cfg, update);
+            if confirming {
+                offset = offset.max(update.update_id + 1);
+            }
+            if !seen.insert(update.update_id) {
+                continue;
+            }
+            let Some(msg) = cfg.reply_message(update).filter(|m| m.date >= not_before) else {
+                continue;
+            };
+            let Some(text) = msg.text.as_deref() else {
+                continue;
+            };
+            let Some(i) = target(&pending, msg).filter(|&i| !pending[i].live) else {
+                if pending.iter().any(|p| p.chat_id == msg.chat.id && !p.live) {
+                    let hint = SendOptions {
+                        reply_to: Some(msg.message_id),
+                        thread_id: msg.message_thread_id,
+                        ..Default::default()
+                    };
+                    // Best effort: the wait goes on either way.
+                    if let Err(e) = client
+                        .send_message(msg.chat.id, AMBIGUOUS_TEXT, &hint)
+                        .await
+                    {
+                        tracing::warn!("could not ask for a threaded reply: {e:#}");
+                    }
+                }
+                continue;
+            };
+            let answered = pending.remove(i);
+            let text = untagged(text, answered.tag.as_deref());
+            let cancelled = cfg.is_cancel(&text);
+            let mut result =
+                ItemResult::settled(&answered, if cancelled { "cancelled" } else { "replied" });
+            if !cancelled {
+                result.reply = Some(text);
+                result.from = msg.from.clone();
+                result.replied_at =
+                    Some(Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true));
+            }
+            settle(out, store, &answered, &result)?;
+            all_replied &= !cancelled;
+        }
+        let now = Instant::now();
+        for expired in extract(&mut pending, |p| !p.live && p.deadline <= now) {
+            settle(
+                out,
+                store,
+                &expired,
+                &ItemResult::settled(&expired, "timed_out"),
+            )?;
+            all_replied = false;
+        }
+    }
+
+    if let Some(store) = store
+        && let Err(e) = offset::save(store, &cfg.bot_token, offset)
+    {
+        tracing::warn!("could not save the update offset: {e:#}");
+    }
+    Ok(all_replied)
+}
+
+/// Whether `update` may be meant for a live prompt: a message routed to one, a message that
+/// can't be routed in a chat where one waits, or a press of a button on one or carrying its
+/// tag.
+fn for_live(pending: &[Pending], cfg: &config::Config, update: &telegram::Update) -> bool {
+    let mut live = pending.iter().filter(|p| p.live).peekable();
+    if live.peek().is_none() {
+        return false;
+    }
+    if let Some(query) = &update.callback_query {
+        let on = query.message.as_ref();
+        let tag = correlate::update_tag(update);
+        return live.any(|p| {
+            on.is_some_and(|m| m.chat.id == p.chat_id && m.message_id == p.message_id)
+                || (tag.is_some() && p.tag.as_deref() == tag)
+        });
+    }
+    let Some(msg) = cfg.reply_message(update) else {
+        return false;
+    };
+    match target(pending, msg) {
+        Some(i) => pending[i].live,
+        None => live.any(|p| p.chat_id == msg.chat.id),
+    }
+}
+
+/// Write a prompt's result and forget its pending record.
+fn settle(
+    out: &mut Results,
+    store: Option<&state::StateStore>,
+    pending: &Pending,
+    result: &ItemResult,
+) -> Result<()> {
+    out.write(result)?;
+    if let (Some(store), Some(record)) = (store, &pending.record) {
+        pending::remove(store, record);
+    }
+    Ok(())
+}
+
+/// Parse and resolve every line up front, so a bad line fails the run before anything is sent.
+fn parse(text: &str, cfg: &config::Config, default_timeout: Duration) -> Result<Vec<Prompt>> {
+    let mut prompts = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let n = n + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let item: Item = serde_json::from_str(line).with_context(|| format!("line {n}"))?;
+        anyhow::ensure!(!item.text.trim().is_empty(), "line {n}: text is empty");
+        let chat_id = match &item.to {
+            None => cfg.primary_user_id(),
+            Some(Recipient::Id(id)) => *id,
+            Some(Recipient::Name(name)) => cfg
+                .resolve_recipient(name)
+                .with_context(|| format!("line {n}"))?,
+        };
+        let timeout = match &item.timeout {
+            Some(t) => duration::parse(t).map_err(|e| anyhow::anyhow!("line {n}: timeout: {e}"))?,
+            None => default_timeout,
+        };
+        prompts.push(Prompt {
+            id: match item.id {
+                serde_json::Value::Null => n.into(),
+                id => id,
+            },
+            text: item.text,
+            chat_id,
+            timeout,
+            tag: None,
+        });
+    }
+    // Replies to prompts sharing a chat may not say which one they answer; a tag lets them.
+    for i in 0..prompts.len() {
+        if prompts
+            .iter()
+            .filter(|p| p.chat_id == prompts[i].chat_id)
+            .count()
+            > 1
+        {
+            prompts[i].tag = Some(correlate::new_tag());
+        }
+    }
+    Ok(prompts)
+}
+
+/// The waiting prompt a message answers: the one it replies to (by id, or by the tag of the
+/// part replied to), else the one whose tag it contains, else the only one waiting in its chat.
+fn target(pending: &[Pending], msg: &telegram::Message) -> Option<usize> {
+    let tagged = |tag: Option<&str>| {
+        tag.and_then(|tag| {
+            pending
+                .iter()
+                .position(|p| p.chat_id == msg.chat.id && p.tag.as_deref() == Some(tag))
+        })
+    };
+    if let Some(replied) = &msg.reply_to_message {
+        return pending
+            .iter()
+            .position(|p| p.chat_id == msg.chat.id && p.message_id == replied.message_id)
+            .or_else(|| tagged(replied.text.as_deref().and_then(correlate::find_tag)));
+    }
+    if let Some(i) = tagged(msg.text.as_deref().and_then(correlate::find_tag)) {
+        return Some(i);
+    }
+    let mut in_chat = pending
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.chat_id == msg.chat.id);
+    match (in_chat.next(), in_chat.next()) {
+        (Some((i, _)), None) => Some(i),
+        _ => None,
+    }
+}
+
+/// A reply's text without the tag that routed it.
+fn untagged(text: &str, tag: Option<&str>) -> String {
+    match tag {
+        Some(tag) if text.contains(tag) => text.replace(tag, "").trim().to_string(),
+        _ => text.to_string(),
+    }
+}
+
+/// Remove and return the entries matching `pred`.
+fn extract(pending: &mut Vec<Pending>, pred: impl Fn(&Pending) -> bool) -> Vec<Pending> {
+    let (matched, rest) = std::mem::take(pending).into_iter().partition(pred);
+    *pending = rest;
+    matched
+}
+
+/// Where results go: lines on the `--out-file` or stdout, or a `take_over` caller.
+enum Results {
+    Lines(Box<dyn Write>),
+    Handler(Box<dyn FnMut(&ItemResult)>),
+}
+
+impl Results {
+    fn open(path: Option<&Path>) -> Result<Self> {
+        Ok(Self::Lines(match path {
+            Some(path) => Box::new(
+                std::fs::File::create(path)
+                    .with_context(|| format!("create {}", path.display()))?,
+            ),
+            None => Box::new(std::io::stdout()),
+        }))
+    }
+
+    /// Write one result line and flush it, so progress can be followed as it happens.
+    fn write(&mut self, result: &ItemResult) -> Result<()> {
+        match self {
+            Self::Lines(out) => {
+                serde_json::to_writer(&mut *out, result)?;
+                out.write_all(b"\n")?;
+                out.flush()?;
+            }
+            Self::Handler(settled) => settled(result),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_items_with_defaults_and_recipients() {
+        let cfg: config::Config = toml::from_str("user_id = 1\n[recipients]\nalice = 42").unwrap();
+        let text = r#"{"text": "hi"}
+
+{"id": "q2", "text": "deploy?", "to": "alice", "timeout": "5m"}
+{"text": "lunch?", "to": 7}
+"#;
+        let prompts = parse(text, &cfg, Duration::from_secs(60)).unwrap();
+        assert_eq!(prompts.len(), 3);
+        assert_eq!(prompts[0].id, 1);
+        assert_eq!(prompts[0].chat_id, 1);
+        assert_eq!(prompts[0].timeout, Duration::from_secs(60));
+        assert_eq!(prompts[1].id, "q2");
+        assert_eq!(prompts[1].chat_id, 42);
+        assert_eq!(prompts[1].timeout, Duration::from_secs(300));
+        assert_eq!(prompts[2].chat_id, 7);
+
+        let err = parse("{\"text\": \"x\", \"to\": \"bob\"}", &cfg, Duration::ZERO)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("line 1"), "error was: {err}");
+    }
+
+    #[test]
+    fn messages_answer_the_prompt_they_reply_to_or_the_only_one_waiting() {
+        let pending = [
+            Pending {
+                id: 1.into(),
+                chat_id: 1,
+                message_id: 10,
+                tag: None,
+                deadline: Instant::now(),
+                record: None,
+                live: false,
+            },
+            Pending {
+                id: 2.into(),
+                chat_id: 2,
+                message_id: 11,
+                tag: Some("#tpAAAAAA".to_string()),
+                deadline: Instant::now(),
+                record: None,
+                live: false,
+            },
+            Pending {
+                id: 3.into(),
+                chat_id: 2,
+                message_id: 12,
+                tag: Some("#tpBBBBBB".to_string()),
+                deadline: Instant::now(),
+                record: None,
+                live: false,
+            },
+        ];
+        let message = |chat: i64, reply_to: Option<i64>| telegram::Message {
+            chat: telegram::Chat { id: chat },
+            reply_to_message: reply_to.map(|id| {
+                Box::new(telegram::Message {
+                    message_id: id,
+                    ..Default::default()
+                })
+            }),
+            ..Default::default()
+        };
+        assert_eq!(target(&pending, &message(1, None)), Some(0));
+        assert_eq!(target(&pending, &message(2, None)), None);
+        assert_eq!(target(&pending, &message(2, Some(12))), Some(2));
+        assert_eq!(target(&pending, &message(2, Some(10))), None);
+
+        let mut tagged = message(2, None);
+        tagged.text = Some("#tpAAAAAA yes".to_string());
+        assert_eq!(target(&pending, &tagged), Some(1));
+        assert_eq!(untagged("#tpAAAAAA yes", Some("#tpAAAAAA")), "yes");
+
+        // A reply to an earlier part of a long prompt is routed by the tag of the whole.
+        let mut earlier_part = message(2, Some(9));
+        earlier_part.reply_to_message.as_mut().unwrap().text = Some("…\n\n#tpBBBBBB".to_string());
+        assert_eq!(target(&pending, &earlier_part), Some(2));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn recover_leaves_the_prompts_of_live_runs_alone() {
+        use crate::mock_api::{MockApi, USER_ID};
+        let api = MockApi::start().await;
+        let config = api.config("");
+        let cfg = config::load(&config).unwrap();
+        let store = state::StateStore::for_config(&cfg).unwrap();
+        let deadline = Utc::now().timestamp() + 10;
+        let mut gone = pending::Prompt::new(&cfg.bot_token, USER_ID, 1, deadline);
+        let mut exited = std::process::Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        gone.pid = Some(exited.id());
+        pending::save(&store, &gone);
+        let live = pending::Prompt::new(&cfg.bot_token, USER_ID, 2, deadline);
+        pending::save(&store, &live);
+        api.message(Some(1), "crashed run's answer");
+        api.message(Some(2), "live run's answer");
+
+        let out = config.with_file_name("results.jsonl");
+        let outcome = recover(&Args::default(), &config, Some(&out))
+            .await
+            .unwrap();
+        assert!(matches!(outcome, Outcome::Done));
+        let results = std::fs::read_to_string(&out).unwrap();
+        assert_eq!(results.lines().count(), 1, "results: {results}");
+        assert!(results.contains("crashed run's answer"));
+        // The live run's record and answer are left for it.
+        let left = pending::list(&store, &cfg.bot_token).unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].message_id, 2);
+        assert_eq!(offset::load(&store, &cfg.bot_token), Some(2));
+    }
+
+    #[test]
+    fn only_prompts_sharing_a_chat_are_tagged() {
+        let cfg: config::Config = toml::from_str("user_id = 1").unwrap();
+        let text = "{\"text\": \"a\"}\n{\"text\": \"b\"}\n{\"text\": \"c\", \"to\": 2}\n";
+        let prompts = parse(text, &cfg, Duration::ZERO).unwrap();
+        assert!(prompts[0].tag.is_some());
+        assert_ne!(prompts[0].tag, prompts[1].tag);
+        assert_eq!(prompts[2].tag, None);
+    }
+}
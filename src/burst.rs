@@ -0,0 +1,304 @@
+use crate::config::BurstConfig;
+use crate::state::StateStore;
+use crate::telegram::{TelegramClient, Update};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{File, TryLockError};
+use std::time::Duration;
+
+const STATE_NAME: &str = "burst";
+const LOCK_NAME: &str = "burst.lock";
+const MUTE_PREFIX: &str = "mute:";
+
+/// How often a run waiting for the burst history checks whether it was released.
+const LOCK_RETRY: Duration = Duration::from_millis(50);
+
+/// Held while a run reads and updates the burst history; released when dropped (or when the
+/// process dies).
+#[derive(Debug)]
+pub struct HistoryLock {
+    _file: File,
+}
+
+/// Lock the burst history against other runs for a read-modify-write, so concurrent
+/// invocations of a crash-looping job each count instead of overwriting each other.
+pub async fn lock(store: &StateStore) -> Result<HistoryLock> {
+    let path = store.dir().join(LOCK_NAME);
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("open lock file: {}", path.display()))?;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(HistoryLock { _file: file }),
+            // Waiting without blocking the thread keeps `serve` answering meanwhile.
+            Err(TryLockError::WouldBlock) => tokio::time::sleep(LOCK_RETRY).await,
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("lock {}", path.display()));
+            }
+        }
+    }
+}
+
+/// Recent prompt fingerprints, persisted so repeated invocations can be collapsed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BurstState {
+    #[serde(default)]
+    entries: BTreeMap<String, BurstEntry>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BurstEntry {
+    /// Unix timestamps (seconds) of recent sends within the window.
+    hits: Vec<i64>,
+    summary_message_id: Option<i64>,
+    muted_until: Option<i64>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Below the threshold: send the prompt normally.
+    Send,
+    /// Over the threshold: send (or update) a single summary message instead.
+    Summarize {
+        count: usize,
+        summary_message_id: Option<i64>,
+    },
+    /// The recipient muted this prompt; send nothing.
+    Muted,
+}
+
+/// Identifies "the same prompt from the same caller to the same chat".
+pub fn fingerprint(caller: &str, chat_ids: &[i64], message: &str) -> String {
+    let chats: Vec<String> = chat_ids.iter().map(i64::to_string).collect();
+    let mut h = Sha256::new();
+    h.update(caller.as_bytes());
+    h.update([0]);
+    h.update(chats.join(",").as_bytes());
+    h.update([0]);
+    h.update(message.as_bytes());
+    h.finalize()[..8]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+impl BurstState {
+    pub fn load(store: &StateStore) -> Result<Self> {
+        store.load(STATE_NAME)
+    }
+
+    pub fn save(&self, store: &StateStore) -> Result<()> {
+        store.save(STATE_NAME, self)
+    }
+
+    /// Record a send attempt at `now` and decide how to deliver it.
+    pub fn record(&mut self, fp: &str, now: i64, cfg: &BurstConfig) -> Decision {
+        self.prune(now, cfg);
+
+        let entry = self.entries.entry(fp.to_string()).or_default();
+        entry.hits.push(now);
+
+        if entry.muted_until.is_some_and(|t| t > now) {
+            return Decision::Muted;
+        }
+        if entry.hits.len() > cfg.threshold {
+            return Decision::Summarize {
+                count: entry.hits.len(),
+                summary_message_id: entry.summary_message_id,
+            };
+        }
+        Decision::Send
+    }
+
+    pub fn set_summary_message(&mut self, fp: &str, message_id: i64) {
+        self.entries
+            .entry(fp.to_string())
+            .or_default()
+            .summary_message_id = Some(message_id);
+    }
+
+    pub fn mute(&mut self, fp: &str, until: i64) {
+        self.entries.entry(fp.to_string()).or_default().muted_until = Some(until);
+    }
+
+    fn prune(&mut self, now: i64, cfg: &BurstConfig) {
+        let cutoff = now - window_s(cfg);
+        self.entries.retain(|_, e| {
+            e.hits.retain(|&t| t > cutoff);
+            if e.hits.is_empty() {
+                // The burst is over; the next one gets a fresh summary message.
+                e.summary_message_id = None;
+            }
+            if e.muted_until.is_some_and(|t| t <= now) {
+                e.muted_until = None;
+            }
+            !e.hits.is_empty() || e.muted_until.is_some()
+        });
+    }
+}
+
+fn window_s(cfg: &BurstConfig) -> i64 {
+    i64::try_from(cfg.window_minutes.saturating_mul(60)).unwrap_or(i64::MAX)
+}
+
+pub fn summary_text(count: usize, cfg: &BurstConfig, message: &str) -> String {
+    format!(
+        "🔁 This alert fired {count} times in the last {} minutes:\n\n{message}",
+        cfg.window_minutes
+    )
+}
+
+pub fn mute_markup(fp: &str, cfg: &BurstConfig) -> serde_json::Value {
+    serde_json::json!({
+        "inline_keyboard": [[{
+            "text": format!("🔇 Mute for {} minutes", cfg.mute_minutes),
+            "callback_data": format!("{MUTE_PREFIX}{fp}"),
+        }]]
+    })
+}
+
+/// Apply any "mute" button presses found in `updates` from people who may answer prompts.
+///
+/// Presses can arrive in whichever teleprompt run happens to be polling, so every run checks.
+pub async fn apply_mutes(
+    client: &TelegramClient,
+    store: &StateStore,
+    cfg: &BurstConfig,
+    may_answer: impl Fn(i64, i64) -> bool,
+    updates: &[Update],
+) -> Result<()> {
+    let presses: Vec<_> = updates
+        .iter()
+        .filter_map(|u| u.callback_query.as_ref())
+        .filter(|q| may_answer(q.chat_id(), q.from.id))
+        .filter_map(|q| Some((q, q.data.as_deref()?.strip_prefix(MUTE_PREFIX)?)))
+        .collect();
+    if presses.is_empty() {
+        return Ok(());
+    }
+
+    let _lock = lock(store).await?;
+    let mut state = BurstState::load(store)?;
+    let until = chrono::Utc::now().timestamp()
+        + i64::try_from(cfg.mute_minutes.saturating_mul(60)).unwrap_or(i64::MAX);
+    for (query, fp) in &presses {
+        state.mute(fp, until);
+        // Best effort: Telegram rejects answers to queries that are too old.
+        let _ = client
+            .answer_callback_query(
+                &query.id,
+                &format!("Muted for {} minutes", cfg.mute_minutes),
+            )
+            .await;
+    }
+    state.save(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> BurstConfig {
+        BurstConfig {
+            threshold: 2,
+            window_minutes: 10,
+            mute_minutes: 60,
+        }
+    }
+
+    #[test]
+    fn fingerprint_depends_on_caller_chat_and_message() {
+        let a = fingerprint("cron", &[1], "disk full");
+        assert_eq!(a, fingerprint("cron", &[1], "disk full"));
+        assert_eq!(a.len(), 16);
+        assert_ne!(a, fingerprint("other", &[1], "disk full"));
+        assert_ne!(a, fingerprint("cron", &[2], "disk full"));
+        assert_ne!(a, fingerprint("cron", &[1], "disk ok"));
+    }
+
+    #[test]
+    fn record_switches_to_summary_above_threshold() {
+        let cfg = cfg();
+        let mut state = BurstState::default();
+
+        assert_eq!(state.record("fp", 100, &cfg), Decision::Send);
+        assert_eq!(state.record("fp", 101, &cfg), Decision::Send);
+        assert_eq!(
+            state.record("fp", 102, &cfg),
+            Decision::Summarize {
+                count: 3,
+                summary_message_id: None
+            }
+        );
+
+        state.set_summary_message("fp", 55);
+        assert_eq!(
+            state.record("fp", 103, &cfg),
+            Decision::Summarize {
+                count: 4,
+                summary_message_id: Some(55)
+            }
+        );
+    }
+
+    #[test]
+    fn record_forgets_hits_outside_the_window() {
+        let cfg = cfg();
+        let mut state = BurstState::default();
+
+        for t in 0..5 {
+            state.record("fp", t, &cfg);
+        }
+        state.set_summary_message("fp", 55);
+
+        assert_eq!(state.record("fp", 10_000, &cfg), Decision::Send);
+    }
+
+    #[test]
+    fn muted_prompts_are_suppressed_until_the_mute_expires() {
+        let cfg = cfg();
+        let mut state = BurstState::default();
+
+        state.mute("fp", 500);
+        assert_eq!(state.record("fp", 100, &cfg), Decision::Muted);
+        assert_eq!(state.record("other", 100, &cfg), Decision::Send);
+        assert_eq!(state.record("fp", 600, &cfg), Decision::Send);
+    }
+
+    #[tokio::test]
+    async fn concurrent_runs_each_count() {
+        let dir = std::env::temp_dir().join(format!(
+            "teleprompt_burst_test_{}_{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let store = StateStore::open(dir.clone()).unwrap();
+        let cfg = cfg();
+
+        let run = || async {
+            let _lock = lock(&store).await.unwrap();
+            let mut state = BurstState::load(&store).unwrap();
+            state.record("fp", 100, &cfg);
+            // Let the other runs try to read the history in between.
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            state.save(&store).unwrap();
+        };
+        tokio::join!(run(), run(), run(), run());
+
+        let mut state = BurstState::load(&store).unwrap();
+        assert_eq!(
+            state.record("fp", 100, &cfg),
+            Decision::Summarize {
+                count: 5,
+                summary_message_id: None
+            }
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
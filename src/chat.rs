@@ -0,0 +1,198 @@
+use crate::telegram::{Message, SendOptions, TelegramClient};
+use crate::{Args, backoff, config, lock, offset, state};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+/// Long-poll duration while chatting; typed lines interrupt it.
+const POLL: Duration = Duration::from_secs(30);
+
+/// How incoming messages are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// For people at a terminal (`teleprompt chat`): `name: text`, media labeled.
+    Chat,
+    /// For programs at the other end of a pipe (`teleprompt bridge`): just the text.
+    Bridge,
+}
+
+/// Relay stdin to the recipients: each line is sent to them, and their messages are written
+/// to stdout as they arrive, until stdin ends.
+pub async fn run(args: &Args, config_path: &Path, to: &[String], style: Style) -> Result<()> {
+    let mut cfg = config::load(config_path)?;
+    if !to.is_empty() {
+        cfg.user_ids = to
+            .iter()
+            .map(|name| cfg.resolve_recipient(name))
+            .collect::<Result<_>>()?;
+    }
+    let client =
+        TelegramClient::for_config(&cfg)?.with_fix_webhook(args.fix_webhook || cfg.fix_webhook);
+    let store = match state::StateStore::for_config(&cfg) {
+        Ok(store) => Some(store),
+        Err(e) => {
+            tracing::debug!("no state store: {e:#}");
+            None
+        }
+    };
+
+    // A chat polls like an uncorrelated prompt run, so it takes the same lock.
+    let _poll_lock = match cfg.state_dir() {
+        Ok(dir) => {
+            let mode = args.poll_lock.unwrap_or(cfg.poll_lock);
+            let patience = Duration::from_secs(cfg.timeout_minutes.saturating_mul(60));
+            lock::acquire(&dir, &cfg.bot_token, mode, patience).await?
+        }
+        Err(e) => {
+            tracing::debug!("polling without a lock: {e:#}");
+            None
+        }
+    };
+    let saved_offset = store
+        .as_ref()
+        .and_then(|store| offset::load(store, &cfg.bot_token));
+    let (mut offset, not_before) = match saved_offset {
+        Some(saved) => (saved, Utc::now().timestamp() - offset::PENDING_GRACE_S),
+        None => (client.drain_updates().await?.0, 0),
+    };
+
+    let opts = SendOptions {
+        thread_id: cfg.thread_id,
+        ..Default::default()
+    };
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    let mut backoff = backoff::Backoff::default();
+    if style == Style::Chat {
+        tracing::info!("chatting; type a line to send it, Ctrl-D to stop");
+    }
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line.context("read stdin")? else {
+                    break;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                for &chat_id in &cfg.user_ids {
+                    client
+                        .send_message(chat_id, &line, &opts)
+                        .await
+                        .with_context(|| format!("send to {chat_id}"))?;
+                }
+            }
+            res = client.get_updates(offset, POLL.as_secs()) => {
+                let updates = match res {
+                    Ok(updates) => {
+                        backoff.reset();
+                        updates
+                    }
+                    Err(e) if backoff::transient(&e) => {
+                        let delay = backoff.fail();
+                        tracing::warn!(
+                            "polling failed ({e:#}); retrying in {:.1}s",
+                            delay.as_secs_f64()
+                        );
+                        tokio::time::sleep(delay).await;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                for update in &updates {
+                    offset = offset.max(update.update_id + 1);
+                    if let Some(msg) = cfg.reply_message(update)
+                        && msg.date >= not_before
+                    {
+                        let out = match style {
+                            Style::Chat => render(msg),
+                            Style::Bridge => bridge_line(msg),
+                        };
+                        stdout.write_all(out.as_bytes()).await?;
+                    }
+                }
+                stdout.flush().await?;
+            }
+        }
+    }
+
+    if let Some(store) = &store
+        && let Err(e) = offset::save(store, &cfg.bot_token, offset)
+    {
+        tracing::warn!("could not save the update offset: {e:#}");
+    }
+    Ok(())
+}
+
+/// One incoming message as a `name: text` line. Media shows as its kind, plus the caption.
+fn render(msg: &Message) -> String {
+    let name = match &msg.from {
+        Some(user) => match &user.username {
+            Some(username) => format!("@{username}"),
+            None => user.first_name.clone(),
+        },
+        None => msg.chat.id.to_string(),
+    };
+    let kind = if msg.photo.is_some() {
+        Some("[photo]")
+    } else if msg.document.is_some() {
+        Some("[document]")
+    } else if msg.voice.is_some() {
+        Some("[voice]")
+    } else if msg.video.is_some() {
+        Some("[video]")
+    } else {
+        None
+    };
+    let text = msg.text.as_deref().or(msg.caption.as_deref()).unwrap_or("");
+    let body = match kind {
+        Some(kind) if text.is_empty() => kind.to_string(),
+        Some(kind) => format!("{kind} {text}"),
+        None => text.to_string(),
+    };
+    // Keep one message per line so the output stays line-oriented.
+    format!("{name}: {}\n", body.replace('\n', "\n  "))
+}
+
+/// One incoming message as input for a program: its text (or caption) and a newline, so a
+/// multi-line message arrives as several lines. Media without a caption writes nothing.
+fn bridge_line(msg: &Message) -> String {
+    match msg.text.as_deref().or(msg.caption.as_deref()) {
+        Some(text) => format!("{text}\n"),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(json: serde_json::Value) -> Message {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn chat_names_the_sender_and_bridge_passes_text_through() {
+        let text = message(serde_json::json!({
+            "message_id": 1,
+            "from": { "id": 7, "first_name": "Ann", "username": "ann" },
+            "chat": { "id": 7 },
+            "text": "on my way\nback soon",
+        }));
+        assert_eq!(render(&text), "@ann: on my way\n  back soon\n");
+
+        let photo = message(serde_json::json!({
+            "message_id": 2,
+            "from": { "id": 7, "first_name": "Ann" },
+            "chat": { "id": 7 },
+            "photo": [{ "file_id": "a" }],
+            "caption": "the rack",
+        }));
+        assert_eq!(render(&photo), "Ann: [photo] the rack\n");
+        assert_eq!(bridge_line(&photo), "the rack\n");
+        assert_eq!(bridge_line(&text), "on my way\nback soon\n");
+    }
+}
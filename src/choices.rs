@@ -0,0 +1,90 @@
+use crate::telegram::CallbackQuery;
+
+/// Callback data prefix of the `--choices` buttons; the rest is the choice's index.
+const CHOICE_PREFIX: &str = "choice:";
+
+/// Buttons per keyboard row.
+const ROW_LEN: usize = 3;
+
+/// One button per choice, in rows of three.
+pub fn markup(choices: &[String]) -> serde_json::Value {
+    let buttons: Vec<serde_json::Value> = choices
+        .iter()
+        .enumerate()
+        .map(|(i, choice)| {
+            serde_json::json!({ "text": choice, "callback_data": format!("{CHOICE_PREFIX}{i}") })
+        })
+        .collect();
+    let rows: Vec<&[serde_json::Value]> = buttons.chunks(ROW_LEN).collect();
+    serde_json::json!({ "inline_keyboard": rows })
+}
+
+/// The choice a button press picks, if it is one of our buttons.
+pub fn parse_press(query: &CallbackQuery, choices: &[String]) -> Option<usize> {
+    let i: usize = query
+        .data
+        .as_deref()?
+        .strip_prefix(CHOICE_PREFIX)?
+        .parse()
+        .ok()?;
+    (i < choices.len()).then_some(i)
+}
+
+/// The choice typed text names: the one it equals ignoring case, or else the only one it is
+/// a prefix of. `None` when nothing, or more than one choice, matches.
+pub fn match_text(choices: &[String], text: &str) -> Option<usize> {
+    let text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return None;
+    }
+    let lower: Vec<String> = choices.iter().map(|c| c.to_lowercase()).collect();
+    if let Some(i) = lower.iter().position(|c| *c == text) {
+        return Some(i);
+    }
+    let mut prefixed = lower
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.starts_with(&text));
+    match (prefixed.next(), prefixed.next()) {
+        (Some((i, _)), None) => Some(i),
+        _ => None,
+    }
+}
+
+/// The try-again message for a reply that isn't one of the choices.
+pub fn invalid_text(choices: &[String]) -> String {
+    format!(
+        "⚠️ That's not one of the choices. Please reply with one of: {}",
+        choices.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn choices() -> Vec<String> {
+        ["yes", "no", "defer", "Deploy"].map(String::from).to_vec()
+    }
+
+    #[test]
+    fn typed_text_matches_exactly_or_by_unique_prefix() {
+        let choices = choices();
+        assert_eq!(match_text(&choices, " YES "), Some(0));
+        assert_eq!(match_text(&choices, "n"), Some(1));
+        assert_eq!(match_text(&choices, "depl"), Some(3));
+        // Both "defer" and "Deploy" start with "de".
+        assert_eq!(match_text(&choices, "de"), None);
+        assert_eq!(match_text(&choices, "maybe"), None);
+        assert_eq!(match_text(&choices, ""), None);
+    }
+
+    #[test]
+    fn markup_rows_hold_three_buttons() {
+        let markup = markup(&choices());
+        let rows = markup["inline_keyboard"].as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1][0]["text"], "Deploy");
+        assert_eq!(rows[1][0]["callback_data"], "choice:3");
+    }
+}
@@ -0,0 +1,64 @@
+use crate::config::{self, Config};
+use crate::telegram::{BotCommand, TelegramClient};
+use anyhow::Result;
+use std::path::Path;
+
+/// Commands the recipient can use with the current config, for the bot's command menu.
+pub fn menu(cfg: &Config) -> Vec<BotCommand> {
+    let mut commands = Vec::new();
+    // Only `/word` cancel commands can appear in the menu.
+    if let Some(name) = cfg.cancel_command.strip_prefix('/')
+        && is_command_name(name)
+    {
+        commands.push(BotCommand {
+            command: name.to_string(),
+            description: "Cancel the pending prompt".to_string(),
+        });
+    }
+    commands
+}
+
+/// Telegram command names are 1-32 lowercase letters, digits and underscores.
+fn is_command_name(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+}
+
+/// Replace the bot's command menu with `menu`, printing what was registered.
+pub async fn sync(config_path: &Path) -> Result<()> {
+    let cfg = config::load(config_path)?;
+    let client = TelegramClient::for_config(&cfg)?;
+    let commands = menu(&cfg);
+    client.set_my_commands(&commands).await?;
+
+    if commands.is_empty() {
+        println!("No commands enabled; cleared the command menu.");
+    }
+    for c in &commands {
+        println!("/{} - {}", c.command, c.description);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn menu_follows_the_cancel_command() {
+        let cfg: Config = toml::from_str("user_id = 1").unwrap();
+        let names: Vec<_> = menu(&cfg).into_iter().map(|c| c.command).collect();
+        assert_eq!(names, ["cancel"]);
+
+        for custom in ["stop", "/Stop", ""] {
+            let raw = format!("user_id = 1\ncancel_command = {custom:?}");
+            let cfg: Config = toml::from_str(&raw).unwrap();
+            assert!(
+                menu(&cfg).is_empty(),
+                "{custom:?} should not be in the menu"
+            );
+        }
+    }
+}
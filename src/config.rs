@@ -1,19 +1,357 @@
 use anyhow::{Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
+    /// Resolved bot token. When `bot_token_command` is used this is filled in by `load`.
+    #[serde(default)]
     pub bot_token: String,
-    pub user_id: i64,
+    /// Shell command whose trimmed stdout is the bot token (e.g. `pass show telegram/bot`).
+    #[serde(default)]
+    pub bot_token_command: Option<String>,
+    /// Telegram user id(s) to message (`user_id` in the file). With several, the prompt is
+    /// broadcast and the first reply from any of them wins.
+    #[serde(rename = "user_id", deserialize_with = "one_or_many")]
+    pub user_ids: Vec<i64>,
     #[serde(default = "default_timeout_minutes")]
     pub timeout_minutes: u64,
+    /// Always upload long prompts as a file (`--long-as-file`).
+    #[serde(default)]
+    pub long_as_file: bool,
+    /// Prompt length, in UTF-16 code units, above which `--long-as-file` kicks in.
+    #[serde(default = "default_long_as_file_threshold")]
+    pub long_as_file_threshold: usize,
+    /// Always use `--correlate`, for bots shared by concurrent teleprompt runs.
+    #[serde(default)]
+    pub correlate: bool,
+    /// Who may answer in group chats listed in `user_id`. Private chats only ever accept their
+    /// own user.
+    #[serde(default)]
+    pub allowed_user_ids: Vec<i64>,
+    /// Forum topic (`message_thread_id`) to post prompts in; replies must come from it too.
+    #[serde(default)]
+    pub thread_id: Option<i64>,
+    /// Address book for `--to`: names mapped to user ids.
+    #[serde(default)]
+    pub recipients: BTreeMap<String, i64>,
+    /// Test recipient that `--canary` runs are rerouted to.
+    #[serde(default)]
+    pub canary_user_id: Option<i64>,
+    /// Reaction emojis accepted as answers, mapped to the answer text (and optionally an exit
+    /// code). Defaults to 👍 = yes and 👎 = no; an empty table turns reactions off.
+    #[serde(default)]
+    pub reactions: Option<BTreeMap<String, ReactionAnswer>>,
+    /// Exit codes for particular answers and outcomes; `--exit-map` entries override these.
+    #[serde(default)]
+    pub exit_map: crate::exit_map::ExitMap,
+    /// Reply that aborts the prompt (exit 3). An empty string disables it.
+    #[serde(default = "default_cancel_command")]
+    pub cancel_command: String,
+    /// Sent in reply to the cancel command, when set.
+    #[serde(default)]
+    pub cancel_reply: Option<String>,
+    /// Proxy for all Telegram requests, e.g. `socks5://127.0.0.1:9050` or
+    /// `http://proxy:3128`. `TELEPROMPT_PROXY` overrides it.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Bot API server to use instead of `https://api.telegram.org`, e.g. a self-hosted one.
+    #[serde(default)]
+    pub api_url: Option<String>,
+    /// PEM bundle of extra CA certificates to trust, e.g. a TLS-intercepting proxy's.
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+    /// PEM client certificate presented to the server, with its key unless `client_key` is set.
+    #[serde(default)]
+    pub client_cert: Option<PathBuf>,
+    #[serde(default)]
+    pub client_key: Option<PathBuf>,
+    /// Give up connecting to the Bot API after this long, e.g. `5s`.
+    #[serde(default, deserialize_with = "opt_duration")]
+    pub connect_timeout: Option<Duration>,
+    /// Give up on a request after this long, e.g. `60s`. Polls get their long-poll time on top.
+    #[serde(default, deserialize_with = "opt_duration")]
+    pub request_timeout: Option<Duration>,
+    /// User-Agent header sent with every request. Default: `teleprompt/<version>`.
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// What to do when another run is polling the same bot (`--poll-lock`).
+    #[serde(default)]
+    pub poll_lock: crate::lock::LockMode,
+    /// Delete a registered webhook when it blocks polling (`--fix-webhook`).
+    #[serde(default)]
+    pub fix_webhook: bool,
+    /// Spool fire-and-forget messages when Telegram can't be reached (`--queue-offline`).
+    #[serde(default)]
+    pub queue_offline: bool,
+    /// Bearer token `serve --http` requires of every request; it won't start without one.
+    #[serde(default)]
+    pub serve_token: Option<String>,
+    /// Directory for persisted state. Defaults to the platform state dir.
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
+    /// How state and run history are kept in `state_dir`.
+    #[serde(default)]
+    pub store: StoreKind,
+    /// Text put before every prompt, e.g. `"🗄 {hostname}: "`, with `--expand` placeholders.
+    #[serde(default)]
+    pub message_prefix: Option<String>,
+    /// Text put after every prompt, with `--expand` placeholders.
+    #[serde(default)]
+    pub message_suffix: Option<String>,
+    /// Shared secret for the 🔏 authenticity footer. Prompts are only signed when set.
+    #[serde(default)]
+    pub signing_secret: Option<String>,
+    /// Detect the reply language and translate replies in other languages. Off unless set.
+    #[serde(default)]
+    pub translate: Option<TranslateConfig>,
+    /// Collapse repeated identical prompts into a single summary message. Off unless set.
+    #[serde(default)]
+    pub burst: Option<BurstConfig>,
+    /// Space out messages, across runs, to stay within Telegram's limits. Off unless set.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// JSONL file that every run appends a record of its prompt and answers to. Off unless set.
+    #[serde(default)]
+    pub audit_log: Option<PathBuf>,
+    /// Whether the audit log keeps the prompt text or only its hash.
+    #[serde(default)]
+    pub audit_prompt: AuditPrompt,
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(de: D) -> std::result::Result<Vec<i64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(i64),
+        Many(Vec<i64>),
+    }
+    Ok(match OneOrMany::deserialize(de)? {
+        OneOrMany::One(id) => vec![id],
+        OneOrMany::Many(ids) => ids,
+    })
+}
+
+/// A humane duration string such as `5s` (see `duration::parse`).
+fn opt_duration<'de, D: Deserializer<'de>>(
+    de: D,
+) -> std::result::Result<Option<Duration>, D::Error> {
+    let raw = String::deserialize(de)?;
+    crate::duration::parse(&raw)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
 }
 
 fn default_timeout_minutes() -> u64 {
     60
 }
 
+fn default_cancel_command() -> String {
+    "/cancel".to_string()
+}
+
+fn default_long_as_file_threshold() -> usize {
+    crate::telegram::MAX_MESSAGE_LEN
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BurstConfig {
+    /// Number of identical prompts allowed within the window before collapsing.
+    #[serde(default = "default_burst_threshold")]
+    pub threshold: usize,
+    #[serde(default = "default_burst_window_minutes")]
+    pub window_minutes: u64,
+    /// How long the summary message's mute button silences the prompt.
+    #[serde(default = "default_burst_mute_minutes")]
+    pub mute_minutes: u64,
+}
+
+fn default_burst_threshold() -> usize {
+    3
+}
+
+fn default_burst_window_minutes() -> u64 {
+    10
+}
+
+fn default_burst_mute_minutes() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    /// Messages per second from the bot, to all chats.
+    #[serde(default = "default_rate_per_second")]
+    pub per_second: u32,
+    #[serde(default = "default_rate_per_chat_per_second")]
+    pub per_chat_per_second: u32,
+    /// Messages per minute to one group or channel.
+    #[serde(default = "default_rate_per_group_per_minute")]
+    pub per_group_per_minute: u32,
+}
+
+fn default_rate_per_second() -> u32 {
+    30
+}
+
+fn default_rate_per_chat_per_second() -> u32 {
+    1
+}
+
+fn default_rate_per_group_per_minute() -> u32 {
+    20
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    /// One JSON file per state document.
+    #[default]
+    Files,
+    /// A single SQLite database, which also records run history. Needs the `sqlite` feature.
+    Sqlite,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditPrompt {
+    /// Only a SHA-256 of the prompt, so the log doesn't hold what was asked.
+    #[default]
+    Hash,
+    Full,
+}
+
+/// What a reaction in `[reactions]` answers: either just the text, or
+/// `{ answer = "...", exit_code = N }`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ReactionAnswer {
+    Text(String),
+    Full {
+        answer: String,
+        #[serde(default)]
+        exit_code: Option<i32>,
+    },
+}
+
+impl ReactionAnswer {
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Text(answer) | Self::Full { answer, .. } => answer,
+        }
+    }
+
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Self::Text(_) => None,
+            Self::Full { exit_code, .. } => *exit_code,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TranslateConfig {
+    /// ISO 639-3 code replies are expected in (e.g. `eng`).
+    #[serde(default = "default_expected_language")]
+    pub expected_language: String,
+    /// Shell command that reads the reply on stdin and prints the translation on stdout.
+    #[serde(default)]
+    pub command: Option<String>,
+}
+
+fn default_expected_language() -> String {
+    "eng".to_string()
+}
+
+impl Config {
+    /// Reroute this run to the canary recipient, so everything (sending, reply matching, burst
+    /// fingerprints) targets the test chat instead of the real one.
+    pub fn use_canary(&mut self) -> Result<()> {
+        let canary = self
+            .canary_user_id
+            .context("--canary requires canary_user_id in the config")?;
+        self.user_ids = vec![canary];
+        Ok(())
+    }
+
+    /// Resolve a `--to` value: a name from `[recipients]`, or a numeric user id.
+    pub fn resolve_recipient(&self, name: &str) -> Result<i64> {
+        if let Some(&id) = self.recipients.get(name) {
+            return Ok(id);
+        }
+        name.parse().map_err(|_| {
+            let known: Vec<&str> = self.recipients.keys().map(String::as_str).collect();
+            anyhow::anyhow!(
+                "unknown recipient {name:?}: not a user id or a name in [recipients] ({})",
+                if known.is_empty() {
+                    "none defined".to_string()
+                } else {
+                    known.join(", ")
+                }
+            )
+        })
+    }
+
+    /// Whether `user_id` may answer a prompt sent to `chat_id`.
+    pub fn may_answer(&self, chat_id: i64, user_id: i64) -> bool {
+        crate::telegram::may_answer(&self.user_ids, &self.allowed_user_ids, chat_id, user_id)
+    }
+
+    /// The update's message, if it may be an answer: sent in a target chat (and topic, when
+    /// `thread_id` is set) by someone allowed to answer there.
+    pub fn reply_message<'a>(
+        &self,
+        update: &'a crate::telegram::Update,
+    ) -> Option<&'a crate::telegram::Message> {
+        crate::telegram::reply_message(update, &self.user_ids, &self.allowed_user_ids)
+            .filter(|m| self.thread_id.is_none() || m.message_thread_id == self.thread_id)
+    }
+
+    /// Whether `text` is the cancel command. Bot commands may carry the bot's name in groups
+    /// (`/cancel@my_bot`), which is ignored.
+    pub fn is_cancel(&self, text: &str) -> bool {
+        let text = text.trim();
+        let command = match text.split_once('@') {
+            Some((command, _)) if self.cancel_command.starts_with('/') => command,
+            _ => text,
+        };
+        !self.cancel_command.is_empty() && command == self.cancel_command
+    }
+
+    /// How many people can answer: one per private chat, plus the allowlist if any target is a
+    /// group (group chat ids are negative).
+    pub fn responder_count(&self) -> usize {
+        let mut people: Vec<i64> = self.user_ids.iter().copied().filter(|&id| id > 0).collect();
+        if self.user_ids.iter().any(|&id| id < 0) {
+            people.extend(&self.allowed_user_ids);
+        }
+        people.sort_unstable();
+        people.dedup();
+        people.len()
+    }
+
+    /// Recipients as `1,2,3`, for messages.
+    pub fn user_ids_display(&self) -> String {
+        let ids: Vec<String> = self.user_ids.iter().map(i64::to_string).collect();
+        ids.join(",")
+    }
+
+    /// The first recipient, for things that go to a single person (e.g. burst summaries).
+    pub fn primary_user_id(&self) -> i64 {
+        self.user_ids[0]
+    }
+
+    pub fn state_dir(&self) -> Result<PathBuf> {
+        match &self.state_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => crate::state::default_state_dir(),
+        }
+    }
+}
+
 pub fn default_config_path() -> Result<PathBuf> {
     default_config_path_impl()
 }
@@ -55,13 +393,103 @@ fn default_config_path_impl() -> Result<PathBuf> {
 }
 
 pub fn load(path: &Path) -> Result<Config> {
+    let mut cfg = parse(path)?;
+    resolve_bot_token(&mut cfg)
+        .with_context(|| format!("resolve bot token: {}", path.display()))?;
+    if let Some(proxy) = std::env::var("TELEPROMPT_PROXY")
+        .ok()
+        .filter(|p| !p.is_empty())
+    {
+        cfg.proxy = Some(proxy);
+    }
+    Ok(cfg)
+}
+
+/// Read and parse the config without resolving the bot token, for commands that never talk to
+/// Telegram (and so shouldn't run `bot_token_command`).
+pub fn parse(path: &Path) -> Result<Config> {
     let raw = std::fs::read_to_string(path)
         .with_context(|| format!("read config file: {}", path.display()))?;
     let cfg: Config =
         toml::from_str(&raw).with_context(|| format!("parse TOML config: {}", path.display()))?;
+    anyhow::ensure!(
+        !cfg.user_ids.is_empty(),
+        "user_id must list at least one user: {}",
+        path.display()
+    );
+    anyhow::ensure!(
+        cfg.store != StoreKind::Sqlite || cfg!(feature = "sqlite"),
+        "store = \"sqlite\" needs teleprompt built with the `sqlite` feature: {}",
+        path.display()
+    );
+    if let Some(limits) = &cfg.rate_limit {
+        anyhow::ensure!(
+            limits.per_second > 0
+                && limits.per_chat_per_second > 0
+                && limits.per_group_per_minute > 0,
+            "[rate_limit] limits must be at least 1: {}",
+            path.display()
+        );
+    }
     Ok(cfg)
 }
 
+fn resolve_bot_token(cfg: &mut Config) -> Result<()> {
+    match (&cfg.bot_token_command, cfg.bot_token.is_empty()) {
+        (Some(_), false) => anyhow::bail!("set only one of bot_token or bot_token_command"),
+        (None, false) => Ok(()),
+        (None, true) => anyhow::bail!("missing bot_token (or bot_token_command)"),
+        (Some(command), true) => {
+            cfg.bot_token = cached_token(command)?;
+            Ok(())
+        }
+    }
+}
+
+/// The output of `bot_token_command`, run once per process: long-running commands such as
+/// `serve` and `mcp` load the config for every request, and a secret manager may prompt, or
+/// rate limit, each time it is asked.
+fn cached_token(command: &str) -> Result<String> {
+    static TOKENS: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+    let tokens = TOKENS.get_or_init(Mutex::default);
+    if let Some(token) = tokens.lock().expect("token cache").get(command) {
+        return Ok(token.clone());
+    }
+    // Failures aren't cached, so a command that failed (e.g. a dismissed prompt) is retried.
+    let token = run_token_command(command)?;
+    tokens
+        .lock()
+        .expect("token cache")
+        .insert(command.to_string(), token.clone());
+    Ok(token)
+}
+
+fn run_token_command(command: &str) -> Result<String> {
+    // stderr is inherited so secret-manager prompts (e.g. a GPG pinentry) still reach the user.
+    let output = crate::shell::command(command)
+        .stdin(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .with_context(|| format!("run bot_token_command: {command}"))?;
+
+    // Never include stdout in errors: it may contain the token.
+    anyhow::ensure!(
+        output.status.success(),
+        "bot_token_command exited with {}: {command}",
+        output.status
+    );
+
+    let token = String::from_utf8(output.stdout)
+        .context("bot_token_command output is not valid UTF-8")?
+        .trim()
+        .to_string();
+    anyhow::ensure!(
+        !token.is_empty(),
+        "bot_token_command printed nothing: {command}"
+    );
+    Ok(token)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -97,7 +525,7 @@ user_id = 123
 "#;
         let cfg: Config = toml::from_str(raw).unwrap();
         assert_eq!(cfg.bot_token, "t");
-        assert_eq!(cfg.user_id, 123);
+        assert_eq!(cfg.user_ids, vec![123]);
         assert_eq!(cfg.timeout_minutes, 60);
     }
 
@@ -112,6 +540,178 @@ timeout_minutes = 5
         assert_eq!(cfg.timeout_minutes, 5);
     }
 
+    #[test]
+    fn user_id_may_be_a_list() {
+        let cfg: Config = toml::from_str("user_id = [1, 2, 3]").unwrap();
+        assert_eq!(cfg.user_ids, vec![1, 2, 3]);
+        assert_eq!(cfg.primary_user_id(), 1);
+    }
+
+    #[test]
+    fn resolves_recipients_by_name_or_id() {
+        let raw = r#"
+user_id = 1
+
+[recipients]
+alice = 12345
+"#;
+        let cfg: Config = toml::from_str(raw).unwrap();
+        assert_eq!(cfg.resolve_recipient("alice").unwrap(), 12345);
+        assert_eq!(cfg.resolve_recipient("678").unwrap(), 678);
+        let err = cfg.resolve_recipient("bob").unwrap_err().to_string();
+        assert!(
+            err.contains("unknown recipient \"bob\""),
+            "error was: {err}"
+        );
+        assert!(err.contains("alice"), "error was: {err}");
+    }
+
+    #[test]
+    fn thread_id_restricts_replies_to_the_topic() {
+        use crate::telegram::{Chat, Message, Update, User};
+
+        let cfg: Config =
+            toml::from_str("user_id = -100\nallowed_user_ids = [7]\nthread_id = 5").unwrap();
+        let in_thread = |thread: Option<i64>| Update {
+            update_id: 1,
+            message: Some(Message {
+                message_thread_id: thread,
+                from: Some(User {
+                    id: 7,
+                    ..Default::default()
+                }),
+                chat: Chat { id: -100 },
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(cfg.reply_message(&in_thread(Some(5))).is_some());
+        assert!(cfg.reply_message(&in_thread(Some(6))).is_none());
+        assert!(cfg.reply_message(&in_thread(None)).is_none());
+    }
+
+    #[test]
+    fn parses_burst_section_with_defaults() {
+        let raw = r#"
+bot_token = "t"
+user_id = 123
+
+[burst]
+threshold = 5
+"#;
+        let cfg: Config = toml::from_str(raw).unwrap();
+        let burst = cfg.burst.unwrap();
+        assert_eq!(burst.threshold, 5);
+        assert_eq!(burst.window_minutes, 10);
+        assert_eq!(burst.mute_minutes, 60);
+    }
+
+    #[test]
+    fn parses_reactions_as_text_or_answer_with_exit_code() {
+        let raw = r#"
+user_id = 123
+
+[reactions]
+"👍" = "yes"
+"🛑" = { answer = "stop", exit_code = 20 }
+"#;
+        let cfg: Config = toml::from_str(raw).unwrap();
+        let reactions = cfg.reactions.unwrap();
+        assert_eq!(reactions["👍"].text(), "yes");
+        assert_eq!(reactions["👍"].exit_code(), None);
+        assert_eq!(reactions["🛑"].text(), "stop");
+        assert_eq!(reactions["🛑"].exit_code(), Some(20));
+    }
+
+    #[test]
+    fn parses_the_exit_map_table() {
+        let raw = r#"
+user_id = 123
+
+[exit_map]
+Yes = 0
+timeout = 20
+"#;
+        let cfg: Config = toml::from_str(raw).unwrap();
+        assert_eq!(cfg.exit_map.answer("yes"), Some(0));
+        assert!(toml::from_str::<Config>("user_id = 1\n[exit_map]\nno = 300").is_err());
+    }
+
+    #[test]
+    fn recognizes_the_cancel_command() {
+        let cfg: Config = toml::from_str("user_id = 123").unwrap();
+        assert!(cfg.is_cancel("/cancel"));
+        assert!(cfg.is_cancel(" /cancel@my_bot\n"));
+        assert!(!cfg.is_cancel("/cancelled"));
+        assert!(!cfg.is_cancel("cancel"));
+
+        let cfg: Config = toml::from_str("user_id = 123\ncancel_command = \"stop\"").unwrap();
+        assert!(cfg.is_cancel("stop"));
+        assert!(!cfg.is_cancel("/cancel"));
+
+        let cfg: Config = toml::from_str("user_id = 123\ncancel_command = \"\"").unwrap();
+        assert!(!cfg.is_cancel(""));
+    }
+
+    #[test]
+    fn canary_reroutes_to_the_test_recipient() {
+        let mut cfg: Config = toml::from_str("user_id = 123\ncanary_user_id = 456").unwrap();
+        cfg.use_canary().unwrap();
+        assert_eq!(cfg.user_ids, vec![456]);
+
+        let mut cfg: Config = toml::from_str("user_id = 123").unwrap();
+        let err = cfg.use_canary().unwrap_err().to_string();
+        assert!(err.contains("canary_user_id"), "error was: {err}");
+    }
+
+    #[test]
+    fn resolves_bot_token_from_command_output() {
+        let raw = r#"
+bot_token_command = "echo  tok-123 "
+user_id = 123
+"#;
+        let mut cfg: Config = toml::from_str(raw).unwrap();
+        resolve_bot_token(&mut cfg).unwrap();
+        assert_eq!(cfg.bot_token, "tok-123");
+    }
+
+    #[test]
+    fn rejects_both_or_neither_token_sources() {
+        let raw = r#"
+bot_token = "t"
+bot_token_command = "echo t"
+user_id = 123
+"#;
+        let mut cfg: Config = toml::from_str(raw).unwrap();
+        let err = resolve_bot_token(&mut cfg).unwrap_err().to_string();
+        assert!(err.contains("only one of"), "error was: {err}");
+
+        let mut cfg: Config = toml::from_str("user_id = 123").unwrap();
+        let err = resolve_bot_token(&mut cfg).unwrap_err().to_string();
+        assert!(err.contains("missing bot_token"), "error was: {err}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn token_command_runs_once_per_process() {
+        let counter = std::env::temp_dir().join(format!(
+            "teleprompt_token_test_{}_{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let command = format!("echo x >> '{}'; echo tok-456", counter.display());
+        assert_eq!(cached_token(&command).unwrap(), "tok-456");
+        assert_eq!(cached_token(&command).unwrap(), "tok-456");
+        assert_eq!(std::fs::read_to_string(&counter).unwrap(), "x\n");
+        let _ = std::fs::remove_file(&counter);
+    }
+
+    #[test]
+    fn failing_token_command_is_an_error() {
+        let err = run_token_command("exit 3").unwrap_err().to_string();
+        assert!(err.contains("bot_token_command exited"), "error was: {err}");
+    }
+
     #[cfg(target_os = "linux")]
     #[test]
     fn default_config_path_linux_prefers_xdg_config_home() {
@@ -145,6 +745,19 @@ timeout_minutes = 5
         set_env("XDG_CONFIG_HOME", old_xdg);
     }
 
+    #[test]
+    fn http_timeouts_parse_as_durations() {
+        let cfg: Config =
+            toml::from_str("user_id = 1\nconnect_timeout = \"5s\"\nrequest_timeout = \"2m\"")
+                .unwrap();
+        assert_eq!(cfg.connect_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(cfg.request_timeout, Some(Duration::from_secs(120)));
+
+        let cfg: Config = toml::from_str("user_id = 1").unwrap();
+        assert_eq!(cfg.connect_timeout, None);
+        assert!(toml::from_str::<Config>("user_id = 1\nconnect_timeout = \"5\"").is_err());
+    }
+
     #[cfg(target_os = "macos")]
     #[test]
     fn default_config_path_macos_uses_application_support() {
@@ -0,0 +1,51 @@
+use crate::ask::{self, Answer, Button};
+use crate::telegram::TelegramClient;
+use crate::{Args, Outcome, config};
+use anyhow::Result;
+use std::path::Path;
+use std::time::Duration;
+
+const YES_NO: &[Button] = &[
+    Button {
+        name: "yes",
+        label: "👍 Yes",
+        words: &["y", "yes", "👍"],
+    },
+    Button {
+        name: "no",
+        label: "👎 No",
+        words: &["n", "no", "👎"],
+    },
+];
+const YES: usize = 0;
+
+/// Ask a yes/no question and answer with the exit code alone: `0` for yes, `1` for no, `2`
+/// if nobody answered (`3` for the cancel command).
+pub async fn run(
+    args: &Args,
+    config_path: &Path,
+    question: &str,
+    to: &[String],
+    timeout: Option<Duration>,
+) -> Result<Outcome> {
+    let mut cfg = config::load(config_path)?;
+    if !to.is_empty() {
+        cfg.user_ids = to
+            .iter()
+            .map(|name| cfg.resolve_recipient(name))
+            .collect::<Result<_>>()?;
+    }
+    let client =
+        TelegramClient::for_config(&cfg)?.with_fix_webhook(args.fix_webhook || cfg.fix_webhook);
+    let timeout =
+        timeout.unwrap_or_else(|| Duration::from_secs(cfg.timeout_minutes.saturating_mul(60)));
+
+    Ok(
+        match ask::ask(&client, &cfg, args, question, YES_NO, timeout).await? {
+            Answer::Chose(YES) => Outcome::Done,
+            Answer::Chose(_) => Outcome::Exit(1),
+            Answer::Cancelled => Outcome::Cancelled,
+            Answer::TimedOut => Outcome::TimedOut,
+        },
+    )
+}
@@ -0,0 +1,99 @@
+use crate::status::StatusStream;
+use crate::{Args, Outcome, OutputFormat, audit, prompt};
+use anyhow::{Context, Result};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// Carry out a `--turns`/`--until` back-and-forth: send a prompt, wait for the answer, then
+/// send the next prompt line from stdin, until a limit is hit or a turn doesn't end in a reply.
+/// Each turn is an ordinary prompt run, so after the first one the saved update offset spares
+/// the later turns a drain.
+pub async fn run(args: &Args, config_path: &Path, status: &mut StatusStream) -> Result<Outcome> {
+    let mut lines = std::io::stdin().lock().lines();
+    let mut send = args.send.clone();
+    if send.message.is_none() && send.message_file.is_none() {
+        send.message = Some(next_prompt(&mut lines)?.context("stdin had no prompt")?);
+    }
+
+    let mut turn = 1;
+    loop {
+        let mut audit = audit::Audit::new(config_path);
+        let res = prompt::run(args, &send, config_path, status, &mut audit, true).await;
+        let reply = audit.entry.replies.last().cloned();
+        audit.finish(&res);
+        let outcome = res?;
+        if !matches!(outcome, Outcome::Replied) {
+            return Ok(outcome);
+        }
+
+        // Bare text replies carry no newline of their own; keep one exchange per line.
+        if args.output == OutputFormat::Text
+            && args.format.is_none()
+            && reply.as_ref().is_some_and(|r| r.get("file").is_none())
+        {
+            let mut out = std::io::stdout().lock();
+            out.write_all(b"\n")?;
+            out.flush()?;
+        }
+
+        let text = reply
+            .as_ref()
+            .and_then(|r| r.get("reply"))
+            .and_then(|r| r.as_str());
+        if let (Some(until), Some(text)) = (&args.until, text)
+            && is_stop_word(text, until)
+        {
+            return Ok(Outcome::Replied);
+        }
+        if args.turns.is_some_and(|turns| turn >= turns) {
+            return Ok(Outcome::Replied);
+        }
+        match next_prompt(&mut lines)? {
+            Some(message) => send.message = Some(message),
+            None => return Ok(Outcome::Replied),
+        }
+        // Only the first prompt waits for --send-at or --delay.
+        send.send_at = None;
+        send.delay = None;
+        turn += 1;
+    }
+}
+
+/// The next non-blank line, or `None` at the end of input.
+fn next_prompt(
+    lines: &mut impl Iterator<Item = std::io::Result<String>>,
+) -> Result<Option<String>> {
+    for line in lines {
+        let line = line.context("read the next prompt from stdin")?;
+        if !line.trim().is_empty() {
+            return Ok(Some(line.trim().to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether a reply is the `--until` word, ignoring case and surrounding whitespace.
+fn is_stop_word(reply: &str, until: &str) -> bool {
+    reply.trim().eq_ignore_ascii_case(until.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_prompt_skips_blank_lines_and_ends_at_eof() {
+        let input = "first\n\n   \n  second  \n";
+        let mut lines = std::io::Cursor::new(input).lines();
+        assert_eq!(next_prompt(&mut lines).unwrap().as_deref(), Some("first"));
+        assert_eq!(next_prompt(&mut lines).unwrap().as_deref(), Some("second"));
+        assert_eq!(next_prompt(&mut lines).unwrap(), None);
+    }
+
+    #[test]
+    fn stop_word_ignores_case_and_whitespace() {
+        assert!(is_stop_word(" /Done\n", "/done"));
+        assert!(!is_stop_word("done", "/done"));
+        assert!(!is_stop_word("/done now", "/done"));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::telegram::Update;
+
+/// Marks a correlation tag inside prompt text. Tags are hashtags so they stand out (and are
+/// searchable) in the chat.
+const TAG_PREFIX: &str = "#tp";
+
+/// Random characters after the prefix.
+const TAG_LEN: usize = 6;
+
+/// A fresh tag such as `#tp7K2QXD`, taken from the random part of a ULID.
+pub fn new_tag() -> String {
+    let id = ulid::Ulid::new().to_string();
+    format!("{TAG_PREFIX}{}", &id[id.len() - TAG_LEN..])
+}
+
+/// The last correlation tag appearing in `text`, if any.
+pub fn find_tag(text: &str) -> Option<&str> {
+    text.match_indices(TAG_PREFIX)
+        .filter_map(|(start, _)| {
+            let tag = text.get(start..start + TAG_PREFIX.len() + TAG_LEN)?;
+            let suffix = &tag[TAG_PREFIX.len()..];
+            suffix
+                .bytes()
+                .all(|b| b.is_ascii_digit() || b.is_ascii_uppercase())
+                .then_some(tag)
+        })
+        .last()
+}
+
+/// Whether `update` is a reply to a prompt tagged for some other teleprompt process, or a
+/// press of a button meant for one: on such a prompt, or carrying its tag. Such updates must
+/// stay unconfirmed so their owner can still receive them.
+pub fn belongs_to_other(update: &Update, own_tag: &str) -> bool {
+    update_tag(update).is_some_and(|tag| tag != own_tag)
+}
+
+/// The tag of the prompt `update` replies to, or of the button pressed: from its callback
+/// data, else from the message it is on.
+pub fn update_tag(update: &Update) -> Option<&str> {
+    if let Some(query) = &update.callback_query {
+        query.data.as_deref().and_then(find_tag).or_else(|| {
+            let message = query.message.as_ref()?;
+            message
+                .text
+                .as_deref()
+                .or(message.caption.as_deref())
+                .and_then(find_tag)
+        })
+    } else {
+        let parent = update.message.as_ref()?.reply_to_message.as_deref()?;
+        parent
+            .text
+            .as_deref()
+            .or(parent.caption.as_deref())
+            .and_then(find_tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telegram::Message;
+
+    fn reply_to(text: &str) -> Update {
+        Update {
+            update_id: 1,
+            message: Some(Message {
+                text: Some("yes".to_string()),
+                reply_to_message: Some(Box::new(Message {
+                    text: Some(text.to_string()),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn new_tags_are_found_again() {
+        let tag = new_tag();
+        let text = format!("deploy?\n\n{tag}");
+        assert_eq!(find_tag(&text), Some(tag.as_str()));
+        assert_eq!(find_tag("see #tpx and #topics"), None);
+    }
+
+    #[test]
+    fn only_replies_to_other_tags_belong_to_others() {
+        assert!(belongs_to_other(&reply_to("ok?\n\n#tpAAAAAA"), "#tpBBBBBB"));
+        assert!(!belongs_to_other(
+            &reply_to("ok?\n\n#tpBBBBBB"),
+            "#tpBBBBBB"
+        ));
+        assert!(!belongs_to_other(&reply_to("untagged"), "#tpBBBBBB"));
+
+        let plain = Update {
+            update_id: 2,
+            message: Some(Message::default()),
+            ..Default::default()
+        };
+        assert!(!belongs_to_other(&plain, "#tpBBBBBB"));
+    }
+
+    #[test]
+    fn presses_for_other_tags_belong_to_others() {
+        let press = |data: &str, on: &str| Update {
+            update_id: 3,
+            callback_query: Some(crate::telegram::CallbackQuery {
+                id: "q".to_string(),
+                from: Default::default(),
+                data: Some(data.to_string()),
+                message: Some(Message {
+                    text: Some(on.to_string()),
+                    ..Default::default()
+                }),
+            }),
+            ..Default::default()
+        };
+        assert!(belongs_to_other(
+            &press("confirm:#tpAAAAAA", "Which one?"),
+            "#tpBBBBBB"
+        ));
+        assert!(!belongs_to_other(
+            &press("confirm:#tpBBBBBB", "Which one?"),
+            "#tpBBBBBB"
+        ));
+        assert!(belongs_to_other(
+            &press("choice:0", "ok?\n\n#tpAAAAAA"),
+            "#tpBBBBBB"
+        ));
+        assert!(!belongs_to_other(&press("mute:ab12", "🔁"), "#tpBBBBBB"));
+    }
+}
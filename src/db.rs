@@ -0,0 +1,250 @@
+//! SQLite store for state documents and run history, used with `store = "sqlite"`.
+
+use crate::DbAction;
+use crate::config::{self, StoreKind};
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Name of the database inside the state dir.
+pub const FILE_NAME: &str = "teleprompt.db";
+
+/// Bumped with every schema change; `open` migrates older databases.
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS documents (
+    name TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS history (
+    id INTEGER PRIMARY KEY,
+    ts TEXT NOT NULL,
+    entry TEXT NOT NULL UNIQUE
+);
+CREATE INDEX IF NOT EXISTS history_ts ON history (ts);
+";
+
+/// How long a run waits for another run's write transaction before failing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Db {
+    conn: Connection,
+    path: PathBuf,
+}
+
+impl Db {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("create state dir: {}", parent.display()))?;
+        }
+        let conn =
+            Connection::open(path).with_context(|| format!("open database: {}", path.display()))?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        // WAL lets concurrent runs read while one writes.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        anyhow::ensure!(
+            version <= SCHEMA_VERSION,
+            "database {} has schema version {version}, newer than this teleprompt supports ({SCHEMA_VERSION})",
+            path.display()
+        );
+        if version < SCHEMA_VERSION {
+            conn.execute_batch(SCHEMA)
+                .with_context(|| format!("migrate database: {}", path.display()))?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        Ok(Self {
+            conn,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A state document's JSON, if stored.
+    pub fn load(&self, name: &str) -> Result<Option<String>> {
+        self.conn
+            .query_row(
+                "SELECT value FROM documents WHERE name = ?1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()
+            .with_context(|| format!("load state document {name:?}"))
+    }
+
+    pub fn save(&self, name: &str, value: &str) -> Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO documents (name, value, updated_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (name) DO UPDATE SET value = ?2, updated_at = ?3",
+                params![name, value, now()],
+            )
+            .with_context(|| format!("save state document {name:?}"))?;
+        Ok(())
+    }
+
+    /// Names of all stored documents, sorted.
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name FROM documents ORDER BY name")?;
+        let names = stmt.query_map([], |row| row.get(0))?;
+        Ok(names.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Delete a document. Returns whether it existed.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let n = self
+            .conn
+            .execute("DELETE FROM documents WHERE name = ?1", [name])?;
+        Ok(n > 0)
+    }
+
+    /// Record a finished run. Returns false if an identical record was already there.
+    pub fn append_history(&self, entry: &serde_json::Value) -> Result<bool> {
+        let ts = entry.get("ts").and_then(|t| t.as_str()).unwrap_or_default();
+        let n = self
+            .conn
+            .execute(
+                "INSERT OR IGNORE INTO history (ts, entry) VALUES (?1, ?2)",
+                params![ts, entry.to_string()],
+            )
+            .context("record history")?;
+        Ok(n > 0)
+    }
+
+    /// Every recorded run, oldest first.
+    pub fn history(&self) -> Result<Vec<serde_json::Value>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT entry FROM history ORDER BY ts, id")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut entries = Vec::new();
+        for raw in rows {
+            entries.push(serde_json::from_str(&raw?).context("parse history entry")?);
+        }
+        Ok(entries)
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+/// `teleprompt db`: maintenance of the SQLite store.
+pub fn run(config_path: &Path, action: &DbAction) -> Result<()> {
+    let cfg = config::parse(config_path)?;
+    anyhow::ensure!(
+        cfg.store == StoreKind::Sqlite,
+        "the config doesn't use the SQLite store; set `store = \"sqlite\"`"
+    );
+    let dir = cfg.state_dir()?;
+    let db = Db::open(&dir.join(FILE_NAME))?;
+
+    match action {
+        DbAction::Info => {
+            let size = std::fs::metadata(db.path()).map(|m| m.len()).unwrap_or(0);
+            let history: i64 = db
+                .conn
+                .query_row("SELECT COUNT(*) FROM history", [], |row| row.get(0))?;
+            println!("path: {}", db.path().display());
+            println!("size: {size} bytes");
+            println!("schema version: {SCHEMA_VERSION}");
+            println!("documents: {}", db.list()?.join(", "));
+            println!("history records: {history}");
+        }
+        DbAction::Check => {
+            let result: String = db
+                .conn
+                .pragma_query_value(None, "integrity_check", |row| row.get(0))?;
+            anyhow::ensure!(result == "ok", "integrity check failed: {result}");
+            println!("ok");
+        }
+        DbAction::Vacuum => {
+            db.conn.execute_batch("VACUUM").context("vacuum database")?;
+            println!("Vacuumed {}.", db.path().display());
+        }
+        DbAction::Import => import(&db, &dir, cfg.audit_log.as_deref())?,
+    }
+    Ok(())
+}
+
+/// Copy state documents (`*.json` in the state dir) and the `audit_log` into the database.
+/// Existing documents are overwritten; history records already present are skipped.
+fn import(db: &Db, dir: &Path, audit_log: Option<&Path>) -> Result<()> {
+    let files = crate::state::StateStore::open(dir.to_path_buf())?;
+    let names = files.list()?;
+    for name in &names {
+        let doc: serde_json::Value = files.load(name)?;
+        db.save(name, &doc.to_string())?;
+    }
+    println!("Imported {} state documents.", names.len());
+
+    let Some(path) = audit_log else {
+        return Ok(());
+    };
+    let raw = match std::fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("read audit log: {}", path.display())),
+    };
+    let mut added = 0;
+    for line in raw.lines().filter(|l| !l.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(entry) => added += usize::from(db.append_history(&entry)?),
+            Err(e) => tracing::warn!("skipping audit log line: {e}"),
+        }
+    }
+    println!("Imported {added} history records.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db() -> Db {
+        let path = std::env::temp_dir().join(format!(
+            "teleprompt_db_test_{}_{}/{FILE_NAME}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        Db::open(&path).unwrap()
+    }
+
+    #[test]
+    fn documents_round_trip_and_list() {
+        let db = temp_db();
+        assert_eq!(db.load("burst").unwrap(), None);
+        db.save("burst", "{\"n\":1}").unwrap();
+        db.save("burst", "{\"n\":2}").unwrap();
+        db.save("alpha", "{}").unwrap();
+        assert_eq!(db.load("burst").unwrap().as_deref(), Some("{\"n\":2}"));
+        assert_eq!(db.list().unwrap(), vec!["alpha", "burst"]);
+        assert!(db.remove("alpha").unwrap());
+        assert!(!db.remove("alpha").unwrap());
+    }
+
+    #[test]
+    fn history_is_ordered_and_deduplicated() {
+        let db = temp_db();
+        let late = serde_json::json!({ "ts": "2024-02-01T00:00:00.000Z", "outcome": "replied" });
+        let early = serde_json::json!({ "ts": "2024-01-01T00:00:00.000Z", "outcome": "sent" });
+        assert!(db.append_history(&late).unwrap());
+        assert!(db.append_history(&early).unwrap());
+        assert!(!db.append_history(&late).unwrap());
+        assert_eq!(db.history().unwrap(), vec![early, late]);
+
+        // Reopening an up-to-date database keeps its contents.
+        let reopened = Db::open(db.path()).unwrap();
+        assert_eq!(reopened.history().unwrap().len(), 2);
+    }
+}
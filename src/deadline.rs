@@ -0,0 +1,101 @@
+use crate::duration;
+use crate::telegram::{self, ParseMode, SendOptions, TelegramClient};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The `--show-deadline` footer: the time left rounded up to the minute, or `None` once
+/// expired.
+pub fn footer(remaining: Option<Duration>) -> String {
+    match remaining {
+        Some(d) => {
+            let minutes = d.as_secs().div_ceil(60).max(1);
+            format!(
+                "⏳ expires in {}",
+                duration::format(Duration::from_secs(minutes * 60))
+            )
+        }
+        None => "⌛ expired".to_string(),
+    }
+}
+
+/// A sent prompt whose deadline footer is kept up to date by editing it.
+pub struct Countdown {
+    /// Escaped prompt text before and after the footer.
+    pub head: String,
+    pub tail: String,
+    pub mode: ParseMode,
+    /// Whether the prompt text went out as a media caption rather than a text message.
+    pub caption: bool,
+    /// Inline keyboard to keep on the message; edits drop it otherwise.
+    pub reply_markup: Option<serde_json::Value>,
+    /// Prompt message id per recipient chat.
+    pub messages: BTreeMap<i64, i64>,
+    /// The footer currently shown.
+    pub shown: String,
+}
+
+impl Countdown {
+    /// Full prompt text with `footer`.
+    pub fn text(head: &str, footer: &str, tail: &str, mode: ParseMode) -> String {
+        format!("{head}{}{tail}", mode.escape(&format!("\n\n{footer}")))
+    }
+
+    /// Edit the prompt if the footer for `remaining` differs from the one shown. Failures are
+    /// only warned about: the countdown is cosmetic.
+    pub async fn update(&mut self, client: &TelegramClient, remaining: Option<Duration>) {
+        let footer = footer(remaining);
+        if footer == self.shown {
+            return;
+        }
+        let text = Self::text(&self.head, &footer, &self.tail, self.mode);
+        self.shown = footer;
+
+        // Long prompts are split; the message we hold is the last part.
+        let text = if self.caption {
+            text
+        } else {
+            telegram::split_message(&text, telegram::MAX_MESSAGE_LEN)
+                .pop()
+                .unwrap_or_default()
+        };
+        let opts = SendOptions {
+            parse_mode: self.mode,
+            reply_markup: self
+                .reply_markup
+                .clone()
+                .filter(|m| m.get("inline_keyboard").is_some()),
+            ..Default::default()
+        };
+        for (&chat_id, &message_id) in &self.messages {
+            let res = if self.caption {
+                client
+                    .edit_message_caption(chat_id, message_id, &text, &opts)
+                    .await
+            } else {
+                client
+                    .edit_message_text(chat_id, message_id, &text, &opts)
+                    .await
+            };
+            if let Err(e) = res {
+                tracing::warn!("could not update the deadline: {e:#}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn footer_rounds_up_to_the_minute() {
+        assert_eq!(footer(Some(Duration::from_secs(720))), "⏳ expires in 12m");
+        assert_eq!(footer(Some(Duration::from_secs(661))), "⏳ expires in 12m");
+        assert_eq!(footer(Some(Duration::from_secs(5))), "⏳ expires in 1m");
+        assert_eq!(
+            footer(Some(Duration::from_secs(5400))),
+            "⏳ expires in 1h30m"
+        );
+        assert_eq!(footer(None), "⌛ expired");
+    }
+}
@@ -0,0 +1,206 @@
+use crate::state::StateStore;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+/// Prefix of the state documents, one per distinct prompt.
+const PREFIX: &str = "dedupe-";
+
+/// How often a run attached to an identical prompt checks whether it has been answered.
+const ATTACH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How the prompt a `--dedupe-window` entry records ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ended {
+    Answered,
+    TimedOut,
+    Cancelled,
+}
+
+/// The last prompt sent with a given fingerprint, for `--dedupe-window`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// When it was sent, and when its run stops waiting, as Unix timestamps (seconds).
+    pub sent_at: i64,
+    pub deadline: i64,
+    /// Until when it may be reused: the sending run's window past `sent_at`.
+    pub expires: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ended: Option<Ended>,
+    /// The answer, as its `--output json` envelope.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply: Option<serde_json::Value>,
+    /// Exit code the answer was mapped to (by a reaction or the exit map), if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+}
+
+/// Identifies "the same prompt from the same bot to the same chats".
+pub fn fingerprint(token: &str, chat_ids: &[i64], message: &str) -> String {
+    let chats: Vec<String> = chat_ids.iter().map(i64::to_string).collect();
+    let mut h = Sha256::new();
+    h.update(token.split(':').next().unwrap_or_default().as_bytes());
+    h.update([0]);
+    h.update(chats.join(",").as_bytes());
+    h.update([0]);
+    h.update(message.as_bytes());
+    h.finalize()[..8]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// The identical prompt sent within `window` before `now` that this one should reuse: one
+/// that was answered, or is still being waited on. Prompts that timed out or were cancelled
+/// are asked again.
+pub fn lookup(store: &StateStore, fp: &str, window: Duration, now: i64) -> Result<Option<Entry>> {
+    let Some(entry) = store.load::<Option<Entry>>(&format!("{PREFIX}{fp}"))? else {
+        return Ok(None);
+    };
+    let recent = now - entry.sent_at < window.as_secs() as i64;
+    Ok(match entry.ended {
+        Some(Ended::Answered) if recent => Some(entry),
+        None if recent && now < entry.deadline => Some(entry),
+        _ => None,
+    })
+}
+
+/// Wait, until `until`, for the run that sent `entry` to end. Returns how it did (still
+/// `ended: None` if it hadn't by then), or `None` if it failed, or its record was replaced.
+pub async fn attach(
+    store: &StateStore,
+    fp: &str,
+    entry: &Entry,
+    until: i64,
+) -> Result<Option<Entry>> {
+    let name = format!("{PREFIX}{fp}");
+    loop {
+        let Some(current) = store.load::<Option<Entry>>(&name)? else {
+            return Ok(None);
+        };
+        if current.sent_at != entry.sent_at {
+            return Ok(None);
+        }
+        if current.ended.is_some() || chrono::Utc::now().timestamp() >= until {
+            return Ok(Some(current));
+        }
+        tokio::time::sleep(ATTACH_INTERVAL).await;
+    }
+}
+
+/// A prompt this run is sending, recorded so identical ones can attach to it. If the run
+/// fails before `finish`, the record is removed when this is dropped, so they send their own.
+pub struct Claim<'a> {
+    store: &'a StateStore,
+    name: String,
+    entry: Entry,
+    finished: bool,
+}
+
+impl<'a> Claim<'a> {
+    /// Record the prompt with fingerprint `fp` as sent now, and clear out records nobody can
+    /// reuse any more.
+    pub fn new(
+        store: &'a StateStore,
+        fp: &str,
+        now: i64,
+        timeout: Duration,
+        window: Duration,
+    ) -> Result<Self> {
+        prune(store, now);
+        let entry = Entry {
+            sent_at: now,
+            deadline: now + timeout.as_secs() as i64,
+            expires: now + window.as_secs() as i64,
+            ..Default::default()
+        };
+        let name = format!("{PREFIX}{fp}");
+        store.save(&name, &entry)?;
+        Ok(Self {
+            store,
+            name,
+            entry,
+            finished: false,
+        })
+    }
+
+    /// Record how the prompt ended: with `reply`, mapped to `exit_code` if given, or not.
+    /// Best effort: on failure identical prompts are asked again.
+    pub fn finish(
+        mut self,
+        ended: Ended,
+        reply: Option<serde_json::Value>,
+        exit_code: Option<i32>,
+    ) {
+        self.finished = true;
+        self.entry.ended = Some(ended);
+        self.entry.reply = reply;
+        self.entry.exit_code = exit_code;
+        if let Err(e) = self.store.save(&self.name, &self.entry) {
+            tracing::warn!("could not record the answer for --dedupe-window: {e:#}");
+        }
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.store.remove(&self.name);
+        }
+    }
+}
+
+/// Remove records that are past both their window and their wait.
+fn prune(store: &StateStore, now: i64) {
+    let Ok(names) = store.list() else { return };
+    for name in names.iter().filter(|n| n.starts_with(PREFIX)) {
+        if let Ok(Some(entry)) = store.load::<Option<Entry>>(name)
+            && now >= entry.expires.max(entry.deadline)
+        {
+            let _ = store.remove(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answered_and_pending_prompts_are_reused_within_the_window() {
+        let dir = std::env::temp_dir().join(format!(
+            "teleprompt-dedupe-test-{}-{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let store = StateStore::open(dir.clone()).unwrap();
+        let fp = fingerprint("123:secret", &[7], "Deploy?");
+        assert_ne!(fp, fingerprint("123:secret", &[8], "Deploy?"));
+        let window = Duration::from_secs(600);
+        let timeout = Duration::from_secs(60);
+
+        let claim = Claim::new(&store, &fp, 1_000, timeout, window).unwrap();
+        let pending = lookup(&store, &fp, window, 1_030).unwrap().unwrap();
+        assert_eq!(pending.ended, None);
+        // Nobody is waiting on it past its deadline.
+        assert_eq!(lookup(&store, &fp, window, 1_060).unwrap(), None);
+
+        let reply = serde_json::json!({ "reply": "yes" });
+        claim.finish(Ended::Answered, Some(reply.clone()), None);
+        let answered = lookup(&store, &fp, window, 1_500).unwrap().unwrap();
+        assert_eq!(answered.reply, Some(reply));
+        assert_eq!(lookup(&store, &fp, window, 1_600).unwrap(), None);
+
+        Claim::new(&store, &fp, 2_000, timeout, window)
+            .unwrap()
+            .finish(Ended::TimedOut, None, None);
+        assert_eq!(lookup(&store, &fp, window, 2_100).unwrap(), None);
+
+        // A run that fails takes its record with it.
+        drop(Claim::new(&store, &fp, 3_000, timeout, window).unwrap());
+        assert_eq!(lookup(&store, &fp, window, 3_001).unwrap(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,24 @@
+use crate::config;
+use crate::telegram::{self, TelegramClient};
+use anyhow::Result;
+use std::path::Path;
+
+/// Delete messages sent earlier, e.g. a "job running..." notice from `notify`. A message that
+/// is already gone only gets a warning, so cleanup can run twice.
+pub async fn run(config_path: &Path, message_ids: &[i64], to: Option<&str>) -> Result<()> {
+    let cfg = config::load(config_path)?;
+    let chat_id = match to {
+        Some(name) => cfg.resolve_recipient(name)?,
+        None => cfg.primary_user_id(),
+    };
+    let client = TelegramClient::for_config(&cfg)?;
+    for &message_id in message_ids {
+        match client.delete_message(chat_id, message_id).await {
+            Err(e) if telegram::bad_request(&e, "message to delete not found") => {
+                tracing::warn!("message {message_id} was already deleted");
+            }
+            res => res?,
+        }
+    }
+    Ok(())
+}
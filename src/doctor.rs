@@ -0,0 +1,151 @@
+use crate::config;
+use crate::telegram::TelegramClient;
+use anyhow::Result;
+use std::path::Path;
+
+/// Result of a single diagnostic check.
+struct Check {
+    name: &'static str,
+    result: Result<String, String>,
+}
+
+#[derive(Default)]
+struct Report {
+    failed: usize,
+}
+
+impl Report {
+    fn add(&mut self, check: Check) {
+        match &check.result {
+            Ok(detail) => println!("[PASS] {}: {detail}", check.name),
+            Err(detail) => {
+                println!("[FAIL] {}: {detail}", check.name);
+                self.failed += 1;
+            }
+        }
+    }
+
+    fn finish(self, skipped_rest: bool) -> Result<()> {
+        if skipped_rest {
+            println!("Skipping remaining checks.");
+        }
+        anyhow::ensure!(self.failed == 0, "doctor: {} check(s) failed", self.failed);
+        println!("All checks passed.");
+        Ok(())
+    }
+}
+
+/// Run end-to-end diagnostics, printing each check's outcome. Later checks that depend on an
+/// earlier failure are skipped. Returns an error if any check failed.
+pub async fn run(config_path: &Path) -> Result<()> {
+    let mut report = Report::default();
+
+    let cfg = match config::load(config_path) {
+        Ok(cfg) => {
+            report.add(Check {
+                name: "config",
+                result: Ok(format!(
+                    "{} (user_id={})",
+                    config_path.display(),
+                    cfg.user_ids_display()
+                )),
+            });
+            cfg
+        }
+        Err(e) => {
+            report.add(Check {
+                name: "config",
+                result: Err(format!("{e:#}")),
+            });
+            return report.finish(true);
+        }
+    };
+
+    let client = match TelegramClient::for_config(&cfg) {
+        Ok(client) => client,
+        Err(e) => {
+            report.add(Check {
+                name: "http client",
+                result: Err(format!("{e:#}")),
+            });
+            return report.finish(true);
+        }
+    };
+
+    match client.get_me().await {
+        Ok(bot) => report.add(Check {
+            name: "bot token",
+            result: Ok(format!(
+                "@{} (id={})",
+                bot.username.as_deref().unwrap_or(&bot.first_name),
+                bot.id
+            )),
+        }),
+        Err(e) => {
+            report.add(Check {
+                name: "bot token",
+                result: Err(explain(&format!("{e:#}"))),
+            });
+            return report.finish(true);
+        }
+    }
+
+    // A chat action is invisible-ish to the user but fails exactly like sendMessage would.
+    for &user_id in &cfg.user_ids {
+        report.add(Check {
+            name: "send to user",
+            result: match client.send_chat_action(user_id, "typing").await {
+                Ok(()) => Ok(format!("bot can message user_id={user_id}")),
+                Err(e) => Err(format!("user_id={user_id}: {}", explain(&format!("{e:#}")))),
+            },
+        });
+    }
+
+    report.add(Check {
+        name: "webhook",
+        result: match client.get_webhook_info().await {
+            Ok(info) if info.url.is_empty() => Ok("no webhook set; polling works".to_string()),
+            Ok(info) => Err(format!(
+                "webhook is set to {}; getUpdates polling will fail until it is deleted \
+                 (run with --fix-webhook to do that)",
+                info.url
+            )),
+            Err(e) => Err(explain(&format!("{e:#}"))),
+        },
+    });
+
+    report.finish(false)
+}
+
+/// Append a human hint to well-known Telegram failures.
+fn explain(err: &str) -> String {
+    let hint = if err.contains("bot was blocked by the user") {
+        "the user blocked the bot; unblock it in Telegram"
+    } else if err.contains("chat not found") {
+        "the user has never messaged this bot (send it /start) or user_id is wrong"
+    } else if err.contains("Unauthorized") {
+        "the bot token is invalid or was revoked; get a new one from @BotFather"
+    } else {
+        return err.to_string();
+    };
+    format!("{err} ({hint})")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explain_adds_hints_for_known_errors() {
+        let msg = explain("telegram api error 403: Forbidden: bot was blocked by the user");
+        assert!(msg.contains("unblock it"), "msg was: {msg}");
+
+        let msg = explain("telegram api error 400: Bad Request: chat not found");
+        assert!(msg.contains("send it /start"), "msg was: {msg}");
+
+        let msg = explain("telegram api error 401: Unauthorized");
+        assert!(msg.contains("@BotFather"), "msg was: {msg}");
+
+        assert_eq!(explain("something else"), "something else");
+    }
+}
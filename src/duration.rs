@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+/// Parse a humane duration such as `90s`, `5m`, `2h` or `1h30m`.
+///
+/// Units are `s`, `m`, `h` and `d`; components may be combined in any order. A bare number is
+/// rejected because it is ambiguous (the config counts minutes, most tools count seconds).
+pub fn parse(input: &str) -> Result<Duration, String> {
+    let s = input.trim();
+    if s.is_empty() {
+        return Err("empty duration".to_string());
+    }
+
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(format!("invalid duration {input:?}: unknown unit {c:?}")),
+        };
+        let n: u64 = digits
+            .parse()
+            .map_err(|_| format!("invalid duration {input:?}: expected a number before {c:?}"))?;
+        digits.clear();
+        total = n
+            .checked_mul(unit)
+            .and_then(|v| total.checked_add(v))
+            .ok_or_else(|| format!("duration {input:?} is too large"))?;
+    }
+
+    if !digits.is_empty() {
+        return Err(format!(
+            "invalid duration {input:?}: missing unit (use e.g. {digits}s or {digits}m)"
+        ));
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Format a duration compactly using the same units `parse` accepts, e.g. `1h30m`.
+pub fn format(d: Duration) -> String {
+    let mut secs = d.as_secs();
+    if secs == 0 {
+        return "0s".to_string();
+    }
+
+    let mut out = String::new();
+    for (unit, size) in [("d", 86_400), ("h", 3_600), ("m", 60), ("s", 1)] {
+        if secs >= size {
+            out.push_str(&format!("{}{unit}", secs / size));
+            secs %= size;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_and_combined_units() {
+        assert_eq!(parse("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse(" 2h ").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse("1d").unwrap(), Duration::from_secs(86_400));
+    }
+
+    #[test]
+    fn rejects_bare_numbers_and_unknown_units() {
+        let err = parse("10").unwrap_err();
+        assert!(err.contains("missing unit"), "error was: {err}");
+        assert!(parse("5x").unwrap_err().contains("unknown unit"));
+        assert!(parse("m").unwrap_err().contains("expected a number"));
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn format_round_trips_through_parse() {
+        for s in ["0s", "45s", "5m", "1h30m", "1d2h3m4s"] {
+            assert_eq!(format(parse(s).unwrap()), s);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use crate::config;
+use crate::telegram::{self, ParseMode, SendOptions, TelegramClient};
+use anyhow::Result;
+use std::io::{IsTerminal, Read};
+use std::path::Path;
+
+/// Replace the text of a message sent earlier, e.g. by `notify`, or its caption if it is a
+/// media message. Editing to the text it already has is not an error, so progress updates can
+/// be sent blindly.
+pub async fn run(
+    config_path: &Path,
+    message_id: i64,
+    to: Option<&str>,
+    message: Option<&str>,
+    parse_mode: ParseMode,
+) -> Result<()> {
+    let cfg = config::load(config_path)?;
+    let chat_id = match to {
+        Some(name) => cfg.resolve_recipient(name)?,
+        None => cfg.primary_user_id(),
+    };
+    let text = match message {
+        Some(m) => m.trim().to_string(),
+        None => read_stdin()?,
+    };
+    anyhow::ensure!(!text.trim().is_empty(), "the new text is empty");
+
+    let client = TelegramClient::for_config(&cfg)?;
+    let opts = SendOptions {
+        parse_mode,
+        ..Default::default()
+    };
+    let res = match client
+        .edit_message_text(chat_id, message_id, &text, &opts)
+        .await
+    {
+        Err(e) if telegram::bad_request(&e, "there is no text in the message") => {
+            client
+                .edit_message_caption(chat_id, message_id, &text, &opts)
+                .await
+        }
+        res => res,
+    };
+    match res {
+        Err(e) if telegram::bad_request(&e, "message is not modified") => Ok(()),
+        res => res,
+    }
+}
+
+fn read_stdin() -> Result<String> {
+    anyhow::ensure!(
+        !std::io::stdin().is_terminal(),
+        "No --message provided and stdin is a terminal; pipe the new text via stdin or pass --message."
+    );
+    let mut raw = String::new();
+    std::io::stdin().read_to_string(&mut raw)?;
+    Ok(raw.trim_end_matches(['\r', '\n']).to_string())
+}
@@ -0,0 +1,95 @@
+use crate::telegram::MessageEntity;
+use serde::{Deserialize, Serialize};
+
+/// A message entity as written in `--output json`: located in the reply text by character
+/// and by byte, rather than by Telegram's UTF-16 code units, and with the text it covers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entity {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The part of the text the entity covers.
+    pub text: String,
+    /// Position and length in Unicode scalar values (`char`s).
+    pub offset: usize,
+    pub length: usize,
+    /// Position and length in bytes of the UTF-8 text.
+    pub byte_offset: usize,
+    pub byte_length: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// Locate `entities` in `text`. Entities that don't fall on character boundaries within the
+/// text (which Telegram never sends) are dropped.
+pub fn resolve(text: &str, entities: &[MessageEntity]) -> Vec<Entity> {
+    // (utf-16 offset, byte offset, char offset) of every character boundary.
+    let mut bounds = Vec::with_capacity(text.len() + 1);
+    let mut utf16 = 0;
+    for (chars, (bytes, c)) in text.char_indices().enumerate() {
+        bounds.push((utf16, bytes, chars));
+        utf16 += c.len_utf16();
+    }
+    bounds.push((utf16, text.len(), bounds.len()));
+    let find = |unit: usize| {
+        bounds
+            .binary_search_by_key(&unit, |&(u, _, _)| u)
+            .ok()
+            .map(|i| bounds[i])
+    };
+
+    entities
+        .iter()
+        .filter_map(|e| {
+            let (_, start_byte, start_char) = find(e.offset)?;
+            let (_, end_byte, end_char) = find(e.offset + e.length)?;
+            Some(Entity {
+                kind: e.kind.clone(),
+                text: text[start_byte..end_byte].to_string(),
+                offset: start_char,
+                length: end_char - start_char,
+                byte_offset: start_byte,
+                byte_length: end_byte - start_byte,
+                url: e.url.clone(),
+                language: e.language.clone(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(kind: &str, offset: usize, length: usize) -> MessageEntity {
+        MessageEntity {
+            kind: kind.to_string(),
+            offset,
+            length,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn converts_utf16_offsets_to_chars_and_bytes() {
+        // 😀 is two UTF-16 units and four bytes; é is one unit and two bytes.
+        let text = "😀 é see https://example.com and `x`";
+        let url_at = text.find("https").unwrap();
+        let entities = resolve(
+            text,
+            &[
+                entity("url", 9, 19),
+                entity("code", 34, 1),
+                entity("bold", 1, 1),
+            ],
+        );
+        assert_eq!(entities.len(), 2, "the entity inside 😀 is dropped");
+        assert_eq!(entities[0].text, "https://example.com");
+        assert_eq!(entities[0].offset, 8);
+        assert_eq!(entities[0].length, 19);
+        assert_eq!(entities[0].byte_offset, url_at);
+        assert_eq!(entities[0].byte_length, 19);
+        assert_eq!(entities[1].text, "x");
+    }
+}
@@ -0,0 +1,141 @@
+use crate::ask::{self, Answer, Button};
+use crate::telegram::TelegramClient;
+use crate::{Args, Outcome, config, tail};
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Most output shown in a failure message, so it (and the edited decision) fits in one
+/// message even if every character takes two UTF-16 units.
+const MAX_OUTPUT_CHARS: usize = 2000;
+
+/// The answers offered for a failed command, in button order.
+const CHOICES: &[Button] = &[
+    Button {
+        name: "retry",
+        label: "🔁 Retry",
+        words: &["retry"],
+    },
+    Button {
+        name: "skip",
+        label: "⏭ Skip",
+        words: &["skip"],
+    },
+    Button {
+        name: "abort",
+        label: "🛑 Abort",
+        words: &["abort"],
+    },
+];
+const RETRY: usize = 0;
+const SKIP: usize = 1;
+
+/// Run `command`; each time it fails, send the end of its output with retry/skip/abort
+/// buttons and act on the answer. Succeeds if the command eventually does or the failure is
+/// skipped; otherwise exits with the command's exit code.
+pub async fn run(
+    args: &Args,
+    config_path: &Path,
+    to: &[String],
+    command: &[String],
+    tail_lines: usize,
+    timeout: Option<Duration>,
+) -> Result<Outcome> {
+    let mut cfg = config::load(config_path)?;
+    if !to.is_empty() {
+        cfg.user_ids = to
+            .iter()
+            .map(|name| cfg.resolve_recipient(name))
+            .collect::<Result<_>>()?;
+    }
+    let client =
+        TelegramClient::for_config(&cfg)?.with_fix_webhook(args.fix_webhook || cfg.fix_webhook);
+    let timeout =
+        timeout.unwrap_or_else(|| Duration::from_secs(cfg.timeout_minutes.saturating_mul(60)));
+
+    for attempt in 1.. {
+        let (code, output) = run_command(command, tail_lines).await?;
+        if code == 0 {
+            return Ok(Outcome::Done);
+        }
+
+        let text = failure_text(command, code, attempt, &output);
+        match ask::ask(&client, &cfg, args, &text, CHOICES, timeout).await? {
+            Answer::Chose(RETRY) => tracing::info!("retrying {}", command[0]),
+            Answer::Chose(SKIP) => return Ok(Outcome::Done),
+            // Abort, cancel or no answer.
+            _ => return Ok(Outcome::Exit(code)),
+        }
+    }
+    unreachable!("attempts are unbounded")
+}
+
+/// Run the command, passing its output through, and return its exit code (`1` if killed by
+/// a signal) with its last `keep` lines of stdout and stderr.
+async fn run_command(command: &[String], keep: usize) -> Result<(i32, Vec<String>)> {
+    let (program, args) = command.split_first().context("no command to run")?;
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("run {program}"))?;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(tail::forward_lines(
+        child.stdout.take().expect("stdout is piped"),
+        tx.clone(),
+        true,
+    ));
+    tokio::spawn(tail::forward_lines(
+        child.stderr.take().expect("stderr is piped"),
+        tx,
+        false,
+    ));
+
+    let mut output = VecDeque::with_capacity(keep);
+    while let Some(line) = rx.recv().await {
+        if output.len() == keep {
+            output.pop_front();
+        }
+        if keep > 0 {
+            output.push_back(line);
+        }
+    }
+    let status = child.wait().await?;
+    Ok((status.code().unwrap_or(1), output.into()))
+}
+
+fn failure_text(command: &[String], code: i32, attempt: u32, output: &[String]) -> String {
+    let mut text = format!("❌ {} exited {code}", command.join(" "));
+    if attempt > 1 {
+        text.push_str(&format!(" (attempt {attempt})"));
+    }
+    if !output.is_empty() {
+        let output = output.join("\n");
+        let skip = output.chars().count().saturating_sub(MAX_OUTPUT_CHARS);
+        text.push_str(if skip > 0 { "\n\n…" } else { "\n\n" });
+        text.extend(output.chars().skip(skip));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failure_text_shows_the_attempt_and_output_tail() {
+        let command = vec!["./nightly.sh".to_string(), "--all".to_string()];
+        assert_eq!(
+            failure_text(&command, 3, 1, &[]),
+            "❌ ./nightly.sh --all exited 3"
+        );
+        assert_eq!(
+            failure_text(&command, 1, 2, &["disk full".to_string()]),
+            "❌ ./nightly.sh --all exited 1 (attempt 2)\n\ndisk full"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use crate::Outcome;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Keys that name an outcome rather than an answer.
+const OUTCOMES: &[&str] = &["timeout", "cancelled", "suppressed", "rejected", "queued"];
+
+/// Exit codes for particular answers and outcomes, from `--exit-map` and `[exit_map]`. Keys are
+/// kept lowercase and trimmed, since answers are matched ignoring case and whitespace.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(try_from = "BTreeMap<String, i32>")]
+pub struct ExitMap(BTreeMap<String, i32>);
+
+impl TryFrom<BTreeMap<String, i32>> for ExitMap {
+    type Error = String;
+
+    fn try_from(raw: BTreeMap<String, i32>) -> Result<Self, String> {
+        let mut map = BTreeMap::new();
+        for (key, code) in raw {
+            let key = key.trim().to_lowercase();
+            if key.is_empty() {
+                return Err("exit map keys must not be empty".to_string());
+            }
+            if !(0..=255).contains(&code) {
+                return Err(format!(
+                    "exit code for {key:?} must be 0 to 255, got {code}"
+                ));
+            }
+            map.insert(key, code);
+        }
+        Ok(Self(map))
+    }
+}
+
+impl ExitMap {
+    /// Parse `--exit-map`, e.g. `yes=0,no=10,timeout=20`.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut raw = BTreeMap::new();
+        for entry in input.split(',').filter(|e| !e.trim().is_empty()) {
+            let (key, code) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected ANSWER=CODE, got {:?}", entry.trim()))?;
+            let code = code
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid exit code {:?} for {:?}", code.trim(), key.trim()))?;
+            raw.insert(key.to_string(), code);
+        }
+        Self::try_from(raw)
+    }
+
+    /// This map with the entries of `overrides` (the command line's) put over it.
+    pub fn merged(mut self, overrides: Option<&ExitMap>) -> Self {
+        if let Some(overrides) = overrides {
+            self.0
+                .extend(overrides.0.iter().map(|(k, &code)| (k.clone(), code)));
+        }
+        self
+    }
+
+    /// The exit code for an answer, ignoring case and surrounding whitespace. Answers that
+    /// happen to spell an outcome key are not looked up.
+    pub fn answer(&self, text: &str) -> Option<i32> {
+        let key = text.trim().to_lowercase();
+        if OUTCOMES.contains(&key.as_str()) {
+            return None;
+        }
+        self.0.get(&key).copied()
+    }
+
+    /// `outcome`, carrying the exit code mapped to it if there is one.
+    pub fn outcome(&self, outcome: Outcome) -> Outcome {
+        let key = match outcome {
+            Outcome::TimedOut => "timeout",
+            Outcome::Cancelled => "cancelled",
+            Outcome::Suppressed => "suppressed",
+            Outcome::Rejected => "rejected",
+            Outcome::Queued => "queued",
+            _ => return outcome,
+        };
+        match self.0.get(key) {
+            Some(&code) => Outcome::Mapped(Box::new(outcome), code),
+            None => outcome,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_answers_and_outcomes() {
+        let map = ExitMap::parse("Yes=0, no = 10,timeout=20").unwrap();
+        assert_eq!(map.answer(" YES\n"), Some(0));
+        assert_eq!(map.answer("no"), Some(10));
+        assert_eq!(map.answer("maybe"), None);
+        assert_eq!(map.answer("timeout"), None);
+        assert!(matches!(
+            map.outcome(Outcome::TimedOut),
+            Outcome::Mapped(_, 20)
+        ));
+        assert!(matches!(
+            map.outcome(Outcome::Cancelled),
+            Outcome::Cancelled
+        ));
+    }
+
+    #[test]
+    fn command_line_entries_override_the_config() {
+        let cfg = ExitMap::parse("yes=1,no=2").unwrap();
+        let map = cfg.merged(Some(&ExitMap::parse("yes=0").unwrap()));
+        assert_eq!(map, ExitMap::parse("yes=0,no=2").unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        assert!(ExitMap::parse("yes").is_err());
+        assert!(ExitMap::parse("yes=y").is_err());
+        assert!(ExitMap::parse("yes=256").is_err());
+        assert!(ExitMap::parse("=1").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::{Context, Result, bail};
+use chrono::Local;
+
+/// Expand the `--expand` placeholders in `text` with this machine's values: `{hostname}`,
+/// `{user}`, `{cwd}`, `{date}` (`YYYY-MM-DD`), `{time}` (`HH:MM`, local) and `{env:NAME}`. `{{`
+/// and `}}` stand for literal braces.
+pub fn expand(text: &str) -> Result<String> {
+    let now = Local::now();
+    render(text, |name| {
+        Ok(match name {
+            "hostname" => hostname().context("could not determine the hostname")?,
+            "user" => std::env::var("USER")
+                .or_else(|_| std::env::var("LOGNAME"))
+                .context("neither USER nor LOGNAME is set")?,
+            "cwd" => std::env::current_dir()
+                .context("read the current directory")?
+                .display()
+                .to_string(),
+            "date" => now.format("%Y-%m-%d").to_string(),
+            "time" => now.format("%H:%M").to_string(),
+            _ => match name.strip_prefix("env:") {
+                Some(var) => std::env::var(var)
+                    .with_context(|| format!("environment variable {var} is not set"))?,
+                None => bail!("unknown placeholder {{{name}}}"),
+            },
+        })
+    })
+}
+
+fn hostname() -> Option<String> {
+    std::fs::read_to_string("/etc/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+}
+
+/// Replace each `{name}` in `text` with `value(name)`.
+fn render(text: &str, value: impl Fn(&str) -> Result<String>) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find(['{', '}']) {
+        out.push_str(&rest[..i]);
+        let brace = &rest[i..i + 1];
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix(brace) {
+            out.push_str(brace);
+            rest = after;
+        } else if brace == "}" {
+            out.push('}');
+        } else {
+            let end = rest
+                .find('}')
+                .with_context(|| format!("unclosed {{ in {:?}", truncate(rest)))?;
+            out.push_str(&value(rest[..end].trim())?);
+            rest = &rest[end + 1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// The start of `text`, for error messages.
+fn truncate(text: &str) -> &str {
+    text.char_indices()
+        .nth(20)
+        .map_or(text, |(i, _)| &text[..i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(name: &str) -> Result<String> {
+        match name {
+            "hostname" => Ok("backup01".to_string()),
+            "env:STAGE" => Ok("prod".to_string()),
+            _ => bail!("unknown placeholder {{{name}}}"),
+        }
+    }
+
+    #[test]
+    fn replaces_placeholders_and_escaped_braces() {
+        assert_eq!(
+            render("{hostname}: deploy to {env:STAGE}?", vars).unwrap(),
+            "backup01: deploy to prod?"
+        );
+        assert_eq!(
+            render("{{\"a\": 1}} } {{hostname}}", vars).unwrap(),
+            "{\"a\": 1} } {hostname}"
+        );
+        assert_eq!(render("no placeholders", vars).unwrap(), "no placeholders");
+    }
+
+    #[test]
+    fn rejects_unknown_and_unclosed_placeholders() {
+        let err = render("{nope}", vars).unwrap_err().to_string();
+        assert!(
+            err.contains("unknown placeholder {nope}"),
+            "error was: {err}"
+        );
+        assert!(render("deploy {hostname", vars).is_err());
+        assert!(expand("{env:TELEPROMPT_SURELY_UNSET_VAR}").is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::telegram::{TelegramClient, Update};
+use crate::{config, lock};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// How long to wait before polling again when only updates some run holds back came back.
+const IDLE: Duration = Duration::from_secs(1);
+
+/// One update stream shared by the correlated runs of a process, such as the questions `serve`
+/// waits on together. Telegram ends a long poll that another one overlaps, so instead of
+/// polling each on its own, the runs read from this stream and one of them polls for all.
+#[derive(Clone, Default)]
+pub struct Feed(Arc<Shared>);
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Woken when a poll ends.
+    polled: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    /// Updates read from Telegram and not yet confirmed, oldest first.
+    updates: Vec<Update>,
+    /// The runs reading, by tag.
+    readers: HashMap<String, Position>,
+    /// Whether a run is polling for all.
+    polling: bool,
+}
+
+/// How far a run has read.
+#[derive(Default, Clone, Copy)]
+struct Position {
+    /// The offset it last asked for. Nothing it may still need is confirmed.
+    offset: i64,
+    /// The last update id it was given.
+    given: i64,
+}
+
+impl std::fmt::Debug for Feed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Feed").finish_non_exhaustive()
+    }
+}
+
+impl Feed {
+    /// Start reading for the run tagged `tag`, until the reader is dropped.
+    pub fn reader(&self, tag: &str) -> Reader {
+        self.state()
+            .readers
+            .insert(tag.to_string(), Position::default());
+        Reader {
+            feed: self.clone(),
+            tag: tag.to_string(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.0.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A run's place in a `Feed`.
+pub struct Reader {
+    feed: Feed,
+    tag: String,
+}
+
+impl Reader {
+    /// The updates from `offset` on, as `getUpdates` would return them: at once if some
+    /// weren't given to this run yet, else once a poll brings new ones or `timeout` passes.
+    /// Whichever run is waiting polls Telegram for all, from the lowest offset any run asked
+    /// for, so each run's updates stay unconfirmed until it has read past them. With `turns`
+    /// (the state dir), the poll takes its turn with other processes' correlated runs.
+    pub async fn get_updates(
+        &self,
+        client: &TelegramClient,
+        cfg: &config::Config,
+        turns: Option<&Path>,
+        offset: i64,
+        timeout: Duration,
+    ) -> Result<Vec<Update>> {
+        let deadline = Instant::now() + timeout;
+        let mut polled = false;
+        loop {
+            let polled_elsewhere = self.feed.0.polled.notified();
+            tokio::pin!(polled_elsewhere);
+            polled_elsewhere.as_mut().enable();
+            let from = {
+                let mut state = self.feed.state();
+                let position = state.readers.entry(self.tag.clone()).or_default();
+                position.offset = offset;
+                let given = position.given;
+                let fresh = state
+                    .updates
+                    .iter()
+                    .any(|u| u.update_id >= offset && u.update_id > given);
+                // Even without time to wait, a run polls once, unless another is at it.
+                let waited = polled || state.polling;
+                if fresh || (waited && Instant::now() >= deadline) {
+                    return Ok(state.give(&self.tag, offset));
+                }
+                if state.polling {
+                    None
+                } else {
+                    state.polling = true;
+                    state.readers.values().map(|p| p.offset).min()
+                }
+            };
+            let Some(from) = from else {
+                let _ = tokio::time::timeout_at(deadline.into(), polled_elsewhere).await;
+                continue;
+            };
+
+            let _polling = Polling(&self.feed);
+            polled = true;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let turn = match turns {
+                Some(dir) => match lock::turn(dir, &cfg.bot_token, remaining).await? {
+                    Some(turn) => Some(turn),
+                    None => continue,
+                },
+                None => None,
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let updates = client.get_updates(from, remaining.as_secs()).await?;
+            drop(turn);
+            let new = {
+                let mut state = self.feed.state();
+                let last = state.updates.last().map_or(i64::MIN, |u| u.update_id);
+                let known = state.updates.len();
+                state
+                    .updates
+                    .extend(updates.into_iter().filter(|u| u.update_id > last));
+                let new = state.updates.len() > known;
+                // Telegram has forgotten those, so no run can be given them again.
+                state.updates.retain(|u| u.update_id >= from);
+                new
+            };
+            if !new {
+                // Held-back updates come back immediately; don't spin on them.
+                tokio::time::sleep(IDLE.min(deadline.saturating_duration_since(Instant::now())))
+                    .await;
+            }
+        }
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.feed.state().readers.remove(&self.tag);
+    }
+}
+
+impl State {
+    /// The updates from `offset` on, noted as given to the run tagged `tag`.
+    fn give(&mut self, tag: &str, offset: i64) -> Vec<Update> {
+        let updates: Vec<Update> = self
+            .updates
+            .iter()
+            .filter(|u| u.update_id >= offset)
+            .cloned()
+            .collect();
+        if let (Some(position), Some(last)) = (self.readers.get_mut(tag), updates.last()) {
+            position.given = position.given.max(last.update_id);
+        }
+        updates
+    }
+}
+
+/// Held while a run polls for all; lets the others take over when it ends, however it ends.
+struct Polling<'a>(&'a Feed);
+
+impl Drop for Polling<'_> {
+    fn drop(&mut self) {
+        self.0.state().polling = false;
+        self.0.0.polled.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_api::MockApi;
+
+    #[tokio::test]
+    async fn runs_share_one_poll_and_keep_each_others_updates() {
+        let api = MockApi::start().await;
+        let cfg = config::load(&api.config("")).unwrap();
+        let client = TelegramClient::for_config(&cfg).unwrap();
+        let feed = Feed::default();
+        let (a, b) = (feed.reader("#tpAAAAAA"), feed.reader("#tpBBBBBB"));
+        let timeout = Duration::from_secs(5);
+
+        let (got_a, got_b, ()) = tokio::join!(
+            a.get_updates(&client, &cfg, None, 0, timeout),
+            b.get_updates(&client, &cfg, None, 0, timeout),
+            async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                api.message(None, "for a");
+            },
+        );
+        let (got_a, got_b) = (got_a.unwrap(), got_b.unwrap());
+        assert_eq!(got_a.len(), 1);
+        assert_eq!(got_b.len(), 1);
+        assert_eq!(api.conflicts(), 0);
+
+        // A reading past the update doesn't confirm it while B hasn't.
+        api.message(None, "for b");
+        let got_a = a
+            .get_updates(&client, &cfg, None, 2, timeout)
+            .await
+            .unwrap();
+        assert_eq!(got_a.len(), 1);
+        assert_eq!(got_a[0].update_id, 2);
+        let got_b = b
+            .get_updates(&client, &cfg, None, 0, timeout)
+            .await
+            .unwrap();
+        assert_eq!(got_b.len(), 2);
+    }
+}
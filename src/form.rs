@@ -0,0 +1,283 @@
+use crate::status::StatusStream;
+use crate::{Args, Outcome, SendArgs, audit, prompt};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::path::Path;
+use std::time::Duration;
+
+/// What a field accepts, and how its answer is written in the JSON object.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// Any reply, as a string (a media reply as the path it was saved to).
+    #[default]
+    Text,
+    /// A whole number.
+    Int,
+    /// Any number, as for `--number`.
+    Number,
+    /// Yes or no, with buttons, as a boolean.
+    YesNo,
+    /// One of `choices`, with buttons, as a string.
+    Choice,
+    /// A JSON value, as for `--json-reply`.
+    Json,
+}
+
+/// One question of a form.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Field {
+    /// Key of the answer in the JSON object.
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub kind: Kind,
+    /// What to ask. Default: the name followed by `?`.
+    #[serde(default)]
+    pub question: Option<String>,
+    /// Bounds for `int` and `number` fields.
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    /// The options of a `choice` field.
+    #[serde(default)]
+    pub choices: Vec<String>,
+}
+
+impl Field {
+    /// Parse `--field NAME[:TYPE[:QUESTION]]`. TYPE is `text` (the default), `int`, `number`,
+    /// `yesno`, `json`, or the options of a choice separated by `|`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.splitn(3, ':').map(str::trim);
+        let name = parts.next().unwrap_or_default().to_string();
+        let kind = parts.next().unwrap_or_default();
+        let question = parts.next().filter(|q| !q.is_empty()).map(str::to_string);
+        let (kind, choices) = match kind {
+            "" | "text" => (Kind::Text, Vec::new()),
+            "int" => (Kind::Int, Vec::new()),
+            "number" => (Kind::Number, Vec::new()),
+            "yesno" => (Kind::YesNo, Vec::new()),
+            "json" => (Kind::Json, Vec::new()),
+            choices if choices.contains('|') => (
+                Kind::Choice,
+                choices.split('|').map(|c| c.trim().to_string()).collect(),
+            ),
+            other => {
+                return Err(format!(
+                    "unknown field type {other:?}: expected text, int, number, yesno, json or \
+                     choices separated by |"
+                ));
+            }
+        };
+        Ok(Field {
+            name,
+            kind,
+            question,
+            min: None,
+            max: None,
+            choices,
+        })
+    }
+
+    fn question(&self) -> String {
+        self.question
+            .clone()
+            .unwrap_or_else(|| format!("{}?", self.name))
+    }
+
+    /// How this field's question is asked and checked, as a prompt run's arguments.
+    fn args(&self, args: &Args, timeout: Option<Duration>) -> Args {
+        let mut field_args = Args {
+            timeout,
+            poll_lock: args.poll_lock,
+            fix_webhook: args.fix_webhook,
+            capture: true,
+            ..Default::default()
+        };
+        match self.kind {
+            Kind::Text => {}
+            Kind::Int => {
+                field_args.number = true;
+                field_args.expect = Some(crate::parse_expect(r"-?\d+").expect("valid pattern"));
+            }
+            Kind::Number => field_args.number = true,
+            Kind::YesNo => field_args.choices = vec!["yes".to_string(), "no".to_string()],
+            Kind::Choice => field_args.choices = self.choices.clone(),
+            Kind::Json => field_args.json_reply = true,
+        }
+        if field_args.number {
+            field_args.min = self.min;
+            field_args.max = self.max;
+        }
+        field_args
+    }
+
+    /// An accepted answer (the written text, or saved media path) as its JSON value.
+    fn value(&self, text: &str) -> Result<serde_json::Value> {
+        Ok(match self.kind {
+            Kind::Text | Kind::Choice => text.into(),
+            Kind::YesNo => (text == "yes").into(),
+            Kind::Int | Kind::Number | Kind::Json => serde_json::from_str(text)
+                .with_context(|| format!("answer to {:?} is not JSON", self.name))?,
+        })
+    }
+}
+
+/// A form definition file: `[[fields]]` tables in TOML, or `{"fields": [...]}` in JSON.
+#[derive(Deserialize)]
+struct FormFile {
+    fields: Vec<Field>,
+}
+
+/// Read a form definition, as JSON if the file name ends in `.json` and TOML otherwise.
+pub fn load(path: &Path) -> Result<Vec<Field>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    let form: FormFile = if path.extension().is_some_and(|e| e == "json") {
+        serde_json::from_str(&text).with_context(|| format!("parse {}", path.display()))?
+    } else {
+        toml::from_str(&text).with_context(|| format!("parse {}", path.display()))?
+    };
+    Ok(form.fields)
+}
+
+/// Ask each field's question in turn, each as an ordinary prompt run that asks again until
+/// the answer is valid, then print one JSON object of the answers. A question that isn't
+/// answered (timeout, cancel) ends the form with its outcome and prints nothing.
+pub async fn run(
+    args: &Args,
+    config_path: &Path,
+    fields: &[Field],
+    to: &[String],
+    timeout: Option<Duration>,
+    status: &mut StatusStream,
+) -> Result<Outcome> {
+    check(fields)?;
+
+    let mut answers = Answers(Vec::new());
+    for (i, field) in fields.iter().enumerate() {
+        let send = SendArgs {
+            message: Some(format!("[{}/{}] {}", i + 1, fields.len(), field.question())),
+            to: to.to_vec(),
+            ..Default::default()
+        };
+        let field_args = field.args(args, timeout);
+        let mut audit = audit::Audit::new(config_path);
+        let res = prompt::run(&field_args, &send, config_path, status, &mut audit, true).await;
+        let reply = audit.entry.replies.last().cloned();
+        audit.finish(&res);
+        let outcome = res?;
+        if !matches!(outcome, Outcome::Replied) {
+            return Ok(outcome);
+        }
+        let reply = reply.context("the question was answered, but no reply was recorded")?;
+        let text = reply
+            .get("file")
+            .or_else(|| reply.get("reply"))
+            .and_then(|t| t.as_str())
+            .unwrap_or_default();
+        answers.0.push((field.name.clone(), field.value(text)?));
+    }
+
+    println!("{}", serde_json::to_string(&answers)?);
+    Ok(Outcome::Done)
+}
+
+/// Reject forms that can't be asked or whose answers would collide.
+fn check(fields: &[Field]) -> Result<()> {
+    anyhow::ensure!(!fields.is_empty(), "the form has no fields");
+    for (i, field) in fields.iter().enumerate() {
+        anyhow::ensure!(!field.name.is_empty(), "field {} has no name", i + 1);
+        anyhow::ensure!(
+            !fields[..i].iter().any(|f| f.name == field.name),
+            "field {:?} is defined twice",
+            field.name
+        );
+        anyhow::ensure!(
+            field.kind != Kind::Choice
+                || (!field.choices.is_empty() && field.choices.iter().all(|c| !c.is_empty())),
+            "choice field {:?} needs non-empty choices",
+            field.name
+        );
+        anyhow::ensure!(
+            matches!(field.kind, Kind::Int | Kind::Number)
+                || (field.min.is_none() && field.max.is_none()),
+            "min and max only apply to int and number fields ({:?})",
+            field.name
+        );
+    }
+    Ok(())
+}
+
+/// The answers as one JSON object, keeping the order the fields were asked in.
+struct Answers(Vec<(String, serde_json::Value)>);
+
+impl Serialize for Answers {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in &self.0 {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_field_specs() {
+        let field = Field::parse("replicas:int:How many replicas?").unwrap();
+        assert_eq!(field.name, "replicas");
+        assert_eq!(field.kind, Kind::Int);
+        assert_eq!(field.question(), "How many replicas?");
+
+        let field = Field::parse("name").unwrap();
+        assert_eq!(field.kind, Kind::Text);
+        assert_eq!(field.question(), "name?");
+
+        let field = Field::parse("env:dev|prod").unwrap();
+        assert_eq!(field.kind, Kind::Choice);
+        assert_eq!(field.choices, ["dev", "prod"]);
+
+        assert!(Field::parse("x:float").is_err());
+    }
+
+    #[test]
+    fn answers_become_typed_values_in_field_order() {
+        let fields = [
+            Field::parse("name").unwrap(),
+            Field::parse("replicas:int").unwrap(),
+            Field::parse("confirm:yesno").unwrap(),
+        ];
+        let answers = Answers(vec![
+            ("name".to_string(), fields[0].value("web").unwrap()),
+            ("replicas".to_string(), fields[1].value("3").unwrap()),
+            ("confirm".to_string(), fields[2].value("no").unwrap()),
+        ]);
+        assert_eq!(
+            serde_json::to_string(&answers).unwrap(),
+            r#"{"name":"web","replicas":3,"confirm":false}"#
+        );
+    }
+
+    #[test]
+    fn loads_toml_definitions_and_rejects_duplicates() {
+        let form: FormFile = toml::from_str(
+            r#"
+[[fields]]
+name = "replicas"
+type = "int"
+min = 1
+
+[[fields]]
+name = "replicas"
+"#,
+        )
+        .unwrap();
+        assert_eq!(form.fields[0].min, Some(1.0));
+        let err = check(&form.fields).unwrap_err().to_string();
+        assert!(err.contains("defined twice"), "error was: {err}");
+    }
+}
@@ -0,0 +1,197 @@
+use crate::approve::{APPROVAL, APPROVE};
+use crate::ask::{self, Answer};
+use crate::telegram::TelegramClient;
+use crate::{Args, Outcome, config};
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+/// Most commits listed per ref.
+const MAX_COMMITS: usize = 10;
+
+/// One ref update from a hook's stdin.
+#[derive(Debug, PartialEq, Eq)]
+struct RefUpdate {
+    name: String,
+    old: String,
+    new: String,
+}
+
+/// Ask for approval of the ref updates a `pre-push` or `pre-receive` hook reads from stdin,
+/// succeeding only if they are approved. `remote` and `url` are pre-push's arguments.
+pub async fn run(
+    args: &Args,
+    config_path: &Path,
+    remote: Option<&str>,
+    url: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<Outcome> {
+    let mut input = String::new();
+    std::io::stdin()
+        .read_to_string(&mut input)
+        .context("read ref updates from stdin")?;
+    let updates = parse_updates(&input)?;
+    if updates.is_empty() {
+        return Ok(Outcome::Done);
+    }
+
+    let cfg = config::load(config_path)?;
+    let client =
+        TelegramClient::for_config(&cfg)?.with_fix_webhook(args.fix_webhook || cfg.fix_webhook);
+    let timeout =
+        timeout.unwrap_or_else(|| Duration::from_secs(cfg.timeout_minutes.saturating_mul(60)));
+    let message = summary(&updates, remote, url);
+
+    Ok(
+        match ask::ask(&client, &cfg, args, &message, APPROVAL, timeout).await? {
+            Answer::Chose(APPROVE) => Outcome::Done,
+            Answer::Chose(_) => Outcome::Rejected,
+            Answer::Cancelled => Outcome::Cancelled,
+            Answer::TimedOut => Outcome::TimedOut,
+        },
+    )
+}
+
+/// Parse pre-push lines (`<local ref> <local sha> <remote ref> <remote sha>`) or pre-receive
+/// lines (`<old sha> <new sha> <ref>`).
+fn parse_updates(input: &str) -> Result<Vec<RefUpdate>> {
+    input
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [_, local_sha, remote_ref, remote_sha] => Ok(RefUpdate {
+                    name: remote_ref.to_string(),
+                    old: remote_sha.to_string(),
+                    new: local_sha.to_string(),
+                }),
+                [old, new, name] => Ok(RefUpdate {
+                    name: name.to_string(),
+                    old: old.to_string(),
+                    new: new.to_string(),
+                }),
+                _ => anyhow::bail!("not a pre-push or pre-receive ref update: {line:?}"),
+            },
+        )
+        .collect()
+}
+
+fn is_zero(sha: &str) -> bool {
+    sha.chars().all(|c| c == '0')
+}
+
+fn short(sha: &str) -> &str {
+    &sha[..sha.len().min(7)]
+}
+
+/// `main`, or `v1.2 (tag)`, for a full ref name.
+fn display_ref(name: &str) -> String {
+    if let Some(branch) = name.strip_prefix("refs/heads/") {
+        branch.to_string()
+    } else if let Some(tag) = name.strip_prefix("refs/tags/") {
+        format!("{tag} (tag)")
+    } else {
+        name.to_string()
+    }
+}
+
+/// Output of a git command, or `None` if it fails (e.g. an object the repo doesn't have).
+fn git(args: &[&str]) -> Option<String> {
+    let out = Command::new("git").args(args).output().ok()?;
+    out.status
+        .success()
+        .then(|| String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+fn summary(updates: &[RefUpdate], remote: Option<&str>, url: Option<&str>) -> String {
+    let repo = std::env::current_dir()
+        .ok()
+        .and_then(|dir| dir.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    let mut text = match (remote, url) {
+        (Some(remote), Some(url)) if remote != url => format!("🔀 Push to {remote} ({url})"),
+        (Some(remote), _) => format!("🔀 Push to {remote}"),
+        _ => "🔀 Push".to_string(),
+    };
+    if !repo.is_empty() {
+        text.push_str(&format!("\nrepo: {repo}"));
+    }
+    for update in updates {
+        text.push_str(&format!("\n\n• {}", describe(update)));
+    }
+    text
+}
+
+/// One ref's line, then its newest commits.
+fn describe(update: &RefUpdate) -> String {
+    let name = display_ref(&update.name);
+    if is_zero(&update.new) {
+        return format!("{name}: delete");
+    }
+    let (head, range) = if is_zero(&update.old) {
+        (
+            format!("{name}: new at {}", short(&update.new)),
+            update.new.clone(),
+        )
+    } else {
+        let mut head = format!("{name}: {}..{}", short(&update.old), short(&update.new));
+        if git(&["merge-base", "--is-ancestor", &update.old, &update.new]).is_none() {
+            head.push_str(" (force)");
+        }
+        if let Some(stat) =
+            git(&["diff", "--shortstat", &update.old, &update.new]).filter(|s| !s.is_empty())
+        {
+            head.push_str(&format!(", {stat}"));
+        }
+        (head, format!("{}..{}", update.old, update.new))
+    };
+    let max = format!("-{}", MAX_COMMITS + 1);
+    let commits = git(&["log", "--oneline", "--no-decorate", &max, &range]).unwrap_or_default();
+    let commits: Vec<&str> = commits.lines().collect();
+    let mut text = head;
+    for commit in commits.iter().take(MAX_COMMITS) {
+        text.push_str(&format!("\n  {commit}"));
+    }
+    if commits.len() > MAX_COMMITS {
+        text.push_str("\n  …");
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pre_push_and_pre_receive_lines() {
+        let zero = "0".repeat(40);
+        let pre_push = format!(
+            "refs/heads/main abc123 refs/heads/main def456\n\n\
+             refs/tags/v1 111 refs/tags/v1 {zero}\n"
+        );
+        let updates = parse_updates(&pre_push).unwrap();
+        assert_eq!(
+            updates[0],
+            RefUpdate {
+                name: "refs/heads/main".to_string(),
+                old: "def456".to_string(),
+                new: "abc123".to_string(),
+            }
+        );
+        assert!(is_zero(&updates[1].old));
+        assert_eq!(display_ref(&updates[1].name), "v1 (tag)");
+
+        let pre_receive = parse_updates("def456 abc123 refs/heads/main\n").unwrap();
+        assert_eq!(pre_receive, updates[..1]);
+        assert!(parse_updates("garbage\n").is_err());
+
+        let deleted = RefUpdate {
+            name: "refs/heads/old".to_string(),
+            old: "abc".to_string(),
+            new: zero,
+        };
+        assert_eq!(describe(&deleted), "old: delete");
+    }
+}
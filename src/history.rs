@@ -0,0 +1,242 @@
+use crate::{OutputFormat, config};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use serde_json::Value;
+use std::path::Path;
+
+/// Longest prompt or reply shown in a table cell.
+const CELL_CHARS: usize = 40;
+
+/// List past runs from the SQLite store or the audit log, oldest first.
+pub fn run(
+    config_path: &Path,
+    last: Option<usize>,
+    since: Option<DateTime<Utc>>,
+    output: OutputFormat,
+) -> Result<()> {
+    let cfg = config::parse(config_path)?;
+    let entries = match cfg.store {
+        #[cfg(feature = "sqlite")]
+        config::StoreKind::Sqlite => {
+            let path = cfg.state_dir()?.join(crate::db::FILE_NAME);
+            crate::db::Db::open(&path)?.history()?
+        }
+        _ => read_audit_log(&cfg)?,
+    };
+    let entries = select(entries, since, last);
+
+    match output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+        OutputFormat::Text if entries.is_empty() => println!("(no history)"),
+        OutputFormat::Text => print!("{}", table(&entries)),
+    }
+    Ok(())
+}
+
+fn read_audit_log(cfg: &config::Config) -> Result<Vec<Value>> {
+    let path = cfg
+        .audit_log
+        .as_ref()
+        .context("history needs `audit_log` (or `store = \"sqlite\"`) set in the config")?;
+    let raw = match std::fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("read audit log: {}", path.display()));
+        }
+    };
+
+    let mut entries = Vec::new();
+    for (i, line) in raw.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        // A torn line (e.g. from a full disk) shouldn't hide the rest of the history.
+        match serde_json::from_str::<Value>(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => tracing::warn!("skipping line {} of {}: {e}", i + 1, path.display()),
+        }
+    }
+    Ok(entries)
+}
+
+/// Parse `--since`: an RFC 3339 time, or a date meaning local midnight.
+pub fn parse_since(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| format!("expected a date like 2024-01-01 or an RFC 3339 time, got {s:?}"))?;
+    Local
+        .from_local_datetime(&date.and_time(chrono::NaiveTime::MIN))
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+        .ok_or_else(|| format!("{s} has no local midnight"))
+}
+
+fn timestamp(entry: &Value) -> Option<DateTime<Utc>> {
+    let ts = entry.get("ts")?.as_str()?;
+    DateTime::parse_from_rfc3339(ts)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// Entries at or after `since`, then only the `last` of those.
+fn select(entries: Vec<Value>, since: Option<DateTime<Utc>>, last: Option<usize>) -> Vec<Value> {
+    let mut entries: Vec<Value> = entries
+        .into_iter()
+        .filter(|e| since.is_none_or(|since| timestamp(e).is_some_and(|t| t >= since)))
+        .collect();
+    if let Some(last) = last {
+        entries.drain(..entries.len().saturating_sub(last));
+    }
+    entries
+}
+
+fn shorten(text: &str) -> String {
+    let text = text.replace(['\r', '\n'], " ");
+    match text.char_indices().nth(CELL_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+/// The prompt cell: its text, or the start of its hash when only that was logged.
+fn prompt_cell(entry: &Value) -> String {
+    if let Some(prompt) = entry.get("prompt").and_then(Value::as_str) {
+        return shorten(prompt);
+    }
+    match entry.get("prompt_sha256").and_then(Value::as_str) {
+        Some(hash) => format!("sha256:{}", &hash[..hash.len().min(12)]),
+        None => String::new(),
+    }
+}
+
+/// The reply cell: each answer's text, saved file or reaction, `[redacted]` under `--secret`.
+fn reply_cell(entry: &Value) -> String {
+    let Some(replies) = entry.get("replies").and_then(Value::as_array) else {
+        return String::new();
+    };
+    let answers: Vec<String> = replies
+        .iter()
+        .map(|r| {
+            if r.get("redacted").and_then(Value::as_bool) == Some(true) {
+                return "[redacted]".to_string();
+            }
+            if let Some(file) = r.get("file").and_then(Value::as_str) {
+                return file.to_string();
+            }
+            r.get("reply")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        })
+        .collect();
+    shorten(&answers.join(" | "))
+}
+
+fn table(entries: &[Value]) -> String {
+    let mut rows = vec![[
+        "TIME".to_string(),
+        "PROFILE".to_string(),
+        "TO".to_string(),
+        "OUTCOME".to_string(),
+        "PROMPT".to_string(),
+        "REPLY".to_string(),
+    ]];
+    for entry in entries {
+        let str_field = |key| {
+            entry
+                .get(key)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        let recipients = entry
+            .get("recipients")
+            .and_then(Value::as_array)
+            .map(|ids| {
+                ids.iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .unwrap_or_default();
+        rows.push([
+            timestamp(entry)
+                .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default(),
+            str_field("profile"),
+            recipients,
+            str_field("outcome"),
+            prompt_cell(entry),
+            reply_cell(entry),
+        ]);
+    }
+
+    let mut widths = [0; 6];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for row in &rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(ts: &str, reply: &str) -> Value {
+        serde_json::json!({
+            "ts": ts,
+            "profile": "config",
+            "recipients": [1],
+            "prompt_sha256": "dd2d251f9addad6de99e5fa153bbe09a8103e37527eac6971e3adede9b49ac1c",
+            "replies": [{ "reply": reply }],
+            "outcome": "replied",
+        })
+    }
+
+    #[test]
+    fn select_filters_by_since_then_keeps_the_last() {
+        let entries = vec![
+            entry("2024-01-01T00:00:00.000Z", "a"),
+            entry("2024-02-01T00:00:00.000Z", "b"),
+            entry("2024-03-01T00:00:00.000Z", "c"),
+        ];
+        let since = parse_since("2024-01-15T00:00:00Z").unwrap();
+
+        let got = select(entries.clone(), Some(since), None);
+        assert_eq!(got, entries[1..]);
+        let got = select(entries.clone(), Some(since), Some(1));
+        assert_eq!(got, entries[2..]);
+        assert_eq!(select(entries.clone(), None, Some(10)), entries);
+
+        assert!(parse_since("2024-01-01").is_ok());
+        assert!(parse_since("last week").is_err());
+    }
+
+    #[test]
+    fn table_shows_hash_prefix_and_redacted_replies() {
+        let mut secret = entry("2024-01-01T00:00:00.000Z", "");
+        secret["replies"][0]["redacted"] = true.into();
+        let out = table(&[entry("2024-01-01T00:00:00.000Z", "yes\nship it"), secret]);
+
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[0].starts_with("TIME"));
+        assert!(lines[1].contains("sha256:dd2d251f9add"));
+        assert!(lines[1].ends_with("yes ship it"));
+        assert!(lines[2].ends_with("[redacted]"));
+    }
+}
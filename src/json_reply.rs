@@ -0,0 +1,84 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Most schema violations listed in a try-again message.
+const MAX_ERRORS: usize = 5;
+
+/// Read and compile a `--schema` file.
+pub fn load_schema(path: &Path) -> Result<jsonschema::Validator> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    let schema: serde_json::Value =
+        serde_json::from_str(&text).with_context(|| format!("parse {}", path.display()))?;
+    jsonschema::validator_for(&schema)
+        .map_err(|e| anyhow::anyhow!("{}: invalid JSON Schema: {e}", path.display()))
+}
+
+/// Parse a `--json-reply` reply and check it against the schema, if any. Returns the JSON
+/// re-serialized compactly, or the try-again message listing what's wrong.
+pub fn check(text: &str, schema: Option<&jsonschema::Validator>) -> Result<String, String> {
+    let value = parse(text.trim())
+        .map_err(|e| format!("⚠️ That's not valid JSON ({e}). Please reply with a JSON value."))?;
+    if let Some(schema) = schema {
+        let errors: Vec<String> = schema
+            .iter_errors(&value)
+            .map(|e| match e.instance_path().to_string() {
+                path if path.is_empty() => format!("• {e}"),
+                path => format!("• {path}: {e}"),
+            })
+            .collect();
+        if !errors.is_empty() {
+            let mut text = "⚠️ That JSON doesn't match the schema:".to_string();
+            for error in errors.iter().take(MAX_ERRORS) {
+                text.push('\n');
+                text.push_str(error);
+            }
+            if errors.len() > MAX_ERRORS {
+                text.push_str(&format!("\n…and {} more", errors.len() - MAX_ERRORS));
+            }
+            return Err(text);
+        }
+    }
+    Ok(value.to_string())
+}
+
+/// Parse JSON, retrying with curly double quotes made straight: phone keyboards often
+/// "smarten" the quotes people type.
+fn parse(text: &str) -> serde_json::Result<serde_json::Value> {
+    serde_json::from_str(text).or_else(|e| {
+        let straight = text.replace(['“', '”'], "\"");
+        serde_json::from_str(&straight).map_err(|_| e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_are_parsed_compacted_and_validated() {
+        assert_eq!(
+            check(" { \"n\": 1,\n \"ok\": true } ", None).as_deref(),
+            Ok(r#"{"n":1,"ok":true}"#)
+        );
+        assert_eq!(check("{“n”: 2}", None).as_deref(), Ok(r#"{"n":2}"#));
+        assert!(
+            check("{n: 1}", None)
+                .unwrap_err()
+                .starts_with("⚠️ That's not valid JSON (")
+        );
+
+        let schema = jsonschema::validator_for(&serde_json::json!({
+            "type": "object",
+            "properties": { "n": { "type": "integer" } },
+            "required": ["n"],
+        }))
+        .unwrap();
+        assert_eq!(
+            check(r#"{"n": 3}"#, Some(&schema)).as_deref(),
+            Ok(r#"{"n":3}"#)
+        );
+        let invalid = check(r#"{"n": "three"}"#, Some(&schema)).unwrap_err();
+        assert!(invalid.starts_with("⚠️ That JSON doesn't match the schema:\n• /n: "));
+        assert_eq!(invalid.lines().count(), 2);
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fs::{File, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often a waiting run checks whether the lock was released.
+const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The same for a correlated run waiting for its turn to poll, which only takes a moment.
+const TURN_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a `serve --standby` checks whether the serving instance is gone.
+const STANDBY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What to do when another run is already polling the same bot.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockMode {
+    /// Wait for the other run to finish.
+    #[default]
+    Wait,
+    /// Fail immediately.
+    Fail,
+    /// Don't lock; runs may take each other's updates.
+    Off,
+}
+
+/// Held while this run polls a bot; released when dropped (or when the process dies).
+#[derive(Debug)]
+pub struct PollLock {
+    _file: File,
+}
+
+/// Held while this process serves a bot's HTTP API; released when dropped (or when the
+/// process dies).
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+/// Take the advisory lock for polling the bot whose token is `token`, waiting at most
+/// `patience` in `Wait` mode. Returns `None` in `Off` mode.
+pub async fn acquire(
+    state_dir: &Path,
+    token: &str,
+    mode: LockMode,
+    patience: Duration,
+) -> Result<Option<PollLock>> {
+    if mode == LockMode::Off {
+        return Ok(None);
+    }
+    let (path, mut file) = open(state_dir, "poll", token)?;
+    let start = Instant::now();
+    let mut announced = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("lock {}", path.display()));
+            }
+        }
+        let holder = std::fs::read_to_string(&path).unwrap_or_default();
+        let holder = match holder.trim() {
+            "" => "another teleprompt run".to_string(),
+            pid => format!("another teleprompt run (pid {pid})"),
+        };
+        anyhow::ensure!(
+            mode == LockMode::Wait,
+            "{holder} is polling this bot; refusing to take its updates (poll_lock = \"fail\")"
+        );
+        anyhow::ensure!(
+            start.elapsed() < patience,
+            "timed out waiting for {holder} to stop polling this bot"
+        );
+        if !announced {
+            tracing::info!("{holder} is polling this bot; waiting for it to finish...");
+            announced = true;
+        }
+        tokio::time::sleep(RETRY_INTERVAL).await;
+    }
+
+    // Best effort: the pid only makes the messages above more helpful.
+    let _ = file.set_len(0);
+    let _ = write!(file, "{}", std::process::id());
+    Ok(Some(PollLock { _file: file }))
+}
+
+/// Take the polling lock for a single `getUpdates` request. Correlated runs share a bot, but
+/// Telegram answers overlapping long polls with a 409, so they take turns. Waits at most
+/// `patience`, returning `None` if the lock wasn't free by then.
+pub async fn turn(state_dir: &Path, token: &str, patience: Duration) -> Result<Option<PollLock>> {
+    let (path, file) = open(state_dir, "poll", token)?;
+    let start = Instant::now();
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(Some(PollLock { _file: file })),
+            Err(TryLockError::WouldBlock) if start.elapsed() < patience => {
+                tokio::time::sleep(TURN_RETRY_INTERVAL.min(patience - start.elapsed())).await;
+            }
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("lock {}", path.display()));
+            }
+        }
+    }
+}
+
+/// Take the lock of the `serve --http` instance for the bot whose token is `token`. If another
+/// instance holds it, fail, or as a `standby`, wait until that instance is gone.
+pub async fn instance(state_dir: &Path, token: &str, standby: bool) -> Result<InstanceLock> {
+    let (path, mut file) = open(state_dir, "serve", token)?;
+    let mut announced = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("lock {}", path.display()));
+            }
+        }
+        let holder = std::fs::read_to_string(&path).unwrap_or_default();
+        let holder = match holder.trim() {
+            "" => "another teleprompt serve".to_string(),
+            pid => format!("another teleprompt serve (pid {pid})"),
+        };
+        anyhow::ensure!(
+            standby,
+            "{holder} is serving this bot; start this one with --standby to take over when it stops"
+        );
+        if !announced {
+            tracing::info!("{holder} is serving this bot; standing by to take over...");
+            announced = true;
+        }
+        tokio::time::sleep(STANDBY_INTERVAL).await;
+    }
+
+    // Best effort: the pid only makes the messages above more helpful.
+    let _ = file.set_len(0);
+    let _ = write!(file, "{}", std::process::id());
+    Ok(InstanceLock { _file: file })
+}
+
+fn open(state_dir: &Path, kind: &str, token: &str) -> Result<(PathBuf, File)> {
+    std::fs::create_dir_all(state_dir)
+        .with_context(|| format!("create state dir: {}", state_dir.display()))?;
+    let bot_id = token.split(':').next().unwrap_or_default();
+    let path = state_dir.join(format!("{kind}-{bot_id}.lock"));
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("open lock file: {}", path.display()))?;
+    Ok((path, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn second_poller_fails_or_times_out_until_the_first_is_done() {
+        let dir = std::env::temp_dir().join(format!(
+            "teleprompt_lock_test_{}_{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let first = acquire(&dir, "1:x", LockMode::Wait, Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(first.is_some());
+
+        let err = acquire(&dir, "1:x", LockMode::Fail, Duration::ZERO)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains(&std::process::id().to_string()));
+        assert!(
+            acquire(&dir, "1:x", LockMode::Wait, Duration::ZERO)
+                .await
+                .is_err()
+        );
+        // Other bots and unlocked runs are unaffected.
+        assert!(
+            acquire(&dir, "2:x", LockMode::Fail, Duration::ZERO)
+                .await
+                .is_ok()
+        );
+        assert!(
+            acquire(&dir, "1:x", LockMode::Off, Duration::ZERO)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        drop(first);
+        assert!(
+            acquire(&dir, "1:x", LockMode::Fail, Duration::ZERO)
+                .await
+                .is_ok()
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn a_standby_takes_over_once_the_instance_is_gone() {
+        let dir = std::env::temp_dir().join(format!(
+            "teleprompt_instance_test_{}_{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let primary = instance(&dir, "1:x", false).await.unwrap();
+        let err = instance(&dir, "1:x", false).await.unwrap_err();
+        assert!(err.to_string().contains("--standby"), "error was: {err}");
+
+        let start = Instant::now();
+        let stop = async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            drop(primary);
+        };
+        let (standby, ()) = tokio::join!(instance(&dir, "1:x", true), stop);
+        assert!(standby.is_ok());
+        assert!(start.elapsed() < Duration::from_secs(3));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,45 +1,930 @@
+mod approve;
+mod ask;
+mod audit;
+mod backoff;
+mod batch;
+mod burst;
+mod chat;
+mod choices;
+mod commands;
 mod config;
+mod confirm;
+mod conversation;
+mod correlate;
+#[cfg(feature = "sqlite")]
+mod db;
+mod dedupe;
+/// Stand-in for builds without SQLite, so `teleprompt db` says what is missing.
+#[cfg(not(feature = "sqlite"))]
+mod db {
+    pub fn run(_config_path: &std::path::Path, _action: &crate::DbAction) -> anyhow::Result<()> {
+        anyhow::bail!("`teleprompt db` needs teleprompt built with the `sqlite` feature")
+    }
+}
+mod deadline;
+mod delete;
+mod doctor;
+mod duration;
+mod edit;
+mod entities;
+mod exec;
+mod exit_map;
+mod expand;
+mod feed;
+mod form;
+mod git_gate;
+mod history;
+mod json_reply;
+mod jsonrpc;
+mod lock;
+mod logging;
+mod mcp;
+#[cfg(test)]
+mod mock_api;
+mod number;
+mod offset;
+mod pending;
+mod poll;
+mod prompt;
+mod quorum;
+mod ratelimit;
+mod reaction;
+mod schedule;
+mod serve;
+mod setup;
+mod shell;
+mod sign;
+mod spool;
+mod ssh_notify;
+mod state;
+mod status;
+mod tail;
 mod telegram;
+mod template;
+mod translate;
 
-use clap::Parser;
+use anyhow::Context;
+use clap::{Parser, Subcommand, ValueEnum};
 use std::io::{IsTerminal, Read, Write};
 use std::path::PathBuf;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Default)]
 #[command(
     name = "teleprompt",
     version,
     about = "Telegram prompt/response relay CLI"
 )]
 struct Args {
-    /// Message text to send. If omitted, the message is read from stdin.
+    #[command(flatten)]
+    send: SendArgs,
+
+    /// Send the message and exit immediately, printing its message_id (same as `notify`).
     #[arg(long)]
-    message: Option<String>,
+    no_wait: bool,
 
-    /// Write the reply to this file (overwrite). If omitted, reply is written to stdout.
+    /// Write the reply to this file (replaced atomically). If omitted, reply is written to
+    /// stdout.
     #[arg(long)]
     out_file: Option<PathBuf>,
 
+    /// Also write the reply to stdout when writing it to --out-file.
+    #[arg(long, requires = "out_file")]
+    tee: bool,
+
+    /// Permissions for --out-file and saved media, in octal (e.g. `0600`), also used for the
+    /// directories created for them (plus `x` where readable). Default: `0600` under --secret.
+    #[arg(long, value_name = "MODE", value_parser = parse_out_mode)]
+    out_mode: Option<u32>,
+
+    /// Append the reply to --out-file as a line instead of replacing the file, to accumulate
+    /// replies across runs.
+    #[arg(long, requires = "out_file")]
+    append: bool,
+
+    /// Save photo/document/voice/video replies into this directory (default: the current
+    /// directory). With --out-file, media is written to that file instead.
+    #[arg(long, value_name = "DIR", conflicts_with = "out_file")]
+    media_dir: Option<PathBuf>,
+
+    /// Send the prompt with a force-reply prompt and only accept answers sent as a reply to it.
+    /// Other messages are offered back with a button to confirm them as the answer.
+    #[arg(long, conflicts_with = "no_wait")]
+    require_reply: bool,
+
+    /// Tag the prompt and only accept a reply to it, leaving replies to other teleprompt
+    /// processes' prompts unconfirmed so concurrent runs on one bot don't steal each other's
+    /// answers. Also enabled by `correlate` in the config.
+    #[arg(long, conflicts_with = "no_wait")]
+    correlate: bool,
+
+    /// Attach approve/reject buttons and wait until this many recipients approve. Prints the
+    /// tally; exits 5 if too many reject for the quorum to be reached.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["no_wait", "require_reply", "correlate"],
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    quorum: Option<u32>,
+
+    /// Gather every reply received during this window instead of stopping at the first, e.g.
+    /// `10m`. Exits 2 if nobody replied.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse,
+        conflicts_with_all = ["no_wait", "timeout", "quorum"]
+    )]
+    collect_for: Option<Duration>,
+
+    /// Output format: the bare reply, or a JSON envelope with the reply and its metadata (a
+    /// JSON array of them under --collect-for).
+    #[arg(long, value_enum, default_value_t)]
+    output: OutputFormat,
+
+    /// Shape the output with a template instead, e.g. `{text}\t{username}\t{elapsed_s}`.
+    /// Placeholders are the --output json fields plus `text`, `user_id`, `username`,
+    /// `first_name` and `elapsed_s`.
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "output")]
+    format: Option<String>,
+
+    /// Offer these answers as buttons, e.g. `yes,no,defer`. Typed replies must name one (any
+    /// case, or a unique prefix) or are answered with a request to try again; the chosen
+    /// option is written as given here.
+    #[arg(
+        long,
+        value_name = "CHOICE,...",
+        value_delimiter = ',',
+        conflicts_with_all = ["no_wait", "quorum", "expect", "number", "json_reply"]
+    )]
+    choices: Vec<String>,
+
+    /// Only accept replies matching this regex in full, e.g. `yes|no` or `\d{6}`. Anything else
+    /// is answered with a request to try again, and the wait continues.
+    #[arg(
+        long,
+        value_name = "REGEX",
+        value_parser = parse_expect,
+        conflicts_with_all = ["no_wait", "quorum"]
+    )]
+    expect: Option<regex::Regex>,
+
+    /// Only accept a number (integer or decimal) as the reply, asking again otherwise, and
+    /// write it normalized: `3.0` and ` 3 ` are written as `3`.
+    #[arg(long, conflicts_with_all = ["no_wait", "quorum", "json_reply"])]
+    number: bool,
+
+    /// With --number, the smallest number accepted.
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "number",
+        allow_negative_numbers = true
+    )]
+    min: Option<f64>,
+
+    /// With --number, the largest number accepted.
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "number",
+        allow_negative_numbers = true
+    )]
+    max: Option<f64>,
+
+    /// Only accept JSON as the reply, asking again (with the parse error) otherwise, and write
+    /// it compacted. For agents and scripts that parse the answer.
+    #[arg(long, conflicts_with_all = ["no_wait", "quorum"])]
+    json_reply: bool,
+
+    /// With --json-reply, a JSON Schema file the reply must also validate against; the
+    /// try-again message lists what doesn't.
+    #[arg(long, value_name = "PATH", requires = "json_reply")]
+    schema: Option<PathBuf>,
+
+    /// Exit with these codes for particular answers (any case) and for the outcomes `timeout`,
+    /// `cancelled`, `suppressed`, `rejected` and `queued`, e.g. `yes=0,no=10,timeout=20`. Overrides
+    /// `[exit_map]` entries.
+    #[arg(
+        long,
+        value_name = "KEY=CODE,...",
+        value_parser = exit_map::ExitMap::parse,
+        conflicts_with_all = ["turns", "until"]
+    )]
+    exit_map: Option<exit_map::ExitMap>,
+
+    /// Only accept a shared location as the answer, written as JSON with its latitude and
+    /// longitude. Other replies are asked to share a location instead.
+    #[arg(
+        long,
+        conflicts_with_all = ["no_wait", "quorum", "choices", "expect", "number", "json_reply"]
+    )]
+    expect_location: bool,
+
+    /// Only accept a shared contact as the answer, written as JSON with its phone number,
+    /// name and Telegram user id. Other replies are asked to share a contact instead.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "no_wait",
+            "quorum",
+            "choices",
+            "expect",
+            "number",
+            "json_reply",
+            "expect_location"
+        ]
+    )]
+    expect_contact: bool,
+
+    /// Answer with the caption of a captioned photo, document, voice or video reply, as if it
+    /// were sent as text. The media itself is only saved when --media-dir is given.
+    #[arg(long, conflicts_with = "quorum")]
+    caption_as_reply: bool,
+
+    /// Take an edit of a text answer made within this long of sending it as the answer
+    /// instead. Waits this long after the answer for edits before writing it.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse,
+        conflicts_with_all = ["no_wait", "quorum", "correlate"]
+    )]
+    accept_edits: Option<Duration>,
+
+    /// For credentials and OTPs: implies --ephemeral --delete-reply, sends the prompt with
+    /// protect_content, keeps the answer out of status events and translation, and writes
+    /// --out-file/media readable by the current user only.
+    #[arg(long, conflicts_with = "no_wait")]
+    secret: bool,
+
+    /// Delete the prompt (and reminders) from the chat once it is answered or times out.
+    #[arg(long, conflicts_with = "no_wait")]
+    ephemeral: bool,
+
+    /// With --ephemeral, delete the recipient's answer message too.
+    #[arg(long, requires = "ephemeral")]
+    delete_reply: bool,
+
+    /// Pin the prompt in the chat until it is answered or times out, so it stays visible in
+    /// busy groups.
+    #[arg(long, conflicts_with = "no_wait")]
+    pin: bool,
+
+    /// If nobody has answered after this long, re-send the prompt to the --escalate-to
+    /// recipients and accept an answer from anyone, e.g. `15m`.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse,
+        requires = "escalate_to",
+        conflicts_with_all = ["no_wait", "quorum", "collect_for"]
+    )]
+    escalate_after: Option<Duration>,
+
+    /// Fallback recipient for --escalate-after (repeatable): a name from `[recipients]` or a
+    /// numeric user id.
+    #[arg(long, value_name = "NAME|USER_ID", requires = "escalate_after")]
+    escalate_to: Vec<String>,
+
+    /// Send a "still waiting" reminder, as a reply to the prompt, at this interval until an
+    /// answer arrives, e.g. `10m`.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse,
+        conflicts_with = "no_wait"
+    )]
+    remind_every: Option<Duration>,
+
+    /// Append a "⏳ expires in 12m" footer to the prompt and keep it updated by editing the
+    /// message; it reads "⌛ expired" after a timeout.
+    #[arg(long, conflicts_with = "no_wait")]
+    show_deadline: bool,
+
+    /// If an identical prompt (same text and recipients) was sent within this window, e.g.
+    /// `10m`, don't send it again: reuse its answer, or wait for the one it gets.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse,
+        conflicts_with_all = ["no_wait", "collect_for", "quorum", "secret", "turns", "until"]
+    )]
+    dedupe_window: Option<Duration>,
+
+    /// Hold a conversation of up to N exchanges: after each answer, the next line of stdin is
+    /// sent as the next prompt (the first prompt is --message, or stdin's first line).
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["no_wait", "out_file", "collect_for", "quorum"]
+    )]
+    turns: Option<u32>,
+
+    /// Like --turns, but carry on until an answer is exactly this text (e.g. `/done`, any
+    /// case) or stdin runs out. Combine with --turns to also cap the number of exchanges.
+    #[arg(
+        long,
+        value_name = "TEXT",
+        conflicts_with_all = ["no_wait", "out_file", "collect_for", "quorum", "secret"]
+    )]
+    until: Option<String>,
+
+    /// How long to wait for a reply, e.g. `90s`, `5m`, `1h30m`. Overrides `timeout_minutes`.
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    timeout: Option<Duration>,
+
     /// Config file path. If omitted, defaults to the platform config path (see --print-config-path).
-    #[arg(long)]
+    #[arg(long, global = true)]
     config: Option<PathBuf>,
 
+    /// If a webhook registered for the bot blocks polling (409 Conflict), delete it and carry
+    /// on. Also enabled by `fix_webhook` in the config.
+    #[arg(long)]
+    fix_webhook: bool,
+
+    /// When another run is already polling the same bot: wait for it, fail, or poll anyway
+    /// (`off`). Overrides `poll_lock`.
+    #[arg(long, value_enum, value_name = "MODE")]
+    poll_lock: Option<lock::LockMode>,
+
+    /// Approve a login as a pam_exec module: ask with approve/deny buttons, using PAM_USER,
+    /// PAM_RHOST etc. from the environment, for 60s (or --timeout). Exits 0 only on approval
+    /// and writes nothing to stdout.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "no_wait",
+            "collect_for",
+            "quorum",
+            "turns",
+            "until",
+            "message",
+            "message_file",
+            "exit_map"
+        ]
+    )]
+    pam: bool,
+
     /// Print the resolved config path and exit.
     #[arg(long)]
     print_config_path: bool,
+
+    /// Write machine-readable NDJSON status events to this already-open file descriptor (Unix).
+    #[arg(long, global = true, value_name = "FD", conflicts_with = "status_file")]
+    status_fd: Option<i32>,
+
+    /// Append machine-readable NDJSON status events to this file.
+    #[arg(long, global = true, value_name = "PATH")]
+    status_file: Option<PathBuf>,
+
+    /// Log verbosity on stderr. Default: `info`, or `warn` with --progress ndjson. `debug`
+    /// traces Telegram requests, with message text redacted and the bot token never logged.
+    #[arg(long, global = true, value_enum, value_name = "LEVEL")]
+    log_level: Option<logging::LogLevel>,
+
+    /// Log line format on stderr.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    log_format: logging::LogFormat,
+
+    /// How progress is reported on stderr: human-readable text, or the NDJSON status events.
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t,
+        conflicts_with_all = ["status_fd", "status_file"]
+    )]
+    progress: Progress,
+
+    /// Set by `form` and `mcp`: answers are recorded in the audit entry for them to read back
+    /// instead of being written, notifications' message ids aren't printed, and the exit map
+    /// is left to the caller.
+    #[arg(skip)]
+    capture: bool,
+
+    /// Set by `serve`: correlated runs read their updates from this stream shared by the
+    /// process instead of each polling on its own.
+    #[arg(skip)]
+    feed: Option<feed::Feed>,
+
+    /// Set by `serve`: the config it loaded once, used instead of loading the config file
+    /// again for every run.
+    #[arg(skip)]
+    loaded_config: Option<config::Config>,
+
+    /// Set by `serve`: the question's id, recorded with its pending prompts so a standby
+    /// taking over can answer for it.
+    #[arg(skip)]
+    question_id: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// How to compose and deliver a message; shared by the default prompt mode and `notify`.
+#[derive(clap::Args, Clone, Debug, Default)]
+struct SendArgs {
+    /// Message text to send. If omitted, the message is read from stdin.
+    #[arg(long)]
+    message: Option<String>,
+
+    /// Read the message text from this file instead of --message or stdin, leaving stdin free.
+    #[arg(long, value_name = "PATH", conflicts_with = "message")]
+    message_file: Option<PathBuf>,
+
+    /// Send to this recipient instead of the configured `user_id` (repeatable): a name from
+    /// `[recipients]` or a numeric user id. With several, the first reply from any wins.
+    #[arg(long, value_name = "NAME|USER_ID")]
+    to: Vec<String>,
+
+    /// Post in this forum topic (message_thread_id) and only accept replies from it. Overrides
+    /// `thread_id`.
+    #[arg(long, value_name = "ID")]
+    thread_id: Option<i64>,
+
+    /// Upload a file along with the message via sendDocument (repeatable). The message text
+    /// becomes the caption of the first attachment.
+    #[arg(long, value_name = "PATH")]
+    attach: Vec<PathBuf>,
+
+    /// Upload an image via sendPhoto so it renders inline in the chat (repeatable). Photos are
+    /// sent before --attach files.
+    #[arg(long, value_name = "PATH")]
+    photo: Vec<PathBuf>,
+
+    /// Caption for the first photo/attachment. Defaults to the message text; when both are
+    /// given, the message is sent as a separate text message first.
+    #[arg(long, value_name = "TEXT")]
+    caption: Option<String>,
+
+    /// Identifies the calling script for burst protection (repeated identical prompts from the
+    /// same caller are collapsed into one summary message).
+    #[arg(long, value_name = "NAME", default_value = "")]
+    caller: String,
+
+    /// Upload prompts longer than `long_as_file_threshold` as a .txt document with a short
+    /// summary instead of splitting them into many messages. Also enabled by `long_as_file`.
+    #[arg(long)]
+    long_as_file: bool,
+
+    /// How Telegram renders markup in the message and caption. Use `teleprompt escape` to
+    /// make interpolated text literal.
+    #[arg(long, value_enum, default_value_t)]
+    parse_mode: telegram::ParseMode,
+
+    /// Wrap the message in a monospace code block, optionally with a language for
+    /// highlighting (e.g. `--code diff`). Escaping is handled internally.
+    #[arg(long, value_name = "LANG", num_args = 0..=1, default_missing_value = "")]
+    code: Option<String>,
+
+    /// Send to the configured `canary_user_id` instead of the real recipient, labeled as a
+    /// canary, to try out templates and workflows end-to-end.
+    #[arg(long)]
+    canary: bool,
+
+    /// Upload stdin as a document (named NAME, default `stdin.txt`) instead of reading the
+    /// message from it; the message comes from --message or --message-file, if at all.
+    #[arg(
+        long,
+        value_name = "attachment[:NAME]",
+        value_parser = parse_stdin_as,
+        conflicts_with_all = ["turns", "until"]
+    )]
+    stdin_as: Option<String>,
+
+    /// Hold the message and send it at this time, then start the reply window: e.g.
+    /// `2024-07-01T09:00` (local time, or RFC 3339 with an offset) or `09:00` (the next one).
+    #[arg(
+        long,
+        value_name = "TIME",
+        value_parser = schedule::parse_send_at,
+        conflicts_with = "delay"
+    )]
+    send_at: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Hold the message this long before sending it, e.g. `2h`.
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    delay: Option<Duration>,
+
+    /// With notify or --no-wait, if Telegram can't be reached, put the message in the spool
+    /// for `teleprompt flush` and exit 6 instead of failing. Also enabled by `queue_offline`.
+    #[arg(long)]
+    queue_offline: bool,
+
+    /// Write the sent message_id (one line per recipient) to this file as soon as the message
+    /// is sent; with notify or --no-wait, instead of printing it.
+    #[arg(long, value_name = "PATH")]
+    id_file: Option<PathBuf>,
+
+    /// Expand placeholders in the message and caption before sending: `{hostname}`, `{user}`,
+    /// `{cwd}`, `{date}`, `{time}` and `{env:NAME}`; `{{` and `}}` are literal braces.
+    #[arg(long)]
+    expand: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Interactively create a config: enter the bot token, then message the bot to capture your
+    /// user id.
+    Setup,
+    /// Check the config, bot token, ability to message the user, and webhook status.
+    Doctor,
+    /// Send a message without waiting for a reply; prints the sent message_id.
+    Notify {
+        #[command(flatten)]
+        send: SendArgs,
+    },
+    /// Replace the text (or caption) of a message sent earlier, e.g. by `notify`, to update a
+    /// status in place.
+    Edit {
+        /// The message to edit, as printed by `notify`.
+        #[arg(long, value_name = "ID")]
+        message_id: i64,
+        /// The new text. If omitted, it is read from stdin.
+        #[arg(long)]
+        message: Option<String>,
+        /// The chat the message is in, if not the configured `user_id`.
+        #[arg(long, value_name = "NAME|USER_ID")]
+        to: Option<String>,
+        #[arg(long, value_enum, default_value_t)]
+        parse_mode: telegram::ParseMode,
+    },
+    /// Delete messages sent earlier, e.g. a temporary notification from `notify`.
+    Delete {
+        /// The message to delete, as printed by `notify` (repeatable).
+        #[arg(long = "message-id", value_name = "ID", required = true)]
+        message_ids: Vec<i64>,
+        /// The chat the messages are in, if not the configured `user_id`.
+        #[arg(long, value_name = "NAME|USER_ID")]
+        to: Option<String>,
+    },
+    /// Wait for the answer to a prompt an earlier run sent, e.g. with `--no-wait --id-file`.
+    /// Only replies to that message count; prompt flags such as --timeout, --choices or
+    /// --out-file go before `resume`.
+    Resume {
+        /// The prompt's message id.
+        #[arg(long, value_name = "ID")]
+        prompt_id: i64,
+        /// The chat the prompt is in, if not the configured `user_id`.
+        #[arg(long, value_name = "NAME|USER_ID")]
+        to: Option<String>,
+    },
+    /// Inspect or clear persisted state (burst history, mutes).
+    State {
+        #[command(subcommand)]
+        action: StateAction,
+    },
+    /// Manage the bot's command menu.
+    Commands {
+        #[command(subcommand)]
+        action: CommandsAction,
+    },
+    /// Check a 🔏 code from a prompt footer against `signing_secret`.
+    Verify {
+        /// The code shown in the prompt footer, e.g. `01J...-a1b2c3d4e5`.
+        code: String,
+    },
+    /// Escape text (argument or stdin) so it shows literally under a parse mode, for
+    /// interpolating untrusted content into --parse-mode prompts.
+    Escape {
+        #[arg(long, value_enum, default_value_t = telegram::ParseMode::MarkdownV2)]
+        parse_mode: telegram::ParseMode,
+        /// Text to escape. If omitted, stdin is escaped.
+        text: Option<String>,
+    },
+    /// Chat live from the terminal: each line typed is sent, and messages from the recipients
+    /// are printed as they arrive, until EOF (Ctrl-D).
+    Chat {
+        /// Chat with this recipient instead of the configured `user_id` (repeatable).
+        #[arg(long, value_name = "NAME|USER_ID")]
+        to: Vec<String>,
+    },
+    /// Pipe a program to the chat: each stdin line is sent as a message, and each message
+    /// from the recipients is written to stdout as a line, until stdin closes.
+    Bridge {
+        /// Bridge to this recipient instead of the configured `user_id` (repeatable).
+        #[arg(long, value_name = "NAME|USER_ID")]
+        to: Vec<String>,
+    },
+    /// Stream a command's output (or a growing file) to the chat, e.g.
+    /// `teleprompt tail -- ./long_job.sh`. Exits with the command's exit code.
+    Tail {
+        /// Follow this file as it grows, like `tail -f`, instead of running a command.
+        #[arg(long, value_name = "PATH", conflicts_with = "command")]
+        follow: Option<PathBuf>,
+        /// How often new lines are forwarded, e.g. `10s`.
+        #[arg(long, value_name = "DURATION", default_value = "5s", value_parser = duration::parse)]
+        every: Duration,
+        /// Send to this recipient instead of the configured `user_id` (repeatable).
+        #[arg(long, value_name = "NAME|USER_ID")]
+        to: Vec<String>,
+        /// The command to run and its arguments, after `--`.
+        #[arg(last = true, required_unless_present = "follow")]
+        command: Vec<String>,
+    },
+    /// Run a command and, each time it fails, send the end of its output and ask whether to
+    /// retry, skip or abort, e.g. `teleprompt exec -- ./nightly.sh`.
+    Exec {
+        /// How many of the last output lines to include in the failure message.
+        #[arg(long, value_name = "N", default_value_t = 20)]
+        tail_lines: usize,
+        /// How long to wait for an answer, e.g. `30m`. Overrides `timeout_minutes`.
+        #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+        timeout: Option<Duration>,
+        /// Ask this recipient instead of the configured `user_id` (repeatable).
+        #[arg(long, value_name = "NAME|USER_ID")]
+        to: Vec<String>,
+        /// The command to run and its arguments, after `--`.
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Run a command only once someone approves, e.g.
+    /// `teleprompt approve --message "Deploy v1.2 to prod?" -- ./deploy.sh`.
+    Approve {
+        /// The question to ask.
+        #[arg(long)]
+        message: String,
+        /// How long to wait for an answer, e.g. `30m`. Overrides `timeout_minutes`.
+        #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+        timeout: Option<Duration>,
+        /// Ask this recipient instead of the configured `user_id` (repeatable).
+        #[arg(long, value_name = "NAME|USER_ID")]
+        to: Vec<String>,
+        /// The command to run and its arguments, after `--`.
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Send a "new login" alert for the current SSH session (from SSH_CONNECTION, or
+    /// PAM_USER/PAM_RHOST under pam_exec), e.g. from `/etc/ssh/sshrc`.
+    SshNotify {
+        /// Attach a "kill session" button and wait for it; pressing it hangs up the process
+        /// that ran teleprompt. Run it in the background so the login isn't held up.
+        #[arg(long)]
+        kill_button: bool,
+        /// How long to wait for a --kill-button press.
+        #[arg(
+            long,
+            value_name = "DURATION",
+            default_value = "5m",
+            value_parser = duration::parse,
+            requires = "kill_button"
+        )]
+        timeout: Duration,
+    },
+    /// Approve pushes from a pre-push or pre-receive hook: summarizes the ref updates read from
+    /// stdin (branches, commits, diffstat) and exits 0 only if approved.
+    GitGate {
+        /// How long to wait for an answer, e.g. `30m`. Overrides `timeout_minutes`.
+        #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+        timeout: Option<Duration>,
+        /// The remote's name, as passed to pre-push.
+        remote: Option<String>,
+        /// The remote's URL, as passed to pre-push.
+        url: Option<String>,
+    },
+    /// Ask a yes/no question and exit 0 for yes, 1 for no, 2 on timeout, printing nothing,
+    /// e.g. `teleprompt confirm "Reboot db01?" && reboot`.
+    Confirm {
+        /// The question to ask.
+        question: String,
+        /// How long to wait for an answer, e.g. `30m`. Overrides `timeout_minutes`.
+        #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+        timeout: Option<Duration>,
+        /// Ask this recipient instead of the configured `user_id` (repeatable).
+        #[arg(long, value_name = "NAME|USER_ID")]
+        to: Vec<String>,
+    },
+    /// Ask with a native Telegram poll and print the chosen option(s), one per line, e.g.
+    /// `teleprompt poll --question "Lunch?" --option pizza --option sushi`.
+    Poll {
+        /// The question to ask.
+        #[arg(long)]
+        question: String,
+        /// An answer to offer (repeatable, at least two, shown in order).
+        #[arg(long = "option", value_name = "TEXT", required = true)]
+        options: Vec<String>,
+        /// Hide who voted. Any vote then answers, so use it with private chats.
+        #[arg(long)]
+        anonymous: bool,
+        /// Let the recipient pick more than one option.
+        #[arg(long, conflicts_with = "quiz")]
+        multiple: bool,
+        /// Make it a quiz: exit 0 only if the --correct option is picked, 1 otherwise.
+        #[arg(long, requires = "correct")]
+        quiz: bool,
+        /// The quiz's right answer, as the option's position (1 for the first).
+        #[arg(long, value_name = "N", requires = "quiz", value_parser = parse_correct)]
+        correct: Option<usize>,
+        /// How long to wait for a vote, e.g. `30m`. Overrides `timeout_minutes`.
+        #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+        timeout: Option<Duration>,
+        /// Ask this recipient instead of the configured `user_id` (repeatable).
+        #[arg(long, value_name = "NAME|USER_ID")]
+        to: Vec<String>,
+    },
+    /// Ask several questions in turn and print the answers as one JSON object, e.g.
+    /// `teleprompt form --field name --field replicas:int --field confirm:yesno`.
+    Form {
+        /// A question, as `NAME[:TYPE[:QUESTION]]` (repeatable, asked in order). TYPE is `text`
+        /// (default), `int`, `number`, `yesno`, `json`, or choices separated by `|`.
+        #[arg(
+            long = "field",
+            value_name = "NAME[:TYPE[:QUESTION]]",
+            value_parser = form::Field::parse,
+            required_unless_present = "file",
+            conflicts_with = "file"
+        )]
+        fields: Vec<form::Field>,
+        /// Read the questions from a form definition (TOML, or JSON if named `*.json`).
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+        /// How long to wait for each answer, e.g. `30m`. Overrides `timeout_minutes`.
+        #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+        timeout: Option<Duration>,
+        /// Ask this recipient instead of the configured `user_id` (repeatable).
+        #[arg(long, value_name = "NAME|USER_ID")]
+        to: Vec<String>,
+    },
+    /// Send many prompts from an NDJSON file, one `{"text", "to"?, "timeout"?, "id"?}` per line,
+    /// wait for their answers, and write one NDJSON result per prompt.
+    Batch {
+        /// The prompts file, or `-` for stdin.
+        input: PathBuf,
+        /// Write the results to this file instead of stdout.
+        #[arg(long, value_name = "PATH")]
+        out_file: Option<PathBuf>,
+        /// Pause between sending two prompts, to stay under Telegram's rate limits.
+        #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = duration::parse)]
+        interval: Duration,
+        /// How long to wait for each answer, for prompts without their own `timeout`.
+        /// Overrides `timeout_minutes`.
+        #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+        timeout: Option<Duration>,
+    },
+    /// Wait for the answers to every prompt still recorded as pending, e.g. after a crash or
+    /// reboot, and write one NDJSON result per prompt as `batch` does.
+    Recover {
+        /// Write the results to this file instead of stdout.
+        #[arg(long, value_name = "PATH")]
+        out_file: Option<PathBuf>,
+    },
+    /// Serve the Model Context Protocol on stdin/stdout, so AI agents can ask the recipients
+    /// questions (`ask_human`) and notify them (`notify_human`).
+    Mcp,
+    /// Serve a JSON-RPC protocol (`send`, `ask`, `cancel`, `status`) so a long-running
+    /// program can keep one teleprompt process for many concurrent questions, or an HTTP API
+    /// for other services on the host.
+    Serve {
+        /// Speak JSON-RPC on stdin/stdout, one JSON message per line.
+        #[arg(long, required_unless_present = "http", conflicts_with = "http")]
+        stdio: bool,
+        /// Serve the HTTP API on this address, e.g. `127.0.0.1:8700`. Requests must carry the
+        /// config's `serve_token` as a bearer token.
+        #[arg(long, value_name = "ADDR")]
+        http: Option<std::net::SocketAddr>,
+        /// With --http, if another instance serves the bot, stand by and take over (with the
+        /// questions it left pending) within seconds of it stopping, instead of failing.
+        #[arg(long, requires = "http")]
+        standby: bool,
+    },
+    /// Send the messages --queue-offline spooled while Telegram couldn't be reached, oldest
+    /// first, printing their message_ids.
+    Flush,
+    /// Print the bot's id, username and name (from getMe).
+    Whoami {
+        #[arg(long, value_enum, default_value_t)]
+        output: OutputFormat,
+    },
+    /// Maintain the SQLite store (`store = "sqlite"`; needs the `sqlite` feature).
+    Db {
+        #[command(subcommand)]
+        action: DbAction,
+    },
+    /// List past prompts and answers from the `audit_log`, oldest first.
+    History {
+        /// Show only the most recent N runs.
+        #[arg(long, value_name = "N")]
+        last: Option<usize>,
+        /// Show only runs since this date (local midnight) or RFC 3339 time, e.g. `2024-01-01`.
+        #[arg(long, value_name = "DATE", value_parser = history::parse_since)]
+        since: Option<chrono::DateTime<chrono::Utc>>,
+        #[arg(long, value_enum, default_value_t)]
+        output: OutputFormat,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CommandsAction {
+    /// Register the commands enabled by the config (e.g. `/cancel`) with setMyCommands, so
+    /// they show up in the recipient's command menu.
+    Sync,
+}
+
+#[derive(Subcommand, Debug)]
+enum DbAction {
+    /// Print the database path, size, schema version and what it holds.
+    Info,
+    /// Run SQLite's integrity check.
+    Check,
+    /// Rebuild the database file to reclaim space.
+    Vacuum,
+    /// Copy JSON state documents and the `audit_log` into the database.
+    Import,
+}
+
+#[derive(Subcommand, Debug)]
+enum StateAction {
+    /// Print the state dir and every stored document.
+    Show,
+    /// Delete one stored document by name, or all of them.
+    Reset {
+        /// Document to delete (as listed by `state show`). Omit to delete all.
+        name: Option<String>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Progress {
+    #[default]
+    Text,
+    Ndjson,
+}
+
+/// How a run finished, when it did not fail outright.
+enum Outcome {
+    /// A subcommand or informational flag completed.
+    Done,
+    Replied,
+    TimedOut,
+    /// The recipient sent the cancel command.
+    Cancelled,
+    /// Burst protection collapsed or muted the prompt; no reply was awaited.
+    Suppressed,
+    /// Enough recipients rejected that `--quorum` can no longer be reached.
+    Rejected,
+    /// Telegram couldn't be reached, and the message was spooled under `--queue-offline`.
+    Queued,
+    /// Answered with a reaction mapped to this exit code in `[reactions]`, or an answer mapped
+    /// to it by the exit map.
+    Exit(i32),
+    /// Ended with an outcome the exit map gives this exit code.
+    Mapped(Box<Outcome>, i32),
 }
 
 #[tokio::main]
 async fn main() {
-    if let Err(e) = run().await {
-        eprintln!("{:#}", e);
-        std::process::exit(1);
+    match run().await {
+        Ok(Outcome::Done | Outcome::Replied) => {}
+        Ok(Outcome::TimedOut) => std::process::exit(2),
+        Ok(Outcome::Cancelled) => std::process::exit(3),
+        Ok(Outcome::Suppressed) => std::process::exit(4),
+        Ok(Outcome::Rejected) => std::process::exit(5),
+        Ok(Outcome::Queued) => std::process::exit(6),
+        Ok(Outcome::Exit(code) | Outcome::Mapped(_, code)) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(error_exit_code(&e));
+        }
+    }
+}
+
+/// Exit code for a failed run. Classified Telegram failures get distinct codes so callers can
+/// tell transient network trouble from problems that need a human.
+fn error_exit_code(e: &anyhow::Error) -> i32 {
+    use telegram::ErrorKind;
+
+    match e.downcast_ref::<telegram::TelegramError>().map(|t| t.kind) {
+        Some(ErrorKind::Dns) => 10,
+        Some(ErrorKind::Tls) => 11,
+        Some(ErrorKind::Connect) => 12,
+        Some(ErrorKind::ConnectTimeout) => 13,
+        Some(ErrorKind::Timeout) => 14,
+        Some(ErrorKind::Server) => 15,
+        Some(ErrorKind::Conflict | ErrorKind::Api) => 16,
+        Some(ErrorKind::InvalidResponse) | None => 1,
     }
 }
 
-async fn run() -> anyhow::Result<()> {
+async fn run() -> anyhow::Result<Outcome> {
     let args = Args::parse();
+    // Progress events take over stderr; only warnings and worse are mixed in by default.
+    let log_level = args.log_level.unwrap_or(match args.progress {
+        // pam_exec may show a login's stderr to the user; stay quiet unless asked.
+        _ if args.pam => logging::LogLevel::Off,
+        Progress::Ndjson => logging::LogLevel::Warn,
+        Progress::Text => logging::LogLevel::Info,
+    });
+    logging::init(log_level, args.log_format);
     let config_path = match &args.config {
         Some(p) => p.clone(),
         None => config::default_config_path()?,
@@ -47,98 +932,463 @@ async fn run() -> anyhow::Result<()> {
 
     if args.print_config_path {
         println!("{}", config_path.display());
-        return Ok(());
+        return Ok(Outcome::Done);
     }
 
-    let message = read_prompt_message(&args)?;
-    let cfg = config::load(&config_path)?;
-
-    let client = telegram::TelegramClient::new(cfg.bot_token);
+    if args.pam {
+        return approve::pam(&args, &config_path).await;
+    }
 
-    // Drain any old updates so only messages after this run count as replies.
-    let mut offset = client.drain_updates().await?;
+    match &args.command {
+        Some(Command::Setup) => {
+            setup::run(&config_path).await?;
+            return Ok(Outcome::Done);
+        }
+        Some(Command::Doctor) => {
+            doctor::run(&config_path).await?;
+            return Ok(Outcome::Done);
+        }
+        Some(
+            Command::Notify { .. }
+            | Command::Form { .. }
+            | Command::Resume { .. }
+            | Command::Flush
+            | Command::Mcp
+            | Command::Serve { .. },
+        ) => {}
+        Some(Command::Edit {
+            message_id,
+            message,
+            to,
+            parse_mode,
+        }) => {
+            edit::run(
+                &config_path,
+                *message_id,
+                to.as_deref(),
+                message.as_deref(),
+                *parse_mode,
+            )
+            .await?;
+            return Ok(Outcome::Done);
+        }
+        Some(Command::Delete { message_ids, to }) => {
+            delete::run(&config_path, message_ids, to.as_deref()).await?;
+            return Ok(Outcome::Done);
+        }
+        Some(Command::State { action }) => {
+            state_command(&config_path, action)?;
+            return Ok(Outcome::Done);
+        }
+        Some(Command::Commands {
+            action: CommandsAction::Sync,
+        }) => {
+            commands::sync(&config_path).await?;
+            return Ok(Outcome::Done);
+        }
+        Some(Command::Verify { code }) => {
+            verify(&config_path, code)?;
+            return Ok(Outcome::Done);
+        }
+        Some(Command::Escape { parse_mode, text }) => {
+            escape(*parse_mode, text.as_deref())?;
+            return Ok(Outcome::Done);
+        }
+        Some(Command::Whoami { output }) => {
+            whoami(&config_path, *output).await?;
+            return Ok(Outcome::Done);
+        }
+        Some(Command::Chat { to }) => {
+            chat::run(&args, &config_path, to, chat::Style::Chat).await?;
+            return Ok(Outcome::Done);
+        }
+        Some(Command::Bridge { to }) => {
+            chat::run(&args, &config_path, to, chat::Style::Bridge).await?;
+            return Ok(Outcome::Done);
+        }
+        Some(Command::Tail {
+            follow,
+            every,
+            to,
+            command,
+        }) => {
+            anyhow::ensure!(
+                *every >= Duration::from_secs(1),
+                "--every must be at least 1s"
+            );
+            let source = match follow {
+                Some(path) => tail::Source::Follow(path.clone()),
+                None => tail::Source::Command(command.clone()),
+            };
+            return tail::run(&config_path, to, source, *every).await;
+        }
+        Some(Command::Exec {
+            tail_lines,
+            timeout,
+            to,
+            command,
+        }) => {
+            return exec::run(&args, &config_path, to, command, *tail_lines, *timeout).await;
+        }
+        Some(Command::Approve {
+            message,
+            timeout,
+            to,
+            command,
+        }) => {
+            return approve::run(&args, &config_path, message, to, command, *timeout).await;
+        }
+        Some(Command::SshNotify {
+            kill_button,
+            timeout,
+        }) => {
+            ssh_notify::run(&args, &config_path, *kill_button, *timeout).await?;
+            return Ok(Outcome::Done);
+        }
+        Some(Command::GitGate {
+            timeout,
+            remote,
+            url,
+        }) => {
+            return git_gate::run(
+                &args,
+                &config_path,
+                remote.as_deref(),
+                url.as_deref(),
+                *timeout,
+            )
+            .await;
+        }
+        Some(Command::Confirm {
+            question,
+            timeout,
+            to,
+        }) => {
+            return confirm::run(&args, &config_path, question, to, *timeout).await;
+        }
+        Some(Command::Poll {
+            question,
+            options,
+            anonymous,
+            multiple,
+            quiz: _,
+            correct,
+            timeout,
+            to,
+        }) => {
+            let spec = poll::Spec {
+                question: question.clone(),
+                options: options.clone(),
+                settings: telegram::PollSettings {
+                    anonymous: *anonymous,
+                    multiple: *multiple,
+                    correct: *correct,
+                },
+            };
+            return poll::run(&args, &config_path, &spec, to, *timeout).await;
+        }
+        Some(Command::Batch {
+            input,
+            out_file,
+            interval,
+            timeout,
+        }) => {
+            return batch::run(
+                &args,
+                &config_path,
+                input,
+                out_file.as_deref(),
+                *interval,
+                *timeout,
+            )
+            .await;
+        }
+        Some(Command::Recover { out_file }) => {
+            return batch::recover(&args, &config_path, out_file.as_deref()).await;
+        }
+        Some(Command::Db { action }) => {
+            db::run(&config_path, action)?;
+            return Ok(Outcome::Done);
+        }
+        Some(Command::History {
+            last,
+            since,
+            output,
+        }) => {
+            history::run(&config_path, *last, *since, *output)?;
+            return Ok(Outcome::Done);
+        }
+        None => {}
+    }
 
-    client.send_message(cfg.user_id, &message).await?;
-    eprintln!(
-        "Waiting for reply from user_id={} (timeout={} minutes)...",
-        cfg.user_id, cfg.timeout_minutes
-    );
+    let mut status = match args.progress {
+        Progress::Ndjson => status::StatusStream::stderr(),
+        Progress::Text => status::StatusStream::open(args.status_fd, args.status_file.as_deref())?,
+    };
 
-    let timeout = Duration::from_secs(cfg.timeout_minutes.saturating_mul(60));
-    let start = Instant::now();
-
-    while start.elapsed() < timeout {
-        let elapsed = start.elapsed();
-        if elapsed >= timeout {
-            break;
-        }
-        let remaining = timeout - elapsed;
-
-        let long_poll = remaining.min(Duration::from_secs(30));
-        let long_poll_s = long_poll.as_secs();
-
-        // Ensure the overall configured timeout is a hard deadline, even if the HTTP request
-        // hangs longer than the long-poll timeout.
-        let request_timeout = (long_poll + Duration::from_secs(5)).min(remaining);
-
-        let updates =
-            match tokio::time::timeout(request_timeout, client.get_updates(offset, long_poll_s))
-                .await
-            {
-                Ok(res) => res?,
-                Err(_) => {
-                    // If we hit the overall deadline, treat this as the normal "no reply" timeout.
-                    if request_timeout == remaining {
-                        break;
-                    }
-                    anyhow::bail!("telegram getUpdates timed out")
-                }
+    let mut audit = audit::Audit::new(&config_path);
+    let res = match &args.command {
+        Some(Command::Notify { send }) => {
+            prompt::run(&args, send, &config_path, &mut status, &mut audit, false).await
+        }
+        Some(Command::Flush) => spool::flush(&config_path, &mut status).await,
+        // Each tool call records its own audit entry, as form questions do.
+        Some(Command::Mcp) => mcp::serve(&args, &config_path, &mut status)
+            .await
+            .map(|()| Outcome::Done),
+        // Requests are runs of their own, reporting over the protocol.
+        Some(Command::Serve {
+            http: Some(addr),
+            standby,
+            ..
+        }) => serve::http(&args, &config_path, *addr, *standby)
+            .await
+            .map(|()| Outcome::Done),
+        Some(Command::Serve { .. }) => serve::stdio(&args, &config_path)
+            .await
+            .map(|()| Outcome::Done),
+        Some(Command::Resume { to, .. }) => {
+            let send = SendArgs {
+                to: to.iter().cloned().collect(),
+                thread_id: args.send.thread_id,
+                ..Default::default()
+            };
+            prompt::run(&args, &send, &config_path, &mut status, &mut audit, true).await
+        }
+        Some(Command::Form {
+            fields,
+            file,
+            timeout,
+            to,
+        }) => {
+            // Each question records its own audit entry, as conversation turns do.
+            let fields = match file {
+                Some(path) => form::load(path)?,
+                None => fields.clone(),
             };
+            form::run(&args, &config_path, &fields, to, *timeout, &mut status).await
+        }
+        _ if args.turns.is_some() || args.until.is_some() => {
+            // Each turn records its own audit entry; this one is never begun, so stays inert.
+            conversation::run(&args, &config_path, &mut status).await
+        }
+        _ => {
+            let wait = !args.no_wait;
+            prompt::run(
+                &args,
+                &args.send,
+                &config_path,
+                &mut status,
+                &mut audit,
+                wait,
+            )
+            .await
+        }
+    };
+    if let Err(e) = &res {
+        status.emit(status::Event::error(e));
+    }
+    audit.finish(&res);
+    res
+}
 
-        for update in &updates {
-            offset = update.update_id + 1;
+fn state_command(config_path: &std::path::Path, action: &StateAction) -> anyhow::Result<()> {
+    // State commands must work even when the config is missing or the token can't be resolved.
+    let store = match config::parse(config_path) {
+        Ok(cfg) => state::StateStore::for_config(&cfg)?,
+        Err(_) if !config_path.exists() => state::StateStore::open(state::default_state_dir()?)?,
+        Err(e) => return Err(e),
+    };
 
-            if let Some(text) = telegram::extract_text_reply(update, cfg.user_id) {
-                write_reply(&args, text)?;
-                return Ok(());
+    match action {
+        StateAction::Show => {
+            println!("state: {}", store.location().display());
+            let names = store.list()?;
+            if names.is_empty() {
+                println!("(no state)");
+            }
+            for name in names {
+                let doc: serde_json::Value = store.load(&name)?;
+                println!("\n{name}:\n{}", serde_json::to_string_pretty(&doc)?);
+            }
+        }
+        StateAction::Reset { name: Some(name) } => {
+            anyhow::ensure!(store.remove(name)?, "no state document named {name:?}");
+            eprintln!("Removed {name}.");
+        }
+        StateAction::Reset { name: None } => {
+            for name in store.list()? {
+                store.remove(&name)?;
+                eprintln!("Removed {name}.");
             }
         }
     }
+    Ok(())
+}
+
+fn verify(config_path: &std::path::Path, code: &str) -> anyhow::Result<()> {
+    let cfg = config::parse(config_path)?;
+    let secret = cfg
+        .signing_secret
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("signing_secret is not set in the config"))?;
+
+    let prompt_id = sign::verify(secret, code).map_err(|e| anyhow::anyhow!(e))?;
+    let sent_at = chrono::DateTime::<chrono::Utc>::from(prompt_id.datetime());
+    println!(
+        "valid: prompt {prompt_id} sent at {}",
+        sent_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    );
+    Ok(())
+}
+
+fn escape(mode: telegram::ParseMode, text: Option<&str>) -> anyhow::Result<()> {
+    match text {
+        Some(text) => println!("{}", mode.escape(text)),
+        None => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            print!("{}", mode.escape(&input));
+        }
+    }
+    Ok(())
+}
+
+async fn whoami(config_path: &std::path::Path, output: OutputFormat) -> anyhow::Result<()> {
+    let cfg = config::load(config_path)?;
+    let client = telegram::TelegramClient::for_config(&cfg)?;
+    let bot = client.get_me().await?;
+
+    match output {
+        OutputFormat::Text => {
+            println!("id: {}", bot.id);
+            println!("username: @{}", bot.username.as_deref().unwrap_or(""));
+            println!("name: {}", bot.first_name);
+        }
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::json!({
+                "id": bot.id,
+                "username": bot.username,
+                "first_name": bot.first_name,
+            })
+        ),
+    }
+    Ok(())
+}
+
+/// Parse `--stdin-as attachment[:NAME]` into the file name to upload stdin as.
+fn parse_stdin_as(value: &str) -> Result<String, String> {
+    match value.split_once(':') {
+        None if value == "attachment" => Ok("stdin.txt".to_string()),
+        Some(("attachment", name)) if !name.is_empty() && !name.contains(['/', '\\']) => {
+            Ok(name.to_string())
+        }
+        _ => Err(format!(
+            "expected attachment or attachment:NAME (a file name), got {value:?}"
+        )),
+    }
+}
 
-    eprintln!("Timed out waiting for reply.");
-    std::process::exit(2);
+/// Compile `--expect`, anchored so it has to match the whole reply.
+fn parse_expect(pattern: &str) -> Result<regex::Regex, String> {
+    regex::Regex::new(&format!("^(?:{pattern})$")).map_err(|e| e.to_string())
 }
 
-fn read_prompt_message(args: &Args) -> anyhow::Result<String> {
-    if let Some(m) = args.message.clone() {
+/// Stdin to upload as a document, as `(file name, contents)`.
+type Upload = (String, Vec<u8>);
+
+/// Name for binary stdin sent as a document.
+const BINARY_STDIN_NAME: &str = "stdin.bin";
+
+/// The prompt message, and stdin as a document if it is one: under `--stdin-as`, or when
+/// stdin isn't text.
+fn read_prompt(send: &SendArgs) -> anyhow::Result<(String, Option<Upload>)> {
+    if let Some(m) = send.message.clone() {
         let m = m.trim().to_string();
         anyhow::ensure!(!m.is_empty(), "--message was provided but empty");
-        return Ok(m);
+        return Ok((m, read_stdin_upload(send)?));
+    }
+
+    if let Some(path) = &send.message_file {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("read --message-file {}", path.display()))?;
+        let raw = raw.trim_end_matches(['\r', '\n']).to_string();
+        anyhow::ensure!(!raw.trim().is_empty(), "{} is empty", path.display());
+        return Ok((raw, read_stdin_upload(send)?));
+    }
+
+    // With --stdin-as stdin is an attachment, which can stand on its own.
+    if send.stdin_as.is_some() {
+        return Ok((String::new(), read_stdin_upload(send)?));
     }
 
     if std::io::stdin().is_terminal() {
+        // Media can stand on its own; the caption is optional.
+        if !send.attach.is_empty() || !send.photo.is_empty() {
+            return Ok((String::new(), None));
+        }
         anyhow::bail!(
             "No --message provided and stdin is a terminal; pipe a message via stdin or pass --message."
         );
     }
 
-    let mut raw = String::new();
-    std::io::stdin().read_to_string(&mut raw)?;
+    let mut raw = Vec::new();
+    std::io::stdin().read_to_end(&mut raw)?;
+    let raw = match stdin_text(raw) {
+        Ok(text) => text,
+        Err(data) => {
+            tracing::warn!(
+                "stdin is not text; sending it as {BINARY_STDIN_NAME} ({} bytes)",
+                data.len()
+            );
+            return Ok((String::new(), Some((BINARY_STDIN_NAME.to_string(), data))));
+        }
+    };
 
     let raw = raw.trim_end_matches(['\r', '\n']).to_string();
     anyhow::ensure!(!raw.trim().is_empty(), "stdin was empty");
-    Ok(raw)
+    Ok((raw, None))
+}
+
+/// Stdin as text, or back as bytes if it isn't UTF-8 or contains NUL bytes (which text
+/// never does, but many binary formats that happen to be valid UTF-8 do).
+fn stdin_text(data: Vec<u8>) -> Result<String, Vec<u8>> {
+    if data.contains(&0) {
+        return Err(data);
+    }
+    String::from_utf8(data).map_err(|e| e.into_bytes())
+}
+
+/// Read all of stdin for `--stdin-as`, if given.
+fn read_stdin_upload(send: &SendArgs) -> anyhow::Result<Option<Upload>> {
+    let Some(name) = &send.stdin_as else {
+        return Ok(None);
+    };
+    anyhow::ensure!(
+        !std::io::stdin().is_terminal(),
+        "--stdin-as was given but stdin is a terminal; pipe the attachment in."
+    );
+    let mut data = Vec::new();
+    std::io::stdin().read_to_end(&mut data)?;
+    anyhow::ensure!(!data.is_empty(), "stdin was empty");
+    Ok(Some((name.clone(), data)))
 }
 
 fn write_reply(args: &Args, reply: &str) -> anyhow::Result<()> {
+    if args.capture {
+        return Ok(());
+    }
     if let Some(path) = &args.out_file {
-        if let Some(parent) = path.parent() {
-            if !parent.as_os_str().is_empty() {
-                std::fs::create_dir_all(parent)?;
-            }
+        create_parent_dir(path, out_mode(args))?;
+        if args.append {
+            append_line(path, reply, out_mode(args))?;
+        } else {
+            write_file(path, reply.as_bytes(), out_mode(args))?;
+        }
+        if !args.tee {
+            return Ok(());
         }
-        std::fs::write(path, reply)?;
-        return Ok(());
     }
 
     let mut out = std::io::stdout().lock();
@@ -147,6 +1397,148 @@ fn write_reply(args: &Args, reply: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Write a downloaded media reply to `--out-file`, or as `file_name` inside `--media-dir`.
+/// Returns where it was saved. With `--collect-for` or `--append`, `--out-file` holds text
+/// replies, so media always goes to the media dir.
+fn save_media_reply(args: &Args, file_name: &str, data: &[u8]) -> anyhow::Result<PathBuf> {
+    let out_file = args
+        .out_file
+        .as_ref()
+        .filter(|_| args.collect_for.is_none() && !args.append);
+    let path = match (out_file, &args.media_dir) {
+        (Some(path), _) => path.clone(),
+        (None, Some(dir)) => dir.join(file_name),
+        (None, None) => PathBuf::from(file_name),
+    };
+    create_parent_dir(&path, out_mode(args))?;
+    write_file(&path, data, out_mode(args))?;
+    Ok(path)
+}
+
+/// Permissions for written replies: `--out-mode`, or `0600` under `--secret`.
+fn out_mode(args: &Args) -> Option<u32> {
+    args.out_mode.or(args.secret.then_some(0o600))
+}
+
+/// Parse `--out-mode`, an octal permission mode such as `0600` or `640`.
+fn parse_out_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value.strip_prefix("0o").unwrap_or(value), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("expected an octal mode such as 0600, got {value:?}"))
+}
+
+/// Parse `--correct`, a 1-based option position, into the 0-based index Telegram expects.
+fn parse_correct(value: &str) -> Result<usize, String> {
+    value
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .ok_or_else(|| format!("expected an option position (1 for the first), got {value:?}"))
+}
+
+/// Create the missing directories above `path`. With a file `mode`, new directories get the
+/// same permissions plus search (`x`) wherever the file is readable, e.g. `0700` for `0600`.
+fn create_parent_dir(path: &std::path::Path, mode: Option<u32>) -> anyhow::Result<()> {
+    let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) else {
+        return Ok(());
+    };
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, mode | ((mode & 0o444) >> 2));
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    builder
+        .create(parent)
+        .with_context(|| format!("create {}", parent.display()))
+}
+
+/// Create or replace `path` with `data`, atomically: the data is written to a temporary file
+/// next to it, which is then renamed over it, so readers never see a partial file. With a
+/// `mode` (Unix), the file gets exactly those permissions; otherwise an existing file keeps
+/// its permissions.
+fn write_file(path: &std::path::Path, data: &[u8], mode: Option<u32>) -> anyhow::Result<()> {
+    let name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file path", path.display()))?;
+    let tmp = path.with_file_name(format!(
+        ".{}.tmp-{}",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    let res = write_and_rename(&tmp, path, data, mode);
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    res.with_context(|| format!("write {}", path.display()))
+}
+
+fn write_and_rename(
+    tmp: &std::path::Path,
+    path: &std::path::Path,
+    data: &[u8],
+    mode: Option<u32>,
+) -> std::io::Result<()> {
+    let mut f = open_with_mode(
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true),
+        tmp,
+        mode,
+    )?;
+    if mode.is_none()
+        && let Ok(meta) = std::fs::metadata(path)
+    {
+        f.set_permissions(meta.permissions())?;
+    }
+    f.write_all(data)?;
+    f.sync_all()?;
+    std::fs::rename(tmp, path)
+}
+
+/// Append `text` to `path` as a line (adding the newline if it has none), in a single write so
+/// concurrent runs don't interleave. With a `mode`, as for `write_file`.
+fn append_line(path: &std::path::Path, text: &str, mode: Option<u32>) -> anyhow::Result<()> {
+    let mut line = text.to_string();
+    if !line.ends_with('\n') {
+        line.push('\n');
+    }
+    let mut f = open_with_mode(
+        std::fs::OpenOptions::new().append(true).create(true),
+        path,
+        mode,
+    )
+    .with_context(|| format!("open {}", path.display()))?;
+    f.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Open `path`, giving it `mode` (Unix) whether it is created or already existed, before
+/// anything is written to it.
+fn open_with_mode(
+    opts: &mut std::fs::OpenOptions,
+    path: &std::path::Path,
+    mode: Option<u32>,
+) -> std::io::Result<std::fs::File> {
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        std::os::unix::fs::OpenOptionsExt::mode(opts, mode);
+    }
+    let f = opts.open(path)?;
+    // `mode` only applies to new files, and is subject to the umask.
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        f.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(f)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -165,26 +1557,37 @@ mod tests {
     }
 
     #[test]
-    fn read_prompt_message_trims_message_flag() {
-        let args = Args {
+    fn read_prompt_trims_message_flag() {
+        let send = SendArgs {
             message: Some("  hello  ".to_string()),
-            out_file: None,
-            config: None,
-            print_config_path: false,
+            ..Default::default()
         };
-        let msg = read_prompt_message(&args).unwrap();
+        let (msg, upload) = read_prompt(&send).unwrap();
         assert_eq!(msg, "hello");
+        assert!(upload.is_none());
     }
 
     #[test]
-    fn read_prompt_message_rejects_empty_message_flag() {
-        let args = Args {
+    fn read_prompt_reads_message_file() {
+        let path = std::env::temp_dir().join(format!("teleprompt-msg-{}", std::process::id()));
+        std::fs::write(&path, "deploy?\n").unwrap();
+        let send = SendArgs {
+            message_file: Some(path.clone()),
+            ..Default::default()
+        };
+        assert_eq!(read_prompt(&send).unwrap().0, "deploy?");
+        std::fs::write(&path, "\n").unwrap();
+        assert!(read_prompt(&send).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_prompt_rejects_empty_message_flag() {
+        let send = SendArgs {
             message: Some("   ".to_string()),
-            out_file: None,
-            config: None,
-            print_config_path: false,
+            ..Default::default()
         };
-        let err = read_prompt_message(&args).unwrap_err();
+        let err = read_prompt(&send).unwrap_err();
         let msg = err.to_string();
         assert!(
             msg.contains("--message was provided but empty"),
@@ -192,14 +1595,55 @@ mod tests {
         );
     }
 
+    #[test]
+    fn binary_stdin_is_not_text() {
+        assert_eq!(stdin_text(b"deploy?\n".to_vec()).unwrap(), "deploy?\n");
+        assert!(stdin_text(vec![0x1f, 0x8b, 0x08, 0x00]).is_err());
+        assert!(stdin_text(b"ELF\0\0".to_vec()).is_err());
+    }
+
+    #[test]
+    fn parses_stdin_as() {
+        assert_eq!(parse_stdin_as("attachment").unwrap(), "stdin.txt");
+        assert_eq!(parse_stdin_as("attachment:build.log").unwrap(), "build.log");
+        assert!(parse_stdin_as("attachment:").is_err());
+        assert!(parse_stdin_as("attachment:../x").is_err());
+        assert!(parse_stdin_as("message").is_err());
+    }
+
+    #[test]
+    fn expect_must_match_the_whole_reply() {
+        let expect = parse_expect(r"yes|no").unwrap();
+        assert!(expect.is_match("no"));
+        assert!(!expect.is_match("yes please"));
+        assert!(!expect.is_match("snow"));
+        assert!(parse_expect("(").is_err());
+    }
+
+    #[test]
+    fn error_exit_code_maps_telegram_error_kinds() {
+        let dns = anyhow::Error::new(telegram::TelegramError::new(
+            telegram::ErrorKind::Dns,
+            "dns error",
+        ))
+        .context("send prompt");
+        assert_eq!(error_exit_code(&dns), 10);
+
+        let api = anyhow::Error::new(telegram::TelegramError::new(
+            telegram::ErrorKind::Api,
+            "telegram api error 400: Bad Request",
+        ));
+        assert_eq!(error_exit_code(&api), 16);
+
+        assert_eq!(error_exit_code(&anyhow::anyhow!("io error")), 1);
+    }
+
     #[test]
     fn write_reply_writes_and_overwrites_out_file_creating_parent_dir() {
         let path = unique_temp_path("nested/reply.txt");
         let args = Args {
-            message: None,
             out_file: Some(path.clone()),
-            config: None,
-            print_config_path: false,
+            ..Default::default()
         };
 
         write_reply(&args, "first").unwrap();
@@ -208,4 +1652,81 @@ mod tests {
         write_reply(&args, "second").unwrap();
         assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
     }
+
+    #[test]
+    fn append_accumulates_reply_lines() {
+        let path = unique_temp_path("replies.txt");
+        let args = Args {
+            out_file: Some(path.clone()),
+            append: true,
+            ..Default::default()
+        };
+        write_reply(&args, "first").unwrap();
+        write_reply(&args, "{\"reply\":\"second\"}\n").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "first\n{\"reply\":\"second\"}\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn secret_replies_are_written_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = unique_temp_path("secret.txt");
+        let mut args = Args {
+            out_file: Some(path.clone()),
+            ..Default::default()
+        };
+        write_reply(&args, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        args.secret = true;
+        write_reply(&args, "123456").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "123456");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn out_mode_applies_to_the_file_and_new_parent_dirs() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = unique_temp_path("private/dir/reply.txt");
+        let args = Args {
+            out_file: Some(path.clone()),
+            out_mode: Some(parse_out_mode("0640").unwrap()),
+            ..Default::default()
+        };
+        write_reply(&args, "yes").unwrap();
+        let mode = |p: &std::path::Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o640);
+        // New directories are subject to the umask, which may only take bits away.
+        let dir_mode = mode(path.parent().unwrap());
+        assert_eq!(dir_mode & !0o750, 0, "{dir_mode:o}");
+        assert_eq!(dir_mode & 0o700, 0o700, "{dir_mode:o}");
+        assert!(parse_out_mode("0999").is_err());
+        assert!(parse_out_mode("1777").is_err());
+    }
+
+    #[test]
+    fn save_media_reply_uses_media_dir_unless_out_file_is_set() {
+        let dir = unique_temp_path("media");
+        let args = Args {
+            media_dir: Some(dir.clone()),
+            ..Default::default()
+        };
+        let saved = save_media_reply(&args, "photo.jpg", b"jpeg").unwrap();
+        assert_eq!(saved, dir.join("photo.jpg"));
+        assert_eq!(std::fs::read(&saved).unwrap(), b"jpeg");
+
+        let out = unique_temp_path("reply.bin");
+        let args = Args {
+            out_file: Some(out.clone()),
+            ..Default::default()
+        };
+        assert_eq!(save_media_reply(&args, "photo.jpg", b"x").unwrap(), out);
+    }
 }
@@ -0,0 +1,471 @@
+use crate::jsonrpc::{
+    INVALID_PARAMS, METHOD_NOT_FOUND, PARSE_ERROR, Request, RpcError, parse, response,
+};
+use crate::status::StatusStream;
+use crate::{Args, Outcome, SendArgs, audit, config, duration, pending, prompt, state};
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::pin::Pin;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::oneshot;
+
+/// MCP revisions spoken, oldest first. A client asking for another is offered the newest.
+const PROTOCOL_VERSIONS: &[&str] = &["2024-11-05", "2025-03-26", "2025-06-18"];
+
+/// Told to the client on `initialize`, for the model using the tools.
+const INSTRUCTIONS: &str = "Reach the human operator on Telegram. Use ask_human when you need \
+a decision, an approval or information only they have, and notify_human to tell them something \
+that needs no answer. Questions block until answered or timed out.";
+
+#[derive(Debug, Deserialize)]
+struct Call {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct Ask {
+    question: String,
+    #[serde(default)]
+    choices: Vec<String>,
+    /// A duration such as `30m`.
+    timeout: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Notify {
+    text: String,
+}
+
+/// Serve the Model Context Protocol on stdin/stdout, one JSON-RPC message per line, until
+/// stdin ends. Tool calls are handled one at a time, each as a prompt run (or `notify`) with
+/// the config and the top-level polling flags; answers go back to the client, never to stdout
+/// as such. Other requests are answered while a call runs, and a call can be cancelled.
+pub async fn serve(args: &Args, config_path: &Path, status: &mut StatusStream) -> Result<()> {
+    // A broken config is better reported now than on the first question.
+    config::load(config_path)?;
+    let stdin = BufReader::new(tokio::io::stdin());
+    serve_io(args, config_path, status, stdin, tokio::io::stdout()).await
+}
+
+/// A tool call running, giving back the status stream it borrowed when it ends: with its
+/// result, or nothing if it was cancelled.
+type Running<'a> =
+    Pin<Box<dyn Future<Output = (Option<Result<Value, RpcError>>, &'a mut StatusStream)> + 'a>>;
+
+/// What the serve loop waits on.
+enum Next<'a> {
+    Line(Option<String>),
+    Ended(Option<Result<Value, RpcError>>, &'a mut StatusStream),
+}
+
+async fn serve_io<'a>(
+    args: &'a Args,
+    config_path: &'a Path,
+    status: &'a mut StatusStream,
+    input: impl AsyncBufRead + Unpin,
+    mut output: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    let mut lines = input.lines();
+    let mut input_open = true;
+    // Calls waiting for the one running to end, with their request ids.
+    let mut queued: VecDeque<(Option<Value>, Value)> = VecDeque::new();
+    let mut running: Option<(Option<Value>, oneshot::Sender<()>, Running<'a>)> = None;
+    let mut idle = Some(status);
+    loop {
+        if running.is_none()
+            && let Some((id, params)) = queued.pop_front()
+        {
+            let status = idle.take().expect("no call is running");
+            let (cancel, cancelled) = oneshot::channel();
+            let call = Box::pin(async move {
+                let res = tokio::select! {
+                    res = call(args, config_path, params, &mut *status) => Some(res),
+                    Ok(()) = cancelled => {
+                        forget(config_path);
+                        None
+                    }
+                };
+                (res, status)
+            });
+            running = Some((id, cancel, call));
+        }
+        let next = match &mut running {
+            None if !input_open => break,
+            None => Next::Line(lines.next_line().await?),
+            Some((_, _, call)) if !input_open => {
+                let (res, status) = call.await;
+                Next::Ended(res, status)
+            }
+            Some((_, _, call)) => tokio::select! {
+                line = lines.next_line() => Next::Line(line?),
+                (res, status) = call => Next::Ended(res, status),
+            },
+        };
+        let response = match next {
+            // Calls already received still get their answers.
+            Next::Line(None) => {
+                input_open = false;
+                continue;
+            }
+            Next::Line(Some(line)) if line.trim().is_empty() => continue,
+            Next::Line(Some(line)) => match serde_json::from_str::<Request>(&line) {
+                Ok(req) if req.method == "tools/call" => {
+                    queued.push_back((req.id, req.params));
+                    continue;
+                }
+                Ok(req) if req.method == "notifications/cancelled" => {
+                    cancel(&mut running, &mut queued, &req.params["requestId"]);
+                    continue;
+                }
+                Ok(req) => {
+                    let res = handle(&req.method, req.params);
+                    req.id.map(|id| response(id, res))
+                }
+                Err(e) => Some(response(
+                    Value::Null,
+                    Err(RpcError::new(PARSE_ERROR, e.to_string())),
+                )),
+            },
+            Next::Ended(res, status) => {
+                idle = Some(status);
+                let (id, _, _) = running.take().expect("a call was running");
+                // A cancelled call gets no response.
+                id.zip(res).map(|(id, res)| response(id, res))
+            }
+        };
+        if let Some(response) = response {
+            let mut line = serde_json::to_vec(&response)?;
+            line.push(b'\n');
+            output.write_all(&line).await?;
+            output.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Cancel the call with request id `id`: stop the one running, or drop it from the queue.
+fn cancel(
+    running: &mut Option<(Option<Value>, oneshot::Sender<()>, Running<'_>)>,
+    queued: &mut VecDeque<(Option<Value>, Value)>,
+    id: &Value,
+) {
+    if id.is_null() {
+        return;
+    }
+    if let Some((running_id, cancel, _)) = running
+        && running_id.as_ref() == Some(id)
+    {
+        // Replaced, so a second cancellation is a no-op.
+        let _ = std::mem::replace(cancel, oneshot::channel().0).send(());
+        return;
+    }
+    queued.retain(|(queued_id, _)| queued_id.as_ref() != Some(id));
+}
+
+/// Clear the pending records of a question cancelled part way through its run, which would
+/// otherwise be left for `recover`. Calls run one at a time, so they are this process's.
+fn forget(config_path: &Path) {
+    let Ok(cfg) = config::load(config_path) else {
+        return;
+    };
+    let own = Some(std::process::id());
+    let records = state::StateStore::for_config(&cfg)
+        .and_then(|store| Ok((pending::list(&store, &cfg.bot_token)?, store)));
+    match records {
+        Ok((records, store)) => {
+            for record in records.iter().filter(|r| r.pid == own) {
+                pending::remove(&store, record);
+            }
+        }
+        Err(e) => tracing::warn!("could not clear the pending prompts: {e:#}"),
+    }
+}
+
+fn handle(method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "initialize" => {
+            let asked = params["protocolVersion"].as_str();
+            let version = asked
+                .filter(|v| PROTOCOL_VERSIONS.contains(v))
+                .unwrap_or(PROTOCOL_VERSIONS[PROTOCOL_VERSIONS.len() - 1]);
+            Ok(json!({
+                "protocolVersion": version,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "teleprompt", "version": env!("CARGO_PKG_VERSION") },
+                "instructions": INSTRUCTIONS,
+            }))
+        }
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools() })),
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method {method}"),
+        )),
+    }
+}
+
+/// Run a `tools/call`.
+async fn call(
+    args: &Args,
+    config_path: &Path,
+    params: Value,
+    status: &mut StatusStream,
+) -> Result<Value, RpcError> {
+    let call: Call = parse(params)?;
+    let res = match call.name.as_str() {
+        "ask_human" => ask_human(args, config_path, parse(call.arguments)?, status).await,
+        "notify_human" => notify_human(args, config_path, parse(call.arguments)?, status).await,
+        name => {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("unknown tool {name}"),
+            ));
+        }
+    };
+    // A failed question is the tool's result for the model to see, not a protocol error.
+    Ok(match res {
+        Ok((text, is_error)) => tool_result(&text, is_error),
+        Err(e) => tool_result(&format!("{e:#}"), true),
+    })
+}
+
+fn tool_result(text: &str, is_error: bool) -> Value {
+    json!({
+        "content": [{ "type": "text", "text": text }],
+        "isError": is_error,
+    })
+}
+
+fn tools() -> Value {
+    json!([
+        {
+            "name": "ask_human",
+            "description": "Ask the human operator a question on Telegram and wait for the \
+                answer, which is returned as text. Blocks until they answer or the timeout \
+                expires.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "question": { "type": "string", "description": "The question to ask." },
+                    "choices": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Answers to offer as buttons; a typed answer must \
+                            name one of them. The chosen one is returned.",
+                    },
+                    "timeout": {
+                        "type": "string",
+                        "description": "How long to wait, e.g. \"30m\" or \"2h\". Defaults to \
+                            the configured timeout.",
+                    },
+                },
+                "required": ["question"],
+            },
+        },
+        {
+            "name": "notify_human",
+            "description": "Send the human operator a message on Telegram without waiting \
+                for an answer.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "text": { "type": "string", "description": "The message." },
+                },
+                "required": ["text"],
+            },
+        },
+    ])
+}
+
+/// Ask as a prompt run, returning the answer (or why there is none) and whether that's an
+/// error.
+async fn ask_human(
+    args: &Args,
+    config_path: &Path,
+    ask: Ask,
+    status: &mut StatusStream,
+) -> Result<(String, bool)> {
+    let timeout = ask
+        .timeout
+        .as_deref()
+        .map(duration::parse)
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+    let ask_args = Args {
+        timeout,
+        choices: ask.choices,
+        poll_lock: args.poll_lock,
+        fix_webhook: args.fix_webhook,
+        capture: true,
+        ..Default::default()
+    };
+    let send = SendArgs {
+        message: Some(ask.question),
+        ..Default::default()
+    };
+    let mut audit = audit::Audit::new(config_path);
+    let res = prompt::run(&ask_args, &send, config_path, status, &mut audit, true).await;
+    let reply = audit.entry.replies.last().cloned();
+    audit.finish(&res);
+    Ok(match res? {
+        Outcome::TimedOut => ("No answer before the timeout.".to_string(), true),
+        Outcome::Cancelled => ("The question was cancelled.".to_string(), true),
+        _ => match reply
+            .as_ref()
+            .and_then(|r| r.get("file").or_else(|| r.get("reply")))
+        {
+            Some(Value::String(text)) => (text.clone(), false),
+            _ => ("The question ended without an answer.".to_string(), true),
+        },
+    })
+}
+
+/// Send as `notify` does.
+async fn notify_human(
+    args: &Args,
+    config_path: &Path,
+    notify: Notify,
+    status: &mut StatusStream,
+) -> Result<(String, bool)> {
+    let notify_args = Args {
+        fix_webhook: args.fix_webhook,
+        capture: true,
+        ..Default::default()
+    };
+    let send = SendArgs {
+        message: Some(notify.text),
+        ..Default::default()
+    };
+    let mut audit = audit::Audit::new(config_path);
+    let res = prompt::run(&notify_args, &send, config_path, status, &mut audit, false).await;
+    audit.finish(&res);
+    Ok(match res? {
+        Outcome::Queued => (
+            "Telegram can't be reached; the message was queued.".to_string(),
+            false,
+        ),
+        Outcome::Suppressed => (
+            "The message was suppressed by burst protection.".to_string(),
+            false,
+        ),
+        _ => ("Sent.".to_string(), false),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn speaks_the_protocol() {
+        let args = Args::default();
+        let config = Path::new("/nonexistent/teleprompt.toml");
+        let mut status = StatusStream::disabled();
+
+        let init = handle("initialize", json!({ "protocolVersion": "2025-03-26" })).unwrap();
+        assert_eq!(init["protocolVersion"], "2025-03-26");
+        assert!(init["capabilities"]["tools"].is_object());
+
+        let list = handle("tools/list", Value::Null).unwrap();
+        let names: Vec<_> = list["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["ask_human", "notify_human"]);
+
+        let missing = call(
+            &args,
+            config,
+            json!({ "name": "ask_human", "arguments": { "choices": ["yes"] } }),
+            &mut status,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(missing.code, INVALID_PARAMS);
+        let unknown = handle("resources/list", Value::Null).unwrap_err();
+        assert_eq!(unknown.code, METHOD_NOT_FOUND);
+
+        // Failures of the question itself are tool results.
+        let failed = call(
+            &args,
+            config,
+            json!({ "name": "ask_human", "arguments": { "question": "Deploy?", "timeout": "soon" } }),
+            &mut status,
+        )
+        .await
+        .unwrap();
+        assert_eq!(failed["isError"], true);
+    }
+
+    #[tokio::test]
+    async fn answers_pings_and_cancels_during_a_question() {
+        let api = crate::mock_api::MockApi::start().await;
+        let config = api.config("");
+        let args = Args::default();
+        let mut status = StatusStream::disabled();
+        let (mut client, server_input) = tokio::io::duplex(1 << 16);
+        let (server_output, mut responses) = tokio::io::duplex(1 << 16);
+        let server = serve_io(
+            &args,
+            &config,
+            &mut status,
+            BufReader::new(server_input),
+            server_output,
+        );
+        let client = async {
+            let mut send = async |message: Value| {
+                let mut line = message.to_string();
+                line.push('\n');
+                client.write_all(line.as_bytes()).await.unwrap();
+            };
+            let mut responses = BufReader::new(&mut responses).lines();
+            send(
+                json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": {
+                "name": "ask_human", "arguments": { "question": "Deploy?", "timeout": "10s" },
+            } }),
+            )
+            .await;
+            api.sent_at_least(1).await;
+            send(json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" })).await;
+            let pong: Value =
+                serde_json::from_str(&responses.next_line().await.unwrap().unwrap()).unwrap();
+            assert_eq!(pong["id"], 2);
+
+            send(
+                json!({ "jsonrpc": "2.0", "method": "notifications/cancelled",
+                "params": { "requestId": 1 } }),
+            )
+            .await;
+            send(json!({ "jsonrpc": "2.0", "id": 3, "method": "ping" })).await;
+            let pong: Value =
+                serde_json::from_str(&responses.next_line().await.unwrap().unwrap()).unwrap();
+            assert_eq!(pong["id"], 3);
+            drop(client);
+            // The cancelled call gets no response.
+            let mut rest = String::new();
+            responses
+                .into_inner()
+                .read_to_string(&mut rest)
+                .await
+                .unwrap();
+            assert_eq!(rest, "");
+        };
+        let (served, ()) = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            tokio::join!(server, client)
+        })
+        .await
+        .unwrap();
+        served.unwrap();
+        let cfg = config::load(&config).unwrap();
+        let store = state::StateStore::for_config(&cfg).unwrap();
+        assert!(pending::list(&store, &cfg.bot_token).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,260 @@
+//! A stand-in for the Telegram Bot API, for tests that run prompts end to end. It keeps one
+//! update queue with `getUpdates` semantics, including Telegram's 409 for overlapping polls,
+//! records the messages sent, and answers every other method with `true`.
+
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The chat, and user, the config written by `MockApi::config` sends prompts to.
+pub const USER_ID: i64 = 7;
+
+/// How often a held `getUpdates` looks for new updates.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Default)]
+struct Queue {
+    updates: Vec<Value>,
+    next_update_id: i64,
+    next_message_id: i64,
+    sent: Vec<Value>,
+    /// Messages queued from the user.
+    received: Vec<Value>,
+    /// Bumped by every `getUpdates`; a held one that sees it change was overtaken.
+    polls: u64,
+    /// `getUpdates` requests terminated by a newer one.
+    conflicts: usize,
+}
+
+#[derive(Clone)]
+pub struct MockApi {
+    pub url: String,
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl MockApi {
+    /// Serve on a free local port until the test's runtime shuts down.
+    pub async fn start() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api = Self {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            queue: Arc::default(),
+        };
+        let queue = Arc::clone(&api.queue);
+        tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                let queue = Arc::clone(&queue);
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(move |req| {
+                        let queue = Arc::clone(&queue);
+                        async move { Ok::<_, std::convert::Infallible>(handle(&queue, req).await) }
+                    });
+                    let io = hyper_util::rt::TokioIo::new(stream);
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(io, service)
+                        .await;
+                });
+            }
+        });
+        api
+    }
+
+    /// Write a config using this API, prompting `USER_ID`, with its state in a fresh
+    /// directory, followed by `extra` lines. Returns the config's path.
+    pub fn config(&self, extra: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "teleprompt_mock_api_test_{}_{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("teleprompt.toml");
+        let config = format!(
+            "bot_token = \"1:mock\"\nuser_id = {USER_ID}\napi_url = \"{}\"\nstate_dir = \"{}\"\n{extra}\n",
+            self.url,
+            dir.join("state").display()
+        );
+        std::fs::write(&path, config).unwrap();
+        path
+    }
+
+    /// The `sendMessage` requests so far, each with the `message_id` it was given.
+    pub fn sent(&self) -> Vec<Value> {
+        self.queue.lock().unwrap().sent.clone()
+    }
+
+    /// Wait until at least `n` messages were sent, and return them.
+    pub async fn sent_at_least(&self, n: usize) -> Vec<Value> {
+        let start = Instant::now();
+        loop {
+            let sent = self.sent();
+            if sent.len() >= n {
+                return sent;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "only {} of {n} messages were sent",
+                sent.len()
+            );
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Queue a message from `USER_ID`, threaded as a reply to message `to` if given.
+    pub fn message(&self, to: Option<i64>, text: &str) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.next_message_id += 1;
+        let date = chrono::Utc::now().timestamp();
+        let mut message = json!({
+            "message_id": 1000 + queue.next_message_id,
+            "date": date,
+            "chat": { "id": USER_ID, "type": "private" },
+            "from": { "id": USER_ID, "is_bot": false, "first_name": "Ann" },
+            "text": text,
+        });
+        if let Some(to) = to {
+            // Like Telegram, include the message replied to, with the tag a prompt carries.
+            let text = queue
+                .sent
+                .iter()
+                .find(|m| m["message_id"] == to)
+                .map_or(Value::Null, |m| m["text"].clone());
+            message["reply_to_message"] = json!({
+                "message_id": to,
+                "date": date,
+                "chat": { "id": USER_ID, "type": "private" },
+                "text": text,
+            });
+        }
+        queue.received.push(message.clone());
+        self.push(json!({ "message": message }), &mut queue);
+    }
+
+    /// Queue a press of the button with callback data `data` on sent message `on`.
+    pub fn press(&self, on: i64, data: &str) {
+        let mut queue = self.queue.lock().unwrap();
+        let sent = queue
+            .sent
+            .iter()
+            .find(|m| m["message_id"] == on)
+            .unwrap()
+            .clone();
+        let mut message = json!({
+            "message_id": on,
+            "date": chrono::Utc::now().timestamp(),
+            "chat": { "id": USER_ID, "type": "private" },
+            "text": sent["text"],
+        });
+        // Like Telegram, include the message the pressed one replies to.
+        let to = &sent["reply_parameters"]["message_id"];
+        if let Some(parent) = queue.received.iter().find(|m| &m["message_id"] == to) {
+            message["reply_to_message"] = parent.clone();
+        }
+        let id = format!("q{}", queue.next_update_id);
+        self.push(
+            json!({ "callback_query": {
+                "id": id,
+                "from": { "id": USER_ID, "is_bot": false, "first_name": "Ann" },
+                "message": message,
+                "data": data,
+            } }),
+            &mut queue,
+        );
+    }
+
+    fn push(&self, mut update: Value, queue: &mut Queue) {
+        queue.next_update_id += 1;
+        update["update_id"] = queue.next_update_id.into();
+        queue.updates.push(update);
+    }
+
+    /// How many `getUpdates` requests were terminated by an overlapping one.
+    pub fn conflicts(&self) -> usize {
+        self.queue.lock().unwrap().conflicts
+    }
+}
+
+async fn handle(
+    queue: &Mutex<Queue>,
+    req: hyper::Request<hyper::body::Incoming>,
+) -> hyper::Response<Full<Bytes>> {
+    let method = req
+        .uri()
+        .path()
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let body = req.into_body().collect().await.unwrap().to_bytes();
+    let params: Value = serde_json::from_slice(&body).unwrap_or_default();
+    let (status, body) = match method.as_str() {
+        "getUpdates" => get_updates(queue, &params).await,
+        "sendMessage" => {
+            let mut queue = queue.lock().unwrap();
+            queue.next_message_id += 1;
+            let message_id = queue.next_message_id;
+            let mut sent = params.clone();
+            sent["message_id"] = message_id.into();
+            queue.sent.push(sent);
+            (
+                200,
+                json!({ "ok": true, "result": {
+                    "message_id": message_id,
+                    "date": chrono::Utc::now().timestamp(),
+                    "chat": { "id": params["chat_id"], "type": "private" },
+                } }),
+            )
+        }
+        _ => (200, json!({ "ok": true, "result": true })),
+    };
+    hyper::Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+/// Confirm the updates before `offset` and return the rest, holding the request for up to
+/// `timeout` seconds while there are none. Like Telegram, a newer `getUpdates` terminates a
+/// held one with a 409.
+async fn get_updates(queue: &Mutex<Queue>, params: &Value) -> (u16, Value) {
+    let offset = params["offset"].as_i64().unwrap_or(0);
+    let hold = Duration::from_secs(params["timeout"].as_u64().unwrap_or(0));
+    let start = Instant::now();
+    let poll = {
+        let mut queue = queue.lock().unwrap();
+        queue.polls += 1;
+        queue.polls
+    };
+    loop {
+        {
+            let mut queue = queue.lock().unwrap();
+            if queue.polls != poll {
+                queue.conflicts += 1;
+                return (
+                    409,
+                    json!({
+                        "ok": false,
+                        "error_code": 409,
+                        "description": "Conflict: terminated by other getUpdates request; make sure that only one bot instance is running",
+                    }),
+                );
+            }
+            if offset > 0 {
+                queue
+                    .updates
+                    .retain(|u| u["update_id"].as_i64().unwrap() >= offset);
+            }
+            if !queue.updates.is_empty() || start.elapsed() >= hold {
+                return (200, json!({ "ok": true, "result": queue.updates }));
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
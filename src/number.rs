@@ -0,0 +1,55 @@
+/// Parse a `--number` reply, returning it normalized (`3`, `2.5`, `-1000` for `-1e3`) or the
+/// try-again message if it isn't a number within `min..=max`.
+pub fn check(text: &str, min: Option<f64>, max: Option<f64>) -> Result<String, String> {
+    let n = text
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite())
+        .ok_or_else(|| invalid_text("That's not a number", min, max))?;
+    if min.is_some_and(|min| n < min) || max.is_some_and(|max| n > max) {
+        return Err(invalid_text("That's out of range", min, max));
+    }
+    Ok(format(n))
+}
+
+/// `n` as written back: without a fractional part if it has none.
+pub fn format(n: f64) -> String {
+    // `-0` reads oddly in a script.
+    let n = if n == 0.0 { 0.0 } else { n };
+    format!("{n}")
+}
+
+fn invalid_text(problem: &str, min: Option<f64>, max: Option<f64>) -> String {
+    let range = match (min, max) {
+        (Some(min), Some(max)) => format!(" from {} to {}", format(min), format(max)),
+        (Some(min), None) => format!(" of at least {}", format(min)),
+        (None, Some(max)) => format!(" of at most {}", format(max)),
+        (None, None) => String::new(),
+    };
+    format!("⚠️ {problem}. Please reply with a number{range}.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_are_normalized_and_range_checked() {
+        assert_eq!(check(" 3 ", None, None).as_deref(), Ok("3"));
+        assert_eq!(check("3.0", None, None).as_deref(), Ok("3"));
+        assert_eq!(check("2.50", Some(0.0), Some(100.0)).as_deref(), Ok("2.5"));
+        assert_eq!(check("-1e3", None, None).as_deref(), Ok("-1000"));
+        assert_eq!(check("-0", None, None).as_deref(), Ok("0"));
+        assert_eq!(
+            check("three", Some(1.0), None),
+            Err("⚠️ That's not a number. Please reply with a number of at least 1.".into())
+        );
+        assert_eq!(
+            check("101", Some(0.0), Some(100.0)),
+            Err("⚠️ That's out of range. Please reply with a number from 0 to 100.".into())
+        );
+        assert!(check("inf", None, None).is_err());
+        assert!(check("NaN", None, None).is_err());
+    }
+}
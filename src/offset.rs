@@ -0,0 +1,90 @@
+use crate::state::StateStore;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const STATE_NAME: &str = "offset";
+
+/// After a week without updates Telegram may number new ones from a random point, so older
+/// offsets aren't trusted.
+const MAX_AGE_S: i64 = 6 * 24 * 60 * 60;
+
+/// Messages already pending at startup still count as answers if they are at most this much
+/// older than the run, e.g. an answer typed while the prompt was being prepared.
+pub const PENDING_GRACE_S: i64 = 60;
+
+/// The next `getUpdates` offset of each bot, as left by the last run that polled it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct OffsetState {
+    /// Keyed by bot id, the part of the token before `:`; the token itself is never stored.
+    #[serde(default)]
+    bots: BTreeMap<String, Saved>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Saved {
+    offset: i64,
+    /// Unix timestamp (seconds).
+    saved_at: i64,
+}
+
+fn bot_key(token: &str) -> &str {
+    token.split(':').next().unwrap_or_default()
+}
+
+impl OffsetState {
+    fn get(&self, token: &str, now: i64) -> Option<i64> {
+        self.bots
+            .get(bot_key(token))
+            .filter(|saved| now - saved.saved_at <= MAX_AGE_S)
+            .map(|saved| saved.offset)
+    }
+}
+
+/// The saved offset for the bot, if there is a recent one. Unreadable state only means the
+/// queue gets drained as usual.
+pub fn load(store: &StateStore, token: &str) -> Option<i64> {
+    match store.load::<OffsetState>(STATE_NAME) {
+        Ok(state) => state.get(token, chrono::Utc::now().timestamp()),
+        Err(e) => {
+            tracing::warn!("ignoring the saved update offset: {e:#}");
+            None
+        }
+    }
+}
+
+pub fn save(store: &StateStore, token: &str, offset: i64) -> Result<()> {
+    let mut state: OffsetState = store.load(STATE_NAME)?;
+    state.bots.insert(
+        bot_key(token).to_string(),
+        Saved {
+            offset,
+            saved_at: chrono::Utc::now().timestamp(),
+        },
+    );
+    store.save(STATE_NAME, &state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_are_per_bot_and_expire() {
+        let mut state = OffsetState::default();
+        state.bots.insert(
+            "123".to_string(),
+            Saved {
+                offset: 42,
+                saved_at: 1_000,
+            },
+        );
+
+        assert_eq!(state.get("123:secret", 1_000 + 60), Some(42));
+        assert_eq!(state.get("456:secret", 1_000 + 60), None);
+        assert_eq!(state.get("123:secret", 1_000 + MAX_AGE_S + 1), None);
+
+        let raw = serde_json::to_string(&state).unwrap();
+        assert!(!raw.contains("secret"));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::state::StateStore;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the state documents, one per prompt, so concurrent runs never overwrite each
+/// other's.
+const PREFIX: &str = "pending-";
+
+/// A prompt that was delivered and is being waited on, persisted before the wait so
+/// `teleprompt recover` can pick it up if the waiting run dies.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Prompt {
+    /// The bot that sent it: the part of the token before `:`.
+    pub bot: String,
+    pub chat_id: i64,
+    pub message_id: i64,
+    /// When the wait gives up, as a Unix timestamp (seconds).
+    pub deadline: i64,
+    /// Correlation tag included in the prompt, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// The `batch` item id, echoed back by `recover`, or the `serve` question id.
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub id: serde_json::Value,
+    /// The process waiting on it, so `recover` leaves the prompts of live runs alone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// The command that recorded it, so `serve` takes over only its own questions. `None`
+    /// for records from older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+}
+
+/// The command a pending prompt was recorded by.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Prompt,
+    Batch,
+    Serve,
+}
+
+impl Prompt {
+    pub fn new(token: &str, chat_id: i64, message_id: i64, deadline: i64) -> Self {
+        Self {
+            bot: bot_key(token).to_string(),
+            chat_id,
+            message_id,
+            deadline,
+            tag: None,
+            id: serde_json::Value::Null,
+            pid: Some(std::process::id()),
+            source: Some(Source::Prompt),
+        }
+    }
+
+    /// Whether the run that recorded the prompt may still be waiting on it, because its process
+    /// is alive. Records without a pid, from older versions, count as abandoned.
+    pub fn owner_alive(&self) -> bool {
+        self.pid.is_some_and(process_alive)
+    }
+
+    fn name(&self) -> String {
+        format!("{PREFIX}{}-{}-{}", self.bot, self.chat_id, self.message_id)
+    }
+}
+
+fn bot_key(token: &str) -> &str {
+    token.split(':').next().unwrap_or_default()
+}
+
+/// Whether a process with id `pid` exists. Pids are reused, so a stranger may pass for the
+/// owner; its prompts are then left for a later `recover`.
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks for the process; EPERM means it exists, run by another user.
+    // SAFETY: sends no signal.
+    let res = unsafe { libc::kill(pid, 0) };
+    res == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    use windows_sys::Win32::Foundation::{CloseHandle, ERROR_ACCESS_DENIED, STILL_ACTIVE};
+    use windows_sys::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    // SAFETY: the handle is checked before use and closed once done with.
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            // Access denied means it exists, run by another user.
+            return std::io::Error::last_os_error().raw_os_error()
+                == Some(ERROR_ACCESS_DENIED as i32);
+        }
+        // A process that exited stays around, with its exit code, while handles to it are open.
+        let mut code: u32 = 0;
+        let exited = GetExitCodeProcess(handle, &mut code) != 0 && code != STILL_ACTIVE as u32;
+        CloseHandle(handle);
+        !exited
+    }
+}
+
+/// Elsewhere every owner counts as alive, so no run's prompts are taken from it.
+#[cfg(not(any(unix, windows)))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+/// Record a prompt as pending. Best effort: a failure only means it can't be recovered.
+pub fn save(store: &StateStore, prompt: &Prompt) {
+    if let Err(e) = store.save(&prompt.name(), prompt) {
+        tracing::warn!("could not record the pending prompt: {e:#}");
+    }
+}
+
+/// Forget a prompt once its wait is over.
+pub fn remove(store: &StateStore, prompt: &Prompt) {
+    if let Err(e) = store.remove(&prompt.name()) {
+        tracing::warn!("could not clear the pending prompt: {e:#}");
+    }
+}
+
+/// The prompts of the bot still recorded as pending, oldest deadline first.
+pub fn list(store: &StateStore, token: &str) -> Result<Vec<Prompt>> {
+    let prefix = format!("{PREFIX}{}-", bot_key(token));
+    let mut prompts = Vec::new();
+    for name in store.list()? {
+        if !name.starts_with(&prefix) {
+            continue;
+        }
+        match store.load::<Option<Prompt>>(&name) {
+            Ok(Some(prompt)) => prompts.push(prompt),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("ignoring pending prompt {name:?}: {e:#}"),
+        }
+    }
+    prompts.sort_by_key(|p| p.deadline);
+    Ok(prompts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompts_are_listed_per_bot_until_removed() {
+        let dir = std::env::temp_dir().join(format!(
+            "teleprompt-pending-test-{}-{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let store = StateStore::open(dir.clone()).unwrap();
+        let late = Prompt::new("123:secret", 7, 2, 2_000);
+        let early = Prompt {
+            tag: Some("#tp-abc".to_string()),
+            ..Prompt::new("123:secret", -100, 1, 1_000)
+        };
+        save(&store, &late);
+        save(&store, &early);
+        save(&store, &Prompt::new("1234:other", 7, 3, 500));
+
+        assert_eq!(list(&store, "123:secret").unwrap(), [early.clone(), late]);
+        remove(&store, &early);
+        assert_eq!(list(&store, "123:secret").unwrap().len(), 1);
+        assert!(
+            !std::fs::read_to_string(dir.join("pending-123-7-2.json"))
+                .unwrap()
+                .contains("secret")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_prompts_of_live_processes_have_an_owner() {
+        let mine = Prompt::new("123:secret", 7, 1, 1_000);
+        assert!(mine.owner_alive());
+
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        let dead = Prompt {
+            pid: Some(child.id()),
+            ..mine.clone()
+        };
+        assert!(!dead.owner_alive());
+        let legacy: Prompt = serde_json::from_str(
+            r#"{"bot": "123", "chat_id": 7, "message_id": 1, "deadline": 1000}"#,
+        )
+        .unwrap();
+        assert!(!legacy.owner_alive());
+    }
+}
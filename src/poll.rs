@@ -0,0 +1,292 @@
+use crate::telegram::{PollSettings, SendOptions, TelegramClient, Update};
+use crate::{Args, Outcome, backoff, config, lock, offset, state};
+use anyhow::Result;
+use chrono::Utc;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The poll a `poll` run asks.
+pub struct Spec {
+    pub question: String,
+    pub options: Vec<String>,
+    pub settings: PollSettings,
+}
+
+/// A poll as sent to one chat.
+struct Sent {
+    chat_id: i64,
+    message_id: i64,
+    poll_id: String,
+}
+
+/// How a poll ended.
+#[derive(Debug, PartialEq, Eq)]
+enum Answer {
+    /// Indexes of the chosen options, in option order.
+    Chose(Vec<usize>),
+    /// The recipient sent the cancel command.
+    Cancelled,
+    TimedOut,
+}
+
+/// Send a native Telegram poll to the recipients and wait for a vote, printing the chosen
+/// option(s) one per line. A quiz exits `1` unless the right option was chosen. Polls like a
+/// prompt run: under the polling lock, from the saved offset or after a drain. Afterwards the
+/// poll is closed.
+pub async fn run(
+    args: &Args,
+    config_path: &Path,
+    spec: &Spec,
+    to: &[String],
+    timeout: Option<Duration>,
+) -> Result<Outcome> {
+    check(spec)?;
+    let mut cfg = config::load(config_path)?;
+    if !to.is_empty() {
+        cfg.user_ids = to
+            .iter()
+            .map(|name| cfg.resolve_recipient(name))
+            .collect::<Result<_>>()?;
+    }
+    let client =
+        TelegramClient::for_config(&cfg)?.with_fix_webhook(args.fix_webhook || cfg.fix_webhook);
+    let timeout =
+        timeout.unwrap_or_else(|| Duration::from_secs(cfg.timeout_minutes.saturating_mul(60)));
+
+    let store = state::StateStore::for_config(&cfg).ok();
+    let _poll_lock = match cfg.state_dir() {
+        Ok(dir) => {
+            let mode = args.poll_lock.unwrap_or(cfg.poll_lock);
+            lock::acquire(&dir, &cfg.bot_token, mode, timeout).await?
+        }
+        Err(_) => None,
+    };
+    let saved_offset = store
+        .as_ref()
+        .and_then(|store| offset::load(store, &cfg.bot_token));
+    let (mut offset, not_before) = match saved_offset {
+        Some(saved) => (saved, Utc::now().timestamp() - offset::PENDING_GRACE_S),
+        None => (client.drain_updates().await?.0, 0),
+    };
+
+    let opts = SendOptions {
+        thread_id: cfg.thread_id,
+        ..Default::default()
+    };
+    let mut sent = Vec::new();
+    for &chat_id in &cfg.user_ids {
+        let poll = client
+            .send_poll(
+                chat_id,
+                &spec.question,
+                &spec.options,
+                &spec.settings,
+                &opts,
+            )
+            .await?;
+        sent.push(Sent {
+            chat_id,
+            message_id: poll.message_id,
+            poll_id: poll.poll.id,
+        });
+    }
+
+    let start = Instant::now();
+    let mut backoff = backoff::Backoff::default();
+    let answer = loop {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            break Answer::TimedOut;
+        }
+        let long_poll = remaining.min(Duration::from_secs(30));
+        let updates = match client.get_updates(offset, long_poll.as_secs()).await {
+            Ok(updates) => {
+                backoff.reset();
+                updates
+            }
+            Err(e) if backoff::transient(&e) => {
+                let delay = backoff.fail().min(remaining);
+                tracing::warn!(
+                    "polling failed ({e:#}); retrying in {:.1}s",
+                    delay.as_secs_f64()
+                );
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let mut found = None;
+        for update in &updates {
+            offset = offset.max(update.update_id + 1);
+            if found.is_none() {
+                found = answer_in(&cfg, &sent, spec.settings.anonymous, update, not_before);
+            }
+        }
+        if let Some(answer) = found {
+            break answer;
+        }
+    };
+
+    if let Some(store) = &store
+        && let Err(e) = offset::save(store, &cfg.bot_token, offset)
+    {
+        tracing::warn!("could not save the update offset: {e:#}");
+    }
+    for poll in &sent {
+        if let Err(e) = client.stop_poll(poll.chat_id, poll.message_id).await {
+            tracing::warn!("could not close the poll: {e:#}");
+        }
+    }
+
+    Ok(match answer {
+        Answer::Chose(ids) => {
+            for &i in &ids {
+                println!("{}", spec.options[i]);
+            }
+            match spec.settings.correct {
+                Some(correct) if ids != [correct] => Outcome::Exit(1),
+                _ => Outcome::Done,
+            }
+        }
+        Answer::Cancelled => Outcome::Cancelled,
+        Answer::TimedOut => Outcome::TimedOut,
+    })
+}
+
+/// Reject polls Telegram would refuse for obvious reasons.
+fn check(spec: &Spec) -> Result<()> {
+    anyhow::ensure!(!spec.question.trim().is_empty(), "the question is empty");
+    anyhow::ensure!(
+        spec.options.len() >= 2,
+        "a poll needs at least two --option"
+    );
+    anyhow::ensure!(
+        spec.options.iter().all(|o| !o.trim().is_empty()),
+        "poll options must not be empty"
+    );
+    if let Some(correct) = spec.settings.correct {
+        anyhow::ensure!(
+            correct < spec.options.len(),
+            "--correct {} is not one of the {} options",
+            correct + 1,
+            spec.options.len()
+        );
+        anyhow::ensure!(
+            !spec.settings.multiple,
+            "a quiz takes a single answer (drop --multiple)"
+        );
+    }
+    Ok(())
+}
+
+/// The answer an update gives, if it's a vote in one of our polls by someone who may answer
+/// (or, for anonymous polls, any vote), or the cancel command.
+fn answer_in(
+    cfg: &config::Config,
+    sent: &[Sent],
+    anonymous: bool,
+    update: &Update,
+    not_before: i64,
+) -> Option<Answer> {
+    if let Some(vote) = &update.poll_answer {
+        let poll = sent.iter().find(|s| s.poll_id == vote.poll_id)?;
+        let user = vote.user.as_ref()?;
+        // A retracted vote leaves the poll open.
+        if vote.option_ids.is_empty() || !cfg.may_answer(poll.chat_id, user.id) {
+            return None;
+        }
+        let mut ids = vote.option_ids.clone();
+        ids.sort_unstable();
+        return Some(Answer::Chose(ids));
+    }
+    if let Some(poll) = &update.poll {
+        // Anonymous votes only show up as new counts, without the voter.
+        if !anonymous || poll.total_voter_count == 0 || !sent.iter().any(|s| s.poll_id == poll.id) {
+            return None;
+        }
+        let ids = (poll.options.iter().enumerate())
+            .filter(|(_, o)| o.voter_count > 0)
+            .map(|(i, _)| i)
+            .collect();
+        return Some(Answer::Chose(ids));
+    }
+    let msg = cfg.reply_message(update).filter(|m| m.date >= not_before)?;
+    cfg.is_cancel(msg.text.as_deref()?)
+        .then_some(Answer::Cancelled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telegram::{Poll, PollAnswer, PollOption, User};
+
+    #[test]
+    fn votes_in_our_polls_answer() {
+        let cfg: config::Config = toml::from_str("user_id = 7").unwrap();
+        let sent = [Sent {
+            chat_id: 7,
+            message_id: 1,
+            poll_id: "p1".to_string(),
+        }];
+        let vote = |poll_id: &str, user_id, option_ids: Vec<usize>| Update {
+            poll_answer: Some(PollAnswer {
+                poll_id: poll_id.to_string(),
+                user: Some(User {
+                    id: user_id,
+                    ..Default::default()
+                }),
+                option_ids,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            answer_in(&cfg, &sent, false, &vote("p1", 7, vec![2, 0]), 0),
+            Some(Answer::Chose(vec![0, 2]))
+        );
+        assert_eq!(
+            answer_in(&cfg, &sent, false, &vote("p1", 7, vec![]), 0),
+            None
+        );
+        assert_eq!(
+            answer_in(&cfg, &sent, false, &vote("p1", 8, vec![1]), 0),
+            None
+        );
+        assert_eq!(
+            answer_in(&cfg, &sent, false, &vote("p2", 7, vec![1]), 0),
+            None
+        );
+
+        let counts = Update {
+            poll: Some(Poll {
+                id: "p1".to_string(),
+                options: vec![PollOption { voter_count: 0 }, PollOption { voter_count: 1 }],
+                total_voter_count: 1,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            answer_in(&cfg, &sent, true, &counts, 0),
+            Some(Answer::Chose(vec![1]))
+        );
+        assert_eq!(answer_in(&cfg, &sent, false, &counts, 0), None);
+    }
+
+    #[test]
+    fn needs_two_options() {
+        let spec = |options: &[&str]| Spec {
+            question: "Lunch?".to_string(),
+            options: options.iter().map(|o| o.to_string()).collect(),
+            settings: PollSettings::default(),
+        };
+        assert!(check(&spec(&["pizza", "sushi"])).is_ok());
+        assert!(check(&spec(&["pizza"])).is_err());
+        assert!(check(&spec(&["pizza", " "])).is_err());
+
+        let mut quiz = spec(&["3", "4"]);
+        quiz.settings.correct = Some(1);
+        assert!(check(&quiz).is_ok());
+        quiz.settings.correct = Some(2);
+        let err = check(&quiz).unwrap_err().to_string();
+        assert!(err.contains("--correct 3"), "error was: {err}");
+    }
+}
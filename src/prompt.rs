@@ -0,0 +1,2410 @@
+use crate::exit_map::ExitMap;
+use crate::status::{Event, StatusStream};
+use crate::telegram::{self, TelegramClient};
+use crate::{
+    Args, Outcome, OutputFormat, SendArgs, audit, backoff, burst, choices, config, correlate,
+    deadline, dedupe, duration, entities, expand, json_reply, lock, number, offset, pending,
+    quorum, reaction, schedule, sign, spool, state, template, translate,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Prefix for `--canary` prompts so the test recipient never mistakes one for a real request.
+const CANARY_LABEL: &str = "🐤 CANARY (test run, not a real request)";
+
+/// Sent when a reply under `--expect-location` isn't a location.
+const LOCATION_INVALID_TEXT: &str = "⚠️ Please answer by sharing a location (📎 → Location).";
+
+/// Sent when a reply under `--expect-contact` isn't a contact.
+const CONTACT_INVALID_TEXT: &str = "⚠️ Please answer by sharing a contact (📎 → Contact).";
+
+/// Pause between polls under `--correlate` when only already-seen updates are pending.
+const CORRELATE_IDLE: Duration = Duration::from_secs(1);
+
+/// Callback data prefix of the "use as answer" button offered under `--require-reply`.
+const CONFIRM_PREFIX: &str = "confirm:";
+
+/// Text of `--remind-every` reminders.
+const REMINDER_TEXT: &str = "⏰ Still waiting for your answer.";
+
+/// File name for prompts uploaded by `--long-as-file`.
+const LONG_FILE_NAME: &str = "prompt.txt";
+
+/// How much of the first line is previewed in the `--long-as-file` summary.
+const LONG_PREVIEW_CHARS: usize = 200;
+
+/// Send a prompt and, unless `wait` is false, block until the recipient replies or the timeout
+/// expires.
+pub async fn run(
+    args: &Args,
+    send: &SendArgs,
+    config_path: &Path,
+    status: &mut StatusStream,
+    audit: &mut audit::Audit,
+    wait: bool,
+) -> Result<Outcome> {
+    // `resume` waits on a prompt an earlier run sent, so there is nothing to read or send.
+    let resume = match &args.command {
+        Some(crate::Command::Resume { prompt_id, .. }) => Some(*prompt_id),
+        _ => None,
+    };
+    let (message, upload) = match resume {
+        Some(_) => (String::new(), None),
+        None => crate::read_prompt(send)?,
+    };
+    let expanded;
+    let (message, send) = if send.expand {
+        expanded = SendArgs {
+            caption: send.caption.as_deref().map(expand::expand).transpose()?,
+            ..send.clone()
+        };
+        (expand::expand(&message)?, &expanded)
+    } else {
+        (message, send)
+    };
+    let mut cfg = match &args.loaded_config {
+        Some(cfg) => cfg.clone(),
+        None => config::load(config_path)?,
+    };
+    if !send.to.is_empty() {
+        cfg.user_ids = send
+            .to
+            .iter()
+            .map(|name| cfg.resolve_recipient(name))
+            .collect::<Result<_>>()?;
+    }
+    if send.thread_id.is_some() {
+        cfg.thread_id = send.thread_id;
+    }
+    if send.canary {
+        cfg.use_canary()?;
+    }
+    // Expanded up front, so a bad placeholder fails the run before anything is sent.
+    for text in [&mut cfg.message_prefix, &mut cfg.message_suffix]
+        .into_iter()
+        .flatten()
+    {
+        *text = expand::expand(text).context("message_prefix/message_suffix")?;
+    }
+    audit.begin(&cfg, &message);
+    // Conversation turns and form questions are parts of a run; they don't pick its exit code.
+    let exit_map = if args.capture || args.turns.is_some() || args.until.is_some() {
+        ExitMap::default()
+    } else {
+        cfg.exit_map.clone().merged(args.exit_map.as_ref())
+    };
+
+    let client =
+        TelegramClient::for_config(&cfg)?.with_fix_webhook(args.fix_webhook || cfg.fix_webhook);
+
+    // Burst protection needs the state store; the saved update offset only uses it if it can.
+    let store = match state::StateStore::for_config(&cfg) {
+        Ok(store) => Some(store),
+        Err(e) if cfg.burst.is_none() => {
+            tracing::debug!("no state store: {e:#}");
+            None
+        }
+        Err(e) => return Err(e),
+    };
+
+    // Held before anything else touches Telegram or the state dir, which may change meanwhile.
+    schedule::hold(send, status).await?;
+
+    if !wait {
+        // Fire-and-forget: no drain, no polling. Pending mute presses are picked up by the
+        // next run that polls.
+        if let (Some(burst_cfg), Some(store)) = (&cfg.burst, &store)
+            && let Some(outcome) =
+                collapse_burst(&client, store, burst_cfg, &cfg, send, &message, status).await?
+        {
+            return Ok(exit_map.outcome(outcome));
+        }
+        let opts = || DeliverOptions {
+            upload: upload
+                .as_ref()
+                .map(|(name, data)| (name.as_str(), data.as_slice())),
+            ..Default::default()
+        };
+        let mut sent = BTreeMap::new();
+        let mut offline = Vec::new();
+        if send.queue_offline || cfg.queue_offline {
+            // One chat at a time, so only the ones that missed out are queued.
+            for &chat_id in &cfg.user_ids {
+                let one = config::Config {
+                    user_ids: vec![chat_id],
+                    ..cfg.clone()
+                };
+                match deliver(&client, &one, send, &message, opts(), status).await {
+                    Ok(delivered) => sent.extend(delivered.messages),
+                    Err(e) if backoff::transient(&e) => {
+                        tracing::warn!("could not reach Telegram ({e:#}); queueing the message");
+                        offline.push(chat_id);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        } else {
+            sent = deliver(&client, &cfg, send, &message, opts(), status)
+                .await?
+                .messages;
+        }
+        match &send.id_file {
+            Some(path) => write_id_file(path, &sent)?,
+            None if args.capture => {}
+            None => {
+                for message_id in sent.values() {
+                    println!("{message_id}");
+                }
+            }
+        }
+        if offline.is_empty() {
+            return Ok(Outcome::Done);
+        }
+        anyhow::ensure!(
+            upload.is_none(),
+            "Telegram can't be reached, and a --stdin-as upload can't be queued"
+        );
+        let queued = spool::Queued::new(&cfg, send, &message, offline.clone());
+        let path = spool::enqueue(&cfg, &queued)?;
+        tracing::info!("queued for `teleprompt flush`: {}", path.display());
+        status.emit(Event::Queued {
+            chat_ids: offline,
+            path,
+        });
+        return Ok(exit_map.outcome(Outcome::Queued));
+    }
+    anyhow::ensure!(
+        !send.queue_offline,
+        "--queue-offline needs notify or --no-wait: a prompt that waits for an answer can't be queued"
+    );
+
+    // A resumed prompt's tag is unknown; strict reply matching takes its place.
+    let correlate = (args.correlate || cfg.correlate) && resume.is_none();
+    let tag = correlate.then(correlate::new_tag);
+    let timeout = args
+        .collect_for
+        .or(args.timeout)
+        .unwrap_or_else(|| Duration::from_secs(cfg.timeout_minutes.saturating_mul(60)));
+    let output = match &args.format {
+        Some(format) => Output::Template(template::Template::parse(format)?),
+        None if args.output == OutputFormat::Json => Output::Json,
+        None => Output::Text,
+    };
+
+    // Checked before taking the polling lock, which the run that sent the prompt may hold.
+    let mut claim = None;
+    if let Some(window) = args.dedupe_window
+        && resume.is_none()
+    {
+        let store = store
+            .as_ref()
+            .context("--dedupe-window needs a usable state dir")?;
+        let fp = dedupe::fingerprint(&cfg.bot_token, &cfg.user_ids, &message);
+        if let Some(outcome) =
+            reuse_answer(args, &output, store, &fp, window, timeout, status, audit).await?
+        {
+            return Ok(exit_map.outcome(outcome));
+        }
+        let now = Utc::now().timestamp();
+        claim = Some(dedupe::Claim::new(store, &fp, now, timeout, window)?);
+    }
+
+    // Uncorrelated runs confirm every update they poll, so only one may poll a bot at a time.
+    // Correlated runs are built to share it.
+    let lock_dir = match (&tag, cfg.state_dir()) {
+        (None, Ok(dir)) => Some(dir),
+        (None, Err(e)) => {
+            tracing::debug!("polling without a lock: {e:#}");
+            None
+        }
+        (Some(_), _) => None,
+    };
+    let lock_mode = args.poll_lock.unwrap_or(cfg.poll_lock);
+    // A run that would fail on the lock does so before sending anything. One that waits for it
+    // sends first, so its question isn't held back until the other run's is answered.
+    let lock_first = lock_mode != lock::LockMode::Wait || resume.is_some();
+    let mut _poll_lock = match &lock_dir {
+        Some(dir) if lock_first => lock::acquire(dir, &cfg.bot_token, lock_mode, timeout).await?,
+        _ => None,
+    };
+    // Update ids already looked at; under --correlate the offset may stay behind them.
+    let mut seen = HashSet::new();
+    let run_started = Utc::now().timestamp();
+
+    // Correlated runs take turns with each polling request instead; see `lock::turn`.
+    let turn_dir = match (&tag, cfg.state_dir()) {
+        (Some(_), Ok(dir)) if lock_mode != lock::LockMode::Off => Some(dir),
+        _ => None,
+    };
+    // Correlated runs must not confirm other runs' pending replies, so they only note what is
+    // already there, unless another run is polling: then its long poll could hold up this
+    // prompt, and the first poll reads the same. Others read pending updates once they have
+    // the lock.
+    // Under `serve`, the process's correlated runs read from one shared stream.
+    let reader = args
+        .feed
+        .as_ref()
+        .zip(tag.as_deref())
+        .map(|(feed, tag)| feed.reader(tag));
+    let pending = match (&tag, &turn_dir, &reader) {
+        (_, turns, Some(reader)) => {
+            reader
+                .get_updates(&client, &cfg, turns.as_deref(), 0, Duration::ZERO)
+                .await?
+        }
+        (Some(_), Some(dir), None) => {
+            match lock::turn(dir, &cfg.bot_token, Duration::ZERO).await? {
+                Some(_turn) => client.get_updates(0, 0).await?,
+                None => Vec::new(),
+            }
+        }
+        (Some(_), None, None) => client.get_updates(0, 0).await?,
+        (None, _, None) => Vec::new(),
+    };
+
+    if let (Some(burst_cfg), Some(store)) = (&cfg.burst, &store) {
+        let may_answer = |chat, user| cfg.may_answer(chat, user);
+        burst::apply_mutes(&client, store, burst_cfg, may_answer, &pending).await?;
+        if resume.is_none()
+            && let Some(outcome) =
+                collapse_burst(&client, store, burst_cfg, &cfg, send, &message, status).await?
+        {
+            return Ok(exit_map.outcome(outcome));
+        }
+    }
+
+    let mut tally = match args.quorum {
+        Some(quorum) => {
+            let quorum = quorum as usize;
+            let voters = cfg.responder_count();
+            anyhow::ensure!(
+                quorum <= voters,
+                "--quorum {quorum} needs at least that many people who can answer, got {voters}"
+            );
+            Some(quorum::Tally::new(quorum, voters))
+        }
+        None => None,
+    };
+
+    anyhow::ensure!(
+        args.choices.iter().all(|c| !c.trim().is_empty()),
+        "--choices must not contain empty choices"
+    );
+    if let (Some(min), Some(max)) = (args.min, args.max) {
+        anyhow::ensure!(min <= max, "--min must not be greater than --max");
+    }
+    let schema = args
+        .schema
+        .as_deref()
+        .map(json_reply::load_schema)
+        .transpose()?;
+    let reply_markup = if tally.is_some() {
+        Some(quorum::markup())
+    } else if !args.choices.is_empty() {
+        Some(choices::markup(&args.choices))
+    } else {
+        (args.require_reply || correlate).then(|| serde_json::json!({ "force_reply": true }))
+    };
+    anyhow::ensure!(
+        args.remind_every.is_none_or(|d| !d.is_zero()),
+        "--remind-every must be longer than 0s"
+    );
+    let mut escalation = match args.escalate_after {
+        Some(after) => {
+            anyhow::ensure!(
+                after < timeout,
+                "--escalate-after must be shorter than the timeout ({})",
+                duration::format(timeout)
+            );
+            // A canary run must never reach the real fallback people.
+            let targets: Vec<i64> = if send.canary {
+                cfg.user_ids.clone()
+            } else {
+                args.escalate_to
+                    .iter()
+                    .map(|name| cfg.resolve_recipient(name))
+                    .collect::<Result<_>>()?
+            };
+            Some((after, targets))
+        }
+        None => None,
+    };
+    let opts = DeliverOptions {
+        reply_markup: reply_markup.clone(),
+        tag: tag.clone(),
+        deadline: args.show_deadline.then_some(timeout),
+        protect_content: args.secret,
+        upload: upload
+            .as_ref()
+            .map(|(name, data)| (name.as_str(), data.as_slice())),
+        ..Default::default()
+    };
+    let Delivered {
+        messages: mut prompt_messages,
+        all: mut sent_messages,
+        mut countdown,
+    } = match resume {
+        Some(prompt_id) => {
+            anyhow::ensure!(
+                args.escalate_after.is_none(),
+                "resume can't --escalate-after: the prompt text is unknown"
+            );
+            let chat_id = cfg.primary_user_id();
+            Delivered {
+                messages: BTreeMap::from([(chat_id, prompt_id)]),
+                all: vec![(chat_id, prompt_id)],
+                countdown: None,
+            }
+        }
+        None => deliver(&client, &cfg, send, &message, opts, status).await?,
+    };
+    let sent_at = chrono::Utc::now();
+    // The timeout counts from the send, including any wait for the lock below.
+    let start = Instant::now();
+    if let Some(path) = &send.id_file {
+        write_id_file(path, &prompt_messages)?;
+    }
+    if let Some(dir) = lock_dir.as_ref().filter(|_| !lock_first) {
+        let patience = timeout.saturating_sub(start.elapsed());
+        match lock::acquire(dir, &cfg.bot_token, lock_mode, patience).await {
+            Ok(lock) => _poll_lock = lock,
+            // Nobody could answer in time: the usual timeout, below.
+            Err(e) if start.elapsed() >= timeout => tracing::info!("{e:#}"),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Only messages after this run count as replies. With an offset saved by the last run,
+    // pending updates are read from there and ones sent before this run are skipped; without
+    // one, ones sent before the prompt are. Either way a reply that arrived while this run
+    // waited for the lock is still there.
+    let saved_offset = match (&tag, &store) {
+        (None, Some(store)) => offset::load(store, &cfg.bot_token),
+        _ => None,
+    };
+    let mut not_before = None;
+    let mut offset = match &tag {
+        // The answer may have arrived long before this run; read everything still pending.
+        None if resume.is_some() => saved_offset.unwrap_or(0),
+        None => {
+            not_before = Some(match saved_offset {
+                Some(_) => run_started - offset::PENDING_GRACE_S,
+                None => sent_at.timestamp(),
+            });
+            saved_offset.unwrap_or(0)
+        }
+        Some(tag) => {
+            seen.extend(pending.iter().map(|u| u.update_id));
+            confirmable_offset(0, &pending, tag)
+        }
+    };
+    let deadline = sent_at.timestamp() + timeout.as_secs() as i64;
+    let mut tracked = track(
+        store.as_ref(),
+        &cfg,
+        &prompt_messages,
+        deadline,
+        tag.as_deref(),
+        args.question_id.as_deref(),
+    );
+    let mut pinned = Vec::new();
+    if args.pin {
+        pinned = pin_messages(&client, &prompt_messages).await;
+    }
+
+    let reactions = cfg.reactions.clone().unwrap_or_else(reaction::default_map);
+
+    let mut collected = Vec::new();
+    // Whether only threaded replies to the prompt count.
+    let strict = tag.is_some() || resume.is_some();
+    tracing::info!(
+        "Waiting for reply from user_id={} (timeout={})...",
+        cfg.user_ids_display(),
+        duration::format(timeout)
+    );
+    status.emit(Event::Polling {
+        timeout_s: timeout.as_secs(),
+    });
+    let mut backoff = backoff::Backoff::default();
+    let mut next_reminder = args.remind_every;
+    // Under --require-reply: un-threaded messages waiting for the user to confirm them.
+    let mut candidates = HashMap::new();
+
+    // Messages an answer arrived in, for --delete-reply.
+    let mut answer_messages = Vec::new();
+    // The single answer written, for --dedupe-window.
+    let mut answered = None;
+    let outcome = 'wait: {
+        while start.elapsed() < timeout {
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                break;
+            }
+            let remaining = timeout - elapsed;
+            if let Some(countdown) = &mut countdown {
+                countdown.update(&client, Some(remaining)).await;
+            }
+            if let Some((after, targets)) = escalation.take_if(|(after, _)| elapsed >= *after) {
+                let mut fallback = cfg.clone();
+                fallback.user_ids = targets
+                    .into_iter()
+                    .filter(|id| !cfg.user_ids.contains(id))
+                    .collect();
+                tracing::info!(
+                    "No answer after {}; escalating to user_id={}",
+                    duration::format(after),
+                    fallback.user_ids_display()
+                );
+                status.emit(Event::Escalated {
+                    chat_ids: fallback.user_ids.clone(),
+                });
+                let opts = DeliverOptions {
+                    reply_markup: reply_markup.clone(),
+                    tag: tag.clone(),
+                    note: Some(format!(
+                        "⏫ Escalated: no answer within {}",
+                        duration::format(after)
+                    )),
+                    protect_content: args.secret,
+                    upload: upload
+                        .as_ref()
+                        .map(|(name, data)| (name.as_str(), data.as_slice())),
+                    ..Default::default()
+                };
+                let sent = deliver(&client, &fallback, send, &message, opts, status).await?;
+                if args.pin {
+                    pinned.extend(pin_messages(&client, &sent.messages).await);
+                }
+                tracked.extend(track(
+                    store.as_ref(),
+                    &cfg,
+                    &sent.messages,
+                    deadline,
+                    tag.as_deref(),
+                    args.question_id.as_deref(),
+                ));
+                prompt_messages.extend(sent.messages);
+                sent_messages.extend(sent.all);
+                audit.entry.escalated_to = fallback.user_ids.clone();
+                cfg.user_ids.extend(fallback.user_ids);
+            }
+            if let (Some(due), Some(interval)) = (next_reminder, args.remind_every)
+                && elapsed >= due
+            {
+                let reminders = remind(&client, &cfg, &prompt_messages, &collected, status).await?;
+                sent_messages.extend(reminders);
+                next_reminder = Some(due + interval);
+            }
+
+            let mut long_poll = remaining.min(Duration::from_secs(30));
+            // Wake up in time for the next reminder or the escalation.
+            for due in [next_reminder, escalation.as_ref().map(|(after, _)| *after)]
+                .into_iter()
+                .flatten()
+            {
+                long_poll = long_poll.min(due.saturating_sub(elapsed).max(Duration::from_secs(1)));
+            }
+            let long_poll_s = long_poll.as_secs();
+
+            // Correlated runs take turns polling: Telegram ends a long poll that another one
+            // overlaps. A shared stream takes the turns itself.
+            let turn = match &turn_dir {
+                Some(dir) if reader.is_none() => {
+                    match lock::turn(dir, &cfg.bot_token, remaining).await? {
+                        Some(turn) => Some(turn),
+                        None => break,
+                    }
+                }
+                _ => None,
+            };
+            let remaining = timeout.saturating_sub(start.elapsed());
+
+            // Ensure the overall configured timeout is a hard deadline, even if the HTTP request
+            // hangs longer than the long-poll timeout.
+            let request_timeout = (long_poll + Duration::from_secs(5)).min(remaining);
+
+            let poll = async {
+                match &reader {
+                    Some(reader) => {
+                        let turns = turn_dir.as_deref();
+                        reader
+                            .get_updates(&client, &cfg, turns, offset, long_poll)
+                            .await
+                    }
+                    None => client.get_updates(offset, long_poll_s).await,
+                }
+            };
+            let res = match tokio::time::timeout(request_timeout, poll).await {
+                Ok(res) => res,
+                Err(_) => {
+                    // If we hit the overall deadline, treat this as the normal "no reply" timeout.
+                    if request_timeout == remaining {
+                        break;
+                    }
+                    Err(telegram::TelegramError::new(
+                        telegram::ErrorKind::Timeout,
+                        "telegram getUpdates timed out",
+                    )
+                    .into())
+                }
+            };
+            let updates = match res {
+                Ok(updates) => {
+                    backoff.reset();
+                    updates
+                }
+                // A network blip shouldn't end a long wait; keep trying until the deadline.
+                Err(e) if backoff::transient(&e) => {
+                    drop(turn);
+                    let delay = backoff.fail().min(timeout.saturating_sub(start.elapsed()));
+                    tracing::warn!(
+                        "polling failed ({e:#}); retrying in {:.1}s",
+                        delay.as_secs_f64()
+                    );
+                    status.emit(Event::Retrying {
+                        attempt: backoff.failures(),
+                        delay_ms: delay.as_millis() as u64,
+                        message: format!("{e:#}"),
+                    });
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            drop(turn);
+
+            if let (Some(burst_cfg), Some(store)) = (&cfg.burst, &store) {
+                let may_answer = |chat, user| cfg.may_answer(chat, user);
+                burst::apply_mutes(&client, store, burst_cfg, may_answer, &updates).await?;
+            }
+
+            if let Some(tag) = &tag {
+                offset = confirmable_offset(offset, &updates, tag);
+                if updates.iter().all(|u| seen.contains(&u.update_id)) {
+                    // Unconfirmed updates come back immediately; don't spin on them.
+                    tokio::time::sleep(CORRELATE_IDLE.min(timeout.saturating_sub(start.elapsed())))
+                        .await;
+                    continue;
+                }
+            }
+
+            for update in &updates {
+                if tag.is_none() {
+                    offset = update.update_id + 1;
+                } else if !seen.insert(update.update_id) {
+                    continue;
+                }
+                if not_before.is_some_and(|t| update.date().is_some_and(|date| date < t)) {
+                    continue;
+                }
+
+                // The cancel command works in every mode, but under --correlate (or resume) only
+                // as a reply to our own prompt, since any run could otherwise claim it.
+                if let Some(msg) = cfg.reply_message(update)
+                    && (!strict || replies_to_prompt(msg, &prompt_messages))
+                    && msg.text.as_deref().is_some_and(|t| cfg.is_cancel(t))
+                {
+                    answer_messages.push((msg.chat.id, msg.message_id));
+                    break 'wait cancel(&client, &cfg, msg, status).await?;
+                }
+
+                if args.expect_location && update.edited_message.is_some() {
+                    for reply in &mut collected {
+                        move_location(update, reply, args.secret, status)?;
+                    }
+                    continue;
+                }
+                if let Some(grace) = args.accept_edits
+                    && update.edited_message.is_some()
+                {
+                    for reply in &mut collected {
+                        accept_edit(args, &cfg, schema.as_ref(), update, reply, grace, status)?;
+                    }
+                    continue;
+                }
+
+                if let Some(tally) = &mut tally {
+                    // Only button votes count towards a quorum; messages are ignored.
+                    if let Some(outcome) =
+                        count_vote(&client, args, &cfg, update, &prompt_messages, tally, status)
+                            .await?
+                    {
+                        break 'wait outcome;
+                    }
+                    continue;
+                }
+
+                if let Some(reacted) = reaction::answer(update, &cfg, &reactions, &prompt_messages)
+                {
+                    let reply = accept_reaction(&reacted, status)?.stamp(&prompt_messages, sent_at);
+                    audit.answer(&reply, args.secret);
+                    if args.collect_for.is_none() {
+                        write_single(args, &output, &reply)?;
+                        answered = Some(serde_json::to_value(&reply)?);
+                        break 'wait reply
+                            .exit_code
+                            .or_else(|| exit_map.answer(&reply.text))
+                            .map_or(Outcome::Replied, Outcome::Exit);
+                    }
+                    collected.push(reply);
+                    continue;
+                }
+
+                if let Some(reply) =
+                    press_choice(&client, args, &cfg, update, &prompt_messages, status).await?
+                {
+                    let reply = reply.stamp(&prompt_messages, sent_at);
+                    audit.answer(&reply, args.secret);
+                    if args.collect_for.is_none() {
+                        write_single(args, &output, &reply)?;
+                        answered = Some(serde_json::to_value(&reply)?);
+                        break 'wait exit_map
+                            .answer(&reply.text)
+                            .map_or(Outcome::Replied, Outcome::Exit);
+                    }
+                    collected.push(reply);
+                    continue;
+                }
+
+                let confirmed;
+                let reply = if let Some(tag) = &tag {
+                    confirmed = correlated_reply(
+                        &client,
+                        &cfg,
+                        store.as_ref(),
+                        update,
+                        &prompt_messages,
+                        tag,
+                    )
+                    .await?;
+                    confirmed.as_ref()
+                } else if strict || args.require_reply {
+                    confirmed =
+                        strict_reply(&client, &cfg, update, &prompt_messages, &mut candidates)
+                            .await?;
+                    confirmed.as_ref()
+                } else {
+                    cfg.reply_message(update)
+                };
+                let Some(msg) = reply else { continue };
+                let checked = if args.expect_location || args.expect_contact {
+                    missing_share(args, msg).map_or(Ok(None), |text| Err(text.to_string()))
+                } else {
+                    check_reply(
+                        args,
+                        schema.as_ref(),
+                        reply_text(args, msg).unwrap_or_default(),
+                    )
+                };
+                let normalized = match checked {
+                    Ok(normalized) => normalized,
+                    Err(invalid) => {
+                        answer_messages.push((msg.chat.id, msg.message_id));
+                        sent_messages.extend(ask_again(&client, &invalid, msg, status).await?);
+                        continue;
+                    }
+                };
+                if let Some(mut reply) = accept_reply(&client, args, &cfg, msg, status).await? {
+                    if let Some(text) = normalized {
+                        reply.text = text;
+                    }
+                    let mut reply = reply.stamp(&prompt_messages, sent_at);
+                    answer_messages.push((msg.chat.id, msg.message_id));
+                    if args.collect_for.is_none() {
+                        if let Some(grace) = args.accept_edits
+                            && reply.file.is_none()
+                        {
+                            let edits = AwaitEdits {
+                                client: &client,
+                                args,
+                                cfg: &cfg,
+                                schema: schema.as_ref(),
+                                grace,
+                            };
+                            edits.run(&mut reply, &mut offset, status).await?;
+                        }
+                        audit.answer(&reply, args.secret);
+                        write_single(args, &output, &reply)?;
+                        answered = Some(serde_json::to_value(&reply)?);
+                        break 'wait exit_map
+                            .answer(&reply.text)
+                            .map_or(Outcome::Replied, Outcome::Exit);
+                    }
+                    audit.answer(&reply, args.secret);
+                    collected.push(reply);
+                }
+            }
+        }
+
+        if !collected.is_empty() {
+            crate::write_reply(args, &render_collected(&collected, &output))?;
+            break 'wait Outcome::Replied;
+        }
+
+        if let Some(countdown) = &mut countdown {
+            countdown.update(&client, None).await;
+        }
+        if let Some(tally) = &tally {
+            crate::write_reply(args, &tally.render())?;
+        }
+        status.emit(Event::Timeout {
+            timeout_s: timeout.as_secs(),
+        });
+        tracing::info!("Timed out waiting for reply.");
+        Outcome::TimedOut
+    };
+
+    if tag.is_none()
+        && let Some(store) = &store
+        && let Err(e) = offset::save(store, &cfg.bot_token, offset)
+    {
+        tracing::warn!("could not save the update offset: {e:#}");
+    }
+
+    if let Some(tally) = &tally {
+        audit.entry.votes = tally
+            .votes()
+            .map(|(user_id, approve)| audit::Vote { user_id, approve })
+            .collect();
+    }
+
+    if let Some(store) = &store {
+        for prompt in &tracked {
+            pending::remove(store, prompt);
+        }
+    }
+    // Other endings (e.g. suppressed) drop the claim, so identical prompts are sent again.
+    let ended = match outcome {
+        Outcome::Replied | Outcome::Exit(_) if answered.is_some() => Some(dedupe::Ended::Answered),
+        Outcome::TimedOut => Some(dedupe::Ended::TimedOut),
+        Outcome::Cancelled => Some(dedupe::Ended::Cancelled),
+        _ => None,
+    };
+    if let (Some(claim), Some(ended)) = (claim, ended) {
+        let exit_code = match outcome {
+            Outcome::Exit(code) => Some(code),
+            _ => None,
+        };
+        claim.finish(ended, answered, exit_code);
+    }
+    unpin_messages(&client, &pinned).await;
+    if args.ephemeral || args.secret {
+        let mut doomed = sent_messages;
+        if args.delete_reply || args.secret {
+            doomed.extend(answer_messages);
+        }
+        delete_messages(&client, &doomed).await;
+    }
+    Ok(exit_map.outcome(outcome))
+}
+
+/// Under `--expect-location` or `--expect-contact`, the try-again message for a reply that
+/// doesn't share what was asked for.
+fn missing_share(args: &Args, msg: &telegram::Message) -> Option<&'static str> {
+    if args.expect_location && msg.location.is_none() {
+        Some(LOCATION_INVALID_TEXT)
+    } else if args.expect_contact && msg.contact.is_none() {
+        Some(CONTACT_INVALID_TEXT)
+    } else {
+        None
+    }
+}
+
+/// Update a collected live location `reply` to where `update` (an edit of its message) says it
+/// has moved. Returns whether it applied. Under `secret` the move isn't reported.
+fn move_location(
+    update: &telegram::Update,
+    reply: &mut Reply,
+    secret: bool,
+    status: &mut StatusStream,
+) -> Result<bool> {
+    let Some(edit) = update.edited_message.as_ref() else {
+        return Ok(false);
+    };
+    let Some(location) = &edit.location else {
+        return Ok(false);
+    };
+    if edit.chat.id != reply.chat_id || reply.message_id != Some(edit.message_id) {
+        return Ok(false);
+    }
+    if !secret {
+        status.emit(Event::Location {
+            chat_id: edit.chat.id,
+            message_id: edit.message_id,
+            latitude: location.latitude,
+            longitude: location.longitude,
+        });
+    }
+    reply.text = serde_json::to_string(location)?;
+    reply.location = Some(location.clone());
+    Ok(true)
+}
+
+/// Under `--accept-edits`, the wait after a single text answer for edits of it.
+struct AwaitEdits<'a> {
+    client: &'a TelegramClient,
+    args: &'a Args,
+    cfg: &'a config::Config,
+    schema: Option<&'a jsonschema::Validator>,
+    grace: Duration,
+}
+
+impl AwaitEdits<'_> {
+    /// Poll for `grace`, applying each edit of `reply` that arrives. Other updates are
+    /// confirmed and ignored, as they would be once the run ends.
+    async fn run(
+        &self,
+        reply: &mut Reply,
+        offset: &mut i64,
+        status: &mut StatusStream,
+    ) -> Result<()> {
+        let deadline = Instant::now() + self.grace;
+        let mut backoff = backoff::Backoff::default();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            let updates = match self.client.get_updates(*offset, remaining.as_secs()).await {
+                Ok(updates) => {
+                    backoff.reset();
+                    updates
+                }
+                // The answer is in; a network blip only costs the chance of an edit.
+                Err(e) if backoff::transient(&e) => {
+                    let delay = backoff.fail().min(remaining);
+                    tracing::warn!("polling for edits failed ({e:#}); retrying");
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            for update in &updates {
+                *offset = update.update_id + 1;
+                accept_edit(
+                    self.args,
+                    self.cfg,
+                    self.schema,
+                    update,
+                    reply,
+                    self.grace,
+                    status,
+                )?;
+            }
+        }
+    }
+}
+
+/// Apply `update` to `reply` if it is an edit of that answer's message, made within `grace` of
+/// sending it, whose new text is a valid answer. Returns whether it was applied. Invalid edits
+/// are ignored with a warning, since the recipient isn't asked again.
+fn accept_edit(
+    args: &Args,
+    cfg: &config::Config,
+    schema: Option<&jsonschema::Validator>,
+    update: &telegram::Update,
+    reply: &mut Reply,
+    grace: Duration,
+    status: &mut StatusStream,
+) -> Result<bool> {
+    let Some(edit) = update.edited_message.as_ref() else {
+        return Ok(false);
+    };
+    let Some(text) = edit.text.as_deref() else {
+        return Ok(false);
+    };
+    let within_grace = edit
+        .edit_date
+        .is_some_and(|at| at.saturating_sub(edit.date) <= grace.as_secs() as i64);
+    if edit.chat.id != reply.chat_id
+        || reply.message_id != Some(edit.message_id)
+        || reply.file.is_some()
+        || !edit
+            .from
+            .as_ref()
+            .is_some_and(|u| cfg.may_answer(edit.chat.id, u.id))
+        || !within_grace
+    {
+        return Ok(false);
+    }
+    let normalized = match check_reply(args, schema, text) {
+        Ok(normalized) => normalized,
+        Err(_) => {
+            tracing::warn!("ignoring an edit that is not a valid answer");
+            return Ok(false);
+        }
+    };
+    let translated = cfg
+        .translate
+        .as_ref()
+        .filter(|_| !args.secret)
+        .and_then(|t| translate::process(t, text).translated);
+    status.emit(Event::Edited {
+        chat_id: edit.chat.id,
+        message_id: edit.message_id,
+        text: if args.secret {
+            String::new()
+        } else {
+            text.to_string()
+        },
+        redacted: args.secret,
+    });
+    reply.entities = match translated {
+        Some(_) => Vec::new(),
+        None => entities::resolve(text, &edit.entities),
+    };
+    reply.text = normalized
+        .or(translated)
+        .unwrap_or_else(|| text.to_string());
+    Ok(true)
+}
+
+/// Advance `offset` over `updates` up to (not past) the first one that belongs to another
+/// correlated prompt.
+fn confirmable_offset(mut offset: i64, updates: &[telegram::Update], tag: &str) -> i64 {
+    for update in updates {
+        if correlate::belongs_to_other(update, tag) {
+            break;
+        }
+        offset = offset.max(update.update_id + 1);
+    }
+    offset
+}
+
+/// Record an approve/reject button press on the prompt. Returns the outcome once the quorum is
+/// decided either way, after writing the tally.
+async fn count_vote(
+    client: &TelegramClient,
+    args: &Args,
+    cfg: &config::Config,
+    update: &telegram::Update,
+    prompt_messages: &BTreeMap<i64, i64>,
+    tally: &mut quorum::Tally,
+    status: &mut StatusStream,
+) -> Result<Option<Outcome>> {
+    let Some(query) = &update.callback_query else {
+        return Ok(None);
+    };
+    let on_prompt = query
+        .message
+        .as_ref()
+        .is_none_or(|m| prompt_messages.get(&m.chat.id) == Some(&m.message_id));
+    let Some(approve) = quorum::parse_vote(query)
+        .filter(|_| on_prompt && cfg.may_answer(query.chat_id(), query.from.id))
+    else {
+        return Ok(None);
+    };
+
+    tally.record(&query.from, approve);
+    // Best effort: Telegram rejects answers to queries that are too old.
+    let _ = client
+        .answer_callback_query(&query.id, &format!("Vote recorded: {}", tally.summary()))
+        .await;
+    status.emit(Event::Vote {
+        user_id: query.from.id,
+        approve,
+        approvals: tally.approvals(),
+        rejections: tally.rejections(),
+        quorum: tally.quorum(),
+    });
+
+    let outcome = match tally.decision() {
+        Some(true) => Outcome::Replied,
+        Some(false) => Outcome::Rejected,
+        None => return Ok(None),
+    };
+    crate::write_reply(args, &tally.render())?;
+    Ok(Some(outcome))
+}
+
+/// Send a reminder, as a reply to the prompt, to every recipient who hasn't answered yet.
+/// Returns the reminders sent, as `(chat_id, message_id)`.
+async fn remind(
+    client: &TelegramClient,
+    cfg: &config::Config,
+    prompt_messages: &BTreeMap<i64, i64>,
+    collected: &[Reply],
+    status: &mut StatusStream,
+) -> Result<Vec<(i64, i64)>> {
+    let mut sent = Vec::new();
+    for (&chat_id, &prompt_id) in prompt_messages {
+        if collected.iter().any(|r| r.chat_id == chat_id) {
+            continue;
+        }
+        let opts = telegram::SendOptions {
+            reply_to: Some(prompt_id),
+            thread_id: cfg.thread_id,
+            ..Default::default()
+        };
+        let message_id = client.send_message(chat_id, REMINDER_TEXT, &opts).await?;
+        status.emit(Event::Reminder {
+            chat_id,
+            message_id,
+        });
+        sent.push((chat_id, message_id));
+    }
+    Ok(sent)
+}
+
+/// Record the prompts as pending until the wait is over, for `teleprompt recover` (or a `serve`
+/// standby, which answers for question `id`).
+fn track(
+    store: Option<&state::StateStore>,
+    cfg: &config::Config,
+    messages: &BTreeMap<i64, i64>,
+    deadline: i64,
+    tag: Option<&str>,
+    id: Option<&str>,
+) -> Vec<pending::Prompt> {
+    let Some(store) = store else {
+        return Vec::new();
+    };
+    let mut tracked = Vec::new();
+    for (&chat_id, &message_id) in messages {
+        let prompt = pending::Prompt {
+            tag: tag.map(str::to_string),
+            id: id.map_or(serde_json::Value::Null, Into::into),
+            source: Some(match id {
+                Some(_) => pending::Source::Serve,
+                None => pending::Source::Prompt,
+            }),
+            ..pending::Prompt::new(&cfg.bot_token, chat_id, message_id, deadline)
+        };
+        pending::save(store, &prompt);
+        tracked.push(prompt);
+    }
+    tracked
+}
+
+/// Send a message `flush` took from the spool, returning its message id in each chat.
+pub async fn deliver_queued(
+    client: &TelegramClient,
+    cfg: &config::Config,
+    send: &SendArgs,
+    message: &str,
+    status: &mut StatusStream,
+) -> Result<Vec<i64>> {
+    let sent = deliver(
+        client,
+        cfg,
+        send,
+        message,
+        DeliverOptions::default(),
+        status,
+    )
+    .await?;
+    Ok(sent.messages.into_values().collect())
+}
+
+/// Write `--id-file`: the prompt's message id in each chat, one per line.
+fn write_id_file(path: &Path, messages: &BTreeMap<i64, i64>) -> Result<()> {
+    let ids: String = messages.values().map(|id| format!("{id}\n")).collect();
+    crate::create_parent_dir(path, None)?;
+    crate::write_file(path, ids.as_bytes(), None)
+        .with_context(|| format!("write --id-file {}", path.display()))
+}
+
+/// Pin the prompts for `--pin`, returning the ones that were pinned. Best effort: in groups the
+/// bot needs to be an admin allowed to pin messages.
+async fn pin_messages(client: &TelegramClient, prompts: &BTreeMap<i64, i64>) -> Vec<(i64, i64)> {
+    let mut pinned = Vec::new();
+    for (&chat_id, &message_id) in prompts {
+        match client.pin_chat_message(chat_id, message_id).await {
+            Ok(()) => pinned.push((chat_id, message_id)),
+            Err(e) => tracing::warn!("could not pin message {message_id}: {e:#}"),
+        }
+    }
+    pinned
+}
+
+async fn unpin_messages(client: &TelegramClient, messages: &[(i64, i64)]) {
+    for &(chat_id, message_id) in messages {
+        if let Err(e) = client.unpin_chat_message(chat_id, message_id).await {
+            tracing::warn!("could not unpin message {message_id}: {e:#}");
+        }
+    }
+}
+
+/// `--ephemeral` cleanup. Best effort: bots can't delete messages older than 48 hours, nor
+/// other people's messages in groups where they aren't an admin.
+async fn delete_messages(client: &TelegramClient, messages: &[(i64, i64)]) {
+    for &(chat_id, message_id) in messages {
+        if let Err(e) = client.delete_message(chat_id, message_id).await {
+            tracing::warn!("could not delete message {message_id}: {e:#}");
+        }
+    }
+}
+
+/// Abort on the cancel command, acknowledging it in the chat if `cancel_reply` is set.
+async fn cancel(
+    client: &TelegramClient,
+    cfg: &config::Config,
+    msg: &telegram::Message,
+    status: &mut StatusStream,
+) -> Result<Outcome> {
+    status.emit(Event::Cancelled {
+        chat_id: msg.chat.id,
+    });
+    if let Some(text) = &cfg.cancel_reply {
+        let opts = telegram::SendOptions {
+            reply_to: Some(msg.message_id),
+            thread_id: msg.message_thread_id,
+            ..Default::default()
+        };
+        // Best effort: the run is cancelled either way.
+        if let Err(e) = client.send_message(msg.chat.id, text, &opts).await {
+            tracing::warn!("could not acknowledge cancel: {e:#}");
+        }
+    }
+    tracing::info!("Cancelled by the recipient.");
+    Ok(Outcome::Cancelled)
+}
+
+/// Check a typed reply against `--choices`, `--expect`, `--number` and `--json-reply`. Returns
+/// the text to write instead of the reply, if it is normalized, or the try-again message.
+fn check_reply(
+    args: &Args,
+    schema: Option<&jsonschema::Validator>,
+    text: &str,
+) -> std::result::Result<Option<String>, String> {
+    if !args.choices.is_empty() {
+        return match choices::match_text(&args.choices, text) {
+            Some(i) => Ok(Some(args.choices[i].clone())),
+            None => Err(choices::invalid_text(&args.choices)),
+        };
+    }
+    if let Some(expect) = &args.expect
+        && !expect.is_match(text.trim())
+    {
+        return Err(invalid_text(expect));
+    }
+    if args.number {
+        return number::check(text, args.min, args.max).map(Some);
+    }
+    if args.json_reply {
+        return json_reply::check(text, schema).map(Some);
+    }
+    Ok(None)
+}
+
+/// Tell the sender of a reply that fails `--choices`, `--expect`, `--number` or
+/// `--json-reply` to try again, with `text`. Returns the message sent, if sending worked.
+async fn ask_again(
+    client: &TelegramClient,
+    text: &str,
+    msg: &telegram::Message,
+    status: &mut StatusStream,
+) -> Result<Option<(i64, i64)>> {
+    status.emit(Event::Invalid {
+        chat_id: msg.chat.id,
+    });
+    let opts = telegram::SendOptions {
+        reply_to: Some(msg.message_id),
+        thread_id: msg.message_thread_id,
+        ..Default::default()
+    };
+    tracing::info!("invalid reply; asking again");
+    // Best effort: the wait goes on either way.
+    match client.send_message(msg.chat.id, text, &opts).await {
+        Ok(message_id) => Ok(Some((msg.chat.id, message_id))),
+        Err(e) => {
+            tracing::warn!("could not ask for a valid answer: {e:#}");
+            Ok(None)
+        }
+    }
+}
+
+/// The try-again message, showing the pattern as given (without the anchoring added to it).
+fn invalid_text(expect: &regex::Regex) -> String {
+    let pattern = expect.as_str();
+    let pattern = pattern
+        .strip_prefix("^(?:")
+        .and_then(|p| p.strip_suffix(")$"))
+        .unwrap_or(pattern);
+    format!("⚠️ That's not a valid answer. Please reply with something matching: {pattern}")
+}
+
+/// Whether `msg` was sent as a Telegram reply to the prompt in its chat.
+fn replies_to_prompt(msg: &telegram::Message, prompt_messages: &BTreeMap<i64, i64>) -> bool {
+    msg.reply_to_message
+        .as_ref()
+        .is_some_and(|p| prompt_messages.get(&msg.chat.id) == Some(&p.message_id))
+}
+
+/// Under `--require-reply`, decide whether an update answers the prompt: either a reply threaded
+/// to it, or an un-threaded message (such as a quick reply from a phone notification, which
+/// carries no reply metadata) that the user confirmed with a button.
+async fn strict_reply(
+    client: &TelegramClient,
+    cfg: &config::Config,
+    update: &telegram::Update,
+    prompt_messages: &BTreeMap<i64, i64>,
+    candidates: &mut HashMap<(i64, i64), telegram::Message>,
+) -> Result<Option<telegram::Message>> {
+    if let Some(query) = &update.callback_query
+        && cfg.may_answer(query.chat_id(), query.from.id)
+        && let Some(id) = query
+            .data
+            .as_deref()
+            .and_then(|d| d.strip_prefix(CONFIRM_PREFIX))
+            .and_then(|id| id.parse::<i64>().ok())
+    {
+        let msg = candidates.remove(&(query.chat_id(), id));
+        let note = match msg {
+            Some(_) => "Using this as the answer.",
+            None => "That prompt is no longer waiting.",
+        };
+        // Best effort: Telegram rejects answers to queries that are too old.
+        let _ = client.answer_callback_query(&query.id, note).await;
+        return Ok(msg);
+    }
+
+    let Some(msg) = cfg.reply_message(update) else {
+        return Ok(None);
+    };
+    match &msg.reply_to_message {
+        Some(_) if replies_to_prompt(msg, prompt_messages) => Ok(Some(msg.clone())),
+        // A reply to some other message is unrelated chatter.
+        Some(_) => Ok(None),
+        None if msg.text.is_some() || msg.media().is_some() => {
+            let button = serde_json::json!({
+                "text": "✅ Use as answer",
+                "callback_data": format!("{CONFIRM_PREFIX}{}", msg.message_id),
+            });
+            offer_confirm(client, msg, vec![button]).await?;
+            candidates.insert((msg.chat.id, msg.message_id), msg.clone());
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Under `--correlate`, decide whether an update answers the prompt: a reply threaded to it,
+/// or an un-threaded message that the user assigned to it with a button. Whichever run sees
+/// such a message first offers one button per correlated prompt waiting in that chat, carrying
+/// the prompt's tag, so a press reaches the run it is meant for. The offer replies to the
+/// message, so that run reads the answer back from the press.
+async fn correlated_reply(
+    client: &TelegramClient,
+    cfg: &config::Config,
+    store: Option<&state::StateStore>,
+    update: &telegram::Update,
+    prompt_messages: &BTreeMap<i64, i64>,
+    tag: &str,
+) -> Result<Option<telegram::Message>> {
+    if let Some(query) = &update.callback_query
+        && cfg.may_answer(query.chat_id(), query.from.id)
+        && query
+            .data
+            .as_deref()
+            .and_then(|d| d.strip_prefix(CONFIRM_PREFIX))
+            == Some(tag)
+    {
+        let msg = query
+            .message
+            .as_ref()
+            .and_then(|offer| offer.reply_to_message.as_deref())
+            .filter(|m| prompt_messages.contains_key(&m.chat.id))
+            .filter(|m| {
+                m.from
+                    .as_ref()
+                    .is_some_and(|u| cfg.may_answer(m.chat.id, u.id))
+            })
+            .cloned();
+        let note = match msg {
+            Some(_) => "Using this as the answer.",
+            None => "That message can't be found anymore; reply to the prompt instead.",
+        };
+        // Best effort: Telegram rejects answers to queries that are too old.
+        let _ = client.answer_callback_query(&query.id, note).await;
+        return Ok(msg);
+    }
+
+    let Some(msg) = cfg.reply_message(update) else {
+        return Ok(None);
+    };
+    if msg.reply_to_message.is_some() {
+        // Only a threaded reply to our own prompt counts; others may be someone else's.
+        return Ok(replies_to_prompt(msg, prompt_messages).then(|| msg.clone()));
+    }
+    if !prompt_messages.contains_key(&msg.chat.id) || (msg.text.is_none() && msg.media().is_none())
+    {
+        return Ok(None);
+    }
+    let now = Utc::now().timestamp();
+    let mut tags: Vec<String> = store
+        .and_then(|store| pending::list(store, &cfg.bot_token).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|p| p.chat_id == msg.chat.id && p.deadline >= now)
+        .filter_map(|p| p.tag)
+        .collect();
+    if !tags.iter().any(|t| t == tag) {
+        tags.push(tag.to_string());
+    }
+    let several = tags.len() > 1;
+    let buttons = tags
+        .iter()
+        .map(|t| {
+            serde_json::json!({
+                "text": if several { format!("✅ Answers {t}") } else { "✅ Use as answer".to_string() },
+                "callback_data": format!("{CONFIRM_PREFIX}{t}"),
+            })
+        })
+        .collect();
+    offer_confirm(client, msg, buttons).await?;
+    Ok(None)
+}
+
+/// Ask whether `msg`, which wasn't sent as a reply, answers a prompt, with `buttons` to say
+/// which. The question replies to `msg`.
+async fn offer_confirm(
+    client: &TelegramClient,
+    msg: &telegram::Message,
+    buttons: Vec<serde_json::Value>,
+) -> Result<()> {
+    let text = match buttons.len() {
+        1 => "This wasn't sent as a reply to the prompt. Use it as the answer?",
+        _ => "This wasn't sent as a reply to a prompt. Which one does it answer?",
+    };
+    let rows: Vec<_> = buttons.into_iter().map(|b| vec![b]).collect();
+    let opts = telegram::SendOptions {
+        reply_markup: Some(serde_json::json!({ "inline_keyboard": rows })),
+        reply_to: Some(msg.message_id),
+        thread_id: msg.message_thread_id,
+        ..Default::default()
+    };
+    client.send_message(msg.chat.id, text, &opts).await?;
+    Ok(())
+}
+
+/// A reply accepted as an answer; serialized as the `--output json` envelope.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Reply {
+    /// The reply text (translated, if it was); empty for media.
+    #[serde(rename = "reply")]
+    text: String,
+    chat_id: i64,
+    from: Option<telegram::User>,
+    /// The answering message; `None` for reactions.
+    message_id: Option<i64>,
+    /// RFC 3339 time Telegram says the answering message was sent; `None` for reactions.
+    date: Option<String>,
+    prompt_message_id: Option<i64>,
+    /// RFC 3339 times the prompt was sent and the answer received.
+    sent_at: String,
+    replied_at: String,
+    elapsed_ms: u64,
+    /// The shared location, under `--expect-location`; `reply` is it as JSON.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<telegram::Location>,
+    /// The shared contact, under `--expect-contact`; `reply` is it as JSON.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contact: Option<telegram::Contact>,
+    /// Links, mentions, code and the like in the reply text, unless it was translated.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    entities: Vec<entities::Entity>,
+    /// Where a media reply was saved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<PathBuf>,
+    /// The emoji, for answers given by reacting to the prompt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reaction: Option<String>,
+    /// Exit code configured for the reaction, if any.
+    #[serde(skip)]
+    exit_code: Option<i32>,
+}
+
+impl Reply {
+    /// Fill in the timing and prompt fields, as of now.
+    fn stamp(mut self, prompt_messages: &BTreeMap<i64, i64>, sent_at: DateTime<Utc>) -> Self {
+        let now = Utc::now();
+        self.prompt_message_id = prompt_messages.get(&self.chat_id).copied();
+        self.sent_at = sent_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        self.replied_at = now.to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        self.elapsed_ms = u64::try_from((now - sent_at).num_milliseconds()).unwrap_or(0);
+        self
+    }
+}
+
+/// How answers are written: per `--output`, or shaped by a `--format` template.
+enum Output {
+    Text,
+    Json,
+    Template(template::Template),
+}
+
+impl Output {
+    /// A reply as one line (with newline) of templated or JSON output.
+    fn line(&self, reply: &Reply) -> String {
+        let mut out = match self {
+            Output::Template(t) => {
+                t.render(&serde_json::to_value(reply).expect("reply serializes"))
+            }
+            _ => serde_json::to_string(reply).expect("reply serializes"),
+        };
+        out.push('\n');
+        out
+    }
+}
+
+/// Under `--dedupe-window`, end the run with the answer to the identical prompt `fp` sent
+/// within `window`: the one it got, or the one it gets while this run waits (up to `timeout`).
+/// `None` if there is no such prompt, or its run failed, so this one should be sent.
+#[allow(clippy::too_many_arguments)]
+async fn reuse_answer(
+    args: &Args,
+    output: &Output,
+    store: &state::StateStore,
+    fp: &str,
+    window: Duration,
+    timeout: Duration,
+    status: &mut StatusStream,
+    audit: &mut audit::Audit,
+) -> Result<Option<Outcome>> {
+    let now = Utc::now().timestamp();
+    let Some(mut entry) = dedupe::lookup(store, fp, window, now)? else {
+        return Ok(None);
+    };
+    let cached = entry.ended.is_some();
+    status.emit(Event::Deduplicated { cached });
+    if !cached {
+        tracing::info!("An identical prompt is already waiting for an answer; waiting on it.");
+        let until = entry.deadline.min(now + timeout.as_secs() as i64);
+        match dedupe::attach(store, fp, &entry, until).await? {
+            Some(ended) => entry = ended,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(match (entry.ended, entry.reply) {
+        (Some(dedupe::Ended::Answered), Some(reply)) => {
+            let reply: Reply = serde_json::from_value(reply).context("parse the reused answer")?;
+            audit.answer(&reply, args.secret);
+            write_single(args, output, &reply)?;
+            entry.exit_code.map_or(Outcome::Replied, Outcome::Exit)
+        }
+        (Some(dedupe::Ended::Cancelled), _) => Outcome::Cancelled,
+        _ => {
+            status.emit(Event::Timeout {
+                timeout_s: timeout.as_secs(),
+            });
+            Outcome::TimedOut
+        }
+    }))
+}
+
+/// Write the answer of a single-reply run: the text (or saved media path), its envelope, or
+/// the rendered template.
+fn write_single(args: &Args, output: &Output, reply: &Reply) -> Result<()> {
+    match output {
+        Output::Json | Output::Template(_) => crate::write_reply(args, &output.line(reply)),
+        // Captions answered with under --caption-as-reply are written as text.
+        Output::Text => match reply.file.as_ref().filter(|_| reply.text.is_empty()) {
+            Some(_) if args.capture => Ok(()),
+            Some(path) => {
+                println!("{}", path.display());
+                Ok(())
+            }
+            None => crate::write_reply(args, &reply.text),
+        },
+    }
+}
+
+/// A press of one of the `--choices` buttons on the prompt by someone who may answer,
+/// acknowledged and reported on the status stream.
+async fn press_choice(
+    client: &TelegramClient,
+    args: &Args,
+    cfg: &config::Config,
+    update: &telegram::Update,
+    prompt_messages: &BTreeMap<i64, i64>,
+    status: &mut StatusStream,
+) -> Result<Option<Reply>> {
+    let Some(query) = &update.callback_query else {
+        return Ok(None);
+    };
+    let on_prompt = query
+        .message
+        .as_ref()
+        .is_none_or(|m| prompt_messages.get(&m.chat.id) == Some(&m.message_id));
+    let Some(i) = choices::parse_press(query, &args.choices)
+        .filter(|_| on_prompt && cfg.may_answer(query.chat_id(), query.from.id))
+    else {
+        return Ok(None);
+    };
+    let choice = &args.choices[i];
+    // Best effort: Telegram rejects answers to queries that are too old.
+    let _ = client
+        .answer_callback_query(&query.id, &format!("Got it: {choice}"))
+        .await;
+    status.emit(Event::Reply {
+        chat_id: query.chat_id(),
+        text: if args.secret {
+            String::new()
+        } else {
+            choice.clone()
+        },
+        language: None,
+        translated: None,
+        file: None,
+        reaction: None,
+        redacted: args.secret,
+    });
+    Ok(Some(Reply {
+        chat_id: query.chat_id(),
+        text: choice.clone(),
+        from: Some(query.from.clone()),
+        ..Default::default()
+    }))
+}
+
+/// Report a reaction answer on the status stream.
+fn accept_reaction(reacted: &reaction::Reacted, status: &mut StatusStream) -> Result<Reply> {
+    status.emit(Event::Reply {
+        chat_id: reacted.chat_id,
+        text: reacted.answer.text().to_string(),
+        language: None,
+        translated: None,
+        file: None,
+        reaction: Some(reacted.emoji.to_string()),
+        redacted: false,
+    });
+    Ok(Reply {
+        chat_id: reacted.chat_id,
+        text: reacted.answer.text().to_string(),
+        from: Some(reacted.user.clone()),
+        reaction: Some(reacted.emoji.to_string()),
+        exit_code: reacted.answer.exit_code(),
+        ..Default::default()
+    })
+}
+
+/// Translate or download a reply and report it on the status stream. Returns `None` for
+/// messages with nothing usable in them (e.g. stickers), so waiting continues.
+async fn accept_reply(
+    client: &TelegramClient,
+    args: &Args,
+    cfg: &config::Config,
+    msg: &telegram::Message,
+    status: &mut StatusStream,
+) -> Result<Option<Reply>> {
+    if let Some(location) = msg.location.as_ref().filter(|_| args.expect_location) {
+        let text = serde_json::to_string(location)?;
+        status.emit(Event::Reply {
+            chat_id: msg.chat.id,
+            text: if args.secret {
+                String::new()
+            } else {
+                text.clone()
+            },
+            language: None,
+            translated: None,
+            file: None,
+            reaction: None,
+            redacted: args.secret,
+        });
+        return Ok(Some(Reply {
+            chat_id: msg.chat.id,
+            text,
+            location: Some(location.clone()),
+            from: msg.from.clone(),
+            message_id: Some(msg.message_id),
+            date: message_date(msg),
+            ..Default::default()
+        }));
+    }
+
+    if let Some(contact) = msg.contact.as_ref().filter(|_| args.expect_contact) {
+        let text = serde_json::to_string(contact)?;
+        status.emit(Event::Reply {
+            chat_id: msg.chat.id,
+            text: if args.secret {
+                String::new()
+            } else {
+                text.clone()
+            },
+            language: None,
+            translated: None,
+            file: None,
+            reaction: None,
+            redacted: args.secret,
+        });
+        return Ok(Some(Reply {
+            chat_id: msg.chat.id,
+            text,
+            contact: Some(contact.clone()),
+            from: msg.from.clone(),
+            message_id: Some(msg.message_id),
+            date: message_date(msg),
+            ..Default::default()
+        }));
+    }
+
+    if let Some(text) = reply_text(args, msg) {
+        // Secrets must not reach a translation command.
+        let translation = cfg
+            .translate
+            .as_ref()
+            .filter(|_| !args.secret)
+            .map(|t| translate::process(t, text))
+            .unwrap_or_default();
+        // A caption answers by its text; its media is kept only if asked for.
+        let file = match msg.media() {
+            Some(media) if msg.text.is_none() && args.media_dir.is_some() => {
+                Some(save_media(client, args, media).await?)
+            }
+            _ => None,
+        };
+        status.emit(Event::Reply {
+            chat_id: msg.chat.id,
+            text: if args.secret {
+                String::new()
+            } else {
+                text.to_string()
+            },
+            language: translation.language,
+            translated: translation.translated.clone(),
+            file: file.clone(),
+            reaction: None,
+            redacted: args.secret,
+        });
+        // Entity offsets only fit the original text.
+        let entities = match (&translation.translated, &msg.text) {
+            (Some(_), _) => Vec::new(),
+            (None, Some(_)) => entities::resolve(text, &msg.entities),
+            (None, None) => entities::resolve(text, &msg.caption_entities),
+        };
+        // Downstream parsing expects the expected language, so prefer the translation.
+        return Ok(Some(Reply {
+            chat_id: msg.chat.id,
+            text: translation.translated.unwrap_or_else(|| text.to_string()),
+            entities,
+            file,
+            from: msg.from.clone(),
+            message_id: Some(msg.message_id),
+            date: message_date(msg),
+            ..Default::default()
+        }));
+    }
+
+    if let Some(file) = msg.media() {
+        let path = save_media(client, args, file).await?;
+        status.emit(Event::Reply {
+            chat_id: msg.chat.id,
+            text: String::new(),
+            language: None,
+            translated: None,
+            file: Some(path.clone()),
+            reaction: None,
+            redacted: false,
+        });
+        return Ok(Some(Reply {
+            chat_id: msg.chat.id,
+            from: msg.from.clone(),
+            message_id: Some(msg.message_id),
+            date: message_date(msg),
+            file: Some(path),
+            ..Default::default()
+        }));
+    }
+
+    Ok(None)
+}
+
+/// When `msg` was sent, as RFC 3339 UTC.
+fn message_date(msg: &telegram::Message) -> Option<String> {
+    DateTime::from_timestamp(msg.date, 0)
+        .filter(|_| msg.date > 0)
+        .map(|d| d.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+}
+
+/// The text a message answers with: its text, or under `--caption-as-reply` the caption of
+/// its media.
+fn reply_text<'a>(args: &Args, msg: &'a telegram::Message) -> Option<&'a str> {
+    msg.text.as_deref().or_else(|| {
+        msg.caption
+            .as_deref()
+            .filter(|c| args.caption_as_reply && !c.trim().is_empty())
+    })
+}
+
+/// `--collect-for` output: one reply per line (newlines inside a reply become spaces; media
+/// replies show the saved path), a JSON array, or one rendered template per reply.
+fn render_collected(replies: &[Reply], output: &Output) -> String {
+    match output {
+        Output::Json => {
+            let mut out = serde_json::to_string(replies).expect("replies serialize");
+            out.push('\n');
+            out
+        }
+        Output::Template(_) => replies.iter().map(|r| output.line(r)).collect(),
+        Output::Text => replies
+            .iter()
+            .map(|r| match r.file.as_ref().filter(|_| r.text.is_empty()) {
+                Some(path) => format!("{}\n", path.display()),
+                None => format!("{}\n", r.text.replace(['\r', '\n'], " ")),
+            })
+            .collect(),
+    }
+}
+
+/// Additions to a prompt beyond what `SendArgs` describes.
+#[derive(Default)]
+struct DeliverOptions<'a> {
+    /// Markup put on every message sent.
+    reply_markup: Option<serde_json::Value>,
+    /// Correlation tag appended to the text.
+    tag: Option<String>,
+    /// Keep the prompt from being forwarded or saved.
+    protect_content: bool,
+    /// Line shown above the prompt, e.g. why it is being sent.
+    note: Option<String>,
+    /// Time left to answer, shown in a footer kept up to date by `Delivered::countdown`.
+    deadline: Option<Duration>,
+    /// In-memory file uploaded after the others, as `(name, contents)`: stdin under
+    /// `--stdin-as`, or when it is binary.
+    upload: Option<(&'a str, &'a [u8])>,
+}
+
+/// A prompt sent by `deliver`.
+struct Delivered {
+    /// Prompt message id per recipient chat.
+    messages: BTreeMap<i64, i64>,
+    /// Every message sent (split parts, uploads), as `(chat_id, message_id)`.
+    all: Vec<(i64, i64)>,
+    /// Set when a deadline was requested.
+    countdown: Option<deadline::Countdown>,
+}
+
+/// Send the prompt text (with tag and footers, if any) and any attachments to every recipient,
+/// and report each on the status stream.
+async fn deliver(
+    client: &TelegramClient,
+    cfg: &config::Config,
+    send: &SendArgs,
+    message: &str,
+    extra: DeliverOptions<'_>,
+    status: &mut StatusStream,
+) -> Result<Delivered> {
+    let prompt_id = ulid::Ulid::new();
+    let (mode, code) = match &send.code {
+        Some(lang) => {
+            let (mode, block) = send.parse_mode.code_block(lang, message);
+            (mode, Some(block))
+        }
+        None => (send.parse_mode, None),
+    };
+    // A code block can't be split across messages without breaking its markup.
+    let long_as_file = ((send.long_as_file || cfg.long_as_file)
+        && telegram::utf16_len(message) > cfg.long_as_file_threshold)
+        || code
+            .as_deref()
+            .is_some_and(|c| telegram::utf16_len(c) > telegram::MAX_MESSAGE_LEN);
+
+    let mut media = Vec::new();
+    let body = if long_as_file {
+        media.push(Media::Bytes(LONG_FILE_NAME, message.as_bytes()));
+        mode.escape(&long_summary(message))
+    } else {
+        code.unwrap_or_else(|| message.to_string())
+    };
+    media.extend(send.photo.iter().map(|p| Media::Photo(p)));
+    media.extend(send.attach.iter().map(|p| Media::Document(p)));
+    media.extend(extra.upload.map(|(name, data)| Media::Bytes(name, data)));
+    let body = format!(
+        "{}{body}{}",
+        mode.escape(cfg.message_prefix.as_deref().unwrap_or_default()),
+        mode.escape(cfg.message_suffix.as_deref().unwrap_or_default())
+    );
+
+    let mut text = if send.canary {
+        format!("{}\n\n{body}", mode.escape(CANARY_LABEL))
+    } else {
+        body
+    };
+    if let Some(note) = &extra.note {
+        text = format!("{}\n\n{text}", mode.escape(note));
+    }
+    if let Some(tag) = &extra.tag {
+        text.push_str(&mode.escape(&format!("\n\n{tag}")));
+    }
+    // The deadline footer goes before the signature, which stays last.
+    let head = text;
+    let signature = match &cfg.signing_secret {
+        Some(secret) => mode.escape(&sign::footer(secret, prompt_id)),
+        None => String::new(),
+    };
+    let shown = extra.deadline.map(|d| deadline::footer(Some(d)));
+    let text = match &shown {
+        Some(footer) => deadline::Countdown::text(&head, footer, &signature, mode),
+        None => format!("{head}{signature}"),
+    };
+    let mut countdown = shown.map(|shown| deadline::Countdown {
+        caption: text_as_caption(send, &text, &media),
+        head,
+        tail: signature,
+        mode,
+        reply_markup: extra.reply_markup.clone(),
+        messages: BTreeMap::new(),
+        shown,
+    });
+
+    let opts = telegram::SendOptions {
+        parse_mode: mode,
+        reply_markup: extra.reply_markup,
+        thread_id: cfg.thread_id,
+        protect_content: extra.protect_content,
+        ..Default::default()
+    };
+
+    let mut sent = BTreeMap::new();
+    let mut all = Vec::new();
+    for &chat_id in &cfg.user_ids {
+        let (message_id, ids) = send_prompt(client, chat_id, send, &text, &media, &opts).await?;
+        all.extend(ids.into_iter().map(|id| (chat_id, id)));
+        status.emit(Event::Sent {
+            prompt_id: prompt_id.to_string(),
+            chat_id,
+            message_id,
+            tag: extra.tag.clone(),
+        });
+        sent.insert(chat_id, message_id);
+    }
+    if let Some(c) = &mut countdown {
+        c.messages = sent.clone();
+    }
+    Ok(Delivered {
+        messages: sent,
+        all,
+        countdown,
+    })
+}
+
+/// Whether `send_prompt` sends `text` as the caption of the first upload rather than as its
+/// own message. Captions are capped well below message length, so long text (or text alongside
+/// an explicit --caption) goes out separately.
+fn text_as_caption(send: &SendArgs, text: &str, media: &[Media<'_>]) -> bool {
+    !media.is_empty()
+        && (text.is_empty()
+            || (send.caption.is_none() && telegram::utf16_len(text) <= telegram::MAX_CAPTION_LEN))
+}
+
+/// Send one recipient's copy of a prompt, returning the id of the message that represents it
+/// and the ids of every message sent.
+async fn send_prompt(
+    client: &TelegramClient,
+    chat_id: i64,
+    send: &SendArgs,
+    text: &str,
+    media: &[Media<'_>],
+    opts: &telegram::SendOptions,
+) -> Result<(i64, Vec<i64>)> {
+    if media.is_empty() {
+        let ids = client.send_message_parts(chat_id, text, opts).await?;
+        Ok((*ids.last().expect("at least one part is sent"), ids))
+    } else {
+        let (mut ids, text_id, mut caption) = if text_as_caption(send, text, media) {
+            (Vec::new(), None, Some(text))
+        } else {
+            let ids = client.send_message_parts(chat_id, text, opts).await?;
+            let last = ids.last().copied();
+            (ids, last, send.caption.as_deref())
+        };
+
+        let mut first_id = None;
+        for item in media {
+            let id = match item {
+                Media::Bytes(name, data) => {
+                    client
+                        .send_document_bytes(chat_id, name, data.to_vec(), caption, opts)
+                        .await?
+                }
+                Media::Photo(path) => client.send_photo(chat_id, path, caption, opts).await?,
+                Media::Document(path) => client.send_document(chat_id, path, caption, opts).await?,
+            };
+            first_id.get_or_insert(id);
+            ids.push(id);
+            caption = None;
+        }
+        Ok((text_id.or(first_id).expect("media is non-empty"), ids))
+    }
+}
+
+/// One upload of a prompt, in sending order.
+enum Media<'a> {
+    /// An in-memory document and its name: the prompt text itself under `--long-as-file`, or
+    /// stdin under `--stdin-as`.
+    Bytes(&'a str, &'a [u8]),
+    Photo(&'a Path),
+    Document(&'a Path),
+}
+
+/// The short message standing in for a prompt that was uploaded as a file.
+fn long_summary(message: &str) -> String {
+    let first_line = message.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+    let mut preview: String = first_line.chars().take(LONG_PREVIEW_CHARS).collect();
+    if preview.len() < first_line.len() {
+        preview.push('…');
+    }
+    format!(
+        "{preview}\n\n📄 Full prompt ({} lines, {} KB) attached as {LONG_FILE_NAME}.",
+        message.lines().count(),
+        message.len().div_ceil(1024)
+    )
+}
+
+/// Download a media reply and save it per `--out-file`/`--media-dir`.
+async fn save_media(
+    client: &TelegramClient,
+    args: &Args,
+    file: &telegram::FileRef,
+) -> Result<PathBuf> {
+    let info = client.get_file(&file.file_id).await?;
+    let file_path = info
+        .file_path
+        .context("telegram did not return a download path (files over 20 MB can't be fetched)")?;
+    let data = client.download_file(&file_path).await?;
+
+    // Never trust a sender-supplied name as a path: keep only its final component.
+    let name = file
+        .file_name
+        .as_deref()
+        .unwrap_or(&file_path)
+        .rsplit(['/', '\\'])
+        .next()
+        .filter(|n| !n.is_empty() && *n != "." && *n != "..")
+        .unwrap_or(&file.file_id)
+        .to_string();
+    crate::save_media_reply(args, &name, &data)
+}
+
+/// Record this prompt in the burst history and, if it is part of a burst, deliver a summary
+/// instead. Returns `None` when the prompt should be sent normally.
+async fn collapse_burst(
+    client: &TelegramClient,
+    store: &state::StateStore,
+    burst_cfg: &config::BurstConfig,
+    cfg: &config::Config,
+    send: &SendArgs,
+    message: &str,
+    status: &mut StatusStream,
+) -> Result<Option<Outcome>> {
+    let fp = burst::fingerprint(&send.caller, &cfg.user_ids, message);
+    // Held until the summary is sent too, so a concurrent run edits it instead of sending
+    // another one.
+    let _lock = burst::lock(store).await?;
+    let mut state = burst::BurstState::load(store)?;
+    let decision = state.record(&fp, chrono::Utc::now().timestamp(), burst_cfg);
+
+    let count = match decision {
+        burst::Decision::Send => {
+            state.save(store)?;
+            return Ok(None);
+        }
+        burst::Decision::Muted => {
+            state.save(store)?;
+            tracing::info!("Prompt is muted by the recipient; not sending.");
+            status.emit(Event::Suppressed {
+                muted: true,
+                count: 0,
+            });
+            return Ok(Some(Outcome::Suppressed));
+        }
+        burst::Decision::Summarize {
+            count,
+            summary_message_id,
+        } => {
+            let text = burst::summary_text(count, burst_cfg, message);
+            let opts = telegram::SendOptions {
+                reply_markup: Some(burst::mute_markup(&fp, burst_cfg)),
+                thread_id: cfg.thread_id,
+                ..Default::default()
+            };
+            let edited = match summary_message_id {
+                Some(id) => client
+                    .edit_message_text(cfg.primary_user_id(), id, &text, &opts)
+                    .await
+                    .is_ok(),
+                None => false,
+            };
+            if !edited {
+                // First summary for this burst, or the old one was deleted: send a new one.
+                let id = client
+                    .send_message(cfg.primary_user_id(), &text, &opts)
+                    .await?;
+                state.set_summary_message(&fp, id);
+            }
+            count
+        }
+    };
+
+    state.save(store)?;
+    tracing::info!(
+        "Prompt repeated {count} times; updated the summary message instead of sending."
+    );
+    status.emit(Event::Suppressed {
+        muted: false,
+        count,
+    });
+    Ok(Some(Outcome::Suppressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_text_shows_the_pattern_as_given() {
+        let expect = crate::parse_expect(r"\d{6}").unwrap();
+        assert_eq!(
+            invalid_text(&expect),
+            "⚠️ That's not a valid answer. Please reply with something matching: \\d{6}"
+        );
+    }
+
+    #[test]
+    fn collected_replies_render_as_lines_or_json() {
+        let replies = vec![
+            Reply {
+                chat_id: 1,
+                text: "yes\nfriday".to_string(),
+                ..Default::default()
+            },
+            Reply {
+                chat_id: 2,
+                text: String::new(),
+                file: Some(PathBuf::from("photo.jpg")),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            render_collected(&replies, &Output::Text),
+            "yes friday\nphoto.jpg\n"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render_collected(&replies, &Output::Json)).unwrap();
+        assert_eq!(json[0]["reply"], "yes\nfriday");
+        assert_eq!(json[1]["chat_id"], 2);
+        assert_eq!(json[1]["file"], "photo.jpg");
+        assert!(json[0].get("file").is_none());
+    }
+
+    #[test]
+    fn stamped_replies_carry_prompt_and_timing() {
+        let sent_at = Utc::now() - chrono::Duration::seconds(2);
+        let reply = Reply {
+            chat_id: 7,
+            text: "ok".to_string(),
+            message_id: Some(101),
+            ..Default::default()
+        }
+        .stamp(&BTreeMap::from([(7, 100)]), sent_at);
+
+        let json = serde_json::to_value(&reply).unwrap();
+        assert_eq!(json["reply"], "ok");
+        assert_eq!(json["message_id"], 101);
+        assert_eq!(json["prompt_message_id"], 100);
+        assert!(json["elapsed_ms"].as_u64().unwrap() >= 2000);
+        assert!(json["sent_at"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[test]
+    fn captions_answer_only_under_caption_as_reply() {
+        let photo = telegram::Message {
+            caption: Some("yes, looks good".to_string()),
+            photo: Some(vec![telegram::FileRef {
+                file_id: "f".to_string(),
+                file_name: None,
+            }]),
+            ..Default::default()
+        };
+        let mut args = Args::default();
+        assert_eq!(reply_text(&args, &photo), None);
+        args.caption_as_reply = true;
+        assert_eq!(reply_text(&args, &photo), Some("yes, looks good"));
+    }
+
+    #[test]
+    fn only_the_expected_kind_of_share_answers() {
+        let contact = telegram::Message {
+            contact: Some(telegram::Contact {
+                phone_number: "+4930123".to_string(),
+                first_name: "Ann".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let args = Args {
+            expect_contact: true,
+            ..Default::default()
+        };
+        assert_eq!(missing_share(&args, &contact), None);
+        assert_eq!(
+            missing_share(&args, &telegram::Message::default()),
+            Some(CONTACT_INVALID_TEXT)
+        );
+        let args = Args {
+            expect_location: true,
+            ..Default::default()
+        };
+        assert_eq!(missing_share(&args, &contact), Some(LOCATION_INVALID_TEXT));
+    }
+
+    #[test]
+    fn live_locations_move_their_collected_reply() {
+        let at = |latitude: f64| telegram::Location {
+            latitude,
+            longitude: 13.4,
+            ..Default::default()
+        };
+        let mut reply = Reply {
+            chat_id: 1,
+            message_id: Some(5),
+            location: Some(at(52.5)),
+            ..Default::default()
+        };
+        let moved = |message_id: i64| telegram::Update {
+            update_id: 1,
+            edited_message: Some(telegram::Message {
+                message_id,
+                chat: telegram::Chat { id: 1 },
+                location: Some(at(52.6)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut status = StatusStream::disabled();
+        assert!(!move_location(&moved(6), &mut reply, false, &mut status).unwrap());
+        assert!(move_location(&moved(5), &mut reply, false, &mut status).unwrap());
+        assert_eq!(reply.location, Some(at(52.6)));
+        assert_eq!(reply.text, r#"{"latitude":52.6,"longitude":13.4}"#);
+    }
+
+    #[test]
+    fn edits_within_the_grace_replace_the_answer() {
+        let cfg: config::Config = toml::from_str("user_id = 1").unwrap();
+        let edit = |chat: i64, edit_date: i64| telegram::Update {
+            update_id: 1,
+            edited_message: Some(telegram::Message {
+                message_id: 5,
+                date: 100,
+                edit_date: Some(edit_date),
+                from: Some(telegram::User {
+                    id: chat,
+                    ..Default::default()
+                }),
+                chat: telegram::Chat { id: chat },
+                text: Some("friday".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut reply = Reply {
+            chat_id: 1,
+            text: "firday".to_string(),
+            message_id: Some(5),
+            ..Default::default()
+        };
+        let grace = Duration::from_secs(30);
+        let mut status = StatusStream::disabled();
+        let mut apply = |update: &telegram::Update, reply: &mut Reply| {
+            accept_edit(
+                &Args::default(),
+                &cfg,
+                None,
+                update,
+                reply,
+                grace,
+                &mut status,
+            )
+            .unwrap()
+        };
+
+        assert!(!apply(&edit(1, 200), &mut reply), "too late");
+        assert!(!apply(&edit(2, 110), &mut reply), "another chat");
+        assert_eq!(reply.text, "firday");
+        assert!(apply(&edit(1, 110), &mut reply));
+        assert_eq!(reply.text, "friday");
+    }
+
+    #[test]
+    fn long_summary_previews_first_line_and_size() {
+        let message = format!("\n{}\nsecond line\n", "x".repeat(300));
+        let summary = long_summary(&message);
+        let (preview, rest) = summary.split_once("\n\n").unwrap();
+        assert_eq!(preview, format!("{}…", "x".repeat(LONG_PREVIEW_CHARS)));
+        assert_eq!(
+            rest,
+            "📄 Full prompt (3 lines, 1 KB) attached as prompt.txt."
+        );
+    }
+
+    /// Run a prompt for `question` against the mock API, returning how it ended and the
+    /// answer recorded.
+    async fn ask_mock(
+        config: &Path,
+        question: &str,
+        args: Args,
+    ) -> (Outcome, Option<serde_json::Value>) {
+        let args = Args {
+            timeout: args.timeout.or(Some(Duration::from_secs(10))),
+            capture: true,
+            ..args
+        };
+        let send = SendArgs {
+            message: Some(question.to_string()),
+            ..Default::default()
+        };
+        let mut audit = audit::Audit::new(config);
+        let mut status = StatusStream::disabled();
+        let outcome = run(&args, &send, config, &mut status, &mut audit, true)
+            .await
+            .unwrap();
+        (
+            outcome,
+            audit.entry.replies.last().map(|r| r["reply"].clone()),
+        )
+    }
+
+    #[tokio::test]
+    async fn a_run_waiting_for_the_lock_has_already_sent_its_prompt() {
+        let api = crate::mock_api::MockApi::start().await;
+        let config = api.config("");
+        let answer = async {
+            // Both prompts go out while the first run still waits for its answer.
+            let sent = api.sent_at_least(2).await;
+            api.message(sent[0]["message_id"].as_i64(), "one");
+            api.message(sent[1]["message_id"].as_i64(), "two");
+        };
+        let started = Instant::now();
+        let (first, second, ()) = tokio::join!(
+            ask_mock(&config, "First?", Args::default()),
+            async {
+                // Let the first run take the lock.
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                ask_mock(&config, "Second?", Args::default()).await
+            },
+            answer,
+        );
+
+        let mut answers = [first, second].map(|(outcome, reply)| {
+            assert!(matches!(outcome, Outcome::Replied));
+            reply.unwrap()
+        });
+        answers.sort_by_key(|a| a.to_string());
+        assert_eq!(
+            answers,
+            [serde_json::json!("one"), serde_json::json!("two")]
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let _ = std::fs::remove_dir_all(config.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn concurrent_correlated_runs_take_turns_polling() {
+        let api = crate::mock_api::MockApi::start().await;
+        let config = api.config("");
+        let correlated = || Args {
+            correlate: true,
+            ..Default::default()
+        };
+        let answer = async {
+            let sent = api.sent_at_least(2).await;
+            let prompt = |question: &str| {
+                sent.iter()
+                    .find(|m| m["text"].as_str().unwrap().starts_with(question))
+                    .unwrap()["message_id"]
+                    .as_i64()
+            };
+            // The second run's answer comes first; neither run may take the other's.
+            api.message(prompt("Second?"), "two");
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            api.message(prompt("First?"), "one");
+        };
+        let (first, second, ()) = tokio::join!(
+            ask_mock(&config, "First?", correlated()),
+            ask_mock(&config, "Second?", correlated()),
+            answer,
+        );
+
+        assert!(matches!(first.0, Outcome::Replied));
+        assert!(matches!(second.0, Outcome::Replied));
+        assert_eq!(first.1, Some(serde_json::json!("one")));
+        assert_eq!(second.1, Some(serde_json::json!("two")));
+        assert_eq!(api.conflicts(), 0);
+        let _ = std::fs::remove_dir_all(config.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn un_threaded_answers_are_offered_to_every_correlated_prompt() {
+        let api = crate::mock_api::MockApi::start().await;
+        let config = api.config("");
+        let correlated = || Args {
+            correlate: true,
+            ..Default::default()
+        };
+        let answer = async {
+            let sent = api.sent_at_least(2).await;
+            let prompt = |question: &str| {
+                sent.iter()
+                    .find(|m| m["text"].as_str().unwrap().starts_with(question))
+                    .unwrap()
+                    .clone()
+            };
+            let second = prompt("Second?");
+            let second_tag = correlate::find_tag(second["text"].as_str().unwrap()).unwrap();
+            // A quick reply from a notification isn't threaded.
+            api.message(None, "two");
+            let offer = api.sent_at_least(3).await[2].clone();
+            assert_eq!(
+                offer["reply_markup"]["inline_keyboard"]
+                    .as_array()
+                    .unwrap()
+                    .len(),
+                2
+            );
+            api.press(
+                offer["message_id"].as_i64().unwrap(),
+                &format!("{CONFIRM_PREFIX}{second_tag}"),
+            );
+            api.message(prompt("First?")["message_id"].as_i64(), "one");
+        };
+        let (first, second, ()) = tokio::join!(
+            ask_mock(&config, "First?", correlated()),
+            ask_mock(&config, "Second?", correlated()),
+            answer,
+        );
+
+        assert_eq!(first.1, Some(serde_json::json!("one")));
+        assert_eq!(second.1, Some(serde_json::json!("two")));
+        let _ = std::fs::remove_dir_all(config.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn resumed_runs_offer_to_take_un_threaded_answers() {
+        let api = crate::mock_api::MockApi::start().await;
+        let config = api.config("");
+        let resume = Args {
+            command: Some(crate::Command::Resume {
+                prompt_id: 42,
+                to: None,
+            }),
+            ..Default::default()
+        };
+        let answer = async {
+            api.message(None, "yes");
+            let offer = api.sent_at_least(1).await[0].clone();
+            assert_eq!(offer["reply_parameters"]["message_id"], 1001);
+            api.press(offer["message_id"].as_i64().unwrap(), "confirm:1001");
+        };
+        let (resumed, ()) = tokio::join!(ask_mock(&config, "", resume), answer);
+
+        assert!(matches!(resumed.0, Outcome::Replied));
+        assert_eq!(resumed.1, Some(serde_json::json!("yes")));
+        let _ = std::fs::remove_dir_all(config.parent().unwrap());
+    }
+}
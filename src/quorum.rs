@@ -0,0 +1,152 @@
+use crate::telegram::{CallbackQuery, User};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Callback data prefix of the approve/reject buttons.
+const VOTE_PREFIX: &str = "vote:";
+
+/// Approve/reject buttons attached to a quorum prompt.
+pub fn markup() -> serde_json::Value {
+    serde_json::json!({
+        "inline_keyboard": [[
+            { "text": "✅ Approve", "callback_data": format!("{VOTE_PREFIX}approve") },
+            { "text": "❌ Reject", "callback_data": format!("{VOTE_PREFIX}reject") },
+        ]]
+    })
+}
+
+/// The vote a button press casts, if it is one of our buttons. `true` approves.
+pub fn parse_vote(query: &CallbackQuery) -> Option<bool> {
+    match query.data.as_deref()?.strip_prefix(VOTE_PREFIX)? {
+        "approve" => Some(true),
+        "reject" => Some(false),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+struct Vote {
+    approve: bool,
+    name: String,
+}
+
+/// Votes cast so far on a prompt that needs `quorum` approvals from `voters` people.
+#[derive(Debug)]
+pub struct Tally {
+    quorum: usize,
+    voters: usize,
+    votes: BTreeMap<i64, Vote>,
+}
+
+impl Tally {
+    pub fn new(quorum: usize, voters: usize) -> Self {
+        Self {
+            quorum,
+            voters,
+            votes: BTreeMap::new(),
+        }
+    }
+
+    /// Record a vote. Each person's latest vote counts, so a misclick can be corrected.
+    pub fn record(&mut self, user: &User, approve: bool) {
+        let name = match &user.username {
+            Some(username) => format!("@{username}"),
+            None => user.first_name.clone(),
+        };
+        self.votes.insert(user.id, Vote { approve, name });
+    }
+
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    pub fn approvals(&self) -> usize {
+        self.votes.values().filter(|v| v.approve).count()
+    }
+
+    pub fn rejections(&self) -> usize {
+        self.votes.len() - self.approvals()
+    }
+
+    /// `Some(true)` once the quorum is reached, `Some(false)` once enough people rejected that
+    /// it no longer can be.
+    pub fn decision(&self) -> Option<bool> {
+        if self.approvals() >= self.quorum {
+            Some(true)
+        } else if self.voters - self.rejections() < self.quorum {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Each voter's latest vote, as `(user_id, approve)`.
+    pub fn votes(&self) -> impl Iterator<Item = (i64, bool)> + '_ {
+        self.votes.iter().map(|(id, vote)| (*id, vote.approve))
+    }
+
+    pub fn summary(&self) -> String {
+        format!("{}/{} approvals", self.approvals(), self.quorum)
+    }
+
+    /// The summary line followed by one `approve|reject <user_id> <name>` line per voter.
+    pub fn render(&self) -> String {
+        let mut out = format!("{}\n", self.summary());
+        for (id, vote) in &self.votes {
+            let verdict = if vote.approve { "approve" } else { "reject" };
+            let _ = writeln!(out, "{verdict} {id} {}", vote.name);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: i64, username: &str) -> User {
+        User {
+            id,
+            username: Some(username.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn quorum_is_reached_by_distinct_latest_approvals() {
+        let mut tally = Tally::new(2, 3);
+        tally.record(&user(1, "ann"), true);
+        tally.record(&user(1, "ann"), true);
+        assert_eq!(tally.decision(), None);
+
+        tally.record(&user(2, "bob"), false);
+        tally.record(&user(2, "bob"), true);
+        assert_eq!(tally.decision(), Some(true));
+        assert_eq!(
+            tally.render(),
+            "2/2 approvals\napprove 1 @ann\napprove 2 @bob\n"
+        );
+    }
+
+    #[test]
+    fn quorum_fails_once_too_many_reject() {
+        let mut tally = Tally::new(2, 3);
+        tally.record(&user(1, "ann"), false);
+        assert_eq!(tally.decision(), None);
+        tally.record(&user(2, "bob"), false);
+        assert_eq!(tally.decision(), Some(false));
+    }
+
+    #[test]
+    fn parses_only_our_vote_buttons() {
+        let query = |data: &str| CallbackQuery {
+            id: "q".to_string(),
+            from: User::default(),
+            data: Some(data.to_string()),
+            message: None,
+        };
+        assert_eq!(parse_vote(&query("vote:approve")), Some(true));
+        assert_eq!(parse_vote(&query("vote:reject")), Some(false));
+        assert_eq!(parse_vote(&query("mute:abc")), None);
+    }
+}
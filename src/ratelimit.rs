@@ -0,0 +1,142 @@
+use crate::config::RateLimitConfig;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Methods that put a message in a chat (or change one), which Telegram's limits count.
+const LIMITED: &[&str] = &[
+    "sendMessage",
+    "sendDocument",
+    "sendPhoto",
+    "sendPoll",
+    "editMessageText",
+    "editMessageCaption",
+];
+
+/// Whether calls of `method` are rate limited.
+pub fn limits(method: &str) -> bool {
+    LIMITED.contains(&method)
+}
+
+/// The slots taken by recent and upcoming messages, and when each chat may next be sent to,
+/// as Unix milliseconds.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Schedule {
+    #[serde(default)]
+    slots: Vec<i64>,
+    #[serde(default)]
+    chats: BTreeMap<i64, i64>,
+}
+
+impl Schedule {
+    /// Reserve the earliest slot at or after `now` for a message to `chat_id`, returning it.
+    /// Any two slots are at least `1/per_second` apart, so a chat that may be sent to now
+    /// isn't held up behind one that has to wait. What has passed is forgotten, as it no
+    /// longer holds anything back.
+    fn reserve(&mut self, limits: &RateLimitConfig, chat_id: i64, now: i64) -> i64 {
+        let spacing = 1000 / i64::from(limits.per_second.max(1));
+        self.slots.retain(|&slot| slot > now - spacing);
+        self.chats.retain(|_, next| *next > now);
+        let mut slot = now.max(self.chats.get(&chat_id).copied().unwrap_or(now));
+        self.slots.sort_unstable();
+        for &taken in &self.slots {
+            if taken > slot - spacing && taken < slot + spacing {
+                slot = taken + spacing;
+            }
+        }
+        self.slots.push(slot);
+        // Groups are limited per minute; a negative id is a group or channel.
+        let gap = if chat_id < 0 {
+            60_000 / i64::from(limits.per_group_per_minute.max(1))
+        } else {
+            1000 / i64::from(limits.per_chat_per_second.max(1))
+        };
+        self.chats.insert(chat_id, slot + gap);
+        slot
+    }
+}
+
+/// Spaces out the messages of every teleprompt run using a bot, through a schedule kept in
+/// the state dir, so together they stay within Telegram's limits instead of being answered
+/// with 429s.
+#[derive(Debug, Clone)]
+pub struct Limiter {
+    path: PathBuf,
+    limits: RateLimitConfig,
+}
+
+impl Limiter {
+    pub fn new(state_dir: &Path, token: &str, limits: RateLimitConfig) -> Result<Self> {
+        std::fs::create_dir_all(state_dir)
+            .with_context(|| format!("create state dir: {}", state_dir.display()))?;
+        let bot_id = token.split(':').next().unwrap_or_default();
+        Ok(Self {
+            path: state_dir.join(format!("rate-{bot_id}.lock")),
+            limits,
+        })
+    }
+
+    /// Wait until a message may be sent to `chat_id`.
+    pub async fn wait(&self, chat_id: i64) -> Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let slot = self.reserve(chat_id, now)?;
+        if slot > now {
+            let delay = Duration::from_millis((slot - now) as u64);
+            tracing::debug!(chat_id, delay_ms = slot - now, "rate limited");
+            tokio::time::sleep(delay).await;
+        }
+        Ok(())
+    }
+
+    /// Take the next slot for `chat_id` from the shared schedule, holding its file lock only
+    /// while reading and updating it.
+    fn reserve(&self, chat_id: i64, now: i64) -> Result<i64> {
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)
+            .with_context(|| format!("open rate limit file: {}", self.path.display()))?;
+        file.lock()
+            .with_context(|| format!("lock {}", self.path.display()))?;
+        let mut raw = String::new();
+        file.read_to_string(&mut raw)?;
+        // A damaged schedule only costs the spacing of messages already sent.
+        let mut schedule: Schedule = serde_json::from_str(&raw).unwrap_or_default();
+        let slot = schedule.reserve(&self.limits, chat_id, now);
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(&serde_json::to_vec(&schedule)?)?;
+        Ok(slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chats_and_the_bot_are_spaced_out() {
+        let limits = RateLimitConfig {
+            per_second: 30,
+            per_chat_per_second: 1,
+            per_group_per_minute: 20,
+        };
+        let mut schedule = Schedule::default();
+        assert_eq!(schedule.reserve(&limits, 7, 1_000), 1_000);
+        // The same chat waits a second; another only the global gap.
+        assert_eq!(schedule.reserve(&limits, 7, 1_000), 2_000);
+        assert_eq!(schedule.reserve(&limits, 8, 1_000), 1_033);
+        // Groups get 20 a minute.
+        assert_eq!(schedule.reserve(&limits, -100, 5_000), 5_000);
+        assert_eq!(schedule.reserve(&limits, -100, 5_000), 8_000);
+        // Chats that are due are forgotten.
+        schedule.reserve(&limits, 9, 60_000);
+        assert_eq!(schedule.chats.keys().collect::<Vec<_>>(), [&9]);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::config::{Config, ReactionAnswer};
+use crate::telegram::{Update, User};
+use std::collections::BTreeMap;
+
+/// Reactions accepted by `--reactions` when the config has no `[reactions]` table.
+pub fn default_map() -> BTreeMap<String, ReactionAnswer> {
+    BTreeMap::from([
+        ("👍".to_string(), ReactionAnswer::Text("yes".to_string())),
+        ("👎".to_string(), ReactionAnswer::Text("no".to_string())),
+    ])
+}
+
+/// A reaction that answers the prompt.
+#[derive(Debug)]
+pub struct Reacted<'a> {
+    pub chat_id: i64,
+    pub user: &'a User,
+    pub emoji: &'a str,
+    pub answer: &'a ReactionAnswer,
+}
+
+/// The answer given by a reaction on one of the prompt's messages (`prompt_messages` maps chat
+/// id to message id), if the update is one from someone allowed to answer.
+pub fn answer<'a>(
+    update: &'a Update,
+    cfg: &Config,
+    map: &'a BTreeMap<String, ReactionAnswer>,
+    prompt_messages: &BTreeMap<i64, i64>,
+) -> Option<Reacted<'a>> {
+    let reaction = update.message_reaction.as_ref()?;
+    let user = reaction.user.as_ref()?;
+    if prompt_messages.get(&reaction.chat.id) != Some(&reaction.message_id)
+        || !cfg.may_answer(reaction.chat.id, user.id)
+    {
+        return None;
+    }
+    // Only reactions just added count; `new_reaction` lists all of the user's current ones.
+    reaction
+        .new_reaction
+        .iter()
+        .filter(|r| !reaction.old_reaction.contains(r))
+        .filter_map(|r| r.emoji.as_deref())
+        .find_map(|emoji| {
+            map.get_key_value(emoji).map(|(emoji, answer)| Reacted {
+                chat_id: reaction.chat.id,
+                user,
+                emoji,
+                answer,
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telegram::{Chat, MessageReactionUpdated, ReactionType};
+
+    fn reacted(user: i64, message_id: i64, old: &[&str], new: &[&str]) -> Update {
+        let types = |emojis: &[&str]| {
+            emojis
+                .iter()
+                .map(|e| ReactionType {
+                    emoji: Some(e.to_string()),
+                })
+                .collect()
+        };
+        Update {
+            update_id: 1,
+            message_reaction: Some(MessageReactionUpdated {
+                chat: Chat { id: user },
+                message_id,
+                date: 0,
+                user: Some(User {
+                    id: user,
+                    ..Default::default()
+                }),
+                old_reaction: types(old),
+                new_reaction: types(new),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn new_mapped_reaction_on_the_prompt_answers() {
+        let cfg: Config = toml::from_str("user_id = 7").unwrap();
+        let map = default_map();
+        let prompts = BTreeMap::from([(7, 100)]);
+
+        let update = reacted(7, 100, &[], &["👎"]);
+        let got = answer(&update, &cfg, &map, &prompts).unwrap();
+        assert_eq!(got.emoji, "👎");
+        assert_eq!(got.answer.text(), "no");
+
+        // Wrong message, unknown user, unmapped emoji, or a reaction that was already there.
+        assert!(answer(&reacted(7, 101, &[], &["👍"]), &cfg, &map, &prompts).is_none());
+        assert!(answer(&reacted(8, 100, &[], &["👍"]), &cfg, &map, &prompts).is_none());
+        assert!(answer(&reacted(7, 100, &[], &["🔥"]), &cfg, &map, &prompts).is_none());
+        assert!(answer(&reacted(7, 100, &["👍"], &["👍"]), &cfg, &map, &prompts).is_none());
+    }
+}
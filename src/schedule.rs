@@ -0,0 +1,115 @@
+use crate::SendArgs;
+use crate::status::{Event, StatusStream};
+use anyhow::Result;
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use std::time::Duration;
+
+/// Longest single sleep while holding a message, so a machine that was suspended notices the
+/// time has come soon after it wakes up.
+const HOLD_STEP: Duration = Duration::from_secs(30);
+
+/// Parse `--send-at`: an RFC 3339 time, a local date and time such as `2024-07-01T09:00` or
+/// `2024-07-01 09:00:30`, or a local time of day such as `09:00`, meaning the next one.
+pub fn parse_send_at(s: &str) -> Result<DateTime<Utc>, String> {
+    let s = s.trim();
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&Utc));
+    }
+    let invalid =
+        || format!("expected a time like 2024-07-01T09:00, 09:00 or an RFC 3339 time, got {s:?}");
+    let local = |t: NaiveDateTime| {
+        Local
+            .from_local_datetime(&t)
+            .earliest()
+            .map(|t| t.with_timezone(&Utc))
+            .ok_or_else(|| format!("{t} does not exist in the local time zone"))
+    };
+    for format in [
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%d %H:%M:%S",
+    ] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(s, format) {
+            return local(t);
+        }
+    }
+    let time = NaiveTime::parse_from_str(s, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M:%S"))
+        .map_err(|_| invalid())?;
+    let now = Local::now();
+    let mut day = now.date_naive();
+    if time <= now.time() {
+        day = day.succ_opt().ok_or_else(invalid)?;
+    }
+    local(day.and_time(time))
+}
+
+/// When `--send-at` or `--delay` says to send the message, if either is given.
+fn send_time(send: &SendArgs, now: DateTime<Utc>) -> Result<Option<DateTime<Utc>>> {
+    if let Some(at) = send.send_at {
+        anyhow::ensure!(
+            at > now,
+            "--send-at {} is in the past",
+            at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")
+        );
+        return Ok(Some(at));
+    }
+    Ok(send.delay.map(|delay| now + delay))
+}
+
+/// Hold the message until the time `--send-at` or `--delay` asks for, if any. Nothing is
+/// persisted: the message is only sent if this process is still running then.
+pub async fn hold(send: &SendArgs, status: &mut StatusStream) -> Result<()> {
+    let Some(at) = send_time(send, Utc::now())? else {
+        return Ok(());
+    };
+    tracing::info!(
+        "Holding the message until {}...",
+        at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")
+    );
+    status.emit(Event::Scheduled {
+        send_at: at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+    });
+    loop {
+        let Ok(left) = (at - Utc::now()).to_std() else {
+            return Ok(());
+        };
+        if left.is_zero() {
+            return Ok(());
+        }
+        tokio::time::sleep(left.min(HOLD_STEP)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send_at_takes_full_and_local_times() {
+        assert_eq!(
+            parse_send_at("2024-07-01T09:00:00+02:00").unwrap(),
+            Utc.with_ymd_and_hms(2024, 7, 1, 7, 0, 0).unwrap()
+        );
+        let local = Local.with_ymd_and_hms(2024, 7, 1, 9, 0, 0).unwrap();
+        assert_eq!(parse_send_at("2024-07-01T09:00").unwrap(), local);
+        assert_eq!(parse_send_at("2024-07-01 09:00:00").unwrap(), local);
+
+        let next = parse_send_at("09:00").unwrap();
+        assert!(next > Utc::now() && next - Utc::now() <= chrono::Duration::days(1));
+        assert_eq!(
+            next.with_timezone(&Local).time(),
+            NaiveTime::MIN + chrono::Duration::hours(9)
+        );
+
+        assert!(parse_send_at("tomorrow").is_err());
+        assert!(parse_send_at("25:00").is_err());
+
+        let send = SendArgs {
+            send_at: Some(Utc.with_ymd_and_hms(2024, 7, 1, 7, 0, 0).unwrap()),
+            ..Default::default()
+        };
+        assert!(send_time(&send, Utc::now()).is_err());
+    }
+}
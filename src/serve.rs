@@ -0,0 +1,987 @@
+use crate::feed::Feed;
+use crate::jsonrpc::{
+    INVALID_PARAMS, METHOD_NOT_FOUND, PARSE_ERROR, Request, RpcError, notification, parse, response,
+};
+use crate::status::StatusStream;
+use crate::{
+    Args, Outcome, SendArgs, audit, batch, config, duration, lock, pending, prompt, state,
+};
+use anyhow::{Context, Result};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+
+/// JSON-RPC error code of a valid request that failed, e.g. a message Telegram refused.
+const FAILED: i64 = -32000;
+
+/// How long `serve --http` keeps the answer to a question for `GET /answers/{id}`.
+const ANSWER_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Largest request body `serve --http` reads.
+const MAX_BODY: usize = 1 << 20;
+
+/// Where responses and notifications go, one JSON-RPC message each.
+type Out = mpsc::UnboundedSender<Value>;
+
+/// Where a prompt was sent.
+#[derive(Debug, Clone, Copy, Serialize)]
+struct Sent {
+    chat_id: i64,
+    message_id: i64,
+}
+
+/// A question being waited on.
+struct Pending {
+    /// Unknown for questions taken over from an instance that is gone.
+    question: Option<String>,
+    asked_at: Option<String>,
+    sent: Rc<RefCell<Vec<Sent>>>,
+    /// Ends the wait; taken by `cancel`.
+    cancel: Option<oneshot::Sender<()>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SendParams {
+    text: String,
+    #[serde(default)]
+    to: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AskParams {
+    question: String,
+    #[serde(default)]
+    choices: Vec<String>,
+    /// A duration such as `30m`.
+    timeout: Option<String>,
+    #[serde(default)]
+    to: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct IdParams {
+    id: Option<String>,
+}
+
+/// The state shared by the requests of one `serve` session.
+struct Server {
+    config_path: PathBuf,
+    /// Loaded once, for every run, so `bot_token_command` doesn't run for each request.
+    cfg: config::Config,
+    fix_webhook: bool,
+    /// Responses and notifications, over stdio. Without it, answers are kept in `answers`.
+    out: Option<Out>,
+    /// Questions still being waited on, by id.
+    asks: RefCell<BTreeMap<String, Pending>>,
+    /// Answers of ended questions, by id, with when they ended.
+    answers: RefCell<BTreeMap<String, (Instant, Value)>>,
+    /// Runs in progress.
+    tasks: RefCell<JoinSet<()>>,
+    /// The update stream the questions share.
+    feed: Feed,
+}
+
+/// Serve JSON-RPC on stdin/stdout, one message per line, until stdin ends. Requests are
+/// handled concurrently: `ask` returns an id at once and its answer follows as an `answer`
+/// notification, so one process can wait on many questions. Questions still pending when
+/// stdin ends are cancelled.
+pub async fn stdio(args: &Args, config_path: &Path) -> Result<()> {
+    // Loaded once, so a broken config is reported now rather than on the first request.
+    let cfg = config::load(config_path)?;
+    // Runs borrow their arguments and status streams, so they share this thread.
+    tokio::task::LocalSet::new()
+        .run_until(serve_stdio(args, config_path, cfg))
+        .await
+}
+
+async fn serve_stdio(args: &Args, config_path: &Path, cfg: config::Config) -> Result<()> {
+    let (out, mut rx) = mpsc::unbounded_channel::<Value>();
+    let writer = tokio::task::spawn_local(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(message) = rx.recv().await {
+            let mut line = serde_json::to_vec(&message)?;
+            line.push(b'\n');
+            stdout.write_all(&line).await?;
+            stdout.flush().await?;
+        }
+        anyhow::Ok(())
+    });
+
+    let server = Rc::new(Server::new(config_path, cfg, args.fix_webhook, Some(out)));
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Request>(&line) {
+            Ok(req) => server.dispatch(req),
+            Err(e) => server.respond(
+                Some(Value::Null),
+                Err(RpcError::new(PARSE_ERROR, e.to_string())),
+            ),
+        }
+    }
+
+    for pending in server.asks.borrow_mut().values_mut() {
+        if let Some(cancel) = pending.cancel.take() {
+            let _ = cancel.send(());
+        }
+    }
+    let mut tasks = server.tasks.take();
+    while tasks.join_next().await.is_some() {}
+    drop(server);
+    writer.await?
+}
+
+/// Serve the HTTP API on `addr` until killed. Every request must carry the config's
+/// `serve_token` as a bearer token. Questions still pending when it is killed are left
+/// recorded as pending, for `recover`.
+pub async fn http(args: &Args, config_path: &Path, addr: SocketAddr, standby: bool) -> Result<()> {
+    let cfg = config::load(config_path)?;
+    let token =
+        cfg.serve_token.clone().filter(|t| !t.is_empty()).context(
+            "serve --http needs `serve_token` in the config, for clients to authenticate",
+        )?;
+    if !addr.ip().is_loopback() {
+        tracing::warn!("serving on {addr}, which is reachable from other hosts, without TLS");
+    }
+    // One instance serves a bot at a time; a standby waits here, then binds the same address.
+    let _instance = lock::instance(&cfg.state_dir()?, &cfg.bot_token, standby).await?;
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("listen on {addr}"))?;
+    tracing::info!("Serving on http://{}", listener.local_addr()?);
+    tokio::task::LocalSet::new()
+        .run_until(serve_http(args, config_path, cfg, listener, token))
+        .await
+}
+
+async fn serve_http(
+    args: &Args,
+    config_path: &Path,
+    cfg: config::Config,
+    listener: tokio::net::TcpListener,
+    token: String,
+) -> Result<()> {
+    let server = Rc::new(Server::new(config_path, cfg, args.fix_webhook, None));
+    if let Err(e) = server.take_over() {
+        tracing::warn!("could not take over the pending questions: {e:#}");
+    }
+    let token = Rc::new(token);
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            // E.g. out of file descriptors; the connection is dropped, not the server.
+            Err(e) => {
+                tracing::warn!("accept failed: {e}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let server = Rc::clone(&server);
+        let token = Rc::clone(&token);
+        tokio::task::spawn_local(async move {
+            let service = hyper::service::service_fn(move |req| {
+                let server = Rc::clone(&server);
+                let token = Rc::clone(&token);
+                async move { Ok::<_, std::convert::Infallible>(server.route(req, &token).await) }
+            });
+            let io = hyper_util::rt::TokioIo::new(stream);
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(io, service)
+                .await
+            {
+                tracing::debug!("connection failed: {e}");
+            }
+        });
+    }
+}
+
+/// A JSON response.
+fn reply(status: StatusCode, body: &Value) -> hyper::Response<Full<Bytes>> {
+    hyper::Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .expect("static response parts are valid")
+}
+
+/// Whether `headers` carry `Authorization: Bearer <token>`, compared in constant time.
+fn authorized(headers: &hyper::HeaderMap, token: &str) -> bool {
+    let Some(given) = headers
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Read a JSON request body.
+async fn read_json<B>(body: B) -> Result<Value, (StatusCode, String)>
+where
+    B: Body,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let bytes = Limited::new(body, MAX_BODY)
+        .collect()
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("read body: {e}")))?
+        .to_bytes();
+    serde_json::from_slice(&bytes)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("parse body: {e}")))
+}
+
+/// The HTTP status for a failed request: the client's fault if it was malformed, else the
+/// relay's.
+fn http_error(e: RpcError) -> (StatusCode, String) {
+    let status = match e.code {
+        INVALID_PARAMS => StatusCode::BAD_REQUEST,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+    (status, e.message)
+}
+
+impl Server {
+    fn new(config_path: &Path, cfg: config::Config, fix_webhook: bool, out: Option<Out>) -> Self {
+        Self {
+            config_path: config_path.to_path_buf(),
+            cfg,
+            fix_webhook,
+            out,
+            asks: RefCell::default(),
+            answers: RefCell::default(),
+            tasks: RefCell::default(),
+            feed: Feed::default(),
+        }
+    }
+
+    /// Run `task` alongside the others, reaping the ones that ended.
+    fn spawn(&self, task: impl Future<Output = ()> + 'static) {
+        let mut tasks = self.tasks.borrow_mut();
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn_local(task);
+    }
+
+    fn respond(&self, id: Option<Value>, res: Result<Value, RpcError>) {
+        if let (Some(id), Some(out)) = (id, &self.out) {
+            let _ = out.send(response(id, res));
+        }
+    }
+
+    fn dispatch(self: &Rc<Self>, req: Request) {
+        let Request { id, method, params } = req;
+        let res = match method.as_str() {
+            "send" => {
+                let server = Rc::clone(self);
+                self.spawn(async move {
+                    let res = match parse(params) {
+                        Ok(params) => server.send(params).await,
+                        Err(e) => Err(e),
+                    };
+                    server.respond(id, res);
+                });
+                return;
+            }
+            "ask" => parse(params).and_then(|params| self.ask(params)),
+            "cancel" => self.cancel(params),
+            "status" => self.status(params),
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {method}"),
+            )),
+        };
+        self.respond(id, res);
+    }
+
+    /// Handle an HTTP API request.
+    async fn route<B>(
+        self: &Rc<Self>,
+        req: hyper::Request<B>,
+        token: &str,
+    ) -> hyper::Response<Full<Bytes>>
+    where
+        B: Body,
+        B::Error: std::error::Error + Send + Sync + 'static,
+    {
+        if !authorized(req.headers(), token) {
+            return reply(
+                StatusCode::UNAUTHORIZED,
+                &json!({ "error": "missing or wrong bearer token" }),
+            );
+        }
+        let (parts, body) = req.into_parts();
+        let path: Vec<&str> = parts.uri.path().trim_matches('/').split('/').collect();
+        let res = match (&parts.method, path.as_slice()) {
+            (&Method::POST, ["ask"]) => match read_json(body).await {
+                Ok(params) => parse(params)
+                    .and_then(|params| self.ask(params))
+                    .map(|id| (StatusCode::ACCEPTED, id))
+                    .map_err(http_error),
+                Err(e) => Err(e),
+            },
+            (&Method::POST, ["notify"]) => match read_json(body)
+                .await
+                .and_then(|params| parse(params).map_err(http_error))
+            {
+                Ok(params) => self
+                    .send(params)
+                    .await
+                    .map(|sent| (StatusCode::OK, sent))
+                    .map_err(http_error),
+                Err(e) => Err(e),
+            },
+            (&Method::GET, ["pending"]) => self
+                .status(Value::Null)
+                .map(|pending| (StatusCode::OK, pending))
+                .map_err(http_error),
+            (&Method::GET, ["answers", id]) => self.answer(id),
+            (&Method::DELETE, ["pending", id]) => self
+                .cancel(json!({ "id": id }))
+                .map(|done| (StatusCode::OK, done))
+                .map_err(|e| (StatusCode::NOT_FOUND, e.message)),
+            _ => Err((StatusCode::NOT_FOUND, "no such endpoint".to_string())),
+        };
+        match res {
+            Ok((status, body)) => reply(status, &body),
+            Err((status, error)) => reply(status, &json!({ "error": error })),
+        }
+    }
+
+    /// The answer to question `id`, or its status while it is still waited on.
+    fn answer(&self, id: &str) -> Result<(StatusCode, Value), (StatusCode, String)> {
+        if let Some((_, answer)) = self.answers.borrow().get(id) {
+            return Ok((StatusCode::OK, answer.clone()));
+        }
+        match self.status(json!({ "id": id })) {
+            Ok(status) => Ok((StatusCode::ACCEPTED, status["pending"][0].clone())),
+            Err(_) => Err((StatusCode::NOT_FOUND, format!("no question {id}"))),
+        }
+    }
+
+    /// A status stream for a run: collects where its prompts were sent and, for question
+    /// `id`, forwards its events.
+    fn events(&self, id: Option<&str>, sent: &Rc<RefCell<Vec<Sent>>>) -> StatusStream {
+        StatusStream::writer(Box::new(Events {
+            id: id.map(str::to_string),
+            sent: Rc::clone(sent),
+            out: self.out.clone(),
+            line: Vec::new(),
+        }))
+    }
+
+    /// Send as `notify` does, answering once it is sent.
+    async fn send(&self, params: SendParams) -> Result<Value, RpcError> {
+        let args = Args {
+            fix_webhook: self.fix_webhook,
+            capture: true,
+            loaded_config: Some(self.cfg.clone()),
+            ..Default::default()
+        };
+        let send = SendArgs {
+            message: Some(params.text),
+            to: params.to,
+            ..Default::default()
+        };
+        let sent = Rc::default();
+        let mut status = self.events(None, &sent);
+        let mut audit = audit::Audit::new(&self.config_path);
+        let res = prompt::run(
+            &args,
+            &send,
+            &self.config_path,
+            &mut status,
+            &mut audit,
+            false,
+        )
+        .await;
+        audit.finish(&res);
+        match res {
+            Ok(outcome) => Ok(json!({
+                "outcome": audit::describe(&outcome).0,
+                "messages": *sent.borrow(),
+            })),
+            Err(e) => Err(RpcError::new(FAILED, format!("{e:#}"))),
+        }
+    }
+
+    /// Start waiting on a question, returning its id.
+    fn ask(self: &Rc<Self>, params: AskParams) -> Result<Value, RpcError> {
+        let timeout = params
+            .timeout
+            .as_deref()
+            .map(duration::parse)
+            .transpose()
+            .map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+        let id = ulid::Ulid::new().to_string();
+        let sent = Rc::default();
+        let (cancel, cancelled) = oneshot::channel();
+        self.asks.borrow_mut().insert(
+            id.clone(),
+            Pending {
+                question: Some(params.question.clone()),
+                asked_at: Some(
+                    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                ),
+                sent: Rc::clone(&sent),
+                cancel: Some(cancel),
+            },
+        );
+        let server = Rc::clone(self);
+        let ask_id = id.clone();
+        self.spawn(async move { server.wait(ask_id, params, timeout, sent, cancelled).await });
+        Ok(json!({ "id": id }))
+    }
+
+    /// Run question `id` as a correlated prompt, so the questions waited on together each get
+    /// their own answer from the server's one update stream, then send the `answer`
+    /// notification (or keep the answer).
+    async fn wait(
+        &self,
+        id: String,
+        params: AskParams,
+        timeout: Option<Duration>,
+        sent: Rc<RefCell<Vec<Sent>>>,
+        cancelled: oneshot::Receiver<()>,
+    ) {
+        let args = Args {
+            timeout,
+            choices: params.choices,
+            correlate: true,
+            fix_webhook: self.fix_webhook,
+            capture: true,
+            feed: Some(self.feed.clone()),
+            loaded_config: Some(self.cfg.clone()),
+            question_id: Some(id.clone()),
+            ..Default::default()
+        };
+        let send = SendArgs {
+            message: Some(params.question),
+            to: params.to,
+            ..Default::default()
+        };
+        let mut status = self.events(Some(&id), &sent);
+        let mut audit = audit::Audit::new(&self.config_path);
+        let res = tokio::select! {
+            res = prompt::run(&args, &send, &self.config_path, &mut status, &mut audit, true) => res,
+            Ok(()) = cancelled => {
+                self.forget(&sent.borrow());
+                Ok(Outcome::Cancelled)
+            }
+        };
+
+        let mut answer = json!({ "id": id });
+        match &res {
+            Ok(outcome) => {
+                let (name, exit_code) = audit::describe(outcome);
+                answer["outcome"] = name.into();
+                if let Some(code) = exit_code {
+                    answer["exit_code"] = code.into();
+                }
+                if let Some(reply) = audit.entry.replies.last() {
+                    answer["reply"] = reply.clone();
+                }
+            }
+            Err(e) => {
+                answer["outcome"] = "error".into();
+                answer["error"] = format!("{e:#}").into();
+            }
+        }
+        audit.finish(&res);
+        self.end(id, answer);
+    }
+
+    /// Stop tracking question `id` and send its `answer` notification (or keep the answer).
+    fn end(&self, id: String, answer: Value) {
+        self.asks.borrow_mut().remove(&id);
+        match &self.out {
+            Some(out) => {
+                let _ = out.send(notification("answer", answer));
+            }
+            None => {
+                let now = Instant::now();
+                let mut answers = self.answers.borrow_mut();
+                answers.retain(|_, (ended, _)| now.duration_since(*ended) < ANSWER_TTL);
+                answers.insert(id, (now, answer));
+            }
+        }
+    }
+
+    /// Wait on the questions that `serve` instances which are gone left recorded as pending,
+    /// as `recover` does, so their clients still get the answers. The first answer settles a
+    /// question sent to several chats; they can't be cancelled.
+    fn take_over(self: &Rc<Self>) -> Result<()> {
+        let store = state::StateStore::for_config(&self.cfg)?;
+        let records: Vec<_> = pending::list(&store, &self.cfg.bot_token)?
+            .into_iter()
+            .filter(|r| r.source == Some(pending::Source::Serve) && !r.owner_alive())
+            .collect();
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut asks = self.asks.borrow_mut();
+        for record in &records {
+            let id = record.id.as_str().unwrap_or_default().to_string();
+            let pending = asks.entry(id).or_insert_with(|| Pending {
+                question: None,
+                asked_at: None,
+                sent: Rc::default(),
+                cancel: None,
+            });
+            pending.sent.borrow_mut().push(Sent {
+                chat_id: record.chat_id,
+                message_id: record.message_id,
+            });
+        }
+        tracing::info!("Taking over {} pending questions...", asks.len());
+        drop(asks);
+
+        let server = Rc::clone(self);
+        let settled = {
+            let server = Rc::clone(self);
+            move |result: &batch::ItemResult| server.settle_taken_over(result)
+        };
+        self.spawn(async move {
+            let res = batch::take_over(&server.cfg, records, server.fix_webhook, settled).await;
+            if let Err(e) = res {
+                tracing::warn!("waiting on the questions taken over failed: {e:#}");
+            }
+        });
+        Ok(())
+    }
+
+    /// End a question taken over with its `result`, unless it was answered already, in another
+    /// chat.
+    fn settle_taken_over(&self, result: &batch::ItemResult) {
+        let Some(id) = result.id.as_str() else {
+            return;
+        };
+        if !self.asks.borrow().contains_key(id) {
+            return;
+        }
+        let mut answer = json!({ "id": id, "outcome": result.status });
+        if let Some(reply) = &result.reply {
+            answer["reply"] = json!({
+                "reply": reply,
+                "chat_id": result.chat_id,
+                "from": result.from,
+                "prompt_message_id": result.message_id,
+                "replied_at": result.replied_at,
+            });
+        }
+        self.end(id.to_string(), answer);
+    }
+
+    /// Clear the pending records of a question cancelled part way through its run, which
+    /// would otherwise be left for `recover`.
+    fn forget(&self, sent: &[Sent]) {
+        match state::StateStore::for_config(&self.cfg) {
+            Ok(store) => {
+                for s in sent {
+                    let prompt =
+                        pending::Prompt::new(&self.cfg.bot_token, s.chat_id, s.message_id, 0);
+                    pending::remove(&store, &prompt);
+                }
+            }
+            Err(e) => tracing::warn!("could not clear the pending prompts: {e:#}"),
+        }
+    }
+
+    /// Stop waiting on a question. Its `answer` notification follows, as cancelled.
+    fn cancel(&self, params: Value) -> Result<Value, RpcError> {
+        let id = parse::<IdParams>(params)?
+            .id
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing field `id`"))?;
+        let cancel = self
+            .asks
+            .borrow_mut()
+            .get_mut(&id)
+            .and_then(|pending| pending.cancel.take())
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("no pending question {id}")))?;
+        let _ = cancel.send(());
+        Ok(json!({}))
+    }
+
+    /// The questions being waited on, or just question `id`.
+    fn status(&self, params: Value) -> Result<Value, RpcError> {
+        let filter = match params {
+            Value::Null => None,
+            params => parse::<IdParams>(params)?.id,
+        };
+        let asks = self.asks.borrow();
+        let pending: Vec<Value> = asks
+            .iter()
+            .filter(|(id, _)| filter.as_ref().is_none_or(|f| f == *id))
+            .map(|(id, pending)| {
+                json!({
+                    "id": id,
+                    "question": pending.question,
+                    "asked_at": pending.asked_at,
+                    "messages": *pending.sent.borrow(),
+                })
+            })
+            .collect();
+        if let Some(id) = &filter
+            && pending.is_empty()
+        {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("no pending question {id}"),
+            ));
+        }
+        Ok(json!({ "pending": pending }))
+    }
+}
+
+/// The status stream of a run, read back line by line.
+struct Events {
+    /// The question whose events are forwarded as `event` notifications, if any.
+    id: Option<String>,
+    sent: Rc<RefCell<Vec<Sent>>>,
+    out: Option<Out>,
+    line: Vec<u8>,
+}
+
+impl Events {
+    fn forward(&mut self, event: Value) {
+        if event["event"] == "sent"
+            && let (Some(chat_id), Some(message_id)) =
+                (event["chat_id"].as_i64(), event["message_id"].as_i64())
+        {
+            self.sent.borrow_mut().push(Sent {
+                chat_id,
+                message_id,
+            });
+        }
+        if let (Some(id), Some(out)) = (&self.id, &self.out) {
+            let _ = out.send(notification("event", json!({ "id": id, "event": event })));
+        }
+    }
+}
+
+impl Write for Events {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        while let Some(end) = self.line.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.line.drain(..=end).collect();
+            if let Ok(event) = serde_json::from_slice(&line) {
+                self.forward(event);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(out: Option<Out>) -> Rc<Server> {
+        Rc::new(Server::new(
+            Path::new("/nonexistent/teleprompt.toml"),
+            toml::from_str("user_id = 1").unwrap(),
+            false,
+            out,
+        ))
+    }
+
+    #[tokio::test]
+    async fn tracks_questions_and_rejects_bad_requests() {
+        let (out, mut rx) = mpsc::unbounded_channel();
+        let server = server(Some(out));
+        let mut call = |method: &str, params: Value| {
+            let req = Request {
+                id: Some(json!(1)),
+                method: method.to_string(),
+                params,
+            };
+            server.dispatch(req);
+            rx.try_recv().unwrap()
+        };
+
+        assert_eq!(call("status", Value::Null)["result"]["pending"], json!([]));
+        assert_eq!(call("poll", Value::Null)["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(
+            call("ask", json!({ "choices": ["yes"] }))["error"]["code"],
+            INVALID_PARAMS
+        );
+        let bad_timeout = call("ask", json!({ "question": "Deploy?", "timeout": "soon" }));
+        assert_eq!(bad_timeout["error"]["code"], INVALID_PARAMS);
+        let unknown = call("cancel", json!({ "id": "01J000" }));
+        assert_eq!(unknown["error"]["code"], INVALID_PARAMS);
+
+        // Sent prompts are collected from the run's events, which are forwarded.
+        let sent = Rc::default();
+        let mut status = server.events(Some("01J000"), &sent);
+        status.emit(crate::status::Event::Sent {
+            prompt_id: "p".to_string(),
+            chat_id: 7,
+            message_id: 42,
+            tag: None,
+        });
+        assert_eq!(sent.borrow()[0].message_id, 42);
+        let event = rx.try_recv().unwrap();
+        assert_eq!(event["method"], "event");
+        assert_eq!(event["params"]["event"]["event"], "sent");
+    }
+
+    #[tokio::test]
+    async fn concurrent_questions_each_get_their_answer() {
+        let api = crate::mock_api::MockApi::start().await;
+        // Without the lock to take turns on, only the shared stream keeps their polls apart.
+        let config = api.config("poll_lock = \"off\"");
+        let (out, mut rx) = mpsc::unbounded_channel();
+        let cfg = config::load(&config).unwrap();
+        let server = Rc::new(Server::new(&config, cfg, false, Some(out)));
+        let ask = |question: &str| {
+            server.dispatch(Request {
+                id: Some(json!(question)),
+                method: "ask".to_string(),
+                params: json!({ "question": question, "timeout": "10s" }),
+            });
+        };
+        let answers = async {
+            ask("Deploy?");
+            ask("Migrate?");
+            // Both are open before either is answered, and answered in the other order.
+            let sent = api.sent_at_least(2).await;
+            let id_of = |question: &str| {
+                let sent = sent
+                    .iter()
+                    .find(|m| m["text"].as_str().unwrap().starts_with(question));
+                sent.unwrap()["message_id"].as_i64().unwrap()
+            };
+            api.message(Some(id_of("Migrate?")), "later");
+            api.message(Some(id_of("Deploy?")), "yes");
+            let mut ids = BTreeMap::new();
+            let mut answers = BTreeMap::new();
+            while answers.len() < 2 {
+                let message = rx.recv().await.unwrap();
+                match message["method"].as_str() {
+                    Some("answer") => {
+                        let answer = &message["params"];
+                        let reply = answer["reply"]["reply"].as_str().unwrap().to_string();
+                        answers.insert(answer["id"].as_str().unwrap().to_string(), reply);
+                    }
+                    Some(_) => {}
+                    None => {
+                        let question = message["id"].as_str().unwrap().to_string();
+                        ids.insert(
+                            message["result"]["id"].as_str().unwrap().to_string(),
+                            question,
+                        );
+                    }
+                }
+            }
+            answers
+                .into_iter()
+                .map(|(id, reply)| (ids[&id].clone(), reply))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let answers = tokio::task::LocalSet::new()
+            .run_until(tokio::time::timeout(Duration::from_secs(10), answers))
+            .await
+            .unwrap();
+        assert_eq!(answers["Deploy?"], "yes");
+        assert_eq!(answers["Migrate?"], "later");
+        assert_eq!(api.conflicts(), 0);
+    }
+
+    #[tokio::test]
+    async fn http_clients_asking_at_once_each_get_their_answer() {
+        let api = crate::mock_api::MockApi::start().await;
+        let config = api.config("poll_lock = \"off\"");
+        let cfg = config::load(&config).unwrap();
+        let server = Rc::new(Server::new(&config, cfg, false, None));
+        let call = |method: &str, path: String, body: Value| {
+            let req = hyper::Request::builder()
+                .method(method)
+                .uri(path)
+                .header(hyper::header::AUTHORIZATION, "Bearer s3cret")
+                .body(Full::new(Bytes::from(body.to_string())))
+                .unwrap();
+            let server = Rc::clone(&server);
+            async move {
+                let res = server.route(req, "s3cret").await;
+                let status = res.status();
+                let body = res.into_body().collect().await.unwrap().to_bytes();
+                (status, serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+        let answers = async {
+            let ask = |question: &str| {
+                call(
+                    "POST",
+                    "/ask".to_string(),
+                    json!({ "question": question, "timeout": "10s" }),
+                )
+            };
+            let ((_, deploy), (_, migrate)) = tokio::join!(ask("Deploy?"), ask("Migrate?"));
+            let sent = api.sent_at_least(2).await;
+            for (question, reply) in [("Deploy?", "yes"), ("Migrate?", "later")] {
+                let sent = sent
+                    .iter()
+                    .find(|m| m["text"].as_str().unwrap().starts_with(question));
+                api.message(sent.unwrap()["message_id"].as_i64(), reply);
+            }
+            let mut replies = Vec::new();
+            for id in [&deploy["id"], &migrate["id"]] {
+                let path = format!("/answers/{}", id.as_str().unwrap());
+                loop {
+                    let (status, answer) = call("GET", path.clone(), Value::Null).await;
+                    if status == StatusCode::OK {
+                        replies.push(answer["reply"]["reply"].clone());
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+            }
+            replies
+        };
+        let replies = tokio::task::LocalSet::new()
+            .run_until(tokio::time::timeout(Duration::from_secs(10), answers))
+            .await
+            .unwrap();
+        assert_eq!(replies, ["yes", "later"]);
+        assert_eq!(api.conflicts(), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn takes_over_the_questions_an_instance_that_is_gone_left() {
+        let api = crate::mock_api::MockApi::start().await;
+        let config = api.config("");
+        let cfg = config::load(&config).unwrap();
+        let store = state::StateStore::for_config(&cfg).unwrap();
+        let mut gone = std::process::Command::new("true").spawn().unwrap();
+        gone.wait().unwrap();
+        let deadline = chrono::Utc::now().timestamp() + 10;
+        let record = pending::Prompt {
+            id: "01JOLD".into(),
+            pid: Some(gone.id()),
+            source: Some(pending::Source::Serve),
+            ..pending::Prompt::new(&cfg.bot_token, crate::mock_api::USER_ID, 5, deadline)
+        };
+        pending::save(&store, &record);
+        // A crashed `batch` run's prompt is left for `recover`.
+        let batch = pending::Prompt {
+            id: "q2".into(),
+            pid: Some(gone.id()),
+            source: Some(pending::Source::Batch),
+            ..pending::Prompt::new(&cfg.bot_token, crate::mock_api::USER_ID, 6, deadline)
+        };
+        pending::save(&store, &batch);
+        api.message(Some(5), "yes");
+
+        let server = Rc::new(Server::new(&config, cfg, false, None));
+        let answer = async {
+            server.take_over().unwrap();
+            loop {
+                if let Ok((StatusCode::OK, answer)) = server.answer("01JOLD") {
+                    return answer;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        let answer = tokio::task::LocalSet::new()
+            .run_until(tokio::time::timeout(Duration::from_secs(10), answer))
+            .await
+            .unwrap();
+        assert_eq!(answer["outcome"], "replied");
+        assert_eq!(answer["reply"]["reply"], "yes");
+        assert!(server.asks.borrow().is_empty());
+        assert_eq!(
+            pending::list(&store, &server.cfg.bot_token).unwrap(),
+            [batch]
+        );
+    }
+
+    #[tokio::test]
+    async fn http_api_requires_the_token() {
+        let server = server(None);
+        let request = |method: &str, path: &str, auth: &str, body: &str| {
+            hyper::Request::builder()
+                .method(method)
+                .uri(path)
+                .header(hyper::header::AUTHORIZATION, auth)
+                .body(Full::new(Bytes::from(body.to_string())))
+                .unwrap()
+        };
+        let status = |method, path, auth, body| {
+            let server = Rc::clone(&server);
+            async move {
+                let res = server
+                    .route(request(method, path, auth, body), "s3cret")
+                    .await;
+                let status = res.status();
+                let body = res.into_body().collect().await.unwrap().to_bytes();
+                (status, serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+        let ok = "Bearer s3cret";
+        assert_eq!(
+            status("GET", "/pending", "Bearer guess", "").await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        let (code, pending) = status("GET", "/pending", ok, "").await;
+        assert_eq!((code, pending), (StatusCode::OK, json!({ "pending": [] })));
+        server.answers.borrow_mut().insert(
+            "01J000".to_string(),
+            (
+                Instant::now(),
+                json!({ "id": "01J000", "outcome": "replied" }),
+            ),
+        );
+        let (code, answer) = status("GET", "/answers/01J000", ok, "").await;
+        assert_eq!(
+            (code, &answer["outcome"]),
+            (StatusCode::OK, &json!("replied"))
+        );
+        assert_eq!(
+            status("GET", "/answers/01J001", ok, "").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status("POST", "/ask", ok, "{").await.0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(
+                "POST",
+                "/ask",
+                ok,
+                r#"{"question": "Deploy?", "timeout": "soon"}"#
+            )
+            .await
+            .0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status("DELETE", "/pending/01J001", ok, "").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(status("GET", "/", ok, "").await.0, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::telegram::{SendOptions, TelegramClient, User};
+use anyhow::{Context, Result};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the user to message the bot before giving up.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Interactive onboarding: ask for the bot token, discover the user's id from a message they
+/// send to the bot, and write a complete config file to `config_path`.
+pub async fn run(config_path: &Path) -> Result<()> {
+    if config_path.exists()
+        && !confirm(&format!(
+            "A config already exists at {}. Overwrite it?",
+            config_path.display()
+        ))?
+    {
+        anyhow::bail!("setup aborted; existing config left unchanged");
+    }
+
+    let token = ask("Bot token (from @BotFather): ")?;
+    anyhow::ensure!(!token.is_empty(), "no bot token entered");
+
+    let client = TelegramClient::new(token.clone());
+    let bot = client.get_me().await.context("verify bot token")?;
+    let bot_name = bot
+        .username
+        .as_deref()
+        .map(|u| format!("@{u}"))
+        .unwrap_or_else(|| bot.first_name.clone());
+
+    // Ignore anything sent before now so we only capture the message the user sends next.
+    let (mut offset, _) = client.drain_updates().await?;
+
+    eprintln!();
+    match &bot.username {
+        Some(u) => {
+            eprintln!("Now open https://t.me/{u} in Telegram and send /start to {bot_name}.")
+        }
+        None => eprintln!("Now open your bot in Telegram and send it /start."),
+    }
+    eprintln!("Waiting for your message...");
+
+    let user = loop {
+        let user = wait_for_private_message(&client, &mut offset).await?;
+        let who = describe(&user);
+        if confirm(&format!("Got a message from {who}. Is this you?"))? {
+            break user;
+        }
+        eprintln!("Ignoring it. Send /start to {bot_name} from your own account...");
+    };
+
+    client
+        .send_message(
+            user.id,
+            "✅ teleprompt is set up. Prompts will arrive in this chat.",
+            &SendOptions::default(),
+        )
+        .await?;
+
+    write_config(config_path, &token, user.id)?;
+    eprintln!("Wrote {}", config_path.display());
+    Ok(())
+}
+
+async fn wait_for_private_message(client: &TelegramClient, offset: &mut i64) -> Result<User> {
+    let start = Instant::now();
+    while start.elapsed() < DISCOVERY_TIMEOUT {
+        let updates = client.get_updates(*offset, 30).await?;
+        for update in updates {
+            *offset = update.update_id + 1;
+
+            let Some(msg) = update.message else {
+                continue;
+            };
+            let Some(from) = msg.from else {
+                continue;
+            };
+            // Only a private chat proves the sender can receive messages from the bot.
+            if msg.chat.id == from.id {
+                return Ok(from);
+            }
+        }
+    }
+    anyhow::bail!(
+        "no message received within {} minutes",
+        DISCOVERY_TIMEOUT.as_secs() / 60
+    )
+}
+
+fn describe(user: &User) -> String {
+    match &user.username {
+        Some(u) => format!("{} (@{u}, user_id={})", user.first_name, user.id),
+        None => format!("{} (user_id={})", user.first_name, user.id),
+    }
+}
+
+fn render_config(token: &str, user_id: i64) -> String {
+    format!(
+        "bot_token = {}\nuser_id = {user_id}\ntimeout_minutes = 60\n",
+        toml::Value::String(token.to_string())
+    )
+}
+
+fn write_config(path: &Path, token: &str, user_id: i64) -> Result<()> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("create config dir: {}", parent.display()))?;
+    }
+
+    let mut opts = std::fs::OpenOptions::new();
+    opts.write(true).create(true).truncate(true);
+    // The file holds the bot token; keep it private to the current user.
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+
+    let mut f = opts
+        .open(path)
+        .with_context(|| format!("write config file: {}", path.display()))?;
+    f.write_all(render_config(token, user_id).as_bytes())?;
+    Ok(())
+}
+
+fn ask(prompt: &str) -> Result<String> {
+    eprint!("{prompt}");
+    std::io::stderr().flush()?;
+
+    let mut line = String::new();
+    let n = std::io::stdin().lock().read_line(&mut line)?;
+    anyhow::ensure!(n > 0, "stdin closed");
+    Ok(line.trim().to_string())
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    let answer = ask(&format!("{question} [y/N] "))?;
+    Ok(matches!(answer.to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn render_config_round_trips_through_config_parser() {
+        let raw = render_config("123:AB\"C", 42);
+        let cfg: Config = toml::from_str(&raw).unwrap();
+        assert_eq!(cfg.bot_token, "123:AB\"C");
+        assert_eq!(cfg.user_ids, vec![42]);
+        assert_eq!(cfg.timeout_minutes, 60);
+    }
+
+    #[test]
+    fn describe_includes_username_when_present() {
+        let user = User {
+            id: 7,
+            first_name: "Ada".to_string(),
+            username: Some("ada".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(describe(&user), "Ada (@ada, user_id=7)");
+
+        let user = User {
+            username: None,
+            ..user
+        };
+        assert_eq!(describe(&user), "Ada (user_id=7)");
+    }
+}
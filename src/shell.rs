@@ -0,0 +1,18 @@
+use std::process::Command;
+
+/// A `Command` that runs `command` through the platform shell (`sh -c` / `cmd /C`), for
+/// user-configured command lines such as `bot_token_command`.
+pub fn command(command: &str) -> Command {
+    #[cfg(windows)]
+    {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(command);
+        c
+    }
+    #[cfg(not(windows))]
+    {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(command);
+        c
+    }
+}
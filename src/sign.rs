@@ -0,0 +1,80 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use ulid::Ulid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Bytes of the HMAC kept in the printed code. 40 bits is plenty for a human-checked code
+/// that an attacker cannot query an oracle for.
+const MAC_BYTES: usize = 5;
+
+/// Authenticity code for a prompt: `<prompt ULID>-<truncated HMAC>`.
+pub fn code(secret: &str, prompt_id: Ulid) -> String {
+    format!("{prompt_id}-{}", mac_hex(secret, prompt_id))
+}
+
+/// The footer appended to signed prompts.
+pub fn footer(secret: &str, prompt_id: Ulid) -> String {
+    format!("\n\n🔏 {}", code(secret, prompt_id))
+}
+
+/// Check a code printed in a prompt footer, returning the prompt id it vouches for.
+pub fn verify(secret: &str, code: &str) -> Result<Ulid, String> {
+    let code = code.trim().trim_start_matches("🔏").trim();
+    let (id, mac) = code
+        .split_once('-')
+        .ok_or_else(|| "malformed code: expected <prompt id>-<mac>".to_string())?;
+    let prompt_id =
+        Ulid::from_string(id).map_err(|e| format!("malformed code: bad prompt id: {e}"))?;
+
+    let expected = mac_hex(secret, prompt_id);
+    // Constant-time comparison isn't needed for an offline check, but it costs nothing.
+    let matches = expected.len() == mac.len()
+        && expected
+            .bytes()
+            .zip(mac.to_ascii_lowercase().bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0;
+    if !matches {
+        return Err("code does not match: this prompt was not signed with our secret".to_string());
+    }
+    Ok(prompt_id)
+}
+
+fn mac_hex(secret: &str, prompt_id: Ulid) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(prompt_id.to_string().as_bytes());
+    mac.finalize().into_bytes()[..MAC_BYTES]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_verifies_with_the_same_secret_only() {
+        let id = Ulid::new();
+        let c = code("s3cret", id);
+
+        assert_eq!(verify("s3cret", &c).unwrap(), id);
+        assert_eq!(verify("s3cret", &format!("🔏 {c}")).unwrap(), id);
+        assert!(verify("other", &c).is_err());
+    }
+
+    #[test]
+    fn verify_rejects_tampered_or_malformed_codes() {
+        let id = Ulid::new();
+        let c = code("s3cret", id);
+        let other = code("s3cret", Ulid::new());
+        let (_, other_mac) = other.split_once('-').unwrap();
+
+        assert!(verify("s3cret", &format!("{id}-{other_mac}")).is_err());
+        assert!(verify("s3cret", "nodash").is_err());
+        assert!(verify("s3cret", "notaulid-abcdef0123").is_err());
+        assert!(verify("s3cret", &c[..c.len() - 1]).is_err());
+    }
+}
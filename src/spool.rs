@@ -0,0 +1,211 @@
+use crate::status::StatusStream;
+use crate::telegram::{ParseMode, TelegramClient};
+use crate::{Outcome, SendArgs, backoff, config, prompt};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// A message that couldn't be sent for lack of a connection, waiting in the spool for
+/// `teleprompt flush`. Everything is resolved when it is queued, so it is sent as it would
+/// have been then.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Queued {
+    /// When it was queued (RFC 3339).
+    pub queued_at: String,
+    pub chat_ids: Vec<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<i64>,
+    /// The message text, expanded.
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    #[serde(default)]
+    pub parse_mode: ParseMode,
+    /// `--code` language, if the message is a code block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// Files to upload, as absolute paths; they are read when the message is finally sent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attach: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub photo: Vec<PathBuf>,
+    #[serde(default)]
+    pub long_as_file: bool,
+    #[serde(default)]
+    pub canary: bool,
+    /// `message_prefix` and `message_suffix`, expanded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_suffix: Option<String>,
+}
+
+impl Queued {
+    /// What to queue for `send` to `chat_ids`, as configured by `cfg`.
+    pub fn new(cfg: &config::Config, send: &SendArgs, message: &str, chat_ids: Vec<i64>) -> Self {
+        let absolute = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|p| std::path::absolute(p).unwrap_or_else(|_| p.clone()))
+                .collect()
+        };
+        Self {
+            queued_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            chat_ids,
+            thread_id: cfg.thread_id,
+            message: message.to_string(),
+            caption: send.caption.clone(),
+            parse_mode: send.parse_mode,
+            code: send.code.clone(),
+            attach: absolute(&send.attach),
+            photo: absolute(&send.photo),
+            long_as_file: send.long_as_file,
+            canary: send.canary,
+            message_prefix: cfg.message_prefix.clone(),
+            message_suffix: cfg.message_suffix.clone(),
+        }
+    }
+
+    fn send_args(&self) -> SendArgs {
+        SendArgs {
+            message: Some(self.message.clone()),
+            caption: self.caption.clone(),
+            parse_mode: self.parse_mode,
+            code: self.code.clone(),
+            attach: self.attach.clone(),
+            photo: self.photo.clone(),
+            long_as_file: self.long_as_file,
+            canary: self.canary,
+            ..Default::default()
+        }
+    }
+}
+
+fn spool_dir(cfg: &config::Config) -> Result<PathBuf> {
+    Ok(cfg.state_dir()?.join("spool"))
+}
+
+/// Put a message in the spool, as `<ulid>.json` so the spool sorts oldest first.
+pub fn enqueue(cfg: &config::Config, queued: &Queued) -> Result<PathBuf> {
+    let dir = spool_dir(cfg)?;
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("create spool dir: {}", dir.display()))?;
+    let path = dir.join(format!("{}.json", ulid::Ulid::new()));
+    crate::write_file(&path, &serde_json::to_vec_pretty(queued)?, None)?;
+    Ok(path)
+}
+
+/// The queued messages, oldest first.
+fn list(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(paths),
+        Err(e) => return Err(e).with_context(|| format!("read spool dir: {}", dir.display())),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Send the queued messages, oldest first, printing the message_id of each (one line per
+/// recipient) and removing it from the spool. Stops at the first one that still can't be sent
+/// for lack of a connection, leaving it and the rest queued. One Telegram refuses is set aside
+/// as `<name>.failed` so it doesn't hold up the others.
+pub async fn flush(config_path: &Path, status: &mut StatusStream) -> Result<Outcome> {
+    let cfg = config::load(config_path)?;
+    let client = TelegramClient::for_config(&cfg)?;
+    let dir = spool_dir(&cfg)?;
+    let mut failed = 0;
+    for path in list(&dir)? {
+        // Claim it first, so two flushes never send the same message.
+        let claimed = path.with_extension(format!("sending-{}", std::process::id()));
+        if std::fs::rename(&path, &claimed).is_err() {
+            continue;
+        }
+        let queued: Queued = match std::fs::read(&claimed)
+            .map_err(anyhow::Error::from)
+            .and_then(|raw| Ok(serde_json::from_slice(&raw)?))
+        {
+            Ok(queued) => queued,
+            Err(e) => {
+                tracing::warn!("setting aside unreadable {}: {e:#}", path.display());
+                std::fs::rename(&claimed, path.with_extension("failed"))?;
+                failed += 1;
+                continue;
+            }
+        };
+        let cfg = config::Config {
+            user_ids: queued.chat_ids.clone(),
+            thread_id: queued.thread_id,
+            message_prefix: queued.message_prefix.clone(),
+            message_suffix: queued.message_suffix.clone(),
+            ..cfg.clone()
+        };
+        let send = queued.send_args();
+        match prompt::deliver_queued(&client, &cfg, &send, &queued.message, status).await {
+            Ok(ids) => {
+                for id in ids {
+                    println!("{id}");
+                }
+                std::fs::remove_file(&claimed)
+                    .with_context(|| format!("remove {}", claimed.display()))?;
+            }
+            Err(e) if backoff::transient(&e) => {
+                std::fs::rename(&claimed, &path)?;
+                tracing::warn!("still offline ({e:#}); the rest stays queued");
+                return Ok(Outcome::Queued);
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "could not send {} (queued {}): {e:#}; set aside as .failed",
+                    path.display(),
+                    queued.queued_at
+                );
+                std::fs::rename(&claimed, path.with_extension("failed"))?;
+                failed += 1;
+            }
+        }
+    }
+    anyhow::ensure!(
+        failed == 0,
+        "{failed} queued message(s) could not be sent; see the .failed files in {}",
+        dir.display()
+    );
+    Ok(Outcome::Done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queued_messages_keep_what_was_resolved_when_queued() {
+        let cfg: config::Config = toml::from_str(
+            "user_id = 1\nthread_id = 9\nmessage_prefix = \"[db01] \"\nstate_dir = \"/tmp\"",
+        )
+        .unwrap();
+        let send = SendArgs {
+            parse_mode: ParseMode::Html,
+            attach: vec![PathBuf::from("report.txt")],
+            ..Default::default()
+        };
+        let queued = Queued::new(&cfg, &send, "<b>done</b>", vec![1, 2]);
+        assert!(queued.attach[0].is_absolute());
+
+        let raw = serde_json::to_string(&queued).unwrap();
+        assert!(raw.contains(r#""parse_mode":"html""#), "{raw}");
+        let back: Queued = serde_json::from_str(&raw).unwrap();
+        assert_eq!(back, queued);
+        let send = back.send_args();
+        assert_eq!(send.message.as_deref(), Some("<b>done</b>"));
+        assert_eq!(send.parse_mode, ParseMode::Html);
+        assert_eq!(back.message_prefix.as_deref(), Some("[db01] "));
+        assert_eq!(back.thread_id, Some(9));
+    }
+}
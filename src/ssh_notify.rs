@@ -0,0 +1,143 @@
+use crate::ask::{self, Answer, Button};
+use crate::telegram::{SendOptions, TelegramClient};
+use crate::{Args, config};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use std::path::Path;
+use std::time::Duration;
+
+const CHOICES: &[Button] = &[
+    Button {
+        name: "kill",
+        label: "🛑 Kill session",
+        words: &["kill"],
+    },
+    Button {
+        name: "mine",
+        label: "👍 It's me",
+        words: &["ok", "me"],
+    },
+];
+const KILL: usize = 0;
+
+/// Send a "new login" alert for the current SSH session. With `kill`, attach a button that
+/// ends the session (by hanging up the parent process) and wait up to `timeout` for it.
+pub async fn run(args: &Args, config_path: &Path, kill: bool, timeout: Duration) -> Result<()> {
+    let cfg = config::load(config_path)?;
+    let client =
+        TelegramClient::for_config(&cfg)?.with_fix_webhook(args.fix_webhook || cfg.fix_webhook);
+    let host = std::fs::read_to_string("/etc/hostname").ok();
+    let text = alert_text(
+        |name| std::env::var(name).ok(),
+        host.as_deref(),
+        Local::now(),
+    );
+
+    if !kill {
+        let opts = SendOptions {
+            thread_id: cfg.thread_id,
+            ..Default::default()
+        };
+        for &chat_id in &cfg.user_ids {
+            client.send_message(chat_id, &text, &opts).await?;
+        }
+        return Ok(());
+    }
+
+    if ask::ask(&client, &cfg, args, &text, CHOICES, timeout).await? == Answer::Chose(KILL) {
+        kill_session()?;
+    }
+    Ok(())
+}
+
+/// Hang up the process that started us: the login shell, or sshd's session process.
+#[cfg(unix)]
+fn kill_session() -> Result<()> {
+    let pid = std::os::unix::process::parent_id();
+    tracing::warn!("killing the session (pid {pid}) as requested");
+    let status = std::process::Command::new("kill")
+        .args(["-HUP", &pid.to_string()])
+        .status()
+        .context("run kill")?;
+    anyhow::ensure!(status.success(), "kill -HUP {pid} failed");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn kill_session() -> Result<()> {
+    anyhow::bail!("killing the session is only supported on Unix")
+}
+
+/// The alert: who logged in, from where, to which host, and when. `SSH_CONNECTION` is
+/// `<client ip> <client port> <server ip> <server port>`; under pam_exec, `PAM_USER` and
+/// `PAM_RHOST` are used when it is missing.
+fn alert_text(
+    env: impl Fn(&str) -> Option<String>,
+    host: Option<&str>,
+    now: DateTime<Local>,
+) -> String {
+    let mut text = "🔔 New SSH login".to_string();
+    let connection = env("SSH_CONNECTION");
+    let parts: Vec<&str> = connection
+        .as_deref()
+        .unwrap_or("")
+        .split_whitespace()
+        .collect();
+
+    if let Some(user) = env("PAM_USER").or_else(|| env("USER")) {
+        text.push_str(&format!("\nuser: {user}"));
+    }
+    match (parts.as_slice(), env("PAM_RHOST")) {
+        ([ip, port, ..], _) => text.push_str(&format!("\nfrom: {ip} (port {port})")),
+        ([], Some(rhost)) => text.push_str(&format!("\nfrom: {rhost}")),
+        _ => {}
+    }
+    let host = host.map(str::trim).filter(|h| !h.is_empty());
+    match (host, parts.as_slice()) {
+        (Some(host), [_, _, ip, port]) => text.push_str(&format!("\nto: {host} ({ip}:{port})")),
+        (Some(host), _) => text.push_str(&format!("\nto: {host}")),
+        (None, [_, _, ip, port]) => text.push_str(&format!("\nto: {ip}:{port}")),
+        _ => {}
+    }
+    text.push_str(&format!("\ntime: {}", now.format("%Y-%m-%d %H:%M:%S %:z")));
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn alert_text_reads_ssh_connection_and_falls_back_to_pam() {
+        let now = Local.with_ymd_and_hms(2024, 5, 1, 9, 30, 0).unwrap();
+        let offset = now.format("%:z");
+
+        let ssh = |name: &str| match name {
+            "USER" => Some("alice".to_string()),
+            "SSH_CONNECTION" => Some("203.0.113.7 52311 10.0.0.5 22".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            alert_text(ssh, Some("web1\n"), now),
+            format!(
+                "🔔 New SSH login\nuser: alice\nfrom: 203.0.113.7 (port 52311)\n\
+                 to: web1 (10.0.0.5:22)\ntime: 2024-05-01 09:30:00 {offset}"
+            )
+        );
+
+        let pam = |name: &str| match name {
+            "USER" => Some("root".to_string()),
+            "PAM_USER" => Some("bob".to_string()),
+            "PAM_RHOST" => Some("198.51.100.2".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            alert_text(pam, None, now),
+            format!(
+                "🔔 New SSH login\nuser: bob\nfrom: 198.51.100.2\n\
+                 time: 2024-05-01 09:30:00 {offset}"
+            )
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use crate::config::{Config, StoreKind};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+
+pub fn default_state_dir() -> Result<PathBuf> {
+    default_state_dir_impl()
+}
+
+#[cfg(target_os = "linux")]
+fn default_state_dir_impl() -> Result<PathBuf> {
+    let base = match std::env::var_os("XDG_STATE_HOME") {
+        Some(xdg) if !xdg.is_empty() => PathBuf::from(xdg),
+        _ => {
+            let home = std::env::var_os("HOME").context("HOME environment variable is not set")?;
+            PathBuf::from(home).join(".local").join("state")
+        }
+    };
+
+    Ok(base.join("teleprompt"))
+}
+
+#[cfg(target_os = "macos")]
+fn default_state_dir_impl() -> Result<PathBuf> {
+    let home = std::env::var_os("HOME").context("HOME environment variable is not set")?;
+    Ok(PathBuf::from(home)
+        .join("Library")
+        .join("Application Support")
+        .join("teleprompt")
+        .join("state"))
+}
+
+#[cfg(target_os = "windows")]
+fn default_state_dir_impl() -> Result<PathBuf> {
+    let local =
+        std::env::var_os("LOCALAPPDATA").context("LOCALAPPDATA environment variable is not set")?;
+    Ok(PathBuf::from(local).join("teleprompt"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn default_state_dir_impl() -> Result<PathBuf> {
+    anyhow::bail!("unsupported OS for default state dir resolution; set state_dir in the config")
+}
+
+/// A directory of small JSON documents that persist between runs.
+///
+/// Each document is read whole and replaced atomically (temp file + rename), so a crash never
+/// leaves a half-written file behind. Concurrent writers are last-writer-wins. With
+/// `store = "sqlite"` the documents live in a database in the directory instead.
+pub struct StateStore {
+    dir: PathBuf,
+    #[cfg(feature = "sqlite")]
+    db: Option<crate::db::Db>,
+}
+
+impl StateStore {
+    /// Open a directory of JSON files.
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("create state dir: {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            #[cfg(feature = "sqlite")]
+            db: None,
+        })
+    }
+
+    /// Open the store the config selects.
+    pub fn for_config(cfg: &Config) -> Result<Self> {
+        #[cfg_attr(not(feature = "sqlite"), allow(unused_mut))]
+        let mut store = Self::open(cfg.state_dir()?)?;
+        if cfg.store == StoreKind::Sqlite {
+            // `config::parse` rejects `store = "sqlite"` in builds without the feature.
+            #[cfg(feature = "sqlite")]
+            {
+                store.db = Some(crate::db::Db::open(&store.dir.join(crate::db::FILE_NAME))?);
+            }
+        }
+        Ok(store)
+    }
+
+    /// Where the documents are: the directory, or the database file.
+    pub fn location(&self) -> PathBuf {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.path().to_path_buf();
+        }
+        self.dir.clone()
+    }
+
+    /// The state directory, where lock files live whichever backend holds the documents.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.json"))
+    }
+
+    /// Load a document, returning its default value if it does not exist yet.
+    pub fn load<T: DeserializeOwned + Default>(&self, name: &str) -> Result<T> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return match db.load(name)? {
+                Some(raw) => serde_json::from_str(&raw)
+                    .with_context(|| format!("parse state document {name:?}")),
+                None => Ok(T::default()),
+            };
+        }
+        let path = self.path(name);
+        let raw = match std::fs::read_to_string(&path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(T::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("read state file: {}", path.display()));
+            }
+        };
+        serde_json::from_str(&raw).with_context(|| format!("parse state file: {}", path.display()))
+    }
+
+    /// Names of all stored documents, sorted.
+    pub fn list(&self) -> Result<Vec<String>> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.list();
+        }
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)
+            .with_context(|| format!("read state dir: {}", self.dir.display()))?
+        {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "json")
+                && let Some(stem) = path.file_stem().and_then(|s| s.to_str())
+            {
+                names.push(stem.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Delete a document. Returns whether it existed.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.remove(name);
+        }
+        let path = self.path(name);
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("remove state file: {}", path.display())),
+        }
+    }
+
+    pub fn save<T: Serialize>(&self, name: &str, value: &T) -> Result<()> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.save(name, &serde_json::to_string(value)?);
+        }
+        let path = self.path(name);
+        let raw = serde_json::to_vec_pretty(value)?;
+        write_atomic(&path, &raw).with_context(|| format!("write state file: {}", path.display()))
+    }
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension(format!("json.tmp.{}", std::process::id()));
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_store() -> StateStore {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time after unix epoch")
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!(
+            "teleprompt_state_test_{}_{nanos}",
+            std::process::id()
+        ));
+        StateStore::open(dir).unwrap()
+    }
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Doc {
+        n: u32,
+    }
+
+    #[test]
+    fn load_missing_document_returns_default() {
+        let store = temp_store();
+        let doc: Doc = store.load("missing").unwrap();
+        assert_eq!(doc, Doc::default());
+    }
+
+    #[test]
+    fn save_then_load_round_trips() {
+        let store = temp_store();
+        store.save("doc", &Doc { n: 7 }).unwrap();
+        store.save("doc", &Doc { n: 8 }).unwrap();
+        let doc: Doc = store.load("doc").unwrap();
+        assert_eq!(doc, Doc { n: 8 });
+    }
+
+    #[test]
+    fn list_and_remove_documents() {
+        let store = temp_store();
+        store.save("b", &Doc { n: 1 }).unwrap();
+        store.save("a", &Doc { n: 2 }).unwrap();
+        std::fs::write(store.location().join("notes.txt"), "ignored").unwrap();
+
+        assert_eq!(store.list().unwrap(), vec!["a", "b"]);
+        assert!(store.remove("a").unwrap());
+        assert!(!store.remove("a").unwrap());
+        assert_eq!(store.list().unwrap(), vec!["b"]);
+    }
+}
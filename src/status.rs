@@ -0,0 +1,342 @@
+use crate::telegram::{ErrorKind, TelegramError};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Lifecycle events written to the machine-readable status stream.
+///
+/// Each event is serialized as one JSON object per line (NDJSON) with an `event` tag and a
+/// `ts` timestamp. Field names are part of the documented interface; only add fields.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Sent {
+        prompt_id: String,
+        chat_id: i64,
+        message_id: i64,
+        /// Correlation tag appended to the prompt, under `--correlate`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tag: Option<String>,
+    },
+    Reply {
+        chat_id: i64,
+        text: String,
+        /// ISO 639-3 code of the reply, when `[translate]` is configured and detection is reliable.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+        /// Translated reply, when it was in a language other than the expected one.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        translated: Option<String>,
+        /// Where a photo/document/voice/video reply was saved; `text` is empty for those.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        file: Option<PathBuf>,
+        /// The emoji, when the answer was a reaction to the prompt.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reaction: Option<String>,
+        /// Set under `--secret`, where `text` is left empty.
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        redacted: bool,
+    },
+    /// An answer was edited within `--accept-edits`; `text` replaces the one in its `reply`.
+    Edited {
+        chat_id: i64,
+        message_id: i64,
+        text: String,
+        /// Set under `--secret`, where `text` is left empty.
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        redacted: bool,
+    },
+    /// A live location answering under `--expect-location` moved.
+    Location {
+        chat_id: i64,
+        message_id: i64,
+        latitude: f64,
+        longitude: f64,
+    },
+    /// Nobody answered within `--escalate-after`; the prompt is re-sent to `chat_ids`.
+    Escalated {
+        chat_ids: Vec<i64>,
+    },
+    /// Telegram couldn't be reached, so under `--queue-offline` the message was spooled for
+    /// `teleprompt flush` instead.
+    Queued {
+        chat_ids: Vec<i64>,
+        path: PathBuf,
+    },
+    /// The message is held until `send_at` (`--send-at`, `--delay`).
+    Scheduled {
+        send_at: String,
+    },
+    /// The prompt is out; waiting for an answer for up to `timeout_s`.
+    Polling {
+        timeout_s: u64,
+    },
+    /// A `--remind-every` reminder was sent.
+    Reminder {
+        chat_id: i64,
+        message_id: i64,
+    },
+    /// Polling failed with a transient error; it is retried after `delay_ms`.
+    Retrying {
+        attempt: u32,
+        delay_ms: u64,
+        message: String,
+    },
+    /// A reply didn't match `--expect`; the recipient was asked again.
+    Invalid {
+        chat_id: i64,
+    },
+    Timeout {
+        timeout_s: u64,
+    },
+    /// An identical prompt was sent within `--dedupe-window`, so this one wasn't: its answer
+    /// is reused (`cached`), or awaited.
+    Deduplicated {
+        cached: bool,
+    },
+    /// The recipient sent the cancel command.
+    Cancelled {
+        chat_id: i64,
+    },
+    Suppressed {
+        muted: bool,
+        count: usize,
+    },
+    Vote {
+        user_id: i64,
+        approve: bool,
+        approvals: usize,
+        rejections: usize,
+        quorum: usize,
+    },
+    Error {
+        message: String,
+        /// Classification of Telegram request failures; absent for other errors.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        kind: Option<ErrorKind>,
+        retryable: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        http_status: Option<u16>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error_code: Option<i64>,
+    },
+}
+
+impl Event {
+    pub fn error(e: &anyhow::Error) -> Self {
+        let tg = e.downcast_ref::<TelegramError>();
+        Event::Error {
+            message: format!("{e:#}"),
+            kind: tg.map(|t| t.kind),
+            retryable: tg.is_some_and(|t| t.kind.retryable()),
+            http_status: tg.and_then(|t| t.http_status),
+            error_code: tg.and_then(|t| t.error_code),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    ts: String,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Destination for status events. Disabled unless `--status-fd`, `--status-file` or
+/// `--progress ndjson` is given.
+pub struct StatusStream {
+    out: Option<Box<dyn Write>>,
+}
+
+impl StatusStream {
+    pub fn disabled() -> Self {
+        Self { out: None }
+    }
+
+    /// Stream events to stderr, in place of the human-facing progress log.
+    pub fn stderr() -> Self {
+        Self {
+            out: Some(Box::new(std::io::stderr())),
+        }
+    }
+
+    /// Stream events to `out`, e.g. to forward them elsewhere.
+    pub fn writer(out: Box<dyn Write>) -> Self {
+        Self { out: Some(out) }
+    }
+
+    pub fn open(fd: Option<i32>, file: Option<&Path>) -> Result<Self> {
+        if let Some(path) = file {
+            let f = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("open status file: {}", path.display()))?;
+            return Ok(Self {
+                out: Some(Box::new(f)),
+            });
+        }
+
+        match fd {
+            Some(fd) => Ok(Self {
+                out: Some(open_fd(fd)?),
+            }),
+            None => Ok(Self::disabled()),
+        }
+    }
+
+    /// Write `event`. Best effort: if the stream fails, e.g. because its reader went away,
+    /// that is logged once and the stream disabled, so the prompt itself carries on.
+    pub fn emit(&mut self, event: Event) {
+        let Some(out) = self.out.as_mut() else {
+            return;
+        };
+
+        let record = Record {
+            ts: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            event: &event,
+        };
+        let mut line = serde_json::to_vec(&record).expect("status events serialize");
+        line.push(b'\n');
+
+        // Flush per line so consumers reading the stream see events as they happen.
+        if let Err(e) = out.write_all(&line).and_then(|()| out.flush()) {
+            tracing::warn!("status stream failed ({e}); no further events will be written");
+            self.out = None;
+        }
+    }
+}
+
+#[cfg(unix)]
+fn open_fd(fd: i32) -> Result<Box<dyn Write>> {
+    use std::os::fd::FromRawFd;
+
+    // Taking ownership of stdin/stdout/stderr would close them on drop and interleave status
+    // lines with the reply or human-facing messages.
+    anyhow::ensure!(
+        fd > 2,
+        "--status-fd must be 3 or higher (got {fd}); use --status-file for other targets"
+    );
+
+    // A descriptor that isn't open would be handed to whatever the process opens next, e.g.
+    // the connection to Telegram, and the events written into that.
+    // SAFETY: F_GETFD only reads the descriptor's flags.
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        let e = std::io::Error::last_os_error();
+        anyhow::bail!(
+            "--status-fd {fd} is not an open file descriptor ({e}); open it first, e.g. with `3>status.ndjson`"
+        );
+    }
+
+    // SAFETY: the caller hands us this descriptor explicitly for our exclusive use (e.g. via
+    // `3>status.ndjson` in the shell); it is open and not otherwise referenced by this process.
+    let f = unsafe { std::fs::File::from_raw_fd(fd) };
+    Ok(Box::new(f))
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: i32) -> Result<Box<dyn Write>> {
+    anyhow::bail!("--status-fd is only supported on Unix; use --status-file instead")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_serializes_with_tag_and_timestamp() {
+        let event = Event::Sent {
+            prompt_id: "01ARZ3NDEKTSV4RRFFQ69G5FAV".to_string(),
+            chat_id: 1,
+            message_id: 2,
+            tag: None,
+        };
+        let record = Record {
+            ts: "2024-01-01T00:00:00.000Z".to_string(),
+            event: &event,
+        };
+
+        let v: serde_json::Value = serde_json::to_value(&record).unwrap();
+        assert_eq!(
+            v,
+            serde_json::json!({
+                "ts": "2024-01-01T00:00:00.000Z",
+                "event": "sent",
+                "prompt_id": "01ARZ3NDEKTSV4RRFFQ69G5FAV",
+                "chat_id": 1,
+                "message_id": 2,
+            })
+        );
+    }
+
+    #[test]
+    fn status_file_receives_one_line_per_event() {
+        let path = std::env::temp_dir().join(format!(
+            "teleprompt_status_test_{}_{}.ndjson",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+
+        let mut stream = StatusStream::open(None, Some(&path)).unwrap();
+        stream.emit(Event::Timeout { timeout_s: 60 });
+        stream.emit(Event::error(&anyhow::anyhow!("boom")));
+
+        let raw = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = raw
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "timeout");
+        assert_eq!(lines[0]["timeout_s"], 60);
+        assert_eq!(lines[1]["event"], "error");
+        assert_eq!(lines[1]["message"], "boom");
+        assert_eq!(lines[1]["retryable"], false);
+        assert!(lines[1].get("kind").is_none());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn error_event_carries_telegram_classification_through_context() {
+        let mut tg = TelegramError::new(ErrorKind::Server, "telegram http error: status=502");
+        tg.http_status = Some(502);
+        let e = anyhow::Error::new(tg).context("send prompt");
+
+        let v = serde_json::to_value(Event::error(&e)).unwrap();
+        assert_eq!(v["kind"], "server");
+        assert_eq!(v["retryable"], true);
+        assert_eq!(v["http_status"], 502);
+        assert_eq!(v["message"], "send prompt: telegram http error: status=502");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn closed_fds_are_refused_and_failed_streams_disabled() {
+        let err = StatusStream::open(Some(1_000), None).err().unwrap();
+        assert!(
+            err.to_string().contains("not an open file descriptor"),
+            "{err}"
+        );
+
+        struct Gone;
+        impl Write for Gone {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut stream = StatusStream::writer(Box::new(Gone));
+        stream.emit(Event::Timeout { timeout_s: 1 });
+        assert!(stream.out.is_none());
+    }
+
+    #[test]
+    fn disabled_stream_ignores_events() {
+        let mut stream = StatusStream::disabled();
+        stream.emit(Event::Timeout { timeout_s: 1 });
+    }
+}
@@ -0,0 +1,248 @@
+use crate::telegram::{self, SendOptions, TelegramClient};
+use crate::{Outcome, config};
+use anyhow::{Context, Result};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::mpsc;
+
+/// How often a followed file is checked for new output.
+const FOLLOW_POLL: Duration = Duration::from_secs(1);
+
+/// Where `teleprompt tail` reads lines from.
+pub enum Source {
+    /// Run this command (program and arguments), forwarding its stdout and stderr.
+    Command(Vec<String>),
+    /// Follow a file as it grows, like `tail -f`.
+    Follow(PathBuf),
+}
+
+/// Forward new lines from `source` to the recipients every `every`: appended to the last
+/// message by editing it while it has room, in new messages once it is full. A command's
+/// output is also passed through to stdout, and its exit status ends the run.
+pub async fn run(
+    config_path: &Path,
+    to: &[String],
+    source: Source,
+    every: Duration,
+) -> Result<Outcome> {
+    let mut cfg = config::load(config_path)?;
+    if !to.is_empty() {
+        cfg.user_ids = to
+            .iter()
+            .map(|name| cfg.resolve_recipient(name))
+            .collect::<Result<_>>()?;
+    }
+    let client = TelegramClient::for_config(&cfg)?;
+    let mut feed = Feed {
+        client,
+        opts: SendOptions {
+            thread_id: cfg.thread_id,
+            ..Default::default()
+        },
+        chat_ids: cfg.user_ids.clone(),
+        messages: Vec::new(),
+        text: String::new(),
+    };
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut child = match &source {
+        Source::Command(command) => {
+            let (program, args) = command.split_first().context("no command to run")?;
+            let mut child = tokio::process::Command::new(program)
+                .args(args)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .with_context(|| format!("run {program}"))?;
+            let stdout = child.stdout.take().expect("stdout is piped");
+            let stderr = child.stderr.take().expect("stderr is piped");
+            tokio::spawn(forward_lines(stdout, tx.clone(), true));
+            tokio::spawn(forward_lines(stderr, tx, false));
+            Some(child)
+        }
+        Source::Follow(path) => {
+            let pos = std::fs::metadata(path)
+                .with_context(|| format!("follow {}", path.display()))?
+                .len();
+            tokio::spawn(follow(path.clone(), pos, tx));
+            None
+        }
+    };
+
+    let mut pending = Vec::new();
+    let mut tick = tokio::time::interval(every);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            line = rx.recv() => match line {
+                Some(line) => pending.push(line),
+                // Every reader is done: the command closed its output.
+                None => break,
+            },
+            _ = tick.tick() => {
+                if let Err(e) = feed.push(&pending.join("\n")).await {
+                    tracing::warn!("could not forward output: {e:#}");
+                }
+                pending.clear();
+            }
+        }
+    }
+
+    let status = child
+        .as_mut()
+        .expect("only a command's output ends")
+        .wait()
+        .await?;
+    pending.push(match status.code() {
+        Some(0) => "✅ exited 0".to_string(),
+        Some(code) => format!("❌ exited {code}"),
+        None => "❌ killed by a signal".to_string(),
+    });
+    feed.push(&pending.join("\n")).await?;
+    Ok(match status.code() {
+        Some(0) => Outcome::Done,
+        code => Outcome::Exit(code.unwrap_or(1)),
+    })
+}
+
+/// Send each line of `reader` down the channel, echoing it to our stdout or stderr.
+pub async fn forward_lines(
+    reader: impl AsyncRead + Unpin,
+    tx: mpsc::UnboundedSender<String>,
+    is_stdout: bool,
+) {
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if is_stdout {
+            println!("{line}");
+        } else {
+            eprintln!("{line}");
+        }
+        if tx.send(line).is_err() {
+            return;
+        }
+    }
+}
+
+/// Send lines appended to `path` after `pos` down the channel, starting over if the file is
+/// truncated. A trailing partial line waits until it is finished.
+async fn follow(path: PathBuf, mut pos: u64, tx: mpsc::UnboundedSender<String>) {
+    let mut partial = String::new();
+    loop {
+        tokio::time::sleep(FOLLOW_POLL).await;
+        let read = (|| -> std::io::Result<String> {
+            let mut file = std::fs::File::open(&path)?;
+            if file.metadata()?.len() < pos {
+                pos = 0;
+                partial.clear();
+            }
+            file.seek(SeekFrom::Start(pos))?;
+            let mut buf = Vec::new();
+            pos += file.read_to_end(&mut buf)? as u64;
+            Ok(String::from_utf8_lossy(&buf).into_owned())
+        })();
+        match read {
+            Ok(new) => partial.push_str(&new),
+            Err(e) => {
+                tracing::warn!("could not read {}: {e}", path.display());
+                continue;
+            }
+        }
+        while let Some(end) = partial.find('\n') {
+            let line = partial[..end].trim_end_matches('\r').to_string();
+            partial.drain(..=end);
+            if tx.send(line).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// The messages output is being appended to.
+struct Feed {
+    client: TelegramClient,
+    opts: SendOptions,
+    chat_ids: Vec<i64>,
+    /// The last message sent to each recipient.
+    messages: Vec<(i64, i64)>,
+    /// Text of those messages.
+    text: String,
+}
+
+impl Feed {
+    async fn push(&mut self, chunk: &str) -> Result<()> {
+        if chunk.trim().is_empty() {
+            return Ok(());
+        }
+        match plan(&self.text, chunk) {
+            Plan::Edit(text) => {
+                for &(chat_id, message_id) in &self.messages {
+                    self.client
+                        .edit_message_text(chat_id, message_id, &text, &self.opts)
+                        .await?;
+                }
+                self.text = text;
+            }
+            Plan::Send(parts) => {
+                let mut messages = Vec::new();
+                for &chat_id in &self.chat_ids {
+                    let mut last = None;
+                    for part in &parts {
+                        last = Some(self.client.send_message(chat_id, part, &self.opts).await?);
+                    }
+                    messages.extend(last.map(|id| (chat_id, id)));
+                }
+                self.messages = messages;
+                self.text = parts.last().cloned().unwrap_or_default();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Plan {
+    /// Replace the last message's text with this.
+    Edit(String),
+    /// Send these as new messages.
+    Send(Vec<String>),
+}
+
+/// Append `chunk` to the last message if it still fits in one message, otherwise start new
+/// ones.
+fn plan(current: &str, chunk: &str) -> Plan {
+    if !current.is_empty() {
+        let text = format!("{current}\n{chunk}");
+        if telegram::utf16_len(&text) <= telegram::MAX_MESSAGE_LEN {
+            return Plan::Edit(text);
+        }
+    }
+    Plan::Send(telegram::split_message(chunk, telegram::MAX_MESSAGE_LEN))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_updates_edit_and_large_chunks_start_new_messages() {
+        assert_eq!(
+            plan("", "building"),
+            Plan::Send(vec!["building".to_string()])
+        );
+        assert_eq!(
+            plan("building", "linking"),
+            Plan::Edit("building\nlinking".to_string())
+        );
+
+        let big = "x".repeat(telegram::MAX_MESSAGE_LEN);
+        assert_eq!(plan("building", &big), Plan::Send(vec![big.clone()]));
+        let Plan::Send(parts) = plan("", &format!("{big}\nmore")) else {
+            panic!("expected new messages");
+        };
+        assert_eq!(parts.len(), 2);
+    }
+}
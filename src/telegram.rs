@@ -1,9 +1,65 @@
-use anyhow::{Context, Result, bail};
-use serde::Deserialize;
+use anyhow::{Context, Result};
 use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
 
 const DEFAULT_BASE_URL: &str = "https://api.telegram.org";
 
+/// How often a rate-limited (429) request is retried before giving up.
+const RATE_LIMIT_RETRIES: u32 = 5;
+
+/// Longer waits than this are reported as errors instead of slept through.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(300);
+
+/// Longest caption Telegram accepts on media messages.
+pub const MAX_CAPTION_LEN: usize = 1024;
+
+/// Longest text Telegram accepts in one message, in UTF-16 code units.
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Length as Telegram counts it: UTF-16 code units (so most emoji count twice).
+pub fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Split text into chunks of at most `max` UTF-16 code units, breaking between lines where
+/// possible and inside a line only when the line alone is too long.
+pub fn split_message(text: &str, max: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut cur = String::new();
+    let mut cur_len = 0;
+
+    for line in text.split_inclusive('\n') {
+        let len = utf16_len(line);
+        if cur_len + len > max && !cur.is_empty() {
+            chunks.push(std::mem::take(&mut cur));
+            cur_len = 0;
+        }
+        if len <= max {
+            cur.push_str(line);
+            cur_len += len;
+            continue;
+        }
+        for ch in line.chars() {
+            if cur_len + ch.len_utf16() > max {
+                chunks.push(std::mem::take(&mut cur));
+                cur_len = 0;
+            }
+            cur.push(ch);
+            cur_len += ch.len_utf16();
+        }
+    }
+    chunks.push(cur);
+
+    // The newline a chunk was split after is noise; an all-blank chunk would be rejected.
+    chunks
+        .into_iter()
+        .map(|c| c.trim_end_matches('\n').to_string())
+        .filter(|c| !c.trim().is_empty())
+        .collect()
+}
+
 fn redact_token(text: &str, token: &str) -> String {
     // If token is empty, `replace` would insert <redacted> between every character.
     if token.is_empty() {
@@ -12,10 +68,238 @@ fn redact_token(text: &str, token: &str) -> String {
     text.replace(token, "<redacted>")
 }
 
+/// Broad category of a failed Telegram request, so callers can tell "retry later" apart from
+/// "something is misconfigured".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The API host name could not be resolved.
+    Dns,
+    /// TLS handshake or certificate verification failed.
+    Tls,
+    /// The TCP connection was refused, reset, or otherwise failed.
+    Connect,
+    /// Establishing the connection timed out.
+    ConnectTimeout,
+    /// The request was sent but no complete response arrived in time.
+    Timeout,
+    /// Telegram answered with an HTTP 5xx.
+    Server,
+    /// Another `getUpdates` request for the bot overlapped this one, which Telegram answers
+    /// with a 409 ("terminated by other getUpdates request").
+    Conflict,
+    /// Telegram rejected the request (`ok: false`, or an HTTP 4xx).
+    Api,
+    /// The response could not be understood.
+    InvalidResponse,
+}
+
+impl ErrorKind {
+    /// Whether the same request may succeed if retried later without any changes.
+    pub fn retryable(self) -> bool {
+        matches!(
+            self,
+            Self::Dns
+                | Self::Connect
+                | Self::ConnectTimeout
+                | Self::Timeout
+                | Self::Server
+                | Self::Conflict
+        )
+    }
+}
+
+/// A classified Telegram request failure. Usually carried inside `anyhow::Error`; recover it
+/// with `downcast_ref::<TelegramError>()`.
+#[derive(Debug)]
+pub struct TelegramError {
+    pub kind: ErrorKind,
+    /// HTTP status, when a response was received.
+    pub http_status: Option<u16>,
+    /// Telegram's `error_code`, for API-level failures.
+    pub error_code: Option<i64>,
+    /// How long Telegram asked us to wait, for 429 Too Many Requests.
+    pub retry_after: Option<Duration>,
+    message: String,
+}
+
+impl TelegramError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            http_status: None,
+            error_code: None,
+            retry_after: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for TelegramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for TelegramError {}
+
+/// Classify a transport-level failure from its flags and the text of its source chain.
+///
+/// reqwest/hyper don't expose DNS or TLS failures as distinct types, so this relies on the
+/// messages hyper-util and rustls produce.
+fn classify_transport(is_timeout: bool, is_connect: bool, chain: &str) -> ErrorKind {
+    let chain = chain.to_ascii_lowercase();
+    if chain.contains("dns error") || chain.contains("failed to lookup address") {
+        ErrorKind::Dns
+    } else if chain.contains("certificate") || chain.contains("tls") || chain.contains("handshake")
+    {
+        ErrorKind::Tls
+    } else if is_timeout && is_connect {
+        ErrorKind::ConnectTimeout
+    } else if is_timeout {
+        ErrorKind::Timeout
+    } else {
+        ErrorKind::Connect
+    }
+}
+
+/// How Telegram should interpret markup in message text and captions.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseMode {
+    /// Send text as-is.
+    #[default]
+    Plain,
+    #[value(name = "markdownv2")]
+    MarkdownV2,
+    Html,
+}
+
+impl ParseMode {
+    fn api_name(self) -> Option<&'static str> {
+        match self {
+            Self::Plain => None,
+            Self::MarkdownV2 => Some("MarkdownV2"),
+            Self::Html => Some("HTML"),
+        }
+    }
+
+    /// Escape text so it is shown literally under this parse mode, e.g. when interpolating
+    /// untrusted content into a formatted prompt.
+    pub fn escape(self, text: &str) -> String {
+        match self {
+            Self::Plain => text.to_string(),
+            Self::MarkdownV2 => {
+                let mut out = String::with_capacity(text.len());
+                for ch in text.chars() {
+                    if "_*[]()~`>#+-=|{}.!\\".contains(ch) {
+                        out.push('\\');
+                    }
+                    out.push(ch);
+                }
+                out
+            }
+            Self::Html => text
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;"),
+        }
+    }
+
+    /// Wrap text in a monospace block, optionally tagged with a language for highlighting.
+    /// Plain mode has no markup, so it gets MarkdownV2; the mode to send with is returned.
+    pub fn code_block(self, lang: &str, text: &str) -> (Self, String) {
+        // The language is interpolated into markup; anything unusual is dropped.
+        let lang: String = lang
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || "+-_#".contains(*c))
+            .collect();
+        match self {
+            Self::Plain | Self::MarkdownV2 => {
+                // Inside pre blocks only ` and \ need escaping.
+                let body = text.replace('\\', "\\\\").replace('`', "\\`");
+                (Self::MarkdownV2, format!("```{lang}\n{body}\n```"))
+            }
+            Self::Html => {
+                let body = self.escape(text);
+                if lang.is_empty() {
+                    (self, format!("<pre>{body}</pre>"))
+                } else {
+                    let block = format!("<pre><code class=\"language-{lang}\">{body}</code></pre>");
+                    (self, block)
+                }
+            }
+        }
+    }
+}
+
+/// Optional send/edit parameters beyond chat and text.
+#[derive(Debug, Clone, Default)]
+pub struct SendOptions {
+    pub reply_markup: Option<serde_json::Value>,
+    pub parse_mode: ParseMode,
+    /// Send as a reply to this message in the same chat.
+    pub reply_to: Option<i64>,
+    /// Forum topic to post in.
+    pub thread_id: Option<i64>,
+    /// Keep the message from being forwarded or saved.
+    pub protect_content: bool,
+}
+
+impl SendOptions {
+    /// The options as Bot API parameters, omitting unset ones.
+    fn params(&self) -> Vec<(&'static str, serde_json::Value)> {
+        let mut params = Vec::new();
+        if let Some(markup) = &self.reply_markup {
+            params.push(("reply_markup", markup.clone()));
+        }
+        if let Some(mode) = self.parse_mode.api_name() {
+            params.push(("parse_mode", mode.into()));
+        }
+        if let Some(id) = self.thread_id {
+            params.push(("message_thread_id", id.into()));
+        }
+        if self.protect_content {
+            params.push(("protect_content", true.into()));
+        }
+        if let Some(id) = self.reply_to {
+            params.push((
+                "reply_parameters",
+                serde_json::json!({ "message_id": id, "allow_sending_without_reply": true }),
+            ));
+        }
+        params
+    }
+
+    fn apply_json(&self, body: &mut serde_json::Value) {
+        for (key, value) in self.params() {
+            body[key] = value;
+        }
+    }
+
+    fn apply_form(&self, mut form: reqwest::multipart::Form) -> reqwest::multipart::Form {
+        for (key, value) in self.params() {
+            // Multipart fields are plain strings; only structured values are JSON-encoded.
+            let value = match value {
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            form = form.text(key, value);
+        }
+        form
+    }
+}
+
 pub struct TelegramClient {
     http: reqwest::Client,
     base_url: String,
     token: String,
+    /// Delete a webhook that blocks `getUpdates` instead of failing.
+    fix_webhook: bool,
+    /// Per-request timeout, if configured; long polls get their hold time on top.
+    request_timeout: Option<Duration>,
+    /// Spaces out messages shared with other runs, under `[rate_limit]`.
+    limiter: Option<crate::ratelimit::Limiter>,
 }
 
 impl TelegramClient {
@@ -24,31 +308,179 @@ impl TelegramClient {
             http: reqwest::Client::new(),
             base_url: DEFAULT_BASE_URL.to_string(),
             token,
+            fix_webhook: false,
+            request_timeout: None,
+            limiter: None,
         }
     }
 
+    /// A client using the config's network settings.
+    pub fn for_config(cfg: &crate::config::Config) -> Result<Self> {
+        let user_agent = cfg
+            .user_agent
+            .clone()
+            .unwrap_or_else(|| concat!("teleprompt/", env!("CARGO_PKG_VERSION")).to_string());
+        let mut http = reqwest::Client::builder().user_agent(user_agent);
+        if let Some(timeout) = cfg.connect_timeout {
+            http = http.connect_timeout(timeout);
+        }
+        if let Some(timeout) = cfg.request_timeout {
+            http = http.timeout(timeout);
+        }
+        if let Some(proxy) = &cfg.proxy {
+            // The URL may carry credentials, so it stays out of the message.
+            let proxy = reqwest::Proxy::all(proxy)
+                .context("invalid proxy URL (config `proxy` or TELEPROMPT_PROXY)")?;
+            http = http.proxy(proxy);
+        }
+        if let Some(path) = &cfg.ca_file {
+            let pem =
+                std::fs::read(path).with_context(|| format!("read ca_file: {}", path.display()))?;
+            let certs = reqwest::Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("parse ca_file: {}", path.display()))?;
+            anyhow::ensure!(
+                !certs.is_empty(),
+                "ca_file has no certificates: {}",
+                path.display()
+            );
+            for cert in certs {
+                http = http.add_root_certificate(cert);
+            }
+        }
+        if let Some(path) = &cfg.client_cert {
+            let mut pem = std::fs::read(path)
+                .with_context(|| format!("read client_cert: {}", path.display()))?;
+            if let Some(key) = &cfg.client_key {
+                pem.push(b'\n');
+                pem.extend(
+                    std::fs::read(key)
+                        .with_context(|| format!("read client_key: {}", key.display()))?,
+                );
+            }
+            let identity = reqwest::Identity::from_pem(&pem)
+                .context("parse client_cert/client_key (PEM certificate and private key)")?;
+            http = http.identity(identity);
+        }
+
+        let mut client = Self::new(cfg.bot_token.clone());
+        client.http = http.build().context("build HTTP client")?;
+        client.request_timeout = cfg.request_timeout;
+        if let Some(url) = &cfg.api_url {
+            client.base_url = url.trim_end_matches('/').to_string();
+        }
+        if let Some(limits) = &cfg.rate_limit {
+            let state_dir = cfg.state_dir().context("rate_limit needs a state dir")?;
+            client.limiter = Some(crate::ratelimit::Limiter::new(
+                &state_dir,
+                &cfg.bot_token,
+                limits.clone(),
+            )?);
+        }
+        Ok(client)
+    }
+
+    /// With `fix`, a `getUpdates` conflict with a registered webhook is resolved by deleting
+    /// the webhook (`--fix-webhook`).
+    pub fn with_fix_webhook(mut self, fix: bool) -> Self {
+        self.fix_webhook = fix;
+        self
+    }
+
     fn method_url(&self, method: &str) -> String {
         format!("{}/bot{}/{}", self.base_url, self.token, method)
     }
 
-    fn reqwest_error(&self, method: &str, e: reqwest::Error) -> anyhow::Error {
+    fn reqwest_error(&self, method: &str, e: reqwest::Error) -> TelegramError {
+        let mut chain = e.to_string();
+        let mut source = std::error::Error::source(&e);
+        while let Some(s) = source {
+            chain.push_str(": ");
+            chain.push_str(&s.to_string());
+            source = s.source();
+        }
+
+        let kind = classify_transport(e.is_timeout(), e.is_connect(), &chain);
         // reqwest::Error Display often includes the full request URL; for Telegram this
         // contains the bot token, so we must redact it.
-        let msg = redact_token(&e.to_string(), &self.token);
-        anyhow::anyhow!("telegram request failed: method={method}: {msg}")
+        let msg = redact_token(&chain, &self.token);
+        TelegramError::new(
+            kind,
+            format!("telegram request failed: method={method}: {msg}"),
+        )
     }
 
+    fn file_url(&self, file_path: &str) -> String {
+        format!("{}/file/bot{}/{}", self.base_url, self.token, file_path)
+    }
+
+    /// POST a JSON request. When rate limited (429), waits as long as Telegram asks and tries
+    /// again, up to `RATE_LIMIT_RETRIES` times.
     async fn post_json<T: DeserializeOwned>(
         &self,
         method: &str,
         body: serde_json::Value,
     ) -> Result<T> {
-        let url = self.method_url(method);
+        self.post_json_held(method, body, Duration::ZERO).await
+    }
 
-        let res = self
-            .http
-            .post(&url)
-            .json(&body)
+    /// `post_json` for a request the server may hold open for `hold` (a long poll) before
+    /// answering; it is added to the configured request timeout.
+    async fn post_json_held<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        body: serde_json::Value,
+        hold: Duration,
+    ) -> Result<T> {
+        let mut retries = 0;
+        loop {
+            self.throttle(method, body["chat_id"].as_i64()).await?;
+            tracing::debug!(method, params = %redact_params(&body), "telegram request");
+            let mut req = self.http.post(self.method_url(method)).json(&body);
+            if let Some(timeout) = self.request_timeout.filter(|_| !hold.is_zero()) {
+                req = req.timeout(timeout + hold);
+            }
+            let res = self.execute(method, req).await;
+            let wait = res.as_ref().err().and_then(retry_after);
+            match wait {
+                Some(wait) if retries < RATE_LIMIT_RETRIES && wait <= MAX_RATE_LIMIT_WAIT => {
+                    retries += 1;
+                    tracing::warn!("{method} was rate limited; retrying in {}s", wait.as_secs());
+                    tokio::time::sleep(wait).await;
+                }
+                _ => return res,
+            }
+        }
+    }
+
+    async fn post_multipart<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        chat_id: i64,
+        form: reqwest::multipart::Form,
+    ) -> Result<T> {
+        self.throttle(method, Some(chat_id)).await?;
+        tracing::debug!(method, "telegram upload");
+        let req = self.http.post(self.method_url(method)).multipart(form);
+        self.execute(method, req).await
+    }
+
+    /// Under `[rate_limit]`, wait until a message may be sent to `chat_id` by `method`.
+    async fn throttle(&self, method: &str, chat_id: Option<i64>) -> Result<()> {
+        match (&self.limiter, chat_id) {
+            (Some(limiter), Some(chat_id)) if crate::ratelimit::limits(method) => {
+                limiter.wait(chat_id).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn execute<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        req: reqwest::RequestBuilder,
+    ) -> Result<T> {
+        let start = std::time::Instant::now();
+        let res = req
             .send()
             .await
             .map_err(|e| self.reqwest_error(method, e))?;
@@ -58,36 +490,381 @@ impl TelegramClient {
             .text()
             .await
             .map_err(|e| self.reqwest_error(method, e))?;
+        tracing::debug!(
+            method,
+            status = status.as_u16(),
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            bytes = text.len(),
+            "telegram response"
+        );
 
-        if !status.is_success() {
-            bail!("telegram http error: method={method} status={status} body={text}");
+        let parsed = serde_json::from_str::<ApiResponse<T>>(&text);
+
+        if status.is_server_error() || (!status.is_success() && parsed.is_err()) {
+            let kind = if status.is_server_error() {
+                ErrorKind::Server
+            } else {
+                ErrorKind::Api
+            };
+            let mut err = TelegramError::new(
+                kind,
+                format!("telegram http error: method={method} status={status} body={text}"),
+            );
+            err.http_status = Some(status.as_u16());
+            return Err(err.into());
+        }
+
+        let parsed = parsed.map_err(|e| {
+            TelegramError::new(
+                ErrorKind::InvalidResponse,
+                format!("parse telegram response json: {method}: {e}"),
+            )
+        })?;
+
+        parsed.into_result().map_err(|mut e| {
+            if !status.is_success() {
+                e.http_status = Some(status.as_u16());
+            }
+            anyhow::Error::new(e).context(format!("telegram method failed: {method}"))
+        })
+    }
+
+    /// Send text, splitting it into several sequential messages if it exceeds
+    /// `MAX_MESSAGE_LEN`. Options such as reply markup apply to the last part, whose id is
+    /// returned.
+    pub async fn send_message(&self, user_id: i64, text: &str, opts: &SendOptions) -> Result<i64> {
+        let ids = self.send_message_parts(user_id, text, opts).await?;
+        Ok(*ids.last().expect("at least one part is sent"))
+    }
+
+    /// Like `send_message`, but returns the ids of every part sent, in order.
+    pub async fn send_message_parts(
+        &self,
+        user_id: i64,
+        text: &str,
+        opts: &SendOptions,
+    ) -> Result<Vec<i64>> {
+        if utf16_len(text) <= MAX_MESSAGE_LEN {
+            return Ok(vec![self.send_message_part(user_id, text, opts).await?]);
+        }
+
+        let parts = split_message(text, MAX_MESSAGE_LEN);
+        let (last, rest) = parts.split_last().context("message has no visible text")?;
+        // Buttons only go on the last part.
+        let rest_opts = SendOptions {
+            reply_markup: None,
+            ..opts.clone()
+        };
+        let mut ids = Vec::new();
+        for part in rest {
+            ids.push(self.send_message_part(user_id, part, &rest_opts).await?);
+        }
+        ids.push(self.send_message_part(user_id, last, opts).await?);
+        Ok(ids)
+    }
+
+    async fn send_message_part(&self, user_id: i64, text: &str, opts: &SendOptions) -> Result<i64> {
+        let mut body = serde_json::json!({
+            "chat_id": user_id,
+            "text": text,
+        });
+        opts.apply_json(&mut body);
+
+        let result: SentMessage = self.post_json("sendMessage", body).await?;
+
+        Ok(result.message_id)
+    }
+
+    /// Upload a local file via `sendDocument`, optionally with a caption.
+    pub async fn send_document(
+        &self,
+        chat_id: i64,
+        path: &std::path::Path,
+        caption: Option<&str>,
+        opts: &SendOptions,
+    ) -> Result<i64> {
+        self.send_file("sendDocument", "document", chat_id, path, caption, opts)
+            .await
+    }
+
+    /// Upload a local image via `sendPhoto` so it renders inline, optionally with a caption.
+    pub async fn send_photo(
+        &self,
+        chat_id: i64,
+        path: &std::path::Path,
+        caption: Option<&str>,
+        opts: &SendOptions,
+    ) -> Result<i64> {
+        self.send_file("sendPhoto", "photo", chat_id, path, caption, opts)
+            .await
+    }
+
+    /// Upload in-memory content as a document named `file_name`.
+    pub async fn send_document_bytes(
+        &self,
+        chat_id: i64,
+        file_name: &str,
+        data: Vec<u8>,
+        caption: Option<&str>,
+        opts: &SendOptions,
+    ) -> Result<i64> {
+        let part = reqwest::multipart::Part::bytes(data).file_name(file_name.to_string());
+        self.upload("sendDocument", "document", chat_id, part, caption, opts)
+            .await
+    }
+
+    async fn send_file(
+        &self,
+        method: &str,
+        field: &'static str,
+        chat_id: i64,
+        path: &std::path::Path,
+        caption: Option<&str>,
+        opts: &SendOptions,
+    ) -> Result<i64> {
+        let data =
+            std::fs::read(path).with_context(|| format!("read attachment: {}", path.display()))?;
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "attachment".to_string());
+        let part = reqwest::multipart::Part::bytes(data).file_name(file_name);
+        self.upload(method, field, chat_id, part, caption, opts)
+            .await
+    }
+
+    async fn upload(
+        &self,
+        method: &str,
+        field: &'static str,
+        chat_id: i64,
+        part: reqwest::multipart::Part,
+        caption: Option<&str>,
+        opts: &SendOptions,
+    ) -> Result<i64> {
+        let mut form = reqwest::multipart::Form::new()
+            .text("chat_id", chat_id.to_string())
+            .part(field, part);
+        if let Some(caption) = caption.filter(|c| !c.is_empty()) {
+            form = form.text("caption", caption.to_string());
         }
+        form = opts.apply_form(form);
+
+        let result: SentMessage = self.post_multipart(method, chat_id, form).await?;
+        Ok(result.message_id)
+    }
 
-        let parsed: ApiResponse<T> = serde_json::from_str(&text)
-            .with_context(|| format!("parse telegram response json: {method}"))?;
+    pub async fn edit_message_text(
+        &self,
+        chat_id: i64,
+        message_id: i64,
+        text: &str,
+        opts: &SendOptions,
+    ) -> Result<()> {
+        let mut body = serde_json::json!({
+            "chat_id": chat_id,
+            "message_id": message_id,
+            "text": text,
+        });
+        opts.apply_json(&mut body);
 
-        parsed
-            .into_result()
-            .with_context(|| format!("telegram method failed: {method}"))
+        // Returns the edited Message (or `true` for inline messages); we don't need either.
+        let _: serde_json::Value = self.post_json("editMessageText", body).await?;
+        Ok(())
     }
 
-    pub async fn send_message(&self, user_id: i64, text: &str) -> Result<i64> {
-        #[derive(Deserialize)]
-        struct SendMessageResult {
-            message_id: i64,
+    /// Send a native poll via `sendPoll`.
+    pub async fn send_poll(
+        &self,
+        chat_id: i64,
+        question: &str,
+        options: &[String],
+        poll: &PollSettings,
+        opts: &SendOptions,
+    ) -> Result<SentPoll> {
+        let options: Vec<_> = options
+            .iter()
+            .map(|text| serde_json::json!({ "text": text }))
+            .collect();
+        let mut body = serde_json::json!({
+            "chat_id": chat_id,
+            "question": question,
+            "options": options,
+            // Telegram makes polls anonymous unless told otherwise.
+            "is_anonymous": poll.anonymous,
+            "allows_multiple_answers": poll.multiple,
+        });
+        if let Some(correct) = poll.correct {
+            body["type"] = "quiz".into();
+            body["correct_option_id"] = correct.into();
         }
+        opts.apply_json(&mut body);
+
+        self.post_json("sendPoll", body).await
+    }
 
-        let result: SendMessageResult = self
+    /// Close a poll so it takes no more votes.
+    pub async fn stop_poll(&self, chat_id: i64, message_id: i64) -> Result<()> {
+        let _: Poll = self
             .post_json(
-                "sendMessage",
+                "stopPoll",
                 serde_json::json!({
-                    "chat_id": user_id,
-                    "text": text,
+                    "chat_id": chat_id,
+                    "message_id": message_id,
                 }),
             )
             .await?;
+        Ok(())
+    }
 
-        Ok(result.message_id)
+    /// Pin a message in its chat, notifying the members as Telegram does by default.
+    pub async fn pin_chat_message(&self, chat_id: i64, message_id: i64) -> Result<()> {
+        let _: bool = self
+            .post_json(
+                "pinChatMessage",
+                serde_json::json!({
+                    "chat_id": chat_id,
+                    "message_id": message_id,
+                }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn unpin_chat_message(&self, chat_id: i64, message_id: i64) -> Result<()> {
+        let _: bool = self
+            .post_json(
+                "unpinChatMessage",
+                serde_json::json!({
+                    "chat_id": chat_id,
+                    "message_id": message_id,
+                }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_message(&self, chat_id: i64, message_id: i64) -> Result<()> {
+        let _: bool = self
+            .post_json(
+                "deleteMessage",
+                serde_json::json!({
+                    "chat_id": chat_id,
+                    "message_id": message_id,
+                }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn edit_message_caption(
+        &self,
+        chat_id: i64,
+        message_id: i64,
+        caption: &str,
+        opts: &SendOptions,
+    ) -> Result<()> {
+        let mut body = serde_json::json!({
+            "chat_id": chat_id,
+            "message_id": message_id,
+            "caption": caption,
+        });
+        opts.apply_json(&mut body);
+
+        let _: serde_json::Value = self.post_json("editMessageCaption", body).await?;
+        Ok(())
+    }
+
+    /// Resolve a `file_id` to a downloadable path (valid for at least an hour).
+    pub async fn get_file(&self, file_id: &str) -> Result<File> {
+        self.post_json("getFile", serde_json::json!({ "file_id": file_id }))
+            .await
+    }
+
+    /// Download a file previously resolved with `get_file`.
+    pub async fn download_file(&self, file_path: &str) -> Result<Vec<u8>> {
+        let method = "file download";
+        let res = self
+            .http
+            .get(self.file_url(file_path))
+            .send()
+            .await
+            .map_err(|e| self.reqwest_error(method, e))?;
+
+        let status = res.status();
+        if !status.is_success() {
+            let kind = if status.is_server_error() {
+                ErrorKind::Server
+            } else {
+                ErrorKind::Api
+            };
+            let mut err = TelegramError::new(
+                kind,
+                format!("telegram http error: method={method} status={status}"),
+            );
+            err.http_status = Some(status.as_u16());
+            return Err(err.into());
+        }
+
+        let bytes = res
+            .bytes()
+            .await
+            .map_err(|e| self.reqwest_error(method, e))?;
+        Ok(bytes.to_vec())
+    }
+
+    pub async fn get_me(&self) -> Result<User> {
+        self.post_json("getMe", serde_json::json!({})).await
+    }
+
+    /// Replace the command menu shown to users (default scope).
+    pub async fn set_my_commands(&self, commands: &[BotCommand]) -> Result<()> {
+        let _: bool = self
+            .post_json("setMyCommands", serde_json::json!({ "commands": commands }))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn send_chat_action(&self, chat_id: i64, action: &str) -> Result<()> {
+        let _: bool = self
+            .post_json(
+                "sendChatAction",
+                serde_json::json!({
+                    "chat_id": chat_id,
+                    "action": action,
+                }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Remove the bot's webhook, keeping pending updates so they can be polled.
+    pub async fn delete_webhook(&self) -> Result<()> {
+        let _: bool = self
+            .post_json(
+                "deleteWebhook",
+                serde_json::json!({ "drop_pending_updates": false }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_webhook_info(&self) -> Result<WebhookInfo> {
+        self.post_json("getWebhookInfo", serde_json::json!({}))
+            .await
+    }
+
+    pub async fn answer_callback_query(&self, callback_query_id: &str, text: &str) -> Result<()> {
+        let _: bool = self
+            .post_json(
+                "answerCallbackQuery",
+                serde_json::json!({
+                    "callback_query_id": callback_query_id,
+                    "text": text,
+                }),
+            )
+            .await?;
+        Ok(())
     }
 
     pub async fn get_updates(&self, offset: i64, timeout_s: u64) -> Result<Vec<Update>> {
@@ -96,103 +873,419 @@ impl TelegramClient {
         body.insert("timeout".to_string(), serde_json::json!(timeout_s));
         body.insert(
             "allowed_updates".to_string(),
-            serde_json::json!(["message"]),
+            serde_json::json!([
+                "message",
+                "edited_message",
+                "callback_query",
+                "message_reaction",
+                "poll",
+                "poll_answer"
+            ]),
         );
 
-        self.post_json("getUpdates", serde_json::Value::Object(body))
-            .await
+        let body = serde_json::Value::Object(body);
+        let hold = Duration::from_secs(timeout_s);
+        match self.post_json_held("getUpdates", body.clone(), hold).await {
+            Err(e) if webhook_conflict(&e) && self.fix_webhook => {
+                tracing::warn!("a webhook is set for this bot; deleting it to poll for updates");
+                self.delete_webhook().await?;
+                self.post_json_held("getUpdates", body, hold).await
+            }
+            Err(e) if webhook_conflict(&e) => Err(e.context(
+                "a webhook is set for this bot, so it can't poll for updates; \
+                 rerun with --fix-webhook (or set `fix_webhook = true`) to delete it",
+            )),
+            res => res,
+        }
     }
 
-    pub async fn drain_updates(&self) -> Result<i64> {
+    /// Confirm all pending updates, returning the next offset and the updates that were skipped
+    /// (so callers can still act on e.g. button presses that arrived while nobody was polling).
+    pub async fn drain_updates(&self) -> Result<(i64, Vec<Update>)> {
         let mut offset: i64 = 0;
+        let mut drained = Vec::new();
 
         loop {
             let updates = self.get_updates(offset, 0).await?;
             if updates.is_empty() {
-                return Ok(offset);
+                return Ok((offset, drained));
             }
 
             let last = updates.last().expect("non-empty updates").update_id;
             offset = last + 1;
+            drained.extend(updates);
         }
     }
 }
 
+#[derive(Deserialize)]
+struct SentMessage {
+    message_id: i64,
+}
+
 #[derive(Debug, Deserialize)]
 struct ApiResponse<T> {
     ok: bool,
     result: Option<T>,
     description: Option<String>,
     error_code: Option<i64>,
+    parameters: Option<ResponseParameters>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseParameters {
+    /// Seconds to wait before repeating a rate-limited request.
+    retry_after: Option<u64>,
+}
+
+/// Whether `getUpdates` failed because a webhook is registered. Other 409s (another process
+/// polling the same bot) are not this.
+fn webhook_conflict(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<TelegramError>().is_some_and(|t| {
+        t.error_code == Some(409) && t.message.to_ascii_lowercase().contains("webhook")
+    })
+}
+
+/// Whether a 400 from Telegram says `needle`, e.g. `message is not modified`.
+pub fn bad_request(e: &anyhow::Error, needle: &str) -> bool {
+    e.downcast_ref::<TelegramError>()
+        .is_some_and(|t| t.error_code == Some(400) && t.message.contains(needle))
+}
+
+/// How long to wait before retrying a rate-limited request, if `e` is a 429.
+pub fn retry_after(e: &anyhow::Error) -> Option<Duration> {
+    e.downcast_ref::<TelegramError>()?.retry_after
 }
 
 impl<T> ApiResponse<T> {
-    fn into_result(self) -> Result<T> {
+    fn into_result(self) -> Result<T, TelegramError> {
         if self.ok {
-            return self
-                .result
-                .context("telegram response missing result despite ok=true");
+            return self.result.ok_or_else(|| {
+                TelegramError::new(
+                    ErrorKind::InvalidResponse,
+                    "telegram response missing result despite ok=true",
+                )
+            });
         }
 
         let code = self.error_code.unwrap_or(0);
         let desc = self
             .description
             .unwrap_or_else(|| "unknown telegram error".to_string());
-        bail!("telegram api error {code}: {desc}")
+        let kind = if code == 409 && desc.contains("terminated by other getUpdates request") {
+            ErrorKind::Conflict
+        } else {
+            ErrorKind::Api
+        };
+        let mut err = TelegramError::new(kind, format!("telegram api error {code}: {desc}"));
+        err.error_code = self.error_code;
+        err.retry_after = self
+            .parameters
+            .and_then(|p| p.retry_after)
+            .map(Duration::from_secs);
+        Err(err)
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct Update {
     pub update_id: i64,
     pub message: Option<Message>,
+    /// A new version of an earlier message; only `--accept-edits` looks at these.
+    pub edited_message: Option<Message>,
+    pub callback_query: Option<CallbackQuery>,
+    pub message_reaction: Option<MessageReactionUpdated>,
+    /// New vote counts of a poll the bot sent.
+    pub poll: Option<Poll>,
+    /// A vote in a non-anonymous poll the bot sent.
+    pub poll_answer: Option<PollAnswer>,
 }
 
-#[derive(Debug, Deserialize)]
+impl Update {
+    /// When the message or reaction was sent (Unix seconds); `None` for button presses.
+    pub fn date(&self) -> Option<i64> {
+        self.message
+            .as_ref()
+            .map(|m| m.date)
+            .or(self.message_reaction.as_ref().map(|r| r.date))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct Message {
+    pub message_id: i64,
+    /// Unix time the message was sent.
+    #[serde(default)]
+    pub date: i64,
+    /// Unix time of the last edit, on edited messages.
+    #[serde(default)]
+    pub edit_date: Option<i64>,
+    /// Forum topic the message belongs to.
+    pub message_thread_id: Option<i64>,
     pub from: Option<User>,
     pub chat: Chat,
     pub text: Option<String>,
+    /// Text accompanying a photo, document, voice or video.
+    pub caption: Option<String>,
+    /// Links, mentions, code and other markup in `text`.
+    #[serde(default)]
+    pub entities: Vec<MessageEntity>,
+    /// The same for `caption`.
+    #[serde(default)]
+    pub caption_entities: Vec<MessageEntity>,
+    /// Available sizes of a photo, smallest first.
+    pub photo: Option<Vec<FileRef>>,
+    pub document: Option<FileRef>,
+    pub voice: Option<FileRef>,
+    pub video: Option<FileRef>,
+    /// A shared (possibly live) location.
+    pub location: Option<Location>,
+    /// A shared contact card.
+    pub contact: Option<Contact>,
+    /// The message this one explicitly replies to. Quick replies from a phone notification
+    /// arrive without it.
+    pub reply_to_message: Option<Box<Message>>,
+}
+
+impl Message {
+    /// The downloadable file of a photo, document, voice or video message. For photos this is
+    /// the largest size.
+    pub fn media(&self) -> Option<&FileRef> {
+        self.photo
+            .as_ref()
+            .and_then(|sizes| sizes.last())
+            .or(self.document.as_ref())
+            .or(self.voice.as_ref())
+            .or(self.video.as_ref())
+    }
+}
+
+/// A point on the map, as shared in a message. Live locations are updated by edits.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Radius of uncertainty, in meters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub horizontal_accuracy: Option<f64>,
+    /// For live locations, how long (seconds) it is updated for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub live_period: Option<i64>,
+}
+
+/// A phone contact, as shared in a message.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Contact {
+    pub phone_number: String,
+    pub first_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_name: Option<String>,
+    /// The contact's Telegram user id, if they use Telegram and are known to the sender.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i64>,
+}
+
+/// How `send_poll` sets up a poll.
+#[derive(Debug, Clone, Default)]
+pub struct PollSettings {
+    /// Hide who voted. Votes in anonymous polls arrive only as counts.
+    pub anonymous: bool,
+    /// Let voters pick more than one option.
+    pub multiple: bool,
+    /// Make it a quiz whose right answer is this option (0-based); Telegram shows it once
+    /// voted. Quizzes take a single answer.
+    pub correct: Option<usize>,
 }
 
+/// The message a poll was sent as.
 #[derive(Debug, Deserialize)]
+pub struct SentPoll {
+    pub message_id: i64,
+    pub poll: Poll,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Poll {
+    pub id: String,
+    pub options: Vec<PollOption>,
+    #[serde(default)]
+    pub total_voter_count: u64,
+}
+
+/// One option of a poll; only its vote count is needed, as options are sent in order.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PollOption {
+    #[serde(default)]
+    pub voter_count: u64,
+}
+
+/// A user's (possibly changed or retracted) vote in a poll.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PollAnswer {
+    pub poll_id: String,
+    /// The voter; absent when voting on behalf of a chat.
+    pub user: Option<User>,
+    /// Indexes of the chosen options; empty when the vote was retracted.
+    #[serde(default)]
+    pub option_ids: Vec<usize>,
+}
+
+/// A special part of a message's text. Offsets and lengths count UTF-16 code units.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+pub struct MessageEntity {
+    /// e.g. `url`, `text_link`, `mention`, `code`, `pre`, `bold`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub offset: usize,
+    pub length: usize,
+    /// Target of a `text_link`.
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Language of a `pre` block.
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+/// The part of Telegram's `PhotoSize`/`Document`/`Voice`/`Video` objects needed to download them.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct FileRef {
+    pub file_id: String,
+    /// Original file name; only documents and videos carry one.
+    #[serde(default)]
+    pub file_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct File {
+    /// Path for `download_file`; absent when the file is too big to download via the Bot API.
+    pub file_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct Chat {
     pub id: i64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct User {
     pub id: i64,
+    #[serde(default)]
+    pub first_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// IETF language tag of the user's Telegram client, e.g. `en`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language_code: Option<String>,
 }
 
-pub fn extract_text_reply<'a>(update: &'a Update, user_id: i64) -> Option<&'a str> {
-    let msg = update.message.as_ref()?;
-    let from = msg.from.as_ref()?;
+#[derive(Debug, Deserialize)]
+pub struct WebhookInfo {
+    pub url: String,
+}
+
+/// An entry in the bot's command menu; `command` has no leading slash.
+#[derive(Debug, Serialize)]
+pub struct BotCommand {
+    pub command: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CallbackQuery {
+    pub id: String,
+    pub from: User,
+    pub data: Option<String>,
+    /// The message carrying the pressed button, when it is still accessible.
+    pub message: Option<Message>,
+}
+
+/// A user changed their reactions to a message. Bots only receive these in groups where they are
+/// administrators.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessageReactionUpdated {
+    pub chat: Chat,
+    pub message_id: i64,
+    /// Unix time of the change.
+    #[serde(default)]
+    pub date: i64,
+    /// Absent for anonymous reactions.
+    pub user: Option<User>,
+    #[serde(default)]
+    pub old_reaction: Vec<ReactionType>,
+    #[serde(default)]
+    pub new_reaction: Vec<ReactionType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ReactionType {
+    /// Set for plain emoji reactions; custom emoji and paid reactions have none.
+    pub emoji: Option<String>,
+}
 
-    // Only accept private-chat replies from the configured user.
-    if from.id != user_id {
-        return None;
+impl CallbackQuery {
+    /// Chat the pressed button lives in. Falls back to the presser's private chat when the
+    /// message is no longer accessible.
+    pub fn chat_id(&self) -> i64 {
+        self.message.as_ref().map_or(self.from.id, |m| m.chat.id)
     }
-    if msg.chat.id != user_id {
-        return None;
+}
+
+/// Request parameters for debug logs, with message text replaced by its length. Replies and
+/// prompts may be secrets; the rest is useful for debugging.
+fn redact_params(body: &serde_json::Value) -> serde_json::Value {
+    let mut body = body.clone();
+    if let Some(map) = body.as_object_mut() {
+        for key in ["text", "caption"] {
+            if let Some(serde_json::Value::String(s)) = map.get(key) {
+                let redacted = format!("<{} chars>", s.chars().count());
+                map.insert(key.to_string(), redacted.into());
+            }
+        }
     }
+    body
+}
 
-    msg.text.as_deref()
+/// Whether `user_id` may answer in `chat_id`, one of the target `chat_ids`: in a private chat
+/// only its user can, in a group only members listed in `allowed_user_ids`.
+pub fn may_answer(chat_ids: &[i64], allowed_user_ids: &[i64], chat_id: i64, user_id: i64) -> bool {
+    chat_ids.contains(&chat_id) && (chat_id == user_id || allowed_user_ids.contains(&user_id))
+}
+
+/// The update's message, if it was sent in one of `chat_ids` by someone who may answer there.
+pub fn reply_message<'a>(
+    update: &'a Update,
+    chat_ids: &[i64],
+    allowed_user_ids: &[i64],
+) -> Option<&'a Message> {
+    let msg = update.message.as_ref()?;
+    let from = msg.from.as_ref()?;
+    may_answer(chat_ids, allowed_user_ids, msg.chat.id, from.id).then_some(msg)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn extract_text_reply(update: &Update, user_id: i64) -> Option<&str> {
+        reply_message(update, &[user_id], &[])?.text.as_deref()
+    }
+
     #[test]
     fn extract_text_reply_filters_non_matching_user_or_chat() {
         let good = Update {
             update_id: 10,
             message: Some(Message {
-                from: Some(User { id: 123 }),
+                from: Some(User {
+                    id: 123,
+                    ..Default::default()
+                }),
                 chat: Chat { id: 123 },
                 text: Some("hi".to_string()),
+                ..Default::default()
             }),
+            ..Default::default()
         };
 
         assert_eq!(extract_text_reply(&good, 123), Some("hi"));
@@ -201,24 +1294,116 @@ mod tests {
         let wrong_chat = Update {
             update_id: 11,
             message: Some(Message {
-                from: Some(User { id: 123 }),
+                from: Some(User {
+                    id: 123,
+                    ..Default::default()
+                }),
                 chat: Chat { id: 456 },
                 text: Some("nope".to_string()),
+                ..Default::default()
             }),
+            ..Default::default()
         };
         assert_eq!(extract_text_reply(&wrong_chat, 123), None);
 
         let no_text = Update {
             update_id: 12,
             message: Some(Message {
-                from: Some(User { id: 123 }),
+                from: Some(User {
+                    id: 123,
+                    ..Default::default()
+                }),
                 chat: Chat { id: 123 },
                 text: None,
+                ..Default::default()
             }),
+            ..Default::default()
         };
         assert_eq!(extract_text_reply(&no_text, 123), None);
     }
 
+    #[test]
+    fn escape_makes_text_literal_for_each_parse_mode() {
+        assert_eq!(
+            ParseMode::MarkdownV2.escape("v1.2 *done* (x-y) a\\b"),
+            "v1\\.2 \\*done\\* \\(x\\-y\\) a\\\\b"
+        );
+        assert_eq!(
+            ParseMode::Html.escape("<b>&</b>"),
+            "&lt;b&gt;&amp;&lt;/b&gt;"
+        );
+        assert_eq!(ParseMode::Plain.escape("*as is*"), "*as is*");
+    }
+
+    #[test]
+    fn code_block_escapes_only_what_pre_blocks_need() {
+        let (mode, block) = ParseMode::Plain.code_block("rust", "let s = `a\\b`; x.y()");
+        assert_eq!(mode, ParseMode::MarkdownV2);
+        assert_eq!(block, "```rust\nlet s = \\`a\\\\b\\`; x.y()\n```");
+
+        let (mode, block) = ParseMode::Html.code_block("sh\"><b", "a < b");
+        assert_eq!(mode, ParseMode::Html);
+        assert_eq!(
+            block,
+            "<pre><code class=\"language-shb\">a &lt; b</code></pre>"
+        );
+    }
+
+    #[test]
+    fn split_message_breaks_on_lines_and_counts_utf16() {
+        assert_eq!(split_message("aa\nbb\ncc", 5), vec!["aa", "bb\ncc"]);
+
+        // A single over-long line is split mid-line; 😀 is two UTF-16 code units.
+        assert_eq!(split_message("ab😀cd", 3), vec!["ab", "😀c", "d"]);
+
+        let long = "line\n".repeat(2000);
+        let parts = split_message(&long, MAX_MESSAGE_LEN);
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|p| utf16_len(p) <= MAX_MESSAGE_LEN));
+        assert_eq!(parts.join("\n"), long.trim_end());
+    }
+
+    #[test]
+    fn media_prefers_largest_photo() {
+        let raw = r#"{
+            "update_id": 13,
+            "message": {
+                "message_id": 7,
+                "from": {"id": 123, "first_name": "A"},
+                "chat": {"id": 123},
+                "photo": [
+                    {"file_id": "small", "width": 90, "height": 90},
+                    {"file_id": "large", "width": 1280, "height": 1280}
+                ]
+            }
+        }"#;
+        let update: Update = serde_json::from_str(raw).unwrap();
+        let msg = reply_message(&update, &[5, 123], &[]).unwrap();
+        assert_eq!(msg.media().unwrap().file_id, "large");
+        assert_eq!(msg.text, None);
+        assert!(reply_message(&update, &[999], &[]).is_none());
+    }
+
+    #[test]
+    fn group_replies_need_an_allowlisted_sender() {
+        let in_group = |from: i64| Update {
+            update_id: 1,
+            message: Some(Message {
+                from: Some(User {
+                    id: from,
+                    ..Default::default()
+                }),
+                chat: Chat { id: -100 },
+                text: Some("on it".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(reply_message(&in_group(7), &[-100], &[7]).is_some());
+        assert!(reply_message(&in_group(8), &[-100], &[7]).is_none());
+        assert!(reply_message(&in_group(7), &[-200], &[7]).is_none());
+    }
+
     #[test]
     fn api_response_into_result_ok_requires_result() {
         let res = ApiResponse::<i64> {
@@ -226,6 +1411,7 @@ mod tests {
             result: None,
             description: None,
             error_code: None,
+            parameters: None,
         };
 
         let err = res.into_result().unwrap_err();
@@ -243,6 +1429,7 @@ mod tests {
             result: None,
             description: Some("nope".to_string()),
             error_code: Some(400),
+            parameters: None,
         };
 
         let err = res.into_result().unwrap_err();
@@ -260,6 +1447,7 @@ mod tests {
             result: None,
             description: None,
             error_code: None,
+            parameters: None,
         };
 
         let err = res.into_result().unwrap_err();
@@ -270,6 +1458,128 @@ mod tests {
         );
     }
 
+    #[test]
+    fn api_response_into_result_error_is_classified_as_api() {
+        let res = ApiResponse::<i64> {
+            ok: false,
+            result: None,
+            description: Some("Forbidden: bot was blocked by the user".to_string()),
+            error_code: Some(403),
+            parameters: None,
+        };
+
+        let err = res.into_result().unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Api);
+        assert_eq!(err.error_code, Some(403));
+        assert!(!err.kind.retryable());
+    }
+
+    #[test]
+    fn only_the_webhook_409_is_a_webhook_conflict() {
+        let conflict = |description: &str| {
+            let mut err = TelegramError::new(
+                ErrorKind::Api,
+                format!("telegram api error 409: {description}"),
+            );
+            err.error_code = Some(409);
+            anyhow::Error::new(err).context("telegram method failed: getUpdates")
+        };
+        assert!(webhook_conflict(&conflict(
+            "Conflict: can't use getUpdates method while webhook is active; use deleteWebhook to delete the webhook first"
+        )));
+        assert!(!webhook_conflict(&conflict(
+            "Conflict: terminated by other getUpdates request; make sure that only one bot instance is running"
+        )));
+    }
+
+    #[test]
+    fn overlapping_polls_are_a_retryable_conflict() {
+        let raw = r#"{"ok":false,"error_code":409,"description":"Conflict: terminated by other getUpdates request; make sure that only one bot instance is running"}"#;
+        let res: ApiResponse<Vec<Update>> = serde_json::from_str(raw).unwrap();
+        let err = anyhow::Error::new(res.into_result().unwrap_err())
+            .context("telegram method failed: getUpdates");
+        assert!(crate::backoff::transient(&err));
+        assert!(!webhook_conflict(&err));
+
+        let raw = r#"{"ok":false,"error_code":409,"description":"Conflict: can't use getUpdates method while webhook is active; use deleteWebhook to delete the webhook first"}"#;
+        let res: ApiResponse<Vec<Update>> = serde_json::from_str(raw).unwrap();
+        assert_eq!(res.into_result().unwrap_err().kind, ErrorKind::Api);
+    }
+
+    #[test]
+    fn bad_requests_are_told_apart_by_description() {
+        let raw = r#"{"ok":false,"error_code":400,"description":"Bad Request: message is not modified: specified new message content and reply markup are exactly the same as a current content and reply markup of the message"}"#;
+        let res: ApiResponse<bool> = serde_json::from_str(raw).unwrap();
+        let err = anyhow::Error::new(res.into_result().unwrap_err())
+            .context("telegram method failed: editMessageText");
+        assert!(bad_request(&err, "message is not modified"));
+        assert!(!bad_request(&err, "there is no text in the message"));
+        assert!(!bad_request(
+            &anyhow::anyhow!("message is not modified"),
+            "message is not modified"
+        ));
+    }
+
+    #[test]
+    fn rate_limited_response_carries_retry_after() {
+        let raw = r#"{"ok":false,"error_code":429,"description":"Too Many Requests: retry after 7","parameters":{"retry_after":7}}"#;
+        let res: ApiResponse<i64> = serde_json::from_str(raw).unwrap();
+
+        let err = anyhow::Error::new(res.into_result().unwrap_err());
+        assert_eq!(retry_after(&err), Some(Duration::from_secs(7)));
+        assert_eq!(retry_after(&anyhow::anyhow!("other")), None);
+    }
+
+    #[test]
+    fn classify_transport_distinguishes_failure_modes() {
+        let dns = "error sending request: client error (Connect): dns error: failed to lookup address information";
+        assert_eq!(classify_transport(false, true, dns), ErrorKind::Dns);
+
+        let tls = "error sending request: client error (Connect): invalid peer certificate: UnknownIssuer";
+        assert_eq!(classify_transport(false, true, tls), ErrorKind::Tls);
+
+        let refused = "error sending request: client error (Connect): tcp connect error: Connection refused (os error 111)";
+        assert_eq!(classify_transport(false, true, refused), ErrorKind::Connect);
+        assert_eq!(
+            classify_transport(true, true, "operation timed out"),
+            ErrorKind::ConnectTimeout
+        );
+        assert_eq!(
+            classify_transport(true, false, "operation timed out"),
+            ErrorKind::Timeout
+        );
+    }
+
+    #[test]
+    fn redact_params_hides_message_text_only() {
+        let body = serde_json::json!({ "chat_id": 1, "text": "hunter2", "parse_mode": "HTML" });
+        assert_eq!(
+            redact_params(&body),
+            serde_json::json!({ "chat_id": 1, "text": "<7 chars>", "parse_mode": "HTML" })
+        );
+    }
+
+    #[test]
+    fn for_config_applies_api_url_and_rejects_empty_ca_file() {
+        let mut cfg: crate::config::Config = toml::from_str(
+            "bot_token = \"123:abc\"\nuser_id = 1\napi_url = \"https://bots.example.test/\"",
+        )
+        .unwrap();
+        let client = TelegramClient::for_config(&cfg).unwrap();
+        assert_eq!(
+            client.method_url("getMe"),
+            "https://bots.example.test/bot123:abc/getMe"
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("teleprompt_ca_test_{}.pem", std::process::id()));
+        std::fs::write(&path, "not a certificate\n").unwrap();
+        cfg.ca_file = Some(path.clone());
+        let err = TelegramClient::for_config(&cfg).err().unwrap();
+        assert!(format!("{err:#}").contains("ca_file"), "{err:#}");
+        let _ = std::fs::remove_file(&path);
+    }
+
     #[test]
     fn method_url_includes_base_url_token_and_method() {
         let mut client = TelegramClient::new("TOKEN".to_string());
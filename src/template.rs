@@ -0,0 +1,161 @@
+use anyhow::{Result, bail};
+use serde_json::Value;
+
+/// Placeholders `--format` understands, as (name, path into the JSON output envelope).
+const FIELDS: &[(&str, &[&str])] = &[
+    ("text", &["reply"]),
+    ("reply", &["reply"]),
+    ("chat_id", &["chat_id"]),
+    ("user_id", &["from", "id"]),
+    ("username", &["from", "username"]),
+    ("first_name", &["from", "first_name"]),
+    ("last_name", &["from", "last_name"]),
+    ("language_code", &["from", "language_code"]),
+    ("message_id", &["message_id"]),
+    ("date", &["date"]),
+    ("prompt_message_id", &["prompt_message_id"]),
+    ("sent_at", &["sent_at"]),
+    ("replied_at", &["replied_at"]),
+    ("elapsed_ms", &["elapsed_ms"]),
+    ("file", &["file"]),
+    ("reaction", &["reaction"]),
+];
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Field(&'static [&'static str]),
+    /// Whole seconds, from `elapsed_ms`.
+    ElapsedSeconds,
+}
+
+/// A parsed `--format` template such as `{text}\t{username}\t{elapsed_s}`.
+///
+/// `{name}` is replaced by a field of the reply (empty when absent), `{{` and `}}` are literal
+/// braces, and `\t`, `\n` and `\\` are escapes, since shells don't expand them in quotes.
+#[derive(Debug)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('\\') => literal.push('\\'),
+                    Some(other) => {
+                        literal.push('\\');
+                        literal.push(other);
+                    }
+                    None => literal.push('\\'),
+                },
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some(end) = rest.find('}') else {
+                        bail!("--format: unclosed {{ in {template:?}");
+                    };
+                    let name = &rest[..end];
+                    chars = rest[end + 1..].chars();
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(field(name)?);
+                }
+                '}' => {
+                    bail!("--format: unmatched }} in {template:?} (use }}}} for a literal brace)")
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self { segments })
+    }
+
+    /// Render against a reply's JSON output envelope.
+    pub fn render(&self, reply: &Value) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(s) => out.push_str(s),
+                Segment::Field(path) => match path.iter().try_fold(reply, |v, key| v.get(key)) {
+                    Some(Value::String(s)) => out.push_str(s),
+                    Some(Value::Null) | None => {}
+                    Some(other) => out.push_str(&other.to_string()),
+                },
+                Segment::ElapsedSeconds => {
+                    let ms = reply.get("elapsed_ms").and_then(Value::as_u64).unwrap_or(0);
+                    out.push_str(&(ms / 1000).to_string());
+                }
+            }
+        }
+        out
+    }
+}
+
+fn field(name: &str) -> Result<Segment> {
+    if name == "elapsed_s" {
+        return Ok(Segment::ElapsedSeconds);
+    }
+    match FIELDS.iter().find(|(n, _)| *n == name) {
+        Some((_, path)) => Ok(Segment::Field(path)),
+        None => {
+            let known: Vec<&str> = FIELDS.iter().map(|(n, _)| *n).collect();
+            bail!(
+                "--format: unknown placeholder {{{name}}} (known: {}, elapsed_s)",
+                known.join(", ")
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_fields_escapes_and_braces() {
+        let reply = serde_json::json!({
+            "reply": "yes",
+            "from": { "id": 7, "first_name": "Ann", "username": "ann" },
+            "message_id": null,
+            "elapsed_ms": 12_345,
+        });
+        let t = Template::parse(r"{text}\t{username}\t{elapsed_s}s {{{user_id}}} {message_id}.")
+            .unwrap();
+        assert_eq!(t.render(&reply), "yes\tann\t12s {7} .");
+
+        let reply = serde_json::json!({
+            "from": { "id": 7, "language_code": "de" },
+            "date": "2026-10-16T09:30:00Z",
+        });
+        let t = Template::parse("{date} {language_code}{last_name}").unwrap();
+        assert_eq!(t.render(&reply), "2026-10-16T09:30:00Z de");
+    }
+
+    #[test]
+    fn rejects_unknown_or_unbalanced_placeholders() {
+        let err = Template::parse("{nope}").unwrap_err().to_string();
+        assert!(
+            err.contains("unknown placeholder {nope}"),
+            "error was: {err}"
+        );
+        assert!(Template::parse("{text").is_err());
+        assert!(Template::parse("text}").is_err());
+    }
+}
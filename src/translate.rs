@@ -0,0 +1,120 @@
+use crate::config::TranslateConfig;
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::process::Stdio;
+
+/// Language detection (and possibly translation) result for a reply.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Translation {
+    /// ISO 639-3 code of the detected language, when detection was reliable.
+    pub language: Option<String>,
+    /// The reply translated by the configured command, when it was in another language.
+    pub translated: Option<String>,
+}
+
+/// Detect the reply's language and, if it is reliably not the expected one, translate it.
+///
+/// Translation failures are reported on stderr and leave `translated` empty: losing the human's
+/// answer over a broken translation hook would be worse than passing it through untranslated.
+pub fn process(cfg: &TranslateConfig, text: &str) -> Translation {
+    let language = detect(text);
+    let needs_translation = language
+        .as_deref()
+        .is_some_and(|l| !l.eq_ignore_ascii_case(&cfg.expected_language));
+
+    let translated = match (&cfg.command, needs_translation) {
+        (Some(command), true) => match run_command(command, text) {
+            Ok(t) => Some(t),
+            Err(e) => {
+                tracing::warn!("translation failed, using the original reply: {e:#}");
+                None
+            }
+        },
+        _ => None,
+    };
+
+    Translation {
+        language,
+        translated,
+    }
+}
+
+fn detect(text: &str) -> Option<String> {
+    let info = whatlang::detect(text)?;
+    // Short answers ("ok", "yes") are routinely misdetected; only trust confident results.
+    info.is_reliable().then(|| info.lang().code().to_string())
+}
+
+fn run_command(command: &str, text: &str) -> Result<String> {
+    let mut child = crate::shell::command(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("run translate command: {command}"))?;
+
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(text.as_bytes())?;
+    let output = child.wait_with_output()?;
+
+    anyhow::ensure!(
+        output.status.success(),
+        "translate command exited with {}: {command}",
+        output.status
+    );
+    let translated = String::from_utf8(output.stdout)
+        .context("translate command output is not valid UTF-8")?
+        .trim_end_matches(['\r', '\n'])
+        .to_string();
+    anyhow::ensure!(
+        !translated.trim().is_empty(),
+        "translate command printed nothing"
+    );
+    Ok(translated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GERMAN: &str = "Ja, bitte spielt die neue Version heute Abend ein. Ich habe die \
+        Änderungen gelesen und bin damit einverstanden, dass wir das jetzt machen.";
+
+    fn cfg(command: Option<&str>) -> TranslateConfig {
+        TranslateConfig {
+            expected_language: "eng".to_string(),
+            command: command.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn expected_language_is_not_translated() {
+        let t = process(
+            &cfg(Some("echo translated")),
+            "Yes, please go ahead and deploy the new release to production tonight.",
+        );
+        assert_eq!(t.language.as_deref(), Some("eng"));
+        assert_eq!(t.translated, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn other_language_is_piped_through_the_command() {
+        let t = process(&cfg(Some("tr a-z A-Z")), GERMAN);
+        assert_eq!(t.language.as_deref(), Some("deu"));
+        assert_eq!(
+            t.translated.as_deref(),
+            Some(GERMAN.to_ascii_uppercase().as_str())
+        );
+    }
+
+    #[test]
+    fn failed_translation_falls_back_to_original() {
+        let t = process(&cfg(Some("exit 1")), GERMAN);
+        assert_eq!(t.language.as_deref(), Some("deu"));
+        assert_eq!(t.translated, None);
+    }
+}